@@ -0,0 +1,195 @@
+//! Declarative binary payload decoders, written in JSON or YAML and stored as a decode script.
+//!
+//! ```yaml
+//! f_ports: [2]
+//! min_len: 5
+//! fields:
+//!   - { id: 1, name: temperature, unit: "°C", offset: 0, type: I16, scale: 0.1 }
+//!   - { id: 2, name: alarm, offset: 2, type: Bitfield, bits: { start: 7, len: 1 } }
+//!   - { id: 3, name: battery, unit: "mV", offset: 3, type: U16, endian: Little }
+//! ```
+use crate::db::{CodeMapItem, DecodeMap};
+use crate::decode::DecodeDataType;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BinaryDecoder {
+    /// Only payloads on these ports are decoded, any port when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub f_ports: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_len: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_len: Option<usize>,
+    pub fields: Vec<BinaryField>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BinaryField {
+    /// Data id the value is stored with.
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub unit: String,
+    /// Byte offset of the field in the payload.
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub t: BinaryType,
+    #[serde(default)]
+    pub endian: Endian,
+    /// Bits of the integer read at `offset`, required for `Bitfield`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bits: Option<BitRange>,
+    /// Number of bytes of a `Bcd` field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub len: Option<usize>,
+    /// The value is `raw * scale + add`.
+    #[serde(default = "BinaryField::default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub add: f64,
+    /// The field is only read from payloads on this port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f_port: Option<u8>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, strum::AsRefStr)]
+pub enum BinaryType {
+    U8,
+    U16,
+    U24,
+    U32,
+    U64,
+    I8,
+    I16,
+    I24,
+    I32,
+    I64,
+    F32,
+    F64,
+    /// Packed decimal, two digits per byte.
+    Bcd,
+    /// `bits` of one byte.
+    Bitfield,
+    Bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Bits counted from the least significant bit.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BitRange {
+    pub start: u8,
+    pub len: u8,
+}
+
+impl BinaryType {
+    /// Bytes read at the field offset, `Bcd` reads the field `len`.
+    pub fn size(&self) -> usize {
+        match self {
+            BinaryType::U8 | BinaryType::I8 | BinaryType::Bitfield | BinaryType::Bool => 1,
+            BinaryType::U16 | BinaryType::I16 => 2,
+            BinaryType::U24 | BinaryType::I24 => 3,
+            BinaryType::U32 | BinaryType::I32 | BinaryType::F32 => 4,
+            BinaryType::U64 | BinaryType::I64 | BinaryType::F64 => 8,
+            BinaryType::Bcd => 0,
+        }
+    }
+
+    fn is_unsigned(&self) -> bool {
+        matches!(self, BinaryType::U8 | BinaryType::U16 | BinaryType::U24 | BinaryType::U32 | BinaryType::U64 | BinaryType::Bitfield | BinaryType::Bool)
+    }
+}
+
+impl BinaryField {
+    fn default_scale() -> f64 {
+        1.0
+    }
+
+    /// Type of the stored value, scaled integers are stored as floats.
+    pub fn data_type(&self) -> DecodeDataType {
+        match self.t {
+            BinaryType::Bool => DecodeDataType::Bool,
+            BinaryType::F32 | BinaryType::F64 => DecodeDataType::F64,
+            _ if self.scale != 1.0 || self.add != 0.0 => DecodeDataType::F64,
+            _ => DecodeDataType::I32,
+        }
+    }
+}
+
+impl BinaryDecoder {
+    /// Reads a JSON or YAML definition and checks the fields.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let decoder: Self = match serde_json::from_str(s) {
+            Ok(decoder) => decoder,
+            Err(_) => serde_yaml::from_str(s).map_err(|e| e.to_string())?,
+        };
+        decoder.check()?;
+        Ok(decoder)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (i, field) in self.fields.iter().enumerate() {
+            if self.fields[..i].iter().any(|it| it.id == field.id) {
+                return Err(format!("data id {} is used more than once", field.id));
+            }
+            match (field.t, field.bits) {
+                (BinaryType::Bitfield, None) => {
+                    return Err(format!("{}: Bitfield needs bits", field.name));
+                }
+                (t, Some(bits)) => {
+                    if !t.is_unsigned() {
+                        return Err(format!("{}: bits need an unsigned type", field.name));
+                    }
+                    if bits.len == 0 || bits.start as usize + bits.len as usize > t.size() * 8 {
+                        return Err(format!("{}: bits out of {} range", field.name, t.as_ref()));
+                    }
+                }
+                _ => {}
+            }
+            if field.t == BinaryType::Bcd && !field.len.is_some_and(|len| (1..=9).contains(&len)) {
+                return Err(format!("{}: Bcd needs a len of 1 to 9 bytes", field.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Data point names and units of the fields.
+    pub fn map(&self) -> DecodeMap {
+        DecodeMap(self.fields.iter().map(|field| CodeMapItem {
+            id: field.id,
+            name: field.name.clone(),
+            unit: field.unit.clone(),
+            t: field.data_type(),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let yaml = r#"
+f_ports: [2]
+fields:
+  - { id: 1, name: temperature, offset: 0, type: I16, scale: 0.1 }
+  - { id: 2, name: alarm, offset: 2, type: Bitfield, bits: { start: 7, len: 1 } }
+"#;
+        let decoder = BinaryDecoder::parse(yaml).unwrap();
+        assert_eq!(decoder.fields[1].t, BinaryType::Bitfield);
+        assert_eq!(decoder.map()[0].t, DecodeDataType::F64);
+        let json = serde_json::to_string(&decoder).unwrap();
+        assert_eq!(BinaryDecoder::parse(&json).unwrap(), decoder);
+        assert!(BinaryDecoder::parse("fields: [{ id: 1, name: a, offset: 0, type: Bitfield }]").is_err());
+        assert!(BinaryDecoder::parse("fields: [{ id: 1, name: a, offset: 0, type: U8, bits: { start: 4, len: 5 } }]").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::decode::{DecodeData, DecodeDataType, GeoPoint, Value};
+use crate::sea_string_type;
+
+/// Payload formats decoded and encoded without a script.
+/// A device without a codec or script uses the Heltec TLV format.
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    strum::AsRefStr,
+    strum::EnumString,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs
+)]
+pub enum PayloadCodec {
+    /// Cayenne LPP, each value is `channel, type, data`.
+    CayenneLpp,
+    /// Cayenne LPP v2 ordering, each value is `type, channel, data`.
+    CayenneLppV2,
+    /// The payload is kept as a hex string.
+    Hex,
+    /// A JSON document, payloads that are not JSON are kept as UTF-8 text.
+    Json,
+}
+
+sea_string_type!(PayloadCodec);
+
+impl sea_orm::sea_query::Nullable for PayloadCodec {
+    fn null() -> sea_orm::Value {
+        sea_orm::Value::String(None)
+    }
+}
+
+/// Data ids of the built-in codecs, above the ids of the model catalog.
+/// A Cayenne LPP value is `BUILTIN_DATA_ID | channel << 12 | type << 4 | index`.
+pub const BUILTIN_DATA_ID: u32 = 0x0100_0000;
+pub const HEX_DATA_ID: u32 = 0x0110_0000;
+pub const JSON_DATA_ID: u32 = 0x0110_0001;
+pub const TEXT_DATA_ID: u32 = 0x0110_0002;
+
+const LPP_GPS: u8 = 136;
+
+struct LppType {
+    id: u8,
+    /// Name of the type in JSON commands.
+    key: &'static str,
+    unit: &'static str,
+    /// Bytes of each value, big endian.
+    size: usize,
+    count: usize,
+    signed: bool,
+    divisor: f64,
+    /// English and Chinese name of each data point.
+    names: &'static [(&'static str, &'static str)],
+}
+
+const LPP_TYPES: &[LppType] = &[
+    LppType { id: 0, key: "digital_input", unit: "", size: 1, count: 1, signed: false, divisor: 1.0, names: &[("Digital Input", "数字输入")] },
+    LppType { id: 1, key: "digital_output", unit: "", size: 1, count: 1, signed: false, divisor: 1.0, names: &[("Digital Output", "数字输出")] },
+    LppType { id: 2, key: "analog_input", unit: "", size: 2, count: 1, signed: true, divisor: 100.0, names: &[("Analog Input", "模拟输入")] },
+    LppType { id: 3, key: "analog_output", unit: "", size: 2, count: 1, signed: true, divisor: 100.0, names: &[("Analog Output", "模拟输出")] },
+    LppType { id: 101, key: "illuminance", unit: "lux", size: 2, count: 1, signed: false, divisor: 1.0, names: &[("Illuminance", "光照度")] },
+    LppType { id: 102, key: "presence", unit: "", size: 1, count: 1, signed: false, divisor: 1.0, names: &[("Presence", "存在")] },
+    LppType { id: 103, key: "temperature", unit: "°C", size: 2, count: 1, signed: true, divisor: 10.0, names: &[("Temperature", "温度")] },
+    LppType { id: 104, key: "humidity", unit: "%", size: 1, count: 1, signed: false, divisor: 2.0, names: &[("Humidity", "湿度")] },
+    LppType { id: 113, key: "accelerometer", unit: "G", size: 2, count: 3, signed: true, divisor: 1000.0, names: &[("Accelerometer X", "加速度 X"), ("Accelerometer Y", "加速度 Y"), ("Accelerometer Z", "加速度 Z")] },
+    LppType { id: 115, key: "barometer", unit: "hPa", size: 2, count: 1, signed: false, divisor: 10.0, names: &[("Barometer", "气压")] },
+    LppType { id: 116, key: "voltage", unit: "V", size: 2, count: 1, signed: false, divisor: 100.0, names: &[("Voltage", "电压")] },
+    LppType { id: 117, key: "current", unit: "A", size: 2, count: 1, signed: false, divisor: 1000.0, names: &[("Current", "电流")] },
+    LppType { id: 120, key: "percentage", unit: "%", size: 1, count: 1, signed: false, divisor: 1.0, names: &[("Percentage", "百分比")] },
+    LppType { id: 125, key: "concentration", unit: "ppm", size: 2, count: 1, signed: false, divisor: 1.0, names: &[("Concentration", "浓度")] },
+    LppType { id: 128, key: "power", unit: "W", size: 2, count: 1, signed: false, divisor: 1.0, names: &[("Power", "功率")] },
+    LppType { id: 130, key: "distance", unit: "m", size: 4, count: 1, signed: false, divisor: 1000.0, names: &[("Distance", "距离")] },
+    LppType { id: 131, key: "energy", unit: "kWh", size: 4, count: 1, signed: false, divisor: 1000.0, names: &[("Energy", "电能")] },
+    LppType { id: 132, key: "direction", unit: "°", size: 2, count: 1, signed: false, divisor: 1.0, names: &[("Direction", "方向")] },
+    LppType { id: 134, key: "gyrometer", unit: "°/s", size: 2, count: 3, signed: true, divisor: 100.0, names: &[("Gyrometer X", "陀螺仪 X"), ("Gyrometer Y", "陀螺仪 Y"), ("Gyrometer Z", "陀螺仪 Z")] },
+    // latitude and longitude in 0.0001°, altitude in 0.01 m, decoded into one position
+    LppType { id: LPP_GPS, key: "gps", unit: "", size: 3, count: 3, signed: true, divisor: 10000.0, names: &[("GPS", "定位")] },
+];
+
+impl LppType {
+    fn divisor(&self, index: usize) -> f64 {
+        if self.id == LPP_GPS && index == 2 { 100.0 } else { self.divisor }
+    }
+
+    fn value(&self, raw: i64) -> Value {
+        if self.divisor == 1.0 {
+            Value::Int(raw)
+        } else {
+            Value::Float(raw as f64 / self.divisor)
+        }
+    }
+}
+
+/// Name, unit and type of a data point written by a built-in codec.
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinEntry {
+    pub en: &'static str,
+    pub zh: &'static str,
+    pub unit: &'static str,
+    pub data_type: DecodeDataType,
+}
+
+pub fn builtin_entry(data_id: u32) -> Option<BuiltinEntry> {
+    let entry = |en, zh, data_type| Some(BuiltinEntry { en, zh, unit: "", data_type });
+    match data_id {
+        HEX_DATA_ID => entry("Payload", "原始数据", DecodeDataType::String),
+        JSON_DATA_ID => entry("JSON", "JSON", DecodeDataType::Object),
+        TEXT_DATA_ID => entry("Text", "文本", DecodeDataType::String),
+        id if id & 0xFFF0_0000 == BUILTIN_DATA_ID => {
+            let lpp = LPP_TYPES.iter().find(|it| it.id as u32 == (id >> 4) & 0xFF)?;
+            let (en, zh) = lpp.names.get((id & 0x0F) as usize)?;
+            let data_type = match lpp.id {
+                LPP_GPS => DecodeDataType::GeoPoint,
+                _ if lpp.divisor == 1.0 => DecodeDataType::I32,
+                _ => DecodeDataType::F64,
+            };
+            Some(BuiltinEntry { en, zh, unit: lpp.unit, data_type })
+        }
+        _ => None,
+    }
+}
+
+fn lpp_data_id(channel: u8, t: u8, index: usize) -> u32 {
+    BUILTIN_DATA_ID | (channel as u32) << 12 | (t as u32) << 4 | index as u32
+}
+
+impl PayloadCodec {
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<DecodeData>, String> {
+        match self {
+            PayloadCodec::CayenneLpp => lpp_decode(bytes, false),
+            PayloadCodec::CayenneLppV2 => lpp_decode(bytes, true),
+            PayloadCodec::Hex => Ok(vec![DecodeData::new(HEX_DATA_ID, hex::encode_upper(bytes).into())]),
+            PayloadCodec::Json => match serde_json::from_slice(bytes) {
+                Ok(json) => Ok(Value::from_json(json)
+                    .map(|v| vec![DecodeData::new(JSON_DATA_ID, v)])
+                    .unwrap_or_default()),
+                Err(_) => {
+                    let text = std::str::from_utf8(bytes).map_err(|_| "payload is neither JSON nor UTF-8 text".to_string())?;
+                    Ok(vec![DecodeData::new(TEXT_DATA_ID, text.into())])
+                }
+            },
+        }
+    }
+
+    /// Encodes a JSON downlink command.
+    /// Cayenne LPP takes an array of `{"channel", "type", "value"}`, hex takes a hex string
+    /// and JSON sends strings as text and other values as JSON.
+    pub fn encode(self, data: &serde_json::Value) -> Result<Vec<u8>, String> {
+        match self {
+            PayloadCodec::CayenneLpp => lpp_encode(data, false),
+            PayloadCodec::CayenneLppV2 => lpp_encode(data, true),
+            PayloadCodec::Hex => {
+                let s = data.as_str().ok_or_else(|| "expected a hex string".to_string())?;
+                hex::decode(s).map_err(|e| e.to_string())
+            }
+            PayloadCodec::Json => match data {
+                serde_json::Value::String(s) => Ok(s.clone().into_bytes()),
+                data => serde_json::to_vec(data).map_err(|e| e.to_string()),
+            },
+        }
+    }
+}
+
+fn lpp_decode(bytes: &[u8], type_first: bool) -> Result<Vec<DecodeData>, String> {
+    let mut data = Vec::new();
+    let mut rest = bytes;
+    while let [a, b, tail @ ..] = rest {
+        let (channel, t) = if type_first { (*b, *a) } else { (*a, *b) };
+        let lpp = LPP_TYPES.iter().find(|it| it.id == t).ok_or_else(|| format!("unknown LPP type {}", t))?;
+        let len = lpp.size * lpp.count;
+        let value = tail.get(..len).ok_or_else(|| format!("LPP channel {} needs {} bytes", channel, len))?;
+        rest = &tail[len..];
+        let raw = value.chunks(lpp.size).map(|bytes| {
+            let u = bytes.iter().fold(0u64, |u, b| u << 8 | *b as u64);
+            if lpp.signed {
+                let shift = 64 - lpp.size as u32 * 8;
+                ((u << shift) as i64) >> shift
+            } else {
+                u as i64
+            }
+        });
+        if lpp.id == LPP_GPS {
+            let raw: Vec<_> = raw.collect();
+            let point = GeoPoint {
+                lat: raw[0] as f64 / lpp.divisor(0),
+                lon: raw[1] as f64 / lpp.divisor(1),
+                alt: Some(raw[2] as f64 / lpp.divisor(2)),
+                accuracy: None,
+            };
+            data.push(DecodeData::new(lpp_data_id(channel, t, 0), point.into()));
+        } else {
+            for (i, raw) in raw.enumerate() {
+                data.push(DecodeData::new(lpp_data_id(channel, t, i), lpp.value(raw)));
+            }
+        }
+    }
+    if !rest.is_empty() {
+        return Err("truncated LPP value".to_string());
+    }
+    Ok(data)
+}
+
+fn lpp_encode(data: &serde_json::Value, type_first: bool) -> Result<Vec<u8>, String> {
+    #[derive(serde::Deserialize)]
+    struct Item {
+        channel: u8,
+        #[serde(rename = "type")]
+        t: String,
+        value: serde_json::Value,
+    }
+    let items: Vec<Item> = serde_json::from_value(data.clone()).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    for item in items {
+        let lpp = LPP_TYPES.iter().find(|it| it.key == item.t).ok_or_else(|| format!("unknown LPP type {}", item.t))?;
+        let values: Vec<f64> = match &item.value {
+            serde_json::Value::Object(_) if lpp.id == LPP_GPS => {
+                let point: GeoPoint = serde_json::from_value(item.value.clone()).map_err(|e| e.to_string())?;
+                vec![point.lat, point.lon, point.alt.unwrap_or_default()]
+            }
+            serde_json::Value::Array(values) => values.iter().filter_map(|it| it.as_f64()).collect(),
+            value => value.as_f64().into_iter().collect(),
+        };
+        if values.len() != lpp.count {
+            return Err(format!("{} needs {} numbers", lpp.key, lpp.count));
+        }
+        if type_first {
+            bytes.extend([lpp.id, item.channel]);
+        } else {
+            bytes.extend([item.channel, lpp.id]);
+        }
+        let bits = lpp.size as u32 * 8;
+        let (min, max) = if lpp.signed {
+            (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1)
+        } else {
+            (0, (1i64 << bits) - 1)
+        };
+        for (i, v) in values.into_iter().enumerate() {
+            let raw = (v * lpp.divisor(i)).round();
+            if raw < min as f64 || raw > max as f64 {
+                return Err(format!("{} of {} is out of range", v, lpp.key));
+            }
+            bytes.extend_from_slice(&(raw as i64).to_be_bytes()[8 - lpp.size..]);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cayenne_lpp() {
+        let bytes = hex::decode("03670110056700FF018806765FF2960A0003E8").unwrap();
+        let data = PayloadCodec::CayenneLpp.decode(&bytes).unwrap();
+        assert_eq!(data, vec![
+            DecodeData::new(lpp_data_id(3, 103, 0), Value::Float(27.2)),
+            DecodeData::new(lpp_data_id(5, 103, 0), Value::Float(25.5)),
+            DecodeData::new(lpp_data_id(1, 136, 0), GeoPoint { lat: 42.3519, lon: -87.9094, alt: Some(10.0), accuracy: None }.into()),
+        ]);
+        let entry = builtin_entry(lpp_data_id(3, 103, 0)).unwrap();
+        assert_eq!((entry.en, entry.unit), ("Temperature", "°C"));
+
+        let command = serde_json::json!([
+            {"channel": 3, "type": "temperature", "value": 27.2},
+            {"channel": 1, "type": "gps", "value": {"lat": 42.3519, "lon": -87.9094, "alt": 10.0}},
+        ]);
+        let encoded = PayloadCodec::CayenneLppV2.encode(&command).unwrap();
+        assert_eq!(hex::encode_upper(&encoded), "67030110880106765FF2960A0003E8");
+        assert_eq!(PayloadCodec::CayenneLppV2.decode(&encoded).unwrap().len(), 2);
+        assert!(PayloadCodec::CayenneLpp.decode(&bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn test_text_codecs() {
+        assert_eq!(PayloadCodec::Hex.decode(&[0x01, 0xAB]).unwrap(), vec![DecodeData::new(HEX_DATA_ID, "01AB".into())]);
+        assert_eq!(PayloadCodec::Hex.encode(&"01ab".into()).unwrap(), vec![0x01, 0xAB]);
+        assert_eq!(PayloadCodec::Json.decode(b"hello").unwrap(), vec![DecodeData::new(TEXT_DATA_ID, "hello".into())]);
+        assert_eq!(PayloadCodec::Json.decode(br#"{"t":1}"#).unwrap()[0].i, JSON_DATA_ID);
+    }
+}
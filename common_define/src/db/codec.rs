@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+/// How the object returned by a TTN codec is flattened into data points,
+/// `{"air": {"temp": 1}}` is stored as the data point named `air.temp`.
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    PartialEq,
+    Eq
+)]
+pub struct CodecFlatten {
+    #[serde(default = "CodecFlatten::default_separator")]
+    pub separator: String,
+    /// Nested objects below this depth are dropped.
+    #[serde(default = "CodecFlatten::default_depth")]
+    pub depth: u8,
+}
+
+impl CodecFlatten {
+    fn default_separator() -> String {
+        ".".to_string()
+    }
+    fn default_depth() -> u8 {
+        3
+    }
+}
+
+impl Default for CodecFlatten {
+    fn default() -> Self {
+        Self {
+            separator: Self::default_separator(),
+            depth: Self::default_depth(),
+        }
+    }
+}
+
+/// Per device values passed to codecs as `variables`.
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[serde(transparent)]
+pub struct CodecVariables(pub BTreeMap<String, String>);
+
+impl CodecVariables {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::convert::From<CodecFlatten> for sea_orm::Value {
+    fn from(source: CodecFlatten) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for CodecFlatten {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for CodecFlatten {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "CodecFlatten".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+impl std::convert::From<CodecVariables> for sea_orm::Value {
+    fn from(source: CodecVariables) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for CodecVariables {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for CodecVariables {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "CodecVariables".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
@@ -2,8 +2,11 @@
 
 
 pub mod snap_decode_script;
+pub mod snap_decode_script_version;
+pub mod snap_decode_test_vector;
 pub mod snap_device_authority;
 pub mod snap_device_data;
+pub mod snap_device_data_rollup;
 pub mod snap_device_data_name;
 pub mod snap_device_function;
 pub mod snap_device_group;
@@ -11,6 +14,7 @@ pub mod snap_device_lora_gate;
 pub mod snap_device_lora_node;
 pub mod snap_device_map_group;
 pub mod snap_device_mqtt;
+pub mod snap_device_profile;
 pub mod snap_devices;
 pub mod snap_snap_device;
 pub mod snap_user_token;
@@ -20,5 +24,8 @@ pub mod snap_integration_mqtt;
 pub mod snap_admin;
 pub mod snap_device_group_map_user;
 pub mod snap_config;
+pub mod snap_data_retention;
+pub mod snap_virtual_point;
+pub mod snap_data_calibration;
 pub mod snap_downlink;
 pub mod snap_product_info;
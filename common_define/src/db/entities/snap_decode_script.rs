@@ -1,6 +1,7 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.1
 
 use sea_orm::entity::prelude::*;
+use crate::db::CodecFlatten;
 use crate::db::map::DecodeMap;
 use crate::Id;
 use crate::time::Timestamp;
@@ -18,6 +19,9 @@ pub struct Model {
     #[sea_orm(column_type = "Text")]
     pub name: String,
     pub map: DecodeMap,
+    pub codec: CodecFlatten,
+    /// Latest version, the script columns are a copy of it.
+    pub version: i32,
     pub create_time: Timestamp,
     pub modify_time: Timestamp,
 }
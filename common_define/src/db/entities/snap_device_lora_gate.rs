@@ -1,7 +1,7 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.1
 
 use sea_orm::entity::prelude::*;
-use crate::db::Eui;
+use crate::db::{Eui, GatewayAllowList, Key};
 use crate::Id;
 use crate::lora::LoRaRegion;
 
@@ -15,6 +15,8 @@ pub struct Model {
     pub region: LoRaRegion,
     #[sea_orm(column_type = "Text")]
     pub eui: Eui,
+    pub allow_list: GatewayAllowList,
+    pub hmac_key: Option<Key>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
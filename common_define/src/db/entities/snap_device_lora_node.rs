@@ -1,9 +1,9 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.1
 
 use sea_orm::entity::prelude::*;
-use crate::db::{Eui, Key, LoRaAddr};
+use crate::db::{Eui, FPortRoutes, Key, LoRaAddr, ProfileOverrides};
 use crate::Id;
-use crate::lora::{LoRaJoinType, LoRaRegion};
+use crate::lora::{LoRaJoinType, LoRaRegion, PayloadEncryption};
 use crate::product::ProductType;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
@@ -54,6 +54,11 @@ pub struct Model {
     pub dev_non: i32,
     pub app_non: i32,
     pub net_id: i32,
+    pub profile: Option<Id>,
+    pub overrides: ProfileOverrides,
+    pub fport_routes: FPortRoutes,
+    pub payload_encryption: PayloadEncryption,
+    pub app_kek: Option<Key>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,7 +1,8 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.1
 
 use sea_orm::entity::prelude::*;
-use crate::db::Eui;
+use crate::db::{CodecVariables, Eui};
+use crate::codec::PayloadCodec;
 use crate::Id;
 use crate::product::DeviceType;
 use crate::time::Timestamp;
@@ -20,12 +21,18 @@ pub struct Model {
     pub enable: bool,
     pub online: bool,
     pub script: Option<Id>,
+    /// Pinned version of the script, the latest version when not set.
+    pub script_version: Option<i32>,
+    pub encoder: Option<Id>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub codec: Option<PayloadCodec>,
     pub data_id: Option<Id>,
     pub product_id: Option<Id>,
     #[sea_orm(column_type = "Text")]
     pub device_type: DeviceType,
     pub active_time: Option<Timestamp>,
     pub create_time: Timestamp,
+    pub variables: CodecVariables,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
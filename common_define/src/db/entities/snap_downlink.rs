@@ -13,6 +13,7 @@ pub struct Model {
     pub name: String,
     #[sea_orm(column_type = "Text")]
     pub data: String,
+    pub json: Option<Json>,
     pub order: i32,
     pub port: i32,
     pub create_time: Timestamp,
@@ -17,6 +17,7 @@ pub struct Model {
     pub description: String,
     #[sea_orm(column_type = "Text")]
     pub image: String,
+    pub encoder: Option<Id>,
     pub create_time: Timestamp,
 }
 
@@ -3,6 +3,7 @@
 use sea_orm::entity::prelude::*;
 use crate::Id;
 use crate::time::Timestamp;
+use crate::unit::UnitPreference;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
 #[sea_orm(table_name = "snap_users")]
@@ -24,6 +25,11 @@ pub struct Model {
     pub active_token: String,
     #[sea_orm(column_type = "Text")]
     pub picture: String,
+    /// Units the data of the user is read in.
+    pub units: UnitPreference,
+    /// IANA time zone days, weeks and months of data are aligned to.
+    #[sea_orm(column_type = "Text", nullable)]
+    pub time_zone: Option<String>,
     pub create_time: Timestamp,
 }
 
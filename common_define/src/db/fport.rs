@@ -0,0 +1,169 @@
+use std::ops::RangeInclusive;
+use crate::Id;
+
+/// FPorts reserved for the LoRaWAN application layer packages (clock sync, multicast, fragmentation, ...).
+pub const APP_PACKAGE_PORTS: RangeInclusive<u8> = 200..=224;
+
+/// Uplink handling for one FPort.
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    PartialEq,
+    Eq
+)]
+pub struct FPortRoute {
+    pub port: u8,
+    /// Decoder script, the device script is used when it is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Id>,
+    /// Store the decoded data as telemetry.
+    #[serde(default = "default_store")]
+    pub store: bool,
+    /// Integration topic the decoded data is published to, below `/v1/device/{id}/`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+fn default_store() -> bool {
+    true
+}
+
+impl FPortRoute {
+    /// Topics are single level names below the device namespace, without wildcards.
+    pub fn is_valid_topic(topic: &str) -> bool {
+        let topic = topic.trim_start_matches('/');
+        !topic.is_empty()
+            && topic.len() <= 128
+            && !topic.starts_with('$')
+            && !topic.contains(['+', '#', '\0'])
+            && topic.split('/').all(|level| !level.is_empty() && level != "." && level != "..")
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[serde(transparent)]
+pub struct FPortRoutes(pub Vec<FPortRoute>);
+
+impl FPortRoutes {
+    pub fn is_reserved(port: u8) -> bool {
+        APP_PACKAGE_PORTS.contains(&port)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn route(&self, port: u8) -> Option<&FPortRoute> {
+        self.0.iter().find(|it| it.port == port)
+    }
+
+    /// The first port that is reserved or configured twice.
+    pub fn invalid_port(&self) -> Option<u8> {
+        self.0.iter().enumerate()
+            .find(|(i, it)| Self::is_reserved(it.port) || self.0[..*i].iter().any(|prev| prev.port == it.port))
+            .map(|(_, it)| it.port)
+    }
+
+    /// The first topic that is empty, has wildcards or leaves the device namespace.
+    pub fn invalid_topic(&self) -> Option<&str> {
+        self.0.iter()
+            .filter_map(|it| it.topic.as_deref())
+            .find(|topic| !FPortRoute::is_valid_topic(topic))
+    }
+
+    /// Routes of `self` with the routes of `base` for ports `self` does not configure.
+    pub fn merge(mut self, base: &FPortRoutes) -> Self {
+        for route in &base.0 {
+            if self.route(route.port).is_none() {
+                self.0.push(route.clone());
+            }
+        }
+        self
+    }
+
+    pub fn scripts(&self) -> impl Iterator<Item = Id> + '_ {
+        self.0.iter().filter_map(|it| it.script)
+    }
+}
+
+impl std::convert::From<FPortRoutes> for sea_orm::Value {
+    fn from(source: FPortRoutes) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for FPortRoutes {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for FPortRoutes {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "FPortRoutes".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(port: u8, store: bool) -> FPortRoute {
+        FPortRoute { port, script: None, store, topic: None }
+    }
+
+    #[test]
+    fn test_fport_routes() {
+        let node = FPortRoutes(vec![route(2, false)]);
+        let profile = FPortRoutes(vec![route(2, true), route(10, true)]);
+        let routes = node.merge(&profile);
+        assert_eq!(routes.0.len(), 2);
+        assert!(!routes.route(2).unwrap().store);
+        assert!(routes.route(10).unwrap().store);
+        assert_eq!(routes.invalid_port(), None);
+        assert_eq!(FPortRoutes(vec![route(3, true), route(3, false)]).invalid_port(), Some(3));
+        assert_eq!(FPortRoutes(vec![route(202, true)]).invalid_port(), Some(202));
+        let r: FPortRoute = serde_json::from_str(r#"{"port": 3}"#).unwrap();
+        assert!(r.store);
+    }
+
+    #[test]
+    fn test_fport_topic() {
+        assert!(FPortRoute::is_valid_topic("alarm"));
+        assert!(FPortRoute::is_valid_topic("/status/battery"));
+        for topic in ["", "/", "a/+/b", "#", "a//b", "../other", "$SYS/x"] {
+            assert!(!FPortRoute::is_valid_topic(topic), "{topic}");
+        }
+        let mut r = route(2, true);
+        r.topic = Some("a/#".to_string());
+        assert_eq!(FPortRoutes(vec![route(3, true), r]).invalid_topic(), Some("a/#"));
+    }
+}
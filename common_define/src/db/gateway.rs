@@ -0,0 +1,77 @@
+use std::net::IpAddr;
+use ipnet::IpNet;
+
+/// Networks a gateway may send from, any source is accepted when empty.
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[serde(transparent)]
+pub struct GatewayAllowList(pub Vec<IpNet>);
+
+impl GatewayAllowList {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.is_empty() || self.0.iter().any(|net| net.contains(&ip))
+    }
+}
+
+impl std::convert::From<GatewayAllowList> for sea_orm::Value {
+    fn from(source: GatewayAllowList) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for GatewayAllowList {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for GatewayAllowList {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "GatewayAllowList".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_list() {
+        let list: GatewayAllowList = serde_json::from_str(r#"["10.0.0.0/8", "192.168.1.7/32", "fd00::/8"]"#).unwrap();
+        assert!(list.allows("10.1.2.3".parse().unwrap()));
+        assert!(list.allows("192.168.1.7".parse().unwrap()));
+        assert!(!list.allows("192.168.1.8".parse().unwrap()));
+        assert!(list.allows("fd12::1".parse().unwrap()));
+        assert!(GatewayAllowList::default().allows("8.8.8.8".parse().unwrap()));
+        assert!(serde_json::from_str::<GatewayAllowList>(r#"["10.0.0.0/33"]"#).is_err());
+    }
+}
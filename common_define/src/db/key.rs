@@ -2,11 +2,25 @@ use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use std::str;
 use std::str::FromStr;
+use std::sync::RwLock;
+use aes_kw::KekAes128;
 use redis::{FromRedisValue, RedisResult, RedisWrite, ToRedisArgs, Value};
 use serde::{Deserializer, Serializer};
 use serde::de::Error;
 use crate::db::{DbErr};
 
+/// Key-encryption key used to wrap every `Key` written to the database or redis.
+/// When unset keys are stored as plain hex, as before.
+static KEY_ENCRYPTION_KEY: RwLock<Option<[u8; 16]>> = RwLock::new(None);
+
+pub fn set_key_encryption_key(kek: Option<[u8; 16]>) {
+    *KEY_ENCRYPTION_KEY.write().unwrap() = kek;
+}
+
+pub fn key_encryption_key() -> Option<[u8; 16]> {
+    *KEY_ENCRYPTION_KEY.read().unwrap()
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Key(pub lorawan::keys::AES128);
 
@@ -25,7 +39,7 @@ impl sea_orm::sea_query::Nullable for Key {
 
 impl std::convert::From<Key> for sea_orm::Value {
     fn from(source: Key) -> Self {
-        source.to_string().into()
+        source.to_stored().into()
     }
 }
 impl sea_orm::TryGetable for Key {
@@ -35,26 +49,14 @@ impl sea_orm::TryGetable for Key {
     ) -> std::result::Result<Self, sea_orm::TryGetError> {
         <String as sea_orm::TryGetable>::try_get_by(res, idx)
             .and_then(|v| {
-                if v.len() != 32 {
-                    return Err(sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(format!("invalid key length: {}", v.len()))));
-                }
-                let mut key = [0; 16];
-                hex::decode_to_slice(&v, &mut key).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string())))?;
-                Ok(Key(lorawan::keys::AES128(key)))
+                Key::from_stored(&v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string())))
             })
     }
 }
 impl sea_orm::sea_query::ValueType for Key {
     fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
         <String as sea_orm::sea_query::ValueType>::try_from(v)
-            .and_then(|v| {
-                if v.len() != 32 {
-                    return Err(sea_orm::sea_query::ValueTypeErr);
-                }
-                let mut key = [0; 16];
-                hex::decode_to_slice(&v, &mut key).map_err(|_e| sea_orm::sea_query::ValueTypeErr)?;
-                Ok(Key(lorawan::keys::AES128(key)))
-            })
+            .and_then(|v| Key::from_stored(&v).map_err(|_e| sea_orm::sea_query::ValueTypeErr))
     }
     fn type_name() -> std::string::String {
         "Key".to_owned()
@@ -75,6 +77,61 @@ impl Key {
     pub fn nil() -> Self {
         Self(lorawan::keys::AES128([0; 16]))
     }
+
+    /// Wraps the key with `kek` (RFC 3394), the result is 48 hex chars.
+    pub fn wrap(&self, kek: [u8; 16]) -> String {
+        let mut out = [0; 24];
+        KekAes128::from(kek).wrap(&self.0.0, &mut out).expect("wrap 16 byte key");
+        hex::encode_upper(out)
+    }
+
+    /// Parses a stored key. 32 hex chars is a plain key, 48 hex chars is a key wrapped with `kek`.
+    pub fn unwrap_stored(value: &str, kek: Option<[u8; 16]>) -> Result<Self, DbErr> {
+        match value.len() {
+            32 => value.parse(),
+            48 => {
+                let kek = kek.ok_or(DbErr::Unwrap)?;
+                let mut wrapped = [0; 24];
+                hex::decode_to_slice(value, &mut wrapped).map_err(|_| DbErr::Parse)?;
+                let mut key = [0; 16];
+                KekAes128::from(kek).unwrap(&wrapped, &mut key).map_err(|_| DbErr::Unwrap)?;
+                Ok(Self::new(key))
+            }
+            _ => Err(DbErr::Len(format!("Key most 32 or 48 byte, found '{}'", value.len())))
+        }
+    }
+
+    pub fn is_wrapped(value: &str) -> bool {
+        value.len() == 48
+    }
+
+    /// Form written to the database and redis, wrapped when a key-encryption key is set.
+    pub fn to_stored(&self) -> String {
+        match key_encryption_key() {
+            Some(kek) => self.wrap(kek),
+            None => self.to_string()
+        }
+    }
+
+    pub fn from_stored(value: &str) -> Result<Self, DbErr> {
+        Self::unwrap_stored(value, key_encryption_key())
+    }
+}
+
+/// Serde helper storing a `Key` in its wrapped form, for keys kept inside JSON values in redis.
+pub mod wrapped_key {
+    use serde::{Deserializer, Serializer};
+    use serde::de::Error;
+    use super::Key;
+
+    pub fn serialize<S>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&key.to_stored())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Key, D::Error> where D: Deserializer<'de> {
+        let s = <&str as serde::Deserialize>::deserialize(deserializer)?;
+        Key::from_stored(s).map_err(D::Error::custom)
+    }
 }
 
 impl serde::Serialize for Key {
@@ -93,14 +150,14 @@ impl<'de> serde::Deserialize<'de> for Key {
 
 impl ToRedisArgs for Key {
     fn write_redis_args<W>(&self, out: &mut W) where W: ?Sized + RedisWrite {
-        self.to_string().write_redis_args(out)
+        self.to_stored().write_redis_args(out)
     }
 }
 
 impl FromRedisValue for Key {
     fn from_redis_value(v: &Value) -> RedisResult<Self> {
         let u = String::from_redis_value(v)?;
-        u.parse()
+        Key::from_stored(&u)
             .map_err(|e: DbErr| redis::RedisError::from((redis::ErrorKind::ResponseError, "redis parse key", e.to_string())))
     }
 }
@@ -131,3 +188,21 @@ impl FromStr for Key {
         Key::try_from(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Key;
+
+    #[test]
+    fn test_key_wrap() {
+        let kek: [u8; 16] = hex::decode("000102030405060708090A0B0C0D0E0F").unwrap().try_into().unwrap();
+        let key: Key = "00112233445566778899AABBCCDDEEFF".parse().unwrap();
+        let wrapped = key.wrap(kek);
+        assert_eq!(wrapped, "1FA68B0A8112B447AEF34BD8FB5A7B829D3E862371D2CFE5");
+        assert!(Key::is_wrapped(&wrapped));
+        assert_eq!(Key::unwrap_stored(&wrapped, Some(kek)).unwrap(), key);
+        assert!(Key::unwrap_stored(&wrapped, None).is_err());
+        assert!(Key::unwrap_stored(&wrapped, Some([0; 16])).is_err());
+        assert_eq!(Key::unwrap_stored("00112233445566778899AABBCCDDEEFF", Some(kek)).unwrap(), key);
+    }
+}
@@ -4,12 +4,28 @@ mod eui;
 mod entities;
 mod map;
 mod data;
+mod profile;
+mod fport;
+mod gateway;
+mod codec;
 mod group_permission;
+mod rollup;
+mod rewrap;
+mod retention;
+mod virtual_point;
 
 pub use group_permission::GroupPermission;
 pub use data::DbDecodeData;
+pub use rollup::Rollup;
+pub use rewrap::{plain_key_rows, rewrap_keys, wrap_plain_keys, RewrappedKeys};
+pub use retention::RetentionScope;
+pub use virtual_point::VirtualPointScope;
+pub use profile::ProfileOverrides;
+pub use fport::{FPortRoute, FPortRoutes, APP_PACKAGE_PORTS};
+pub use gateway::GatewayAllowList;
+pub use codec::{CodecFlatten, CodecVariables};
 pub use addr::LoRaAddr;
-pub use key::Key;
+pub use key::{Key, set_key_encryption_key, key_encryption_key, wrapped_key};
 
 pub use eui::Eui;
 pub use map::DecodeMap;
@@ -24,7 +40,9 @@ pub enum DbErr {
     #[error("{0}")]
     Len(String),
     #[error("Max is 80000000")]
-    Max
+    Max,
+    #[error("Key unwrap failed, check the key encryption key")]
+    Unwrap,
 }
 
 pub use entities::snap_device_data_name::Entity as SnapDeviceDataNameEntity;
@@ -73,10 +91,34 @@ pub use entities::snap_decode_script::Entity as DecodeScriptEntity;
 pub use entities::snap_decode_script::Model as DecodeScriptModel;
 pub use entities::snap_decode_script::ActiveModel as DecodeScriptActiveModel;
 pub use entities::snap_decode_script::Column as DecodeScriptColumn;
+pub use entities::snap_decode_script_version::Entity as DecodeScriptVersionEntity;
+pub use entities::snap_decode_script_version::Model as DecodeScriptVersionModel;
+pub use entities::snap_decode_script_version::ActiveModel as DecodeScriptVersionActiveModel;
+pub use entities::snap_decode_script_version::Column as DecodeScriptVersionColumn;
+pub use entities::snap_decode_test_vector::Entity as DecodeTestVectorEntity;
+pub use entities::snap_decode_test_vector::Model as DecodeTestVectorModel;
+pub use entities::snap_decode_test_vector::ActiveModel as DecodeTestVectorActiveModel;
+pub use entities::snap_decode_test_vector::Column as DecodeTestVectorColumn;
 pub use entities::snap_device_data::Entity as DeviceDataEntity;
 pub use entities::snap_device_data::Model as DeviceDataModel;
 pub use entities::snap_device_data::ActiveModel as DeviceDataActiveModel;
 pub use entities::snap_device_data::Column as DeviceDataColumn;
+pub use entities::snap_device_data_rollup::Entity as DeviceDataRollupEntity;
+pub use entities::snap_device_data_rollup::Model as DeviceDataRollupModel;
+pub use entities::snap_device_data_rollup::ActiveModel as DeviceDataRollupActiveModel;
+pub use entities::snap_device_data_rollup::Column as DeviceDataRollupColumn;
+pub use entities::snap_data_retention::Entity as DataRetentionEntity;
+pub use entities::snap_data_retention::Model as DataRetentionModel;
+pub use entities::snap_data_retention::ActiveModel as DataRetentionActiveModel;
+pub use entities::snap_data_retention::Column as DataRetentionColumn;
+pub use entities::snap_virtual_point::Entity as VirtualPointEntity;
+pub use entities::snap_virtual_point::Model as VirtualPointModel;
+pub use entities::snap_virtual_point::ActiveModel as VirtualPointActiveModel;
+pub use entities::snap_virtual_point::Column as VirtualPointColumn;
+pub use entities::snap_data_calibration::Entity as DataCalibrationEntity;
+pub use entities::snap_data_calibration::Model as DataCalibrationModel;
+pub use entities::snap_data_calibration::ActiveModel as DataCalibrationActiveModel;
+pub use entities::snap_data_calibration::Column as DataCalibrationColumn;
 pub use entities::snap_device_group::Entity as DeviceGroupEntity;
 pub use entities::snap_device_group::Model as DeviceGroupModel;
 pub use entities::snap_device_group::ActiveModel as DeviceGroupActiveModel;
@@ -109,6 +151,10 @@ pub use entities::snap_device_lora_node::Entity as DeviceLoraNodeEntity;
 pub use entities::snap_device_lora_node::Model as DeviceLoraNodeModel;
 pub use entities::snap_device_lora_node::ActiveModel as DeviceLoraNodeActiveModel;
 pub use entities::snap_device_lora_node::Column as DeviceLoraNodeColumn;
+pub use entities::snap_device_profile::Entity as DeviceProfileEntity;
+pub use entities::snap_device_profile::Model as DeviceProfileModel;
+pub use entities::snap_device_profile::ActiveModel as DeviceProfileActiveModel;
+pub use entities::snap_device_profile::Column as DeviceProfileColumn;
 pub use entities::snap_device_mqtt::Entity as DeviceMqttEntity;
 pub use entities::snap_device_mqtt::Model as DeviceMqttModel;
 pub use entities::snap_device_mqtt::ActiveModel as DeviceMqttActiveModel;
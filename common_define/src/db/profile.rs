@@ -0,0 +1,104 @@
+use sea_orm::{ConnectionTrait, EntityTrait};
+use crate::db::{DeviceLoraNodeModel, DeviceProfileEntity, DeviceProfileModel};
+use crate::lora::LoRaRegion;
+
+/// Per-node values that take precedence over the node's device profile.
+#[derive(
+    Debug,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct ProfileOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<LoRaRegion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_b: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_c: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adr: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx1_delay: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx1_dro: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx2_dr: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx2_freq: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dutycyle: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d_retry: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c_retry: Option<i16>,
+}
+
+impl std::convert::From<ProfileOverrides> for sea_orm::Value {
+    fn from(source: ProfileOverrides) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for ProfileOverrides {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for ProfileOverrides {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "ProfileOverrides".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+impl DeviceLoraNodeModel {
+    /// Replaces the profile managed settings with the profile values, keeping the node overrides.
+    /// FPort routes of the node take precedence over the profile routes for the same port.
+    pub fn apply_profile(mut self, profile: &DeviceProfileModel) -> Self {
+        let o = &self.overrides;
+        self.region = o.region.unwrap_or(profile.region);
+        self.class_b = o.class_b.unwrap_or(profile.class_b);
+        self.class_c = o.class_c.unwrap_or(profile.class_c);
+        self.adr = o.adr.unwrap_or(profile.adr);
+        self.rx1_delay = o.rx1_delay.unwrap_or(profile.rx1_delay);
+        self.rx1_dro = o.rx1_dro.unwrap_or(profile.rx1_dro);
+        self.rx2_dr = o.rx2_dr.unwrap_or(profile.rx2_dr);
+        self.rx2_freq = o.rx2_freq.unwrap_or(profile.rx2_freq);
+        self.dutycyle = o.dutycyle.unwrap_or(profile.dutycyle);
+        self.d_retry = o.d_retry.unwrap_or(profile.d_retry);
+        self.c_retry = o.c_retry.unwrap_or(profile.c_retry);
+        self.des_rx1_delay = self.rx1_delay;
+        self.des_rx1_dro = self.rx1_dro;
+        self.des_rx2_dr = self.rx2_dr;
+        self.des_rx2_freq = self.rx2_freq;
+        self.fport_routes = self.fport_routes.merge(&profile.fport_routes);
+        self
+    }
+
+    pub async fn load_profile<C: ConnectionTrait>(&self, conn: &C) -> Result<Option<DeviceProfileModel>, sea_orm::DbErr> {
+        match self.profile {
+            Some(profile) => DeviceProfileEntity::find_by_id(profile).one(conn).await,
+            None => Ok(None)
+        }
+    }
+}
@@ -0,0 +1,21 @@
+/// Level a data retention policy is set at.
+/// The policy of a device overrides the one of its owner, which overrides the system policy.
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    strum::AsRefStr,
+    strum::EnumString,
+    Eq,
+    PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum RetentionScope {
+    System,
+    User,
+    Device,
+}
+
+crate::sea_string_type!(RetentionScope);
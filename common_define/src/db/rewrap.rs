@@ -0,0 +1,199 @@
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect};
+use sea_orm::sea_query::Expr;
+use crate::db::{key_encryption_key, DbErr, DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraNodeColumn, DeviceLoraNodeEntity, Eui, Key, SnapDeviceColumn, SnapDeviceEntity};
+use crate::Id;
+
+/// Keys of every device after [`rewrap_keys`], the redis copies are written from them once the transaction commits.
+#[derive(Default)]
+pub struct RewrappedKeys {
+    /// `app_key`, `nwk_skey`, `app_skey` and `app_kek` of each LoRaWAN node.
+    pub nodes: Vec<(Eui, [Key; 3], Option<Key>)>,
+    pub snaps: Vec<(Eui, Key)>,
+    pub gates: Vec<(Eui, Key)>,
+    /// Rows written again.
+    pub changed: usize,
+}
+
+/// Key-encryption key stored values were wrapped with before.
+#[derive(Copy, Clone)]
+enum OldKek {
+    Key(Option<[u8; 16]>),
+    /// Only plain values are wrapped, wrapped ones are left as they are.
+    PlainOnly,
+}
+
+/// Key of a stored value and whether it has to be written again with the current key-encryption key,
+/// `None` when it is left as it is. Values already in the current form are kept.
+fn restore(value: &str, old_kek: OldKek) -> Result<Option<(Key, bool)>, DbErr> {
+    let kek = key_encryption_key();
+    if Key::is_wrapped(value) == kek.is_some() {
+        if let Ok(key) = Key::unwrap_stored(value, kek) {
+            return Ok(Some((key, false)));
+        }
+    }
+    match old_kek {
+        OldKek::Key(old_kek) => Ok(Some((Key::unwrap_stored(value, old_kek)?, true))),
+        OldKek::PlainOnly if Key::is_wrapped(value) => Ok(None),
+        OldKek::PlainOnly => Ok(Some((value.parse()?, true))),
+    }
+}
+
+fn db_err(e: DbErr) -> sea_orm::DbErr {
+    sea_orm::DbErr::Custom(e.to_string())
+}
+
+/// Writes every stored LoRaWAN node, snap device and gateway key with the current key-encryption key.
+///
+/// Run it in a transaction so no key is left wrapped with another key-encryption key when a row fails.
+/// Rows already wrapped with the current key are skipped, so running it again is harmless.
+pub async fn rewrap_keys<C: ConnectionTrait>(old_kek: Option<[u8; 16]>, conn: &C) -> Result<RewrappedKeys, sea_orm::DbErr> {
+    rewrap(OldKek::Key(old_kek), conn).await
+}
+
+/// Wraps the keys still stored as plain hex, wrapped keys are left as they are.
+/// Only [`RewrappedKeys::changed`] is filled in.
+pub async fn wrap_plain_keys<C: ConnectionTrait>(conn: &C) -> Result<usize, sea_orm::DbErr> {
+    rewrap(OldKek::PlainOnly, conn).await.map(|keys| keys.changed)
+}
+
+/// Rows with a key stored as plain hex, these are wrapped once a key-encryption key is configured.
+pub async fn plain_key_rows<C: ConnectionTrait>(conn: &C) -> Result<usize, sea_orm::DbErr> {
+    let nodes: Vec<String> = DeviceLoraNodeEntity::find()
+        .select_only()
+        .column(DeviceLoraNodeColumn::AppKey)
+        .into_tuple()
+        .all(conn)
+        .await?;
+    let snaps: Vec<String> = SnapDeviceEntity::find()
+        .select_only()
+        .column(SnapDeviceColumn::Key)
+        .into_tuple()
+        .all(conn)
+        .await?;
+    let gates: Vec<String> = DeviceLoraGateEntity::find()
+        .select_only()
+        .column(DeviceLoraGateColumn::HmacKey)
+        .filter(DeviceLoraGateColumn::HmacKey.is_not_null())
+        .into_tuple()
+        .all(conn)
+        .await?;
+    Ok(nodes.iter().chain(&snaps).chain(&gates).filter(|key| !Key::is_wrapped(key)).count())
+}
+
+async fn rewrap<C: ConnectionTrait>(old_kek: OldKek, conn: &C) -> Result<RewrappedKeys, sea_orm::DbErr> {
+    let mut keys = RewrappedKeys::default();
+    let nodes: Vec<(Id, Eui, String, String, String, Option<String>)> = DeviceLoraNodeEntity::find()
+        .select_only()
+        .columns([
+            DeviceLoraNodeColumn::Id,
+            DeviceLoraNodeColumn::DevEui,
+            DeviceLoraNodeColumn::AppKey,
+            DeviceLoraNodeColumn::NwkSkey,
+            DeviceLoraNodeColumn::AppSkey,
+            DeviceLoraNodeColumn::AppKek,
+        ])
+        .into_tuple()
+        .all(conn)
+        .await?;
+    for (id, eui, app_key, nwk_skey, app_skey, app_kek) in nodes {
+        let (
+            Some((app_key, app_key_changed)),
+            Some((nwk_skey, nwk_skey_changed)),
+            Some((app_skey, app_skey_changed)),
+        ) = (
+            restore(&app_key, old_kek).map_err(db_err)?,
+            restore(&nwk_skey, old_kek).map_err(db_err)?,
+            restore(&app_skey, old_kek).map_err(db_err)?,
+        ) else {
+            continue
+        };
+        let (app_kek, app_kek_changed) = match app_kek {
+            Some(app_kek) => match restore(&app_kek, old_kek).map_err(db_err)? {
+                Some((app_kek, changed)) => (Some(app_kek), changed),
+                None => continue,
+            },
+            None => (None, false),
+        };
+        if app_key_changed || nwk_skey_changed || app_skey_changed || app_kek_changed {
+            DeviceLoraNodeEntity::update_many()
+                .col_expr(DeviceLoraNodeColumn::AppKey, Expr::value(app_key))
+                .col_expr(DeviceLoraNodeColumn::NwkSkey, Expr::value(nwk_skey))
+                .col_expr(DeviceLoraNodeColumn::AppSkey, Expr::value(app_skey))
+                .col_expr(DeviceLoraNodeColumn::AppKek, Expr::value(app_kek))
+                .filter(DeviceLoraNodeColumn::Id.eq(id))
+                .exec(conn)
+                .await?;
+            keys.changed += 1;
+        }
+        keys.nodes.push((eui, [app_key, nwk_skey, app_skey], app_kek));
+    }
+
+    let snaps: Vec<(Id, Eui, String)> = SnapDeviceEntity::find()
+        .select_only()
+        .columns([
+            SnapDeviceColumn::Id,
+            SnapDeviceColumn::Eui,
+            SnapDeviceColumn::Key,
+        ])
+        .into_tuple()
+        .all(conn)
+        .await?;
+    for (id, eui, key) in snaps {
+        let Some((key, changed)) = restore(&key, old_kek).map_err(db_err)? else {
+            continue
+        };
+        if changed {
+            SnapDeviceEntity::update_many()
+                .col_expr(SnapDeviceColumn::Key, Expr::value(key))
+                .filter(SnapDeviceColumn::Id.eq(id))
+                .exec(conn)
+                .await?;
+            keys.changed += 1;
+        }
+        keys.snaps.push((eui, key));
+    }
+
+    let gates: Vec<(Id, Eui, String)> = DeviceLoraGateEntity::find()
+        .select_only()
+        .columns([
+            DeviceLoraGateColumn::Id,
+            DeviceLoraGateColumn::Eui,
+            DeviceLoraGateColumn::HmacKey,
+        ])
+        .filter(DeviceLoraGateColumn::HmacKey.is_not_null())
+        .into_tuple()
+        .all(conn)
+        .await?;
+    for (id, eui, hmac_key) in gates {
+        let Some((hmac_key, changed)) = restore(&hmac_key, old_kek).map_err(db_err)? else {
+            continue
+        };
+        if changed {
+            DeviceLoraGateEntity::update_many()
+                .col_expr(DeviceLoraGateColumn::HmacKey, Expr::value(hmac_key))
+                .filter(DeviceLoraGateColumn::Id.eq(id))
+                .exec(conn)
+                .await?;
+            keys.changed += 1;
+        }
+        keys.gates.push((eui, hmac_key));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Key;
+    use super::{restore, OldKek};
+
+    #[test]
+    fn test_restore() {
+        let key: Key = "00112233445566778899AABBCCDDEEFF".parse().unwrap();
+        let old = [1; 16];
+        // no key-encryption key is set in tests
+        assert_eq!(restore(&key.to_string(), OldKek::Key(None)).unwrap(), Some((key, false)));
+        assert_eq!(restore(&key.wrap(old), OldKek::Key(Some(old))).unwrap(), Some((key, true)));
+        assert!(restore(&key.wrap(old), OldKek::Key(None)).is_err());
+        assert_eq!(restore(&key.wrap(old), OldKek::PlainOnly).unwrap(), None);
+    }
+}
@@ -0,0 +1,125 @@
+use sea_orm::{DbBackend, Statement};
+use crate::db::DbDecodeData;
+use crate::Id;
+use crate::time::{whole_hour_offsets, Timestamp, Tz};
+
+/// Upserts the values of one uplink at `$2` of device `$1` into every rollup period, `$3` is the decoded data.
+const UPDATE_SQL: &str = r"INSERT INTO snap_device_data_rollup AS r (device_id, data_id, period, bucket, count, num_count, min, max, sum, last, last_time)
+SELECT $1, (e.e->>'i')::bigint, p.period, to_timestamp(floor(extract(epoch FROM $2::timestamptz) / p.period) * p.period),
+       count(*), count(x.n), min(x.n), max(x.n), sum(x.n), (array_agg(e.e->'v' ORDER BY e.o DESC))[1], $2
+FROM json_array_elements(CASE WHEN json_typeof($3::json) = 'array' THEN $3::json ELSE '[]'::json END) WITH ORDINALITY AS e(e, o)
+CROSS JOIN LATERAL (SELECT CASE WHEN json_typeof(e.e->'v') = 'number' THEN (e.e->>'v')::float8 END AS n) AS x
+CROSS JOIN (VALUES {periods}) AS p(period)
+GROUP BY 2, 3
+ON CONFLICT (device_id, data_id, period, bucket) DO UPDATE SET
+    count = r.count + EXCLUDED.count,
+    num_count = r.num_count + EXCLUDED.num_count,
+    min = LEAST(r.min, EXCLUDED.min),
+    max = GREATEST(r.max, EXCLUDED.max),
+    sum = CASE WHEN EXCLUDED.sum IS NULL THEN r.sum ELSE coalesce(r.sum, 0) + EXCLUDED.sum END,
+    last = CASE WHEN EXCLUDED.last_time >= r.last_time THEN EXCLUDED.last ELSE r.last END,
+    last_time = GREATEST(r.last_time, EXCLUDED.last_time)";
+
+/// UTC days between `$1` and `$2` with stored uplinks of device `$3`, or of every device when it is `NULL`.
+///
+/// The first day left after raw data was pruned is skipped when older rollups exist, it may have lost
+/// part of its uplinks. Days with no uplinks left are never rebuilt so the rollups retention keeps survive.
+const DAYS_SQL: &str = r"WITH days AS (
+    SELECT k.device_id, k.day FROM (
+        SELECT DISTINCT d.device_id, to_timestamp(floor(extract(epoch FROM d.create_time) / {day}) * {day}) AS day
+        FROM snap_device_data d
+        WHERE d.create_time >= $1 AND d.create_time < $2 AND ($3::bigint IS NULL OR d.device_id = $3)
+    ) k
+    WHERE EXISTS (SELECT 1 FROM snap_device_data o WHERE o.device_id = k.device_id AND o.create_time < k.day)
+       OR NOT EXISTS (SELECT 1 FROM snap_device_data_rollup o WHERE o.device_id = k.device_id AND o.bucket < k.day)
+)";
+
+const DELETE_SQL: &str = r"{days}
+DELETE FROM snap_device_data_rollup r USING days
+WHERE r.device_id = days.device_id AND r.bucket >= days.day AND r.bucket < days.day + make_interval(secs => {day})";
+
+/// Aggregates the stored uplinks of the days of [`DAYS_SQL`].
+const REBUILD_SQL: &str = r"{days}
+INSERT INTO snap_device_data_rollup AS r (device_id, data_id, period, bucket, count, num_count, min, max, sum, last, last_time)
+SELECT d.device_id, (e.e->>'i')::bigint, p.period, to_timestamp(floor(extract(epoch FROM d.create_time) / p.period) * p.period),
+       count(*), count(x.n), min(x.n), max(x.n), sum(x.n), (array_agg(e.e->'v' ORDER BY d.create_time DESC, e.o DESC))[1], max(d.create_time)
+FROM snap_device_data d
+JOIN days ON days.device_id = d.device_id AND d.create_time >= days.day AND d.create_time < days.day + make_interval(secs => {day})
+CROSS JOIN LATERAL json_array_elements(CASE WHEN json_typeof(d.data) = 'array' THEN d.data ELSE '[]'::json END) WITH ORDINALITY AS e(e, o)
+CROSS JOIN LATERAL (SELECT CASE WHEN json_typeof(e.e->'v') = 'number' THEN (e.e->>'v')::float8 END AS n) AS x
+CROSS JOIN (VALUES {periods}) AS p(period)
+GROUP BY 1, 2, 3, 4
+ON CONFLICT (device_id, data_id, period, bucket) DO UPDATE SET
+    count = EXCLUDED.count,
+    num_count = EXCLUDED.num_count,
+    min = EXCLUDED.min,
+    max = EXCLUDED.max,
+    sum = EXCLUDED.sum,
+    last = EXCLUDED.last,
+    last_time = EXCLUDED.last_time";
+
+/// Hourly and daily aggregates of the values of every data ID of a device, kept in `snap_device_data_rollup`.
+///
+/// Buckets start at multiples of their period since the epoch, so a day is a UTC day.
+/// Days, weeks and months of other time zones are summed up from the hourly rollup.
+pub struct Rollup;
+
+impl Rollup {
+    pub const HOUR: i64 = 3600;
+    pub const DAY: i64 = 24 * Self::HOUR;
+    /// Periods of the rollups, finest first, each one divides the next.
+    pub const PERIODS: [i64; 2] = [Self::HOUR, Self::DAY];
+
+    fn sql(sql: &str) -> String {
+        let periods = Self::PERIODS.iter()
+            .map(|it| format!("({})", it))
+            .collect::<Vec<_>>()
+            .join(", ");
+        sql.replace("{days}", DAYS_SQL)
+            .replace("{periods}", &periods)
+            .replace("{day}", &Self::DAY.to_string())
+    }
+
+    /// The coarsest period calendar buckets in `tz` can be summed up from between `start` and `end`,
+    /// `None` when an hour since the epoch spans two local days.
+    pub fn calendar_period(tz: Tz, start: Timestamp, end: Timestamp) -> Option<i64> {
+        if tz == Tz::UTC {
+            Some(Self::DAY)
+        } else {
+            whole_hour_offsets(tz, start, end).then_some(Self::HOUR)
+        }
+    }
+
+    /// Adds an uplink stored at `time` to the rollups of the device.
+    pub fn update(device: Id, time: Timestamp, data: &DbDecodeData) -> Statement {
+        Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            Self::sql(UPDATE_SQL),
+            [device.into(), time.into(), data.clone().into()],
+        )
+    }
+
+    /// Recomputes the rollups from the stored uplinks, of one device or of every device.
+    ///
+    /// The range is widened to whole days so no bucket is left with part of its uplinks,
+    /// days whose uplinks were pruned keep their rollups. The statements must run in order in one transaction.
+    pub fn rebuild(start: Timestamp, end: Timestamp, device: Option<Id>) -> [Statement; 2] {
+        let (start, end) = Self::align(start, end, Self::DAY);
+        let values = || [start.into(), end.into(), device.into()];
+        [
+            Statement::from_sql_and_values(DbBackend::Postgres, Self::sql(DELETE_SQL), values()),
+            Statement::from_sql_and_values(DbBackend::Postgres, Self::sql(REBUILD_SQL), values()),
+        ]
+    }
+
+    /// Widens a range to the buckets of `period` it touches, `end` included.
+    pub fn align(start: Timestamp, end: Timestamp, period: i64) -> (Timestamp, Timestamp) {
+        let period = period as u64 * 1000;
+        let start = start.timestamp_millis() / period * period;
+        let end = (end.timestamp_millis() / period + 1) * period;
+        (
+            Timestamp::from_timestamp_millis(start).unwrap_or(Timestamp::now()),
+            Timestamp::from_timestamp_millis(end).unwrap_or(Timestamp::now()),
+        )
+    }
+}
@@ -0,0 +1,20 @@
+/// What a virtual data point is defined for.
+/// A point of a device replaces the point of its product with the same data ID.
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    strum::AsRefStr,
+    strum::EnumString,
+    Eq,
+    PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum VirtualPointScope {
+    Device,
+    Product,
+}
+
+crate::sea_string_type!(VirtualPointScope);
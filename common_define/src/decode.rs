@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use base64::Engine;
 use derive_new::new;
 use crate::time::Timestamp;
@@ -5,6 +6,12 @@ use crate::time::Timestamp;
 #[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, strum::AsRefStr, strum::EnumString,  redis_macros::FromRedisValue, redis_macros::ToRedisArgs)]
 pub enum DecodeLang {
     JS,
+    /// TTN / ChirpStack codec exporting `decodeUplink`, `encodeDownlink` and `decodeDownlink`.
+    TTN,
+    /// Declarative decoder, see [`crate::binary::BinaryDecoder`].
+    Binary,
+    /// Base64 of a WebAssembly module exporting `alloc`, `decode` and `memory`.
+    Wasm,
 }
 
 
@@ -12,7 +19,18 @@ pub enum DecodeLang {
 pub enum DecodeDataType {
     I32,
     F64,
-    Bool
+    Bool,
+    String,
+    Array,
+    Object,
+    GeoPoint,
+}
+
+impl DecodeDataType {
+    /// Values of these types are kept whole instead of being flattened into data points.
+    pub fn is_nested(&self) -> bool {
+        matches!(self, Self::Array | Self::Object | Self::GeoPoint)
+    }
 }
 #[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, strum::AsRefStr, strum::EnumString, Eq, PartialEq)]
 pub enum CustomDecodeDataType {
@@ -40,12 +58,68 @@ pub struct DecodeData {
     pub v: Value
 }
 
+/// A position reported by a device, `alt` in meters and `accuracy` as the radius in meters.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+}
+
+impl GeoPoint {
+    /// Only reads objects, `[lat, lon]` arrays stay arrays.
+    fn deserialize_map<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MapVisitor;
+        impl<'de> serde::de::Visitor<'de> for MapVisitor {
+            type Value = GeoPoint;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an object with lat and lon")
+            }
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                serde::Deserialize::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+            }
+        }
+        deserializer.deserialize_map(MapVisitor)
+    }
+}
+
+/// A decoded value, stored untagged so rows written before the string and
+/// nested variants were added read back unchanged.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Value {
     Int(i64),
     Float(f64),
     Bool(bool),
+    String(String),
+    /// Tried before `Object`, an object with only these fields is a position.
+    #[serde(deserialize_with = "GeoPoint::deserialize_map")]
+    GeoPoint(GeoPoint),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Converts a JSON value, `null` has no value.
+    pub fn from_json(value: serde_json::Value) -> Option<Self> {
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn data_type(&self) -> DecodeDataType {
+        match self {
+            Value::Int(_) => DecodeDataType::I32,
+            Value::Float(_) => DecodeDataType::F64,
+            Value::Bool(_) => DecodeDataType::Bool,
+            Value::String(_) => DecodeDataType::String,
+            Value::GeoPoint(_) => DecodeDataType::GeoPoint,
+            Value::Array(_) => DecodeDataType::Array,
+            Value::Object(_) => DecodeDataType::Object,
+        }
+    }
 }
 
 impl PartialEq for Value {
@@ -54,6 +128,10 @@ impl PartialEq for Value {
             (Value::Int(a), Value::Int(b)) => a == b,
             (Value::Float(a), Value::Float(b)) => a == b,
             (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::GeoPoint(a), Value::GeoPoint(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => a == b,
             _ => false,
         }
     }
@@ -94,4 +172,42 @@ value_from!(bool, bool, Value::Bool);
 value_from!(f32, f64, Value::Float);
 value_from!(f64, f64, Value::Float);
 
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
 
+impl From<GeoPoint> for Value {
+    fn from(value: GeoPoint) -> Self {
+        Value::GeoPoint(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value() {
+        let old: Vec<DecodeData> = serde_json::from_str(r#"[{"i":1,"v":12},{"i":2,"v":1.5},{"i":3,"v":true}]"#).unwrap();
+        assert_eq!(old[0].v, Value::Int(12));
+        assert_eq!(old[1].v, Value::Float(1.5));
+        assert_eq!(old[2].v, Value::Bool(true));
+
+        let v: Value = serde_json::from_str(r#"{"lat":31.2,"lon":121.5,"accuracy":5}"#).unwrap();
+        assert_eq!(v, Value::GeoPoint(GeoPoint { lat: 31.2, lon: 121.5, alt: None, accuracy: Some(5.0) }));
+        let v: Value = serde_json::from_str(r#"{"lat":31.2,"lon":121.5,"name":"a"}"#).unwrap();
+        assert_eq!(v.data_type(), DecodeDataType::Object);
+        let v: Value = serde_json::from_str(r#"["1.0.2",[1,2]]"#).unwrap();
+        assert_eq!(v, Value::Array(vec!["1.0.2".into(), Value::Array(vec![1u8.into(), 2u8.into()])]));
+        assert_eq!(serde_json::to_string(&v).unwrap(), r#"["1.0.2",[1,2]]"#);
+        assert!(Value::from_json(serde_json::Value::Null).is_none());
+    }
+}
@@ -20,7 +20,8 @@ pub struct GatewaySource {
 pub enum GatewayEventType {
     Status(GatewayStatus),
     Join(JoinPayload),
-    Data(DataPayload)
+    Data(DataPayload),
+    Conflict(GatewayConflict),
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -49,6 +50,13 @@ pub struct GatewayStatus {
 
 
 
+/// The gateway EUI was seen from `source` while `previous` was still active.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GatewayConflict {
+    pub previous: String,
+    pub previous_time: Timestamp,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JoinPayload {
     pub app_eui: Eui,
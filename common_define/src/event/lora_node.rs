@@ -12,6 +12,9 @@ pub struct JoinRequest {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JoinAccept {
     pub dev_addr: LoRaAddr,
+    /// AppSKey wrapped with the application key-encryption key, only for application side encryption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_s_key: Option<String>,
     pub time: i64
 }
 
@@ -23,6 +26,9 @@ pub struct UplinkData {
     pub f_cnt: i32,
     pub payload: Option<String>,
     pub decoded_payload: Option<String>,
+    /// FRMPayload still encrypted with the AppSKey, only for application side encryption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frm_payload: Option<String>,
     pub gateway: GatewayRxStatus,
     pub time: i64
 }
@@ -3,13 +3,17 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 use crate::event::lora_gateway::GatewayEvent;
 use crate::event::lora_node::{DownLinkData, JoinAccept, JoinRequest, UplinkData};
 use crate::Id;
+use crate::time::Timestamp;
 
 
 pub mod lora_node;
 pub mod lora_gateway;
 mod log;
+mod redecode;
 pub use log::PlatformLog;
-use crate::db::Eui;
+pub use redecode::{ReDecodeDiff, ReDecodeJob, ReDecodeProgress, ReDecodeState};
+use crate::db::{CodecVariables, Eui, VirtualPointScope};
+use crate::decode::{DecodeData, DecodeLang};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DeviceEvent {
@@ -25,7 +29,8 @@ pub enum DeviceEventType {
     UplinkData(UplinkData),
     DownLinkData(DownLinkData),
     Gateway(GatewayEvent),
-    SnapDevice(SnapEvent)
+    SnapDevice(SnapEvent),
+    Codec(CodecEvent)
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -34,6 +39,15 @@ pub struct SnapEvent {
     pub data: Vec<u8>
 }
 
+/// Warnings and errors returned by a TTN codec.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CodecEvent {
+    pub script: Id,
+    pub f_port: u8,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>
+}
+
 #[derive(Serialize_repr, Deserialize_repr, Clone)]
 #[repr(u8)]
 pub enum DeviceType {
@@ -47,6 +61,148 @@ pub struct DownEvent {
     pub eui: Eui,
     pub port: u8,
     pub data: String,
+    /// Set when `data` was already encrypted by the application with this downlink FCnt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f_cnt: Option<u32>,
+}
+
+/// A decode script was modified or deleted, compiled copies older than `modify_time` are stale.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptEvent {
+    pub script: Id,
+    /// Not set when the script was deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modify_time: Option<Timestamp>,
+}
+
+impl ScriptEvent {
+    pub const TOPIC: &'static str = "Decode-Script";
+}
+
+/// Virtual data points of a device or product were changed, devices_manager loads them again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VirtualPointEvent {
+    pub scope: VirtualPointScope,
+    pub target: Id,
+}
+
+impl VirtualPointEvent {
+    pub const TOPIC: &'static str = "Virtual-Point";
+}
+
+/// Calibrations of a device were changed, devices_manager loads them again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CalibrationEvent {
+    pub device: Id,
+}
+
+impl CalibrationEvent {
+    pub const TOPIC: &'static str = "Calibration";
+}
+
+/// Runs a script in devices_manager, the [`ScriptReply`] is pushed to the `reply` list.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptCall {
+    pub reply: String,
+    pub job: ScriptJob,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "job")]
+pub enum ScriptJob {
+    /// Calls `encodeDownlink` of the script with `{data, variables}`.
+    Encode {
+        script: Id,
+        data: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        variables: Option<CodecVariables>,
+    },
+    /// Decodes one payload with a script that is not saved.
+    Test {
+        lang: DecodeLang,
+        script: String,
+        bytes: Vec<u8>,
+        #[serde(default)]
+        f_port: u8,
+    },
+    /// Decodes each payload with the script, answered with [`ScriptReply::Batch`] in payload order.
+    Batch {
+        source: ScriptSource,
+        payloads: Vec<ScriptPayload>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        variables: Option<CodecVariables>,
+    },
+}
+
+/// Script a [`ScriptJob::Batch`] decodes with.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "source")]
+pub enum ScriptSource {
+    /// A saved script, the latest version unless `version` is set.
+    Saved {
+        script: Id,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<i32>,
+    },
+    /// A script that is not saved.
+    Draft {
+        lang: DecodeLang,
+        script: String,
+    },
+    /// The built-in format of devices without a script or codec.
+    Builtin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScriptPayload {
+    pub bytes: Vec<u8>,
+    #[serde(default)]
+    pub f_port: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "result")]
+pub enum ScriptReply {
+    Decoded {
+        data: Vec<DecodeData>,
+        warnings: Vec<String>,
+        errors: Vec<String>,
+        /// Bytes the JS runtime allocated for the call.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        memory: Option<usize>,
+    },
+    Encoded {
+        bytes: Vec<u8>,
+        f_port: Option<u8>,
+        warnings: Vec<String>,
+        /// The bytes read back by `decodeDownlink` of the codec.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        decoded: Option<serde_json::Value>,
+    },
+    /// The script rejected the input.
+    Invalid {
+        errors: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
+    /// The script ran out of time or fuel.
+    Timeout {
+        message: String,
+    },
+    Batch {
+        replies: Vec<ScriptReply>,
+    },
+}
+
+impl ScriptCall {
+    pub const TOPIC: &'static str = "Script-Call";
+    /// Seconds a caller waits for the reply.
+    pub const TIMEOUT: u64 = 5;
+    /// Seconds a caller waits for the reply of a [`ScriptJob::Batch`].
+    pub const BATCH_TIMEOUT: u64 = 30;
+    /// Most payloads of a [`ScriptJob::Batch`].
+    pub const MAX_BATCH: usize = 50;
 }
 
 impl DeviceEvent {
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use crate::decode::DecodeData;
+use crate::Id;
+use crate::time::Timestamp;
+
+/// Re-runs a decoder over the stored uplinks of devices, published by snap_api to devices_manager.
+/// The virtual data points of each device are computed again as well.
+/// The job reports into [`ReDecodeProgress`] and stops when its cancel key is set.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReDecodeJob {
+    pub id: String,
+    pub owner: Id,
+    pub devices: Vec<Id>,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// Decoder to run, the decoder of each device when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Id>,
+    /// Version of `script`, the latest version when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+    /// Only collects the diff, rows are not written.
+    pub dry_run: bool,
+    /// Keeps the decoded data and only computes the virtual data points again.
+    #[serde(default)]
+    pub virtual_only: bool,
+}
+
+impl ReDecodeJob {
+    pub const TOPIC: &'static str = "ReDecode-Job";
+    /// Seconds the progress and diff of a job are kept.
+    pub const TTL: u64 = 7 * 24 * 3600;
+    /// Most diff entries kept for a job.
+    pub const MAX_DIFF: usize = 1000;
+
+    pub fn progress_key(id: &str) -> String {
+        format!("redecode:{}", id)
+    }
+    pub fn diff_key(id: &str) -> String {
+        format!("redecode:{}:diff", id)
+    }
+    pub fn cancel_key(id: &str) -> String {
+        format!("redecode:{}:cancel", id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReDecodeState {
+    Pending,
+    Running,
+    Done,
+    Canceled,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, redis_macros::FromRedisValue, redis_macros::ToRedisArgs)]
+pub struct ReDecodeProgress {
+    pub owner: Id,
+    pub state: ReDecodeState,
+    pub dry_run: bool,
+    /// Rows in the time range of all devices.
+    pub total: u64,
+    pub done: u64,
+    /// Rows whose decoded data differs.
+    pub changed: u64,
+    /// Rows the decoder failed on, they are left unchanged.
+    pub failed: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ReDecodeProgress {
+    pub fn new(job: &ReDecodeJob) -> Self {
+        Self {
+            owner: job.owner,
+            state: ReDecodeState::Pending,
+            dry_run: job.dry_run,
+            total: 0,
+            done: 0,
+            changed: 0,
+            failed: 0,
+            message: None,
+        }
+    }
+}
+
+/// A row whose decoded data differs.
+#[derive(Serialize, Deserialize, Clone, Debug, redis_macros::FromRedisValue, redis_macros::ToRedisArgs)]
+pub struct ReDecodeDiff {
+    pub row: Id,
+    pub device: Id,
+    pub time: Timestamp,
+    pub old: Vec<DecodeData>,
+    pub new: Vec<DecodeData>,
+}
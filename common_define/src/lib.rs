@@ -3,12 +3,17 @@ pub mod event;
 mod client_id;
 pub mod db;
 pub mod decode;
+pub mod binary;
+pub mod codec;
 mod id;
 mod key;
 pub mod lora;
 pub mod lorawan_bridge;
 pub mod product;
+pub mod telemetry;
 pub mod time;
+pub mod unit;
+pub mod virtual_point;
 mod user;
 
 pub use key::last_device_data_key;
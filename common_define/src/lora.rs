@@ -68,6 +68,53 @@ pub enum LoRaJoinType {
 
 sea_string_type!(LoRaJoinType);
 
+/// How uplink frame counters are checked.
+/// `Relaxed` accepts a counter reset (ABP devices after a reboot), `Strict` only accepts counters
+/// greater than the last one and drops everything else as a replay.
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    strum::AsRefStr,
+    strum::EnumString,
+    Eq
+    , PartialEq)]
+pub enum FCntPolicy {
+    #[default]
+    Relaxed,
+    Strict,
+}
+
+sea_string_type!(FCntPolicy);
+
+/// Who holds the AppSKey of a node.
+/// With `Application` the network server only checks the MIC and forwards the encrypted FRMPayload.
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    redis_macros::FromRedisValue,
+    redis_macros::ToRedisArgs,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    strum::AsRefStr,
+    strum::EnumString,
+    Eq
+    , PartialEq)]
+pub enum PayloadEncryption {
+    #[default]
+    Server,
+    Application,
+}
+
+sea_string_type!(PayloadEncryption);
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, redis_macros::FromRedisValue, redis_macros::ToRedisArgs)]
 #[serde(transparent)]
 pub struct LoRaDevAddr(u32);
@@ -94,7 +94,43 @@ impl Debug for GatewayUpData {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GatewaySource {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub ip: Option<std::net::SocketAddr>
+    pub ip: Option<std::net::SocketAddr>,
+    #[serde(skip)]
+    pub signature: Option<GatewaySignature>,
+}
+
+/// Signing extension of the forwarders we control, the UDP packet ends with `HMAC`,
+/// a big-endian 64-bit counter and the HMAC-SHA256 of everything before it.
+/// The counter goes up with every packet of the gateway, its unix time in milliseconds works,
+/// so a packet seen before is rejected.
+#[derive(Clone)]
+pub struct GatewaySignature {
+    /// The packet and the trailer up to the MAC.
+    pub signed: Vec<u8>,
+    pub counter: u64,
+    pub mac: [u8; 32],
+}
+
+impl GatewaySignature {
+    pub const MAGIC: &'static [u8] = b"HMAC";
+    pub const LEN: usize = 44;
+
+    /// Splits the signature trailer off a packet of at least `min` bytes.
+    pub fn split(s: &[u8], min: usize) -> (&[u8], Option<Self>) {
+        if s.len() < min + Self::LEN {
+            return (s, None);
+        }
+        let (packet, trailer) = s.split_at(s.len() - Self::LEN);
+        if &trailer[..4] != Self::MAGIC {
+            return (s, None);
+        }
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&trailer[4..12]);
+        let mut mac = [0; 32];
+        mac.copy_from_slice(&trailer[12..]);
+        let signed = s[..s.len() - mac.len()].to_vec();
+        (packet, Some(Self { signed, counter: u64::from_be_bytes(counter), mac }))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use crate::db::DbDecodeData;
+use crate::Id;
+use crate::telemetry::{TelemetryResult, TelemetryRow, TelemetryStore};
+use crate::time::Timestamp;
+
+/// Locked by every process writing to the directory, it is never renamed or removed.
+const LOCK_FILE: &str = ".lock";
+
+/// One JSON Lines file per device under a directory, for tests and tools without a database.
+/// Every read scans the whole file of the device, rollups are not kept.
+/// Appends and rewrites hold an OS lock on [`LOCK_FILE`], so they do not interleave
+/// even when devices_manager and snap_api share the directory.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Waits for the lock of the directory, it is released when the file is dropped.
+    async fn lock(&self) -> TelemetryResult<std::fs::File> {
+        let path = self.dir.join(LOCK_FILE);
+        let file = tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)?;
+            file.lock()?;
+            Ok::<_, std::io::Error>(file)
+        }).await.map_err(std::io::Error::other)??;
+        Ok(file)
+    }
+
+    async fn append(&self, device: Id, text: &str) -> TelemetryResult {
+        let _lock = self.lock().await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(device))
+            .await?;
+        file.write_all(text.as_bytes()).await?;
+        Ok(())
+    }
+
+    fn path(&self, device: Id) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", device))
+    }
+
+    async fn read(&self, device: Id) -> TelemetryResult<Vec<TelemetryRow>> {
+        let text = match tokio::fs::read_to_string(self.path(device)).await {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut rows = vec![];
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            rows.push(serde_json::from_str(line)?);
+        }
+        Ok(rows)
+    }
+}
+
+#[async_trait::async_trait]
+impl TelemetryStore for FileStore {
+
+    async fn init(&self) -> TelemetryResult {
+        Ok(tokio::fs::create_dir_all(&self.dir).await?)
+    }
+
+    async fn write(&self, device: Id, time: Timestamp, data: &DbDecodeData, bytes: &str) -> TelemetryResult {
+        let row = TelemetryRow { time, data: data.0.clone(), bytes: bytes.to_string() };
+        let mut line = serde_json::to_string(&row)?;
+        line.push('\n');
+        self.append(device, &line).await
+    }
+
+    /// Appends the uplinks with a single write.
+    async fn write_batch(&self, device: Id, rows: &[TelemetryRow]) -> TelemetryResult {
+        let mut text = String::new();
+        for row in rows {
+            text.push_str(&serde_json::to_string(row)?);
+            text.push('\n');
+        }
+        self.append(device, &text).await
+    }
+
+    async fn range(&self, device: Id, start: Timestamp, end: Timestamp) -> TelemetryResult<Vec<TelemetryRow>> {
+        let mut rows: Vec<_> = self.read(device).await?
+            .into_iter()
+            .filter(|row| row.time >= start && row.time < end)
+            .collect();
+        rows.sort_by_key(|row| row.time);
+        Ok(rows)
+    }
+
+    async fn last(&self, device: Id) -> TelemetryResult<Option<TelemetryRow>> {
+        Ok(self.read(device).await?.into_iter().max_by_key(|row| row.time))
+    }
+
+    /// Rewrites the file without the old uplinks, `limit` is not applied.
+    async fn prune(&self, device: Id, before: Timestamp, _limit: u64) -> TelemetryResult<u64> {
+        let _lock = self.lock().await?;
+        let rows = self.read(device).await?;
+        let len = rows.len();
+        let mut text = String::new();
+        for row in rows.into_iter().filter(|row| row.time >= before) {
+            text.push_str(&serde_json::to_string(&row)?);
+            text.push('\n');
+        }
+        let pruned = len - text.lines().count();
+        if pruned > 0 {
+            let tmp = self.path(device).with_extension("jsonl.tmp");
+            let replaced = async {
+                tokio::fs::write(&tmp, text).await?;
+                tokio::fs::rename(&tmp, self.path(device)).await
+            }.await;
+            if replaced.is_err() {
+                let _ = tokio::fs::remove_file(&tmp).await;
+            }
+            replaced?;
+        }
+        Ok(pruned as u64)
+    }
+
+    async fn delete(&self, devices: &[Id]) -> TelemetryResult {
+        let _lock = self.lock().await?;
+        for device in devices {
+            match tokio::fs::remove_file(self.path(*device)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DbDecodeData;
+    use crate::decode::{DecodeData, Value};
+    use crate::Id;
+    use crate::telemetry::{FileStore, TelemetryRow, TelemetryStore};
+    use crate::time::Timestamp;
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("telemetry-test-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir);
+        store.init().await.unwrap();
+        let time = |millis| Timestamp::from_timestamp_millis(millis).unwrap();
+        let device = Id(7);
+        for millis in [3000, 1000, 2000] {
+            let data = DbDecodeData(vec![DecodeData::new(1, Value::Int(millis as i64))]);
+            store.write(device, time(millis), &data, "AQ==").await.unwrap();
+        }
+        let rows = store.range(device, time(1000), time(3000)).await.unwrap();
+        assert_eq!(rows.iter().map(|row| row.time).collect::<Vec<_>>(), [time(1000), time(2000)]);
+        assert_eq!(store.last(device).await.unwrap().unwrap().data[0].v, Value::Int(3000));
+        assert!(store.last(Id(8)).await.unwrap().is_none());
+
+        assert_eq!(store.prune(device, time(2500), 100).await.unwrap(), 2);
+        assert_eq!(store.range(device, time(0), time(10000)).await.unwrap().len(), 1);
+
+        let rows: Vec<_> = [4000, 5000].map(|millis| TelemetryRow { time: time(millis), data: vec![], bytes: String::new() }).into();
+        store.write_batch(device, &rows).await.unwrap();
+        assert_eq!(store.range(device, time(3500), time(10000)).await.unwrap(), rows);
+        store.delete(&[device, Id(8)]).await.unwrap();
+        assert!(store.last(device).await.unwrap().is_none());
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_file_store_shared() {
+        let dir = std::env::temp_dir().join(format!("telemetry-test-{}", uuid::Uuid::new_v4()));
+        // two stores on one directory stand for devices_manager and snap_api
+        let writer = std::sync::Arc::new(FileStore::new(&dir));
+        let pruner = FileStore::new(&dir);
+        writer.init().await.unwrap();
+        let time = |millis| Timestamp::from_timestamp_millis(millis).unwrap();
+        let device = Id(7);
+        let data = DbDecodeData(vec![]);
+        pruner.write(device, time(1), &data, "").await.unwrap();
+        let writes = {
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                for millis in 1000..1200 {
+                    writer.write(device, time(millis), &DbDecodeData(vec![]), "").await.unwrap();
+                }
+            })
+        };
+        for _ in 0..50 {
+            pruner.prune(device, time(2), 100).await.unwrap();
+        }
+        writes.await.unwrap();
+        assert_eq!(writer.range(device, time(0), time(10000)).await.unwrap().len(), 200);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
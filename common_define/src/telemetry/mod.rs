@@ -0,0 +1,75 @@
+//! Storage of decoded uplinks, the backend is chosen with [`snap_config::TelemetryConfig`].
+//!
+//! Bucketed range queries, rollups, exports and re-decoding read the Postgres table
+//! directly, so [`FileStore`] can not be configured until they go through [`TelemetryStore`].
+
+use std::sync::Arc;
+use sea_orm::DatabaseConnection;
+use snap_config::TelemetryConfig;
+use crate::db::DbDecodeData;
+use crate::decode::DecodeData;
+use crate::Id;
+use crate::time::Timestamp;
+
+mod file;
+mod postgres;
+mod timescale;
+
+pub use file::FileStore;
+pub use postgres::PostgresStore;
+pub use timescale::TimescaleStore;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TelemetryError {
+    #[error("db error {0}")]
+    Db(#[from] sea_orm::DbErr),
+    #[error("io error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+}
+
+pub type TelemetryResult<T = ()> = Result<T, TelemetryError>;
+
+/// One stored uplink.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct TelemetryRow {
+    pub time: Timestamp,
+    pub data: Vec<DecodeData>,
+    /// Payload in base64, empty for imported values.
+    pub bytes: String,
+}
+
+#[async_trait::async_trait]
+pub trait TelemetryStore: Send + Sync {
+    /// Prepares the storage, run once at startup.
+    async fn init(&self) -> TelemetryResult {
+        Ok(())
+    }
+
+    /// Stores one uplink.
+    async fn write(&self, device: Id, time: Timestamp, data: &DbDecodeData, bytes: &str) -> TelemetryResult;
+
+    /// Stores uplinks of a device at once, none are stored when one fails.
+    async fn write_batch(&self, device: Id, rows: &[TelemetryRow]) -> TelemetryResult;
+
+    /// Uplinks of a device in `[start, end)`, oldest first.
+    async fn range(&self, device: Id, start: Timestamp, end: Timestamp) -> TelemetryResult<Vec<TelemetryRow>>;
+
+    /// The newest uplink of a device.
+    async fn last(&self, device: Id) -> TelemetryResult<Option<TelemetryRow>>;
+
+    /// Deletes at most `limit` uplinks of a device older than `before`, returns how many were deleted.
+    async fn prune(&self, device: Id, before: Timestamp, limit: u64) -> TelemetryResult<u64>;
+
+    /// Deletes everything stored for the devices.
+    async fn delete(&self, devices: &[Id]) -> TelemetryResult;
+}
+
+/// Opens the configured store, [`TelemetryStore::init`] is left to the caller.
+pub fn open(config: &TelemetryConfig, db: DatabaseConnection) -> Arc<dyn TelemetryStore> {
+    match config {
+        TelemetryConfig::Postgres => Arc::new(PostgresStore::new(db)),
+        TelemetryConfig::Timescale { chunk_days } => Arc::new(TimescaleStore::new(db, *chunk_days)),
+    }
+}
@@ -0,0 +1,117 @@
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Statement, TransactionTrait};
+use crate::db::{DbDecodeData, DeviceDataActiveModel, DeviceDataColumn, DeviceDataEntity, DeviceDataModel, DeviceDataRollupColumn, DeviceDataRollupEntity, Rollup};
+use crate::Id;
+use crate::telemetry::{TelemetryResult, TelemetryRow, TelemetryStore};
+use crate::time::Timestamp;
+
+/// Deletes at most `$3` uplinks of device `$1` stored before `$2`.
+const PRUNE_SQL: &str = r"DELETE FROM snap_device_data WHERE id IN (
+    SELECT id FROM snap_device_data WHERE device_id = $1 AND create_time < $2 LIMIT $3
+)";
+
+/// Uplinks inserted per statement of a batch.
+const BATCH: usize = 1000;
+
+/// Uplinks in `snap_device_data`, with the hourly and daily rollups kept up to date on write.
+pub struct PostgresStore {
+    pub(super) db: DatabaseConnection,
+}
+
+impl PostgresStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl From<DeviceDataModel> for TelemetryRow {
+    fn from(value: DeviceDataModel) -> Self {
+        Self {
+            time: value.create_time,
+            data: value.data.0,
+            bytes: value.bytes,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TelemetryStore for PostgresStore {
+
+    /// Inserts the uplink and updates the rollups of its hour and day in one transaction.
+    async fn write(&self, device: Id, time: Timestamp, data: &DbDecodeData, bytes: &str) -> TelemetryResult {
+        let txn = self.db.begin().await?;
+        DeviceDataActiveModel {
+            id: Default::default(),
+            device_id: ActiveValue::Set(device),
+            data: ActiveValue::Set(data.clone()),
+            bytes: ActiveValue::Set(bytes.to_string()),
+            create_time: ActiveValue::Set(time),
+        }.insert(&txn).await?;
+        txn.execute(Rollup::update(device, time, data)).await?;
+        Ok(txn.commit().await?)
+    }
+
+    /// Inserts the uplinks and rebuilds the rollups of their days in one transaction.
+    async fn write_batch(&self, device: Id, rows: &[TelemetryRow]) -> TelemetryResult {
+        let (Some(start), Some(end)) = (rows.iter().map(|row| row.time).min(), rows.iter().map(|row| row.time).max()) else {
+            return Ok(())
+        };
+        let txn = self.db.begin().await?;
+        for chunk in rows.chunks(BATCH) {
+            let models = chunk.iter().map(|row| DeviceDataActiveModel {
+                id: Default::default(),
+                device_id: ActiveValue::Set(device),
+                data: ActiveValue::Set(DbDecodeData(row.data.clone())),
+                bytes: ActiveValue::Set(row.bytes.clone()),
+                create_time: ActiveValue::Set(row.time),
+            });
+            DeviceDataEntity::insert_many(models).exec(&txn).await?;
+        }
+        for statement in Rollup::rebuild(start, end, Some(device)) {
+            txn.execute(statement).await?;
+        }
+        Ok(txn.commit().await?)
+    }
+
+    async fn range(&self, device: Id, start: Timestamp, end: Timestamp) -> TelemetryResult<Vec<TelemetryRow>> {
+        let rows = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.eq(device))
+            .filter(DeviceDataColumn::CreateTime.gte(start))
+            .filter(DeviceDataColumn::CreateTime.lt(end))
+            .order_by_asc(DeviceDataColumn::CreateTime)
+            .order_by_asc(DeviceDataColumn::Id)
+            .all(&self.db)
+            .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn last(&self, device: Id) -> TelemetryResult<Option<TelemetryRow>> {
+        let row = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.eq(device))
+            .order_by_desc(DeviceDataColumn::CreateTime)
+            .order_by_desc(DeviceDataColumn::Id)
+            .one(&self.db)
+            .await?;
+        Ok(row.map(Into::into))
+    }
+
+    async fn prune(&self, device: Id, before: Timestamp, limit: u64) -> TelemetryResult<u64> {
+        let statement = Statement::from_sql_and_values(
+            self.db.get_database_backend(),
+            PRUNE_SQL,
+            [device.into(), before.into(), (limit as i64).into()],
+        );
+        Ok(self.db.execute(statement).await?.rows_affected())
+    }
+
+    async fn delete(&self, devices: &[Id]) -> TelemetryResult {
+        DeviceDataEntity::delete_many()
+            .filter(DeviceDataColumn::DeviceId.is_in(devices.iter().copied()))
+            .exec(&self.db)
+            .await?;
+        DeviceDataRollupEntity::delete_many()
+            .filter(DeviceDataRollupColumn::DeviceId.is_in(devices.iter().copied()))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+}
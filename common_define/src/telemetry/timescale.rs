@@ -0,0 +1,81 @@
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement, TransactionTrait};
+use tracing::info;
+use crate::db::DbDecodeData;
+use crate::Id;
+use crate::telemetry::{PostgresStore, TelemetryResult, TelemetryRow, TelemetryStore};
+use crate::time::Timestamp;
+
+/// Serializes the conversion between the services starting at the same time.
+const LOCK_SQL: &str = "SELECT pg_advisory_xact_lock(hashtext('snap_device_data_hypertable'))";
+
+const IS_HYPERTABLE_SQL: &str = "SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = 'snap_device_data'";
+
+/// A hypertable needs the time column in every unique index, so the primary key becomes `(id, create_time)`.
+const CONVERT_SQL: [&str; 3] = [
+    "ALTER TABLE snap_device_data DROP CONSTRAINT IF EXISTS snap_device_data_pkey",
+    "ALTER TABLE snap_device_data ADD PRIMARY KEY (id, create_time)",
+    "SELECT create_hypertable('snap_device_data', 'create_time', chunk_time_interval => make_interval(days => $1), migrate_data => true)",
+];
+
+/// [`PostgresStore`] on `snap_device_data` turned into a TimescaleDB hypertable partitioned by time,
+/// the extension must be available to the database.
+pub struct TimescaleStore {
+    inner: PostgresStore,
+    chunk_days: u32,
+}
+
+impl TimescaleStore {
+    pub fn new(db: DatabaseConnection, chunk_days: u32) -> Self {
+        Self {
+            inner: PostgresStore::new(db),
+            chunk_days,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TelemetryStore for TimescaleStore {
+
+    /// Converts the table on the first start, existing rows are moved into chunks.
+    async fn init(&self) -> TelemetryResult {
+        let db = &self.inner.db;
+        let backend = db.get_database_backend();
+        db.execute_unprepared("CREATE EXTENSION IF NOT EXISTS timescaledb").await?;
+        let txn = db.begin().await?;
+        txn.execute_unprepared(LOCK_SQL).await?;
+        if txn.query_one(Statement::from_string(backend, IS_HYPERTABLE_SQL)).await?.is_some() {
+            return Ok(txn.commit().await?)
+        }
+        let [drop_key, add_key, create] = CONVERT_SQL;
+        txn.execute_unprepared(drop_key).await?;
+        txn.execute_unprepared(add_key).await?;
+        txn.execute(Statement::from_sql_and_values(backend, create, [(self.chunk_days.max(1) as i32).into()])).await?;
+        txn.commit().await?;
+        info!(chunk_days = self.chunk_days, "snap_device_data is now a hypertable");
+        Ok(())
+    }
+
+    async fn write(&self, device: Id, time: Timestamp, data: &DbDecodeData, bytes: &str) -> TelemetryResult {
+        self.inner.write(device, time, data, bytes).await
+    }
+
+    async fn write_batch(&self, device: Id, rows: &[TelemetryRow]) -> TelemetryResult {
+        self.inner.write_batch(device, rows).await
+    }
+
+    async fn range(&self, device: Id, start: Timestamp, end: Timestamp) -> TelemetryResult<Vec<TelemetryRow>> {
+        self.inner.range(device, start, end).await
+    }
+
+    async fn last(&self, device: Id) -> TelemetryResult<Option<TelemetryRow>> {
+        self.inner.last(device).await
+    }
+
+    async fn prune(&self, device: Id, before: Timestamp, limit: u64) -> TelemetryResult<u64> {
+        self.inner.prune(device, before, limit).await
+    }
+
+    async fn delete(&self, devices: &[Id]) -> TelemetryResult {
+        self.inner.delete(devices).await
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt::{Debug, Display, Formatter};
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveTime, Offset, TimeZone};
 use redis::{RedisResult, RedisWrite, Value};
+pub use chrono_tz::Tz;
 
 #[derive(
     sea_orm::DeriveValueType,
@@ -10,7 +12,9 @@ use redis::{RedisResult, RedisWrite, Value};
     serde::Serialize,
     PartialEq,
     PartialOrd,
-    Eq
+    Eq,
+    Ord,
+    Hash
 )]
 #[serde(try_from = "u64", into = "u64")]
 pub struct Timestamp(chrono::DateTime<chrono::Utc>);
@@ -94,6 +98,12 @@ impl TryFrom<u64> for Timestamp {
     }
 }
 
+impl sea_orm::TryFromU64 for Timestamp {
+    fn try_from_u64(n: u64) -> Result<Self, sea_orm::DbErr> {
+        Self::try_from(n).map_err(sea_orm::DbErr::Custom)
+    }
+}
+
 impl redis::ToRedisArgs for Timestamp {
     fn write_redis_args<W>(&self, out: &mut W)
     where
@@ -108,4 +118,124 @@ impl redis::FromRedisValue for Timestamp {
         Self::from_timestamp_millis(u)
             .ok_or(redis::RedisError::from((redis::ErrorKind::TypeError, "time from error")))
     }
-}
\ No newline at end of file
+}
+/// Calendar unit data is bucketed by in a time zone, a bucket starts at local midnight.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum::EnumString, strum::AsRefStr)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Calendar {
+    Day,
+    /// Starts on Monday.
+    Week,
+    Month,
+}
+
+impl Calendar {
+    /// Start of the bucket `time` is in.
+    pub fn floor(self, time: Timestamp, tz: Tz) -> Timestamp {
+        let date = time.0.with_timezone(&tz).date_naive();
+        let date = match self {
+            Self::Day => date,
+            Self::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Self::Month => date.with_day(1).unwrap_or(date),
+        };
+        Self::midnight(date, tz)
+    }
+
+    /// Start of the bucket after the one starting at `start`.
+    pub fn next(self, start: Timestamp, tz: Tz) -> Timestamp {
+        let date = start.0.with_timezone(&tz).date_naive();
+        let date = match self {
+            Self::Day => date + Days::new(1),
+            Self::Week => date + Days::new(7),
+            Self::Month => date + Months::new(1),
+        };
+        Self::midnight(date, tz)
+    }
+
+    /// Seconds of the shortest bucket, a day is 23 hours long when DST starts.
+    pub fn min_seconds(self) -> i64 {
+        let days = match self {
+            Self::Day => 1,
+            Self::Week => 7,
+            Self::Month => 28,
+        };
+        days * 24 * 3600 - 3600
+    }
+
+    /// First instant of a local date, later than midnight when a DST change skips it.
+    fn midnight(date: NaiveDate, tz: Tz) -> Timestamp {
+        let mut time = date.and_time(NaiveTime::MIN);
+        loop {
+            if let Some(local) = tz.from_local_datetime(&time).earliest() {
+                return Timestamp(local.with_timezone(&chrono::Utc));
+            }
+            time += chrono::Duration::minutes(15);
+        }
+    }
+}
+
+/// Time zone of an IANA name such as `Europe/Berlin`.
+pub fn time_zone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
+/// Fixed time zone of a whole number of hours east of UTC, the way devices report their time zone.
+pub fn offset_time_zone(hours: i32) -> Option<Tz> {
+    match hours {
+        0 => Some(Tz::UTC),
+        // the sign of the Etc zones is inverted, Etc/GMT-8 is UTC+8
+        -12..=14 => format!("Etc/GMT{:+}", -hours).parse().ok(),
+        _ => None,
+    }
+}
+
+/// Whether the UTC offset of `tz` stays a whole number of hours from `start` to `end`,
+/// so no hour since the epoch spans two local days.
+pub fn whole_hour_offsets(tz: Tz, start: Timestamp, end: Timestamp) -> bool {
+    let mut time = start;
+    loop {
+        if tz.offset_from_utc_datetime(&time.0.naive_utc()).fix().local_minus_utc() % 3600 != 0 {
+            return false;
+        }
+        if time >= end {
+            return true;
+        }
+        time = (time + chrono::Duration::days(1)).min(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time::{offset_time_zone, time_zone, whole_hour_offsets, Calendar, Timestamp, Tz};
+
+    fn at(rfc3339: &str) -> Timestamp {
+        Timestamp(chrono::DateTime::parse_from_rfc3339(rfc3339).unwrap().to_utc())
+    }
+
+    #[test]
+    fn test_calendar() {
+        let berlin = time_zone("Europe/Berlin").unwrap();
+        let time = at("2026-03-29T12:00:00+02:00");
+        assert_eq!(Calendar::Day.floor(time, berlin), at("2026-03-29T00:00:00+01:00"));
+        // DST starts, the day is 23 hours long
+        assert_eq!(Calendar::Day.next(Calendar::Day.floor(time, berlin), berlin), at("2026-03-30T00:00:00+02:00"));
+        assert_eq!(Calendar::Week.floor(time, berlin), at("2026-03-23T00:00:00+01:00"));
+        assert_eq!(Calendar::Month.floor(time, berlin), at("2026-03-01T00:00:00+01:00"));
+        assert_eq!(Calendar::Month.next(at("2026-03-01T00:00:00+01:00"), berlin), at("2026-04-01T00:00:00+02:00"));
+
+        // midnight is skipped when DST starts in Santiago
+        let santiago = time_zone("America/Santiago").unwrap();
+        assert_eq!(Calendar::Day.floor(at("2026-09-06T12:00:00-03:00"), santiago), at("2026-09-06T01:00:00-03:00"));
+
+        assert_eq!(offset_time_zone(8), Some(Tz::Etc__GMTMinus8));
+        assert_eq!(offset_time_zone(-5), Some(Tz::Etc__GMTPlus5));
+        assert_eq!(offset_time_zone(20), None);
+        assert!(time_zone("Mars/Olympus").is_none());
+
+        let start = at("2026-01-01T00:00:00Z");
+        let end = at("2026-12-31T00:00:00Z");
+        assert!(whole_hour_offsets(berlin, start, end));
+        assert!(!whole_hour_offsets(time_zone("Asia/Kolkata").unwrap(), start, end));
+    }
+}
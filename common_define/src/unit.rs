@@ -0,0 +1,301 @@
+//! Calibration of sensor values at ingest and unit conversion at query time.
+
+use crate::decode::{DecodeData, Value};
+
+/// Correction of the raw value of a data point, applied before the value is stored.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Calibration {
+    /// `raw * gain + offset`.
+    Linear {
+        #[serde(default)]
+        offset: f64,
+        #[serde(default = "Calibration::default_gain")]
+        gain: f64,
+    },
+    /// `[raw, value]` pairs sorted by raw value, interpolated linearly between two pairs
+    /// and extrapolated from the first or last two outside them.
+    Table {
+        points: Vec<[f64; 2]>,
+    },
+}
+
+impl Calibration {
+    fn default_gain() -> f64 {
+        1.0
+    }
+
+    /// Error message when the calibration cannot be applied.
+    pub fn check(&self) -> Result<(), &'static str> {
+        match self {
+            Self::Linear { offset, gain } => {
+                if !offset.is_finite() || !gain.is_finite() {
+                    return Err("offset and gain must be finite numbers");
+                }
+            }
+            Self::Table { points } => {
+                if points.len() < 2 {
+                    return Err("a table needs at least two points");
+                }
+                if points.iter().flatten().any(|it| !it.is_finite()) {
+                    return Err("table points must be finite numbers");
+                }
+                if points.windows(2).any(|it| it[0][0] >= it[1][0]) {
+                    return Err("table points must be sorted by raw value without duplicates");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, raw: f64) -> f64 {
+        match self {
+            Self::Linear { offset, gain } => raw * gain + offset,
+            Self::Table { points } => {
+                if points.len() < 2 {
+                    return raw;
+                }
+                let segment = match points.iter().position(|it| raw < it[0]) {
+                    Some(0) => 0,
+                    Some(i) => i - 1,
+                    None => points.len() - 2,
+                };
+                let ([x0, y0], [x1, y1]) = (points[segment], points[segment + 1]);
+                y0 + (raw - x0) * (y1 - y0) / (x1 - x0)
+            }
+        }
+    }
+
+    /// Calibrates a numeric value, other values are kept.
+    pub fn apply_value(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::Int(v) => Some(Value::Float(self.apply(*v as f64))),
+            Value::Float(v) => Some(Value::Float(self.apply(*v))),
+            _ => None,
+        }
+    }
+
+    /// Calibrates the values of decoded data that have a calibration in `calibrations`.
+    pub fn apply_all(calibrations: &[(u32, Self)], data: &mut [DecodeData]) {
+        for item in data {
+            if let Some((_, calibration)) = calibrations.iter().find(|(id, _)| *id == item.i) {
+                if let Some(value) = calibration.apply_value(&item.v) {
+                    item.v = value;
+                }
+            }
+        }
+    }
+}
+
+impl std::convert::From<Calibration> for sea_orm::Value {
+    fn from(source: Calibration) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for Calibration {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for Calibration {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "Calibration".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+impl sea_orm::sea_query::Nullable for Calibration {
+    fn null() -> sea_orm::Value {
+        sea_orm::Value::Json(None)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    #[default]
+    #[serde(rename = "°C")]
+    Celsius,
+    #[serde(rename = "°F")]
+    Fahrenheit,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PressureUnit {
+    #[default]
+    #[serde(rename = "kPa")]
+    KiloPascal,
+    #[serde(rename = "psi")]
+    Psi,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LengthUnit {
+    #[default]
+    #[serde(rename = "m")]
+    Meter,
+    #[serde(rename = "ft")]
+    Foot,
+}
+
+/// Units a user reads data in. The metric default keeps values in the unit they are stored in,
+/// the other units convert the values of data points stored in a metric unit.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    serde::Serialize,
+    serde::Deserialize,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct UnitPreference {
+    #[serde(default)]
+    pub temperature: TemperatureUnit,
+    #[serde(default)]
+    pub pressure: PressureUnit,
+    #[serde(default)]
+    pub length: LengthUnit,
+}
+
+impl UnitPreference {
+    /// Converts `value` stored in `unit` to the preferred unit, with the unit it was converted to.
+    /// `None` when the unit is not known or already the preferred one.
+    pub fn convert(&self, unit: &str, value: f64) -> Option<(f64, &'static str)> {
+        match unit.trim() {
+            "°C" | "℃" | "C" if self.temperature == TemperatureUnit::Fahrenheit => Some((value * 9.0 / 5.0 + 32.0, "°F")),
+            "Pa" | "hPa" | "kPa" | "MPa" if self.pressure == PressureUnit::Psi => {
+                let kpa = match unit.trim() {
+                    "Pa" => value / 1000.0,
+                    "hPa" => value / 10.0,
+                    "MPa" => value * 1000.0,
+                    _ => value,
+                };
+                Some((kpa * 0.145_037_737_730_209_2, "psi"))
+            }
+            "mm" | "cm" | "m" if self.length == LengthUnit::Foot => {
+                let meter = match unit.trim() {
+                    "mm" => value / 1000.0,
+                    "cm" => value / 100.0,
+                    _ => value,
+                };
+                Some((meter / 0.3048, "ft"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts a numeric value, see [`Self::convert`].
+    pub fn convert_value(&self, unit: &str, value: &Value) -> Option<(Value, &'static str)> {
+        let value = match value {
+            Value::Int(v) => *v as f64,
+            Value::Float(v) => *v,
+            _ => return None,
+        };
+        self.convert(unit, value).map(|(value, unit)| (Value::Float(value), unit))
+    }
+
+    /// The unit values stored in `unit` are read in.
+    pub fn unit_of(&self, unit: &str) -> Option<&'static str> {
+        self.convert(unit, 0.0).map(|(_, unit)| unit)
+    }
+}
+
+impl std::convert::From<UnitPreference> for sea_orm::Value {
+    fn from(source: UnitPreference) -> Self {
+        sea_orm::Value::Json(
+            Some(Box::new(serde_json::to_value(source).unwrap_or_default()))
+        )
+    }
+}
+
+impl sea_orm::TryGetable for UnitPreference {
+    fn try_get_by<I: sea_orm::ColIdx>(
+        res: &sea_orm::QueryResult,
+        idx: I,
+    ) -> std::result::Result<Self, sea_orm::TryGetError> {
+        <serde_json::Value as sea_orm::TryGetable>::try_get_by(res, idx)
+            .and_then(|v| serde_json::from_value(v).map_err(|e| sea_orm::TryGetError::DbErr(sea_orm::DbErr::Custom(e.to_string()))))
+    }
+}
+
+impl sea_orm::sea_query::ValueType for UnitPreference {
+    fn try_from(v: sea_orm::Value) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+        <serde_json::Value as sea_orm::sea_query::ValueType>::try_from(v)
+            .and_then(|v| serde_json::from_value(v).map_err(|_| sea_orm::sea_query::ValueTypeErr))
+    }
+    fn type_name() -> std::string::String {
+        "UnitPreference".to_owned()
+    }
+    fn array_type() -> sea_orm::sea_query::ArrayType {
+        sea_orm::sea_query::ArrayType::Json
+    }
+    fn column_type() -> sea_orm::sea_query::ColumnType {
+        sea_orm::prelude::ColumnType::Json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::{DecodeData, Value};
+    use crate::unit::{Calibration, LengthUnit, PressureUnit, TemperatureUnit, UnitPreference};
+
+    #[test]
+    fn test_calibration() {
+        let linear = Calibration::Linear { offset: -0.5, gain: 2.0 };
+        assert_eq!(linear.apply(3.0), 5.5);
+        let table = Calibration::Table { points: vec![[0.0, 0.0], [10.0, 100.0], [20.0, 150.0]] };
+        assert!(table.check().is_ok());
+        assert_eq!(table.apply(5.0), 50.0);
+        assert_eq!(table.apply(15.0), 125.0);
+        assert_eq!(table.apply(-1.0), -10.0);
+        assert_eq!(table.apply(30.0), 200.0);
+        assert!(Calibration::Table { points: vec![[1.0, 0.0], [1.0, 2.0]] }.check().is_err());
+        assert!(Calibration::Table { points: vec![[1.0, 0.0]] }.check().is_err());
+
+        let parsed: Calibration = serde_json::from_str(r#"{"type":"linear","offset":1.5}"#).unwrap();
+        assert_eq!(parsed, Calibration::Linear { offset: 1.5, gain: 1.0 });
+
+        let mut data = vec![DecodeData::new(1, Value::Int(3)), DecodeData::new(2, Value::Bool(true)), DecodeData::new(3, Value::Float(1.0))];
+        Calibration::apply_all(&[(1, linear.clone()), (2, linear)], &mut data);
+        assert_eq!(data[0].v, Value::Float(5.5));
+        assert_eq!(data[1].v, Value::Bool(true));
+        assert_eq!(data[2].v, Value::Float(1.0));
+    }
+
+    #[test]
+    fn test_convert() {
+        let metric = UnitPreference::default();
+        assert_eq!(metric.convert("°C", 20.0), None);
+        let us = UnitPreference {
+            temperature: TemperatureUnit::Fahrenheit,
+            pressure: PressureUnit::Psi,
+            length: LengthUnit::Foot,
+        };
+        assert_eq!(us.convert("°C", 100.0), Some((212.0, "°F")));
+        let (psi, unit) = us.convert("hPa", 1013.25).unwrap();
+        assert_eq!(unit, "psi");
+        assert!((psi - 14.696).abs() < 0.001);
+        assert_eq!(us.convert("m", 0.3048), Some((1.0, "ft")));
+        assert_eq!(us.convert("%", 50.0), None);
+        assert_eq!(us.convert_value("°C", &Value::String("x".into())), None);
+        assert_eq!(serde_json::to_string(&us).unwrap(), r#"{"temperature":"°F","pressure":"psi","length":"ft"}"#);
+    }
+}
@@ -0,0 +1,455 @@
+//! Virtual data points, values computed from the other values of an uplink.
+//!
+//! An expression is arithmetic (`+ - * / % ^` and parentheses) over numbers and:
+//! - `v<ID>`, the value of data ID `<ID>` in the uplink,
+//! - `p<ID>`, its value in the previous uplink of the device,
+//! - `dt`, the seconds since the previous uplink,
+//! - `pi`, and the functions `abs sqrt exp ln log10 round floor ceil` and `pow min max` of two values.
+//!
+//! Integer and boolean values are used as numbers, a missing or non-numeric input leaves the point out.
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use sea_orm::{ConnectionTrait, DbErr, EntityTrait, Statement};
+use crate::db::{VirtualPointEntity, VirtualPointModel};
+use crate::decode::{DecodeData, LastDecodeData, Value};
+use crate::Id;
+use crate::time::Timestamp;
+
+/// Points of device `$1` and of its product, the one of the device when both define a data ID.
+const DEVICE_POINTS_SQL: &str = r"SELECT DISTINCT ON (data_id) * FROM snap_virtual_point
+WHERE (scope = 'device' AND target_id = $1)
+   OR (scope = 'product' AND target_id = (SELECT product_id FROM snap_devices WHERE id = $1))
+ORDER BY data_id, scope = 'product'";
+
+/// Deepest nesting of an expression, each parenthesis, call argument, sign and power adds a level.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Var {
+    Value(u32),
+    Prev(u32),
+    Elapsed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Func {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Round,
+    Floor,
+    Ceil,
+    Pow,
+    Min,
+    Max,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "log10" => Self::Log10,
+            "round" => Self::Round,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "pow" => Self::Pow,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Pow | Self::Min | Self::Max => 2,
+            _ => 1,
+        }
+    }
+
+    fn call(self, args: &[f64]) -> f64 {
+        match self {
+            Self::Abs => args[0].abs(),
+            Self::Sqrt => args[0].sqrt(),
+            Self::Exp => args[0].exp(),
+            Self::Ln => args[0].ln(),
+            Self::Log10 => args[0].log10(),
+            Self::Round => args[0].round(),
+            Self::Floor => args[0].floor(),
+            Self::Ceil => args[0].ceil(),
+            Self::Pow => args[0].powf(args[1]),
+            Self::Min => args[0].min(args[1]),
+            Self::Max => args[0].max(args[1]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Num(f64),
+    Var(Var),
+    Neg(Box<Node>),
+    Bin(Op, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+/// A parsed expression, displayed as its source text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("{message} at {position}")]
+pub struct ExprError {
+    pub message: String,
+    /// Byte offset in the source.
+    pub position: usize,
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser { source, position: 0, depth: 0 };
+        let root = parser.sum()?;
+        parser.skip_space();
+        if parser.position < source.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Self { source: source.to_string(), root })
+    }
+
+    /// Data IDs the expression reads, in the uplink or the previous one.
+    pub fn data_ids(&self) -> BTreeSet<u32> {
+        fn visit(node: &Node, ids: &mut BTreeSet<u32>) {
+            match node {
+                Node::Num(_) | Node::Var(Var::Elapsed) => {}
+                Node::Var(Var::Value(id) | Var::Prev(id)) => {
+                    ids.insert(*id);
+                }
+                Node::Neg(node) => visit(node, ids),
+                Node::Bin(_, left, right) => {
+                    visit(left, ids);
+                    visit(right, ids);
+                }
+                Node::Call(_, args) => args.iter().for_each(|arg| visit(arg, ids)),
+            }
+        }
+        let mut ids = BTreeSet::new();
+        visit(&self.root, &mut ids);
+        ids
+    }
+
+    /// Evaluates with the variables from `var`, `None` when one is missing or the result is not finite.
+    pub fn eval(&self, var: &impl Fn(Var) -> Option<f64>) -> Option<f64> {
+        fn eval(node: &Node, var: &impl Fn(Var) -> Option<f64>) -> Option<f64> {
+            Some(match node {
+                Node::Num(n) => *n,
+                Node::Var(v) => var(*v)?,
+                Node::Neg(node) => -eval(node, var)?,
+                Node::Bin(op, left, right) => {
+                    let (a, b) = (eval(left, var)?, eval(right, var)?);
+                    match op {
+                        Op::Add => a + b,
+                        Op::Sub => a - b,
+                        Op::Mul => a * b,
+                        Op::Div => a / b,
+                        Op::Rem => a % b,
+                        Op::Pow => a.powf(b),
+                    }
+                }
+                Node::Call(func, args) => {
+                    let args = args.iter().map(|arg| eval(arg, var)).collect::<Option<Vec<_>>>()?;
+                    func.call(&args)
+                }
+            })
+        }
+        eval(&self.root, var).filter(|it| it.is_finite())
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+    /// Levels of [`Parser::unary`] entered, every recursion passes through it.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ExprError {
+        ExprError { message: message.to_string(), position: self.position }
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.position..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(c) {
+            self.position += c.len_utf8();
+            return true;
+        }
+        false
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &str {
+        let start = self.position;
+        let len = self.rest().find(|c: char| !f(c)).unwrap_or(self.rest().len());
+        self.position += len;
+        &self.source[start..self.position]
+    }
+
+    fn sum(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(node);
+            };
+            node = Node::Bin(op, Box::new(node), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else if self.eat('%') {
+                Op::Rem
+            } else {
+                return Ok(node);
+            };
+            node = Node::Bin(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    /// Counts the nesting, see [`MAX_DEPTH`].
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("expression nested too deeply"));
+        }
+        self.depth += 1;
+        let node = self.signed();
+        self.depth -= 1;
+        node
+    }
+
+    /// `-a ^ b` is `-(a ^ b)` and `a ^ b ^ c` is `a ^ (b ^ c)`.
+    fn signed(&mut self) -> Result<Node, ExprError> {
+        if self.eat('-') {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        let node = self.atom()?;
+        if self.eat('^') {
+            return Ok(Node::Bin(Op::Pow, Box::new(node), Box::new(self.unary()?)));
+        }
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, ExprError> {
+        if self.eat('(') {
+            let node = self.sum()?;
+            if !self.eat(')') {
+                return Err(self.error("expected `)`"));
+            }
+            return Ok(node);
+        }
+        self.skip_space();
+        let start = self.position;
+        let c = self.rest().chars().next().ok_or_else(|| self.error("unexpected end"))?;
+        if c.is_ascii_digit() || c == '.' {
+            let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+            return number.parse()
+                .map(Node::Num)
+                .map_err(|_| ExprError { message: format!("invalid number `{}`", number), position: start });
+        }
+        if !c.is_ascii_alphabetic() {
+            return Err(self.error("unexpected character"));
+        }
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_').to_string();
+        let unknown = || ExprError { message: format!("unknown name `{}`", name), position: start };
+        if let Some(func) = Func::parse(&name) {
+            if !self.eat('(') {
+                return Err(self.error("expected `(`"));
+            }
+            let mut args = vec![self.sum()?];
+            while self.eat(',') {
+                args.push(self.sum()?);
+            }
+            if !self.eat(')') {
+                return Err(self.error("expected `)`"));
+            }
+            if args.len() != func.arity() {
+                return Err(ExprError { message: format!("`{}` takes {} arguments", name, func.arity()), position: start });
+            }
+            return Ok(Node::Call(func, args));
+        }
+        match name.as_str() {
+            "dt" => return Ok(Node::Var(Var::Elapsed)),
+            "pi" => return Ok(Node::Num(std::f64::consts::PI)),
+            _ => {}
+        }
+        let (prefix, id) = name.split_at(1);
+        let id: u32 = id.parse().map_err(|_| unknown())?;
+        match prefix {
+            "v" => Ok(Node::Var(Var::Value(id))),
+            "p" => Ok(Node::Var(Var::Prev(id))),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(v) => Some(*v as f64),
+        Value::Float(v) => Some(*v),
+        Value::Bool(v) => Some(*v as u8 as f64),
+        _ => None,
+    }
+}
+
+/// A virtual data point of a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualPoint {
+    pub id: u32,
+    pub expr: Expr,
+}
+
+impl VirtualPoint {
+    /// Points that apply to a device.
+    pub async fn of_device(db: &impl ConnectionTrait, device: Id) -> Result<Vec<VirtualPointModel>, DbErr> {
+        VirtualPointEntity::find()
+            .from_raw_sql(Statement::from_sql_and_values(db.get_database_backend(), DEVICE_POINTS_SQL, [device.into()]))
+            .all(db)
+            .await
+    }
+
+    pub fn new(model: &VirtualPointModel) -> Result<Self, ExprError> {
+        Ok(Self {
+            id: model.data_id as u32,
+            expr: Expr::parse(&model.expression)?,
+        })
+    }
+
+    /// Appends the values of `points` to the decoded data of an uplink at `time`.
+    /// A point can use other points, whatever their order.
+    /// A point whose ID is already decoded, or whose expression has no value, is left out.
+    pub fn compute(points: &[Self], data: &mut Vec<DecodeData>, prev: Option<&LastDecodeData>, time: Timestamp) {
+        loop {
+            let mut computed = false;
+            for point in points {
+                if data.iter().any(|it| it.i == point.id) {
+                    continue;
+                }
+                let value = point.expr.eval(&|var| match var {
+                    Var::Value(id) => data.iter().find(|it| it.i == id).and_then(|it| number(&it.v)),
+                    Var::Prev(id) => prev?.v.iter().find(|it| it.i == id).and_then(|it| number(&it.v)),
+                    Var::Elapsed => prev.map(|prev| (time - prev.t).num_milliseconds() as f64 / 1000.0),
+                });
+                if let Some(value) = value {
+                    data.push(DecodeData::new(point.id, Value::Float(value)));
+                    computed = true;
+                }
+            }
+            if !computed {
+                return;
+            }
+        }
+    }
+
+    /// Removes the values of `points` from stored data, before they are computed again.
+    pub fn strip(points: &[Self], data: &mut Vec<DecodeData>) {
+        data.retain(|it| points.iter().all(|point| point.id != it.i));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::{DecodeData, LastDecodeData, Value};
+    use crate::time::Timestamp;
+    use crate::virtual_point::{Expr, VirtualPoint};
+
+    #[test]
+    fn test_expr() {
+        let eval = |source: &str| Expr::parse(source).unwrap().eval(&|_| Some(2.0));
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("-2 ^ 2"), Some(-4.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Some(512.0));
+        assert_eq!(eval("v1 * p1 / dt"), Some(2.0));
+        assert_eq!(eval("max(v1, 5) % 3"), Some(2.0));
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(Expr::parse("v1 + v12 - p3").unwrap().data_ids().into_iter().collect::<Vec<_>>(), [1, 3, 12]);
+        assert_eq!(Expr::parse("1 +").unwrap_err().position, 3);
+        assert!(Expr::parse("x1").is_err());
+        assert!(Expr::parse("pow(1)").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(63)), Some(1.0));
+        assert!(Expr::parse(&nested(64)).is_err());
+        assert!(Expr::parse(&"-".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn test_compute() {
+        let time = |millis| Timestamp::from_timestamp_millis(millis).unwrap();
+        let points = [
+            VirtualPoint { id: 12, expr: Expr::parse("v10 + 1").unwrap() },
+            VirtualPoint { id: 10, expr: Expr::parse("v1 * v2").unwrap() },
+            VirtualPoint { id: 11, expr: Expr::parse("(v1 - p1) / dt").unwrap() },
+            VirtualPoint { id: 2, expr: Expr::parse("0").unwrap() },
+        ];
+        let mut data = vec![DecodeData::new(1, Value::Int(12)), DecodeData::new(2, Value::Float(0.5))];
+        VirtualPoint::compute(&points, &mut data, None, time(10_000));
+        assert_eq!(data[2..], [DecodeData::new(10, Value::Float(6.0)), DecodeData::new(12, Value::Float(7.0))]);
+        assert!(data.iter().all(|it| it.i != 11));
+
+        let prev = LastDecodeData::new(vec![DecodeData::new(1, Value::Int(2))], time(5_000));
+        VirtualPoint::strip(&points[..3], &mut data);
+        VirtualPoint::compute(&points[..3], &mut data, Some(&prev), time(10_000));
+        assert_eq!(data.len(), 5);
+        assert_eq!(data[3], DecodeData::new(11, Value::Float(2.0)));
+    }
+}
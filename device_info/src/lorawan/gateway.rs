@@ -1,5 +1,5 @@
 use std::net::SocketAddr;
-use common_define::db::Eui;
+use common_define::db::{DeviceLoraGateModel, Eui, GatewayAllowList, Key};
 use common_define::Id;
 use derive_new::new;
 use serde::{Deserialize, Serialize};
@@ -8,6 +8,14 @@ use common_define::time::Timestamp;
 use hash_name::{HashNames, RedisOps};
 use crate::MyOption;
 
+/// Sets `KEYS[1]` to `ARGV[1]` when it is greater, returns whether it was set.
+const ADVANCE_COUNTER: &str = r"local last = redis.call('GET', KEYS[1])
+if last and last >= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1])
+return 1";
+
 #[derive(Debug, Clone, Serialize, Deserialize, RedisOps, new, HashNames)]
 pub struct GatewayInfo {
     pub device: Id,
@@ -15,7 +23,16 @@ pub struct GatewayInfo {
     pub version: u8,
     pub time: Timestamp,
     pub a: Option<Timestamp>,
-    pub down: Option<String>
+    pub down: Option<String>,
+    #[new(default)]
+    pub allow_list: Option<GatewayAllowList>,
+    #[new(default)]
+    pub hmac_key: Option<Key>,
+    /// Address of the last accepted packet.
+    #[new(default)]
+    pub source: Option<String>,
+    #[new(default)]
+    pub source_time: Option<Timestamp>,
 }
 
 impl GatewayInfo {
@@ -24,6 +41,39 @@ impl GatewayInfo {
         format!("info:gateway:{}", eui)
     }
 
+    /// Takes the source protections of the gateway.
+    pub fn with_source(mut self, gate: &DeviceLoraGateModel) -> Self {
+        self.allow_list = (!gate.allow_list.is_empty()).then(|| gate.allow_list.clone());
+        self.hmac_key = gate.hmac_key;
+        self
+    }
+
+    pub async fn update<C: redis::aio::ConnectionLike, V: redis::ToRedisArgs>(
+        eui: Eui,
+        key: &str,
+        v: V,
+        con: &mut C,
+    ) -> redis::RedisResult<()> {
+        let k = Self::eui_key(eui);
+        // only a registered gateway is updated, a bare field would look like a cached gateway
+        if redis::Cmd::exists(&k).query_async(con).await? {
+            let _: () = redis::Cmd::hset(&k, key, v).query_async(con).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn reset<C: redis::aio::ConnectionLike>(
+        eui: Eui,
+        key: &str,
+        con: &mut C,
+    ) -> redis::RedisResult<()> {
+        let k = Self::eui_key(eui);
+        if redis::Cmd::exists(&k).query_async(con).await? {
+            let _: () = redis::Cmd::hdel(&k, key).query_async(con).await?;
+        }
+        Ok(())
+    }
+
     #[instrument(skip(conn))]
     pub async fn check_eui<C: redis::aio::ConnectionLike>(
         dev_eui: Eui,
@@ -46,7 +96,35 @@ impl GatewayInfo {
         con: &mut C,
     ) -> redis::RedisResult<()> {
         let k = format!("info:gateway:{}", eui);
-        redis::Cmd::del(&k).query_async(con).await
+        redis::Cmd::del(&[k, Self::counter_key(eui)]).query_async(con).await
+    }
+
+    fn counter_key(eui: Eui) -> String {
+        format!("info:gateway:{}:counter", eui)
+    }
+
+    /// Signature counter of the last accepted packet of the gateway.
+    pub async fn load_counter<C: redis::aio::ConnectionLike>(
+        eui: Eui,
+        con: &mut C,
+    ) -> redis::RedisResult<Option<u64>> {
+        let counter: Option<String> = redis::Cmd::get(Self::counter_key(eui)).query_async(con).await?;
+        Ok(counter.and_then(|it| it.parse().ok()))
+    }
+
+    /// Stores the signature counter of an accepted packet, `false` when it is not above the stored one,
+    /// so of two packets with the same counter only one is accepted.
+    pub async fn advance_counter<C: redis::aio::ConnectionLike>(
+        eui: Eui,
+        counter: u64,
+        con: &mut C,
+    ) -> redis::RedisResult<bool> {
+        // zero padded, so the strings compare like the numbers
+        redis::Script::new(ADVANCE_COUNTER)
+            .key(Self::counter_key(eui))
+            .arg(format!("{:020}", counter))
+            .invoke_async(con)
+            .await
     }
     pub async fn update_active_time<C: redis::aio::ConnectionLike>(
         eui: Eui,
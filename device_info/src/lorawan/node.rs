@@ -1,9 +1,10 @@
 use redis::AsyncCommands;
 use serde::Serialize;
 use tracing::{instrument, warn};
-use common_define::db::{DeviceLoraNodeModel, DevicesModel, Eui, Key, LoRaAddr};
+use common_define::db::{CodecVariables, DeviceLoraNodeModel, DeviceProfileModel, DevicesModel, Eui, FPortRoutes, Key, LoRaAddr};
+use common_define::codec::PayloadCodec;
 use common_define::Id;
-use common_define::lora::{LoRaJoinType, LoRaRegion};
+use common_define::lora::{FCntPolicy, LoRaJoinType, LoRaRegion, PayloadEncryption};
 use common_define::product::ProductType;
 use common_define::time::Timestamp;
 use hash_name::{HashNames, RedisOps};
@@ -50,9 +51,18 @@ pub struct NodeInfo {
     pub enable: bool,
     pub online: bool,
     pub script: Option<Id>,
+    /// Pinned version of `script`, only a script set on the device is pinned.
+    pub script_version: Option<i32>,
     pub active_time: Option<Timestamp>,
     
-    pub gateway: Option<Eui>
+    pub gateway: Option<Eui>,
+    pub profile: Option<Id>,
+    pub fcnt_policy: Option<FCntPolicy>,
+    pub fport_routes: Option<FPortRoutes>,
+    pub payload_encryption: Option<PayloadEncryption>,
+    pub app_kek: Option<Key>,
+    pub variables: Option<CodecVariables>,
+    pub codec: Option<PayloadCodec>,
 }
 
 impl NodeInfo {
@@ -68,6 +78,11 @@ impl NodeInfo {
         format!("info:node:{}", addr)
     }
 
+    /// The AppSKey is held by the application, not by the network server.
+    pub fn is_app_encrypted(&self) -> bool {
+        self.payload_encryption == Some(PayloadEncryption::Application)
+    }
+
     #[instrument(skip(conn, v))]
     pub async fn update_by_addr<C: redis::aio::ConnectionLike, V: redis::ToRedisArgs>(
         addr: LoRaAddr,
@@ -94,7 +109,7 @@ impl NodeInfo {
             }
         };
         if redis::Cmd::exists(&k).query_async(conn).await? {
-            redis::cmd("HDEL").arg(&k).arg(key).query_async(conn).await?;
+            let _: () = redis::cmd("HDEL").arg(&k).arg(key).query_async(conn).await?;
         }
         Ok(())
     }
@@ -114,7 +129,7 @@ impl NodeInfo {
             }
         };
         if redis::Cmd::exists(&k).query_async(conn).await? {
-            redis::cmd("HSET").arg(&k).arg(key).arg(v).query_async(conn).await?;
+            let _: () = redis::cmd("HSET").arg(&k).arg(key).arg(v).query_async(conn).await?;
         }
         Ok(())
     }
@@ -217,7 +232,12 @@ impl NodeInfo {
         redis::cmd("HGETALL").arg(&k).query_async::<MyOption<Self>>(conn).await.map(Into::into)
     }
 
-    pub async fn register_to_redis<C: redis::aio::ConnectionLike>(node: DeviceLoraNodeModel, device: DevicesModel, conn: &mut C) -> redis::RedisResult<Self> {
+    /// Registers the node, resolving its settings from `profile` and the node overrides.
+    pub async fn register_to_redis<C: redis::aio::ConnectionLike>(node: DeviceLoraNodeModel, device: DevicesModel, profile: Option<DeviceProfileModel>, conn: &mut C) -> redis::RedisResult<Self> {
+        let (node, script, fcnt_policy) = match profile {
+            Some(profile) => (node.apply_profile(&profile), device.script.or(profile.script), Some(profile.fcnt_policy)),
+            None => (node, device.script, None)
+        };
         let node_info = NodeInfo {
             device_id: node.device_id,
             region: node.region,
@@ -257,11 +277,94 @@ impl NodeInfo {
             net_id: node.net_id,
             enable: device.enable,
             online: device.online,
-            script: device.script,
+            script,
+            script_version: device.script.and(device.script_version),
             active_time: device.active_time,
             gateway: None,
+            profile: node.profile,
+            fcnt_policy,
+            fport_routes: (!node.fport_routes.is_empty()).then_some(node.fport_routes),
+            payload_encryption: Some(node.payload_encryption),
+            app_kek: node.app_kek,
+            variables: (!device.variables.is_empty()).then_some(device.variables),
+            codec: device.codec,
         };
         node_info.register(node.dev_eui, node.dev_addr, conn).await?;
         Ok(node_info)
     }
-}
\ No newline at end of file
+
+    /// Rewrites the profile managed fields of a registered node, counters and session state are kept.
+    #[instrument(skip_all)]
+    pub async fn sync_profile<C: redis::aio::ConnectionLike>(
+        node: DeviceLoraNodeModel,
+        device: &DevicesModel,
+        profile: Option<&DeviceProfileModel>,
+        conn: &mut C,
+    ) -> redis::RedisResult<()> {
+        let k = Self::addr_key(node.dev_addr);
+        if !redis::cmd("EXISTS").arg(&k).query_async::<bool>(conn).await? {
+            return Ok(())
+        }
+        let (node, script, fcnt_policy) = match profile {
+            Some(profile) => (node.apply_profile(profile), device.script.or(profile.script), Some(profile.fcnt_policy)),
+            None => (node, device.script, None)
+        };
+        let mut cmd = redis::cmd("HSET");
+        cmd.arg(&k)
+            .arg(Self::region()).arg(node.region)
+            .arg(Self::class_b()).arg(node.class_b)
+            .arg(Self::class_c()).arg(node.class_c)
+            .arg(Self::adr()).arg(node.adr)
+            .arg(Self::rx1_delay()).arg(node.rx1_delay)
+            .arg(Self::des_rx1_delay()).arg(node.des_rx1_delay)
+            .arg(Self::rx1_dro()).arg(node.rx1_dro)
+            .arg(Self::des_rx1_dro()).arg(node.des_rx1_dro)
+            .arg(Self::rx2_dr()).arg(node.rx2_dr)
+            .arg(Self::des_rx2_dr()).arg(node.des_rx2_dr)
+            .arg(Self::rx2_freq()).arg(node.rx2_freq)
+            .arg(Self::des_rx2_freq()).arg(node.des_rx2_freq)
+            .arg(Self::dutycyle()).arg(node.dutycyle)
+            .arg(Self::d_retry()).arg(node.d_retry)
+            .arg(Self::c_retry()).arg(node.c_retry);
+        if let Some(profile) = node.profile {
+            cmd.arg(Self::profile()).arg(profile);
+        }
+        if let Some(fcnt_policy) = fcnt_policy {
+            cmd.arg(Self::fcnt_policy()).arg(fcnt_policy);
+        }
+        if let Some(script) = script {
+            cmd.arg(Self::script()).arg(script);
+        }
+        let script_version = device.script.and(device.script_version);
+        if let Some(version) = script_version {
+            cmd.arg(Self::script_version()).arg(version);
+        }
+        if !node.fport_routes.is_empty() {
+            cmd.arg(Self::fport_routes()).arg(&node.fport_routes);
+        }
+        let _: () = cmd.query_async(conn).await?;
+        let mut del = redis::cmd("HDEL");
+        del.arg(&k);
+        let mut stale = false;
+        if node.profile.is_none() {
+            del.arg(Self::profile()).arg(Self::fcnt_policy());
+            stale = true;
+        }
+        if script.is_none() {
+            del.arg(Self::script());
+            stale = true;
+        }
+        if script_version.is_none() {
+            del.arg(Self::script_version());
+            stale = true;
+        }
+        if node.fport_routes.is_empty() {
+            del.arg(Self::fport_routes());
+            stale = true;
+        }
+        if stale {
+            let _: () = del.query_async(conn).await?;
+        }
+        Ok(())
+    }
+}
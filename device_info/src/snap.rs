@@ -1,7 +1,8 @@
 use derive_new::new;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
-use common_define::db::{Eui, Key};
+use common_define::db::{CodecVariables, Eui, Key};
+use common_define::codec::PayloadCodec;
 use common_define::Id;
 use common_define::time::Timestamp;
 use hash_name::{HashNames, RedisOps};
@@ -15,7 +16,13 @@ pub struct SnapDeviceInfo {
     pub up_count: u32,
     pub down: Option<String>,
     pub script: Option<Id>,
-    pub freq: Option<f32>
+    pub freq: Option<f32>,
+    #[new(default)]
+    pub variables: Option<CodecVariables>,
+    #[new(default)]
+    pub codec: Option<PayloadCodec>,
+    #[new(default)]
+    pub script_version: Option<i32>,
 }
 
 impl SnapDeviceInfo {
@@ -41,7 +48,18 @@ impl SnapDeviceInfo {
     ) -> redis::RedisResult<()> {
         let k = Self::eui_key(eui);
         if redis::Cmd::exists(&k).query_async(conn).await? {
-            redis::cmd("HSET").arg(&k).arg(key).arg(v).query_async(conn).await?;
+            let _: () = redis::cmd("HSET").arg(&k).arg(key).arg(v).query_async(conn).await?;
+        }
+        Ok(())
+    }
+    pub async fn reset_by_eui<C: redis::aio::ConnectionLike>(
+        eui: Eui,
+        key: &str,
+        conn: &mut C,
+    ) -> redis::RedisResult<()> {
+        let k = Self::eui_key(eui);
+        if redis::Cmd::exists(&k).query_async(conn).await? {
+            let _: () = redis::cmd("HDEL").arg(&k).arg(key).query_async(conn).await?;
         }
         Ok(())
     }
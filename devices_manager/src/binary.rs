@@ -0,0 +1,119 @@
+use common_define::binary::{BinaryDecoder, BinaryField, BinaryType, Endian};
+use common_define::decode::Value;
+use crate::decode::{DecodeData, DecodeDataItem};
+
+/// Runs a declarative decoder, fields the payload can not supply are reported as warnings.
+/// Payloads on other ports decode to nothing.
+pub(crate) fn decode(decoder: &BinaryDecoder, bytes: &[u8], f_port: u8) -> DecodeData {
+    let mut data = DecodeData::default();
+    if !decoder.f_ports.is_empty() && !decoder.f_ports.contains(&f_port) {
+        return data
+    }
+    if let Some(min) = decoder.min_len.filter(|min| bytes.len() < *min) {
+        data.errors.push(format!("payload length {} is below {}", bytes.len(), min));
+        return data
+    }
+    if let Some(max) = decoder.max_len.filter(|max| bytes.len() > *max) {
+        data.errors.push(format!("payload length {} is above {}", bytes.len(), max));
+        return data
+    }
+    for field in &decoder.fields {
+        if field.f_port.is_some_and(|port| port != f_port) {
+            continue
+        }
+        match read(field, bytes) {
+            Ok(v) => data.data.push(DecodeDataItem { v, i: field.id as i32 }),
+            Err(e) => data.warnings.push(format!("{}: {}", field.name, e)),
+        }
+    }
+    data
+}
+
+fn read(field: &BinaryField, bytes: &[u8]) -> Result<Value, String> {
+    let size = match field.t {
+        BinaryType::Bcd => field.len.unwrap_or(1),
+        t => t.size(),
+    };
+    let raw = field.offset.checked_add(size)
+        .and_then(|end| bytes.get(field.offset..end))
+        .ok_or_else(|| format!("{} bytes at {} are out of the payload", size, field.offset))?;
+    let mut u = 0u64;
+    match field.endian {
+        Endian::Big => raw.iter().for_each(|b| u = u << 8 | *b as u64),
+        Endian::Little => raw.iter().rev().for_each(|b| u = u << 8 | *b as u64),
+    }
+    let raw = match field.t {
+        BinaryType::F32 => return Ok(scale_float(field, f32::from_bits(u as u32) as f64)),
+        BinaryType::F64 => return Ok(scale_float(field, f64::from_bits(u))),
+        BinaryType::Bcd => {
+            let mut v = 0i64;
+            for b in raw {
+                let (high, low) = (b >> 4, b & 0x0F);
+                if high > 9 || low > 9 {
+                    return Err(format!("{:02X} is not a BCD byte", b));
+                }
+                v = v * 100 + (high * 10 + low) as i64;
+            }
+            v
+        }
+        BinaryType::I8 | BinaryType::I16 | BinaryType::I24 | BinaryType::I32 | BinaryType::I64 => {
+            let shift = 64 - size as u32 * 8;
+            ((u << shift) as i64) >> shift
+        }
+        _ => {
+            if let Some(bits) = field.bits {
+                u = (u >> bits.start) & (u64::MAX >> (64 - bits.len as u32));
+            }
+            if field.t == BinaryType::Bool {
+                return Ok(Value::Bool(u != 0));
+            }
+            match i64::try_from(u) {
+                Ok(v) => v,
+                Err(_) => return Ok(scale_float(field, u as f64)),
+            }
+        }
+    };
+    if field.scale == 1.0 && field.add == 0.0 {
+        Ok(Value::Int(raw))
+    } else {
+        Ok(scale_float(field, raw as f64))
+    }
+}
+
+fn scale_float(field: &BinaryField, v: f64) -> Value {
+    Value::Float(v * field.scale + field.add)
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::binary::BinaryDecoder;
+    use common_define::decode::Value;
+    use super::decode;
+
+    #[test]
+    fn test_binary_decode() {
+        let decoder = BinaryDecoder::parse(r#"
+f_ports: [2]
+min_len: 4
+fields:
+  - { id: 1, name: temperature, offset: 0, type: I16, scale: 0.1 }
+  - { id: 2, name: alarm, offset: 2, type: Bool, bits: { start: 7, len: 1 } }
+  - { id: 3, name: mode, offset: 2, type: Bitfield, bits: { start: 0, len: 4 } }
+  - { id: 4, name: battery, offset: 3, type: U16, endian: Little }
+  - { id: 5, name: serial, offset: 5, type: Bcd, len: 2 }
+  - { id: 6, name: extra, offset: 7, type: U8 }
+"#).unwrap();
+        let data = decode(&decoder, &[0xFF, 0x38, 0x83, 0xE4, 0x0C, 0x12, 0x34], 2);
+        let values: Vec<_> = data.data.iter().map(|it| (it.i, it.v.clone())).collect();
+        assert_eq!(values, vec![
+            (1, Value::Float(-20.0)),
+            (2, Value::Bool(true)),
+            (3, Value::Int(3)),
+            (4, Value::Int(3300)),
+            (5, Value::Int(1234)),
+        ]);
+        assert_eq!(data.warnings.len(), 1);
+        assert!(decode(&decoder, &[0xFF, 0x38, 0x83, 0xE4], 3).data.is_empty());
+        assert_eq!(decode(&decoder, &[0xFF], 2).errors.len(), 1);
+    }
+}
@@ -1,10 +1,14 @@
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use derive_new::new;
 use rquickjs::CatchResultExt;
-use common_define::db::DbDecodeData;
+use tokio::sync::oneshot;
+use tracing::{debug, error};
+use common_define::db::{CodecFlatten, CodecVariables, DbDecodeData, DecodeMap};
+use common_define::decode::DecodeDataType;
+use common_define::time::Timestamp;
 use crate::man::data::{DataError, ValueType};
 
 fn check_data_length(bytes: &[u8]) -> Result<(), DataError> {
@@ -276,22 +280,79 @@ pub(crate) fn up_data_decode(bytes: &[u8]) -> Result<DecodeDataDecoded, DataErro
 
 #[derive(Debug)]
 pub struct RawData {
-    bytes: Vec<u8>
+    bytes: Vec<u8>,
+    f_port: u8,
+    variables: Option<CodecVariables>,
+    recv_time: Timestamp,
 }
 
 impl RawData {
     pub fn new<B: Into<Vec<u8>>>(bytes: B) -> Self {
         Self {
-            bytes: bytes.into()
+            bytes: bytes.into(),
+            f_port: 0,
+            variables: None,
+            recv_time: Timestamp::now(),
         }
     }
+
+    /// Sets the FPort and device variables passed to TTN codecs.
+    pub fn with_codec(mut self, f_port: u8, variables: Option<CodecVariables>) -> Self {
+        self.f_port = f_port;
+        self.variables = variables;
+        self
+    }
+
+    /// Time of the uplink, now unless the payload was stored earlier.
+    pub fn with_recv_time(mut self, recv_time: Timestamp) -> Self {
+        self.recv_time = recv_time;
+        self
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn f_port(&self) -> u8 {
+        self.f_port
+    }
 }
 
 const JS_FUNCTION_NAME: &str = "decodeUplink";
+const JS_ENCODE_NAME: &str = "encodeDownlink";
+const JS_DECODE_DOWN_NAME: &str = "decodeDownlink";
+/// Export of the codec shim, holds the codec functions whether the script exports them or not.
+const CODEC_EXPORT: &str = "__codec";
 
-#[derive(Debug, serde::Serialize)]
+/// Vendor codecs declare their functions without `export`, the appended export takes them
+/// from the module scope so the script runs unchanged.
+fn codec_shim(code: &str) -> String {
+    let functions = [JS_FUNCTION_NAME, JS_ENCODE_NAME, JS_DECODE_DOWN_NAME]
+        .map(|f| format!("{f}: typeof {f} === \"function\" ? {f} : undefined"))
+        .join(", ");
+    format!("{code}\n;export const {CODEC_EXPORT} = {{ {functions} }};\n")
+}
+
+/// Function of a codec, taken from the shim export or else from the module exports.
+fn codec_function<'js>(m: &rquickjs::Module<'js>, function: &str) -> Result<rquickjs::Function<'js>, JsDecodeError> {
+    let f = match m.get::<_, rquickjs::Object>(CODEC_EXPORT) {
+        Ok(codec) => codec.get(function),
+        Err(_) => m.get(function),
+    };
+    f.map_err(|_| JsDecodeError::Export(format!("most export {}", function)))
+}
+
+#[derive(Debug, Default, serde::Serialize)]
 pub struct DecodeData {
-    pub data: Vec<DecodeDataItem>
+    pub data: Vec<DecodeDataItem>,
+    /// Reported by TTN codecs, kept as device events.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Bytes the JS runtime allocated for the call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<usize>,
 }
 
 impl From<DecodeData> for DbDecodeData {
@@ -302,7 +363,7 @@ impl From<DecodeData> for DbDecodeData {
     }
 }
 
-fn js_to_serde_value(data: &rquickjs::Value) -> Option<common_define::decode::Value> {
+fn js_to_serde_value<'js>(ctx: rquickjs::Ctx<'js>, data: &rquickjs::Value<'js>) -> Option<common_define::decode::Value> {
     if let Some(b) = data.as_bool() {
         return Some(common_define::decode::Value::from(b))
     };
@@ -312,6 +373,13 @@ fn js_to_serde_value(data: &rquickjs::Value) -> Option<common_define::decode::Va
     if let Some(b) = data.as_float() {
         return Some(common_define::decode::Value::from(b))
     };
+    if let Some(b) = data.as_string() {
+        return b.to_string().ok().map(common_define::decode::Value::from)
+    };
+    if data.is_array() || data.is_object() {
+        let json = ctx.json_stringify(data.clone()).ok()??.to_string().ok()?;
+        return serde_json::from_str(&json).ok()
+    }
     None
 }
 
@@ -321,13 +389,13 @@ pub struct DecodeDataItem {
     pub i: i32
 }
 impl<'js> rquickjs::FromJs<'js> for DecodeDataItem {
-    fn from_js(_ctx: rquickjs::Ctx<'js>, value: rquickjs::Value<'js>) -> rquickjs::Result<Self> {
+    fn from_js(ctx: rquickjs::Ctx<'js>, value: rquickjs::Value<'js>) -> rquickjs::Result<Self> {
         let obj = rquickjs::Object::from_value(value)?;
         let data: rquickjs::Value = obj.get("data")?;
-        let data = js_to_serde_value(&data).ok_or(rquickjs::Error::FromJs {
+        let data = js_to_serde_value(ctx, &data).ok_or(rquickjs::Error::FromJs {
             from: "data",
-            to: "number or bool",
-            message: Some(format!("data: {:?}, not a number or bool", data)),
+            to: "value",
+            message: Some(format!("data: {:?}, not a number, bool, string, array or object", data)),
         })?;
         let id: i32 = obj.get("id").map_err(|_| rquickjs::Error::FromJs {
             from: "",
@@ -350,51 +418,156 @@ impl<'js> rquickjs::FromJs<'js> for DecodeData {
             })?;
         let data: Vec<DecodeDataItem> = obj.get("data")?;
         Ok(Self {
-            data
+            data,
+            ..Default::default()
         })
     }
 }
 
-impl<'js> rquickjs::IntoJs<'js> for RawData {
-    fn into_js(self, ctx: rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
-        rquickjs::Object::new(ctx)
-            .and_then(|obj| {
-                obj.prop("bytes", self.bytes)
-                    .map(|_| obj)
-            }).into_js(ctx)
-    }
+/// Object returned by `decodeUplink` of a TTN / ChirpStack codec.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct CodecOutput {
+    #[serde(default)]
+    pub data: serde_json::Value,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    /// Bytes the JS runtime allocated for the call.
+    #[serde(skip)]
+    pub memory: Option<usize>,
 }
 
+impl CodecOutput {
+    /// Flattens `data` into named values and keeps the ones with a data point in `map`.
+    pub fn into_decode_data(self, flatten: &CodecFlatten, map: &DecodeMap) -> DecodeData {
+        let mut warnings = self.warnings;
+        let mut values = Vec::new();
+        flatten_value(None, &self.data, flatten, map, &mut values, &mut warnings);
+        let mut data = Vec::new();
+        for (name, value) in values {
+            let Some(item) = map.iter().find(|it| it.name == name) else {
+                debug!("codec value {} has no data point", name);
+                continue;
+            };
+            let v = match item.t {
+                DecodeDataType::I32 => value.as_i64().map(common_define::decode::Value::Int),
+                DecodeDataType::F64 => value.as_f64().map(common_define::decode::Value::Float),
+                DecodeDataType::Bool => value.as_bool().map(common_define::decode::Value::Bool),
+                DecodeDataType::String => value.as_str().map(common_define::decode::Value::from),
+                _ => common_define::decode::Value::from_json(value.clone())
+                    .filter(|v| v.data_type() == item.t),
+            };
+            match v {
+                Some(v) => data.push(DecodeDataItem { v, i: item.id as i32 }),
+                None => warnings.push(format!("{} is not a {}", name, item.t.as_ref())),
+            }
+        }
+        DecodeData {
+            data,
+            warnings,
+            errors: self.errors,
+            memory: self.memory,
+        }
+    }
+}
 
-struct JsContext {
-    hand: tokio::task::JoinHandle<()>,
-    ctx: rquickjs::Context
+/// Object returned by `encodeDownlink`.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct CodecEncoded {
+    #[serde(default)]
+    pub bytes: Vec<u8>,
+    #[serde(default, rename = "fPort")]
+    pub f_port: Option<u8>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub errors: Vec<String>,
 }
 
-impl Drop for JsContext {
-    fn drop(&mut self) {
-        self.hand.abort();
+/// Collects the scalar values under `value`, nested keys are joined with the separator.
+/// Arrays and objects mapped to a nested data point are kept whole.
+fn flatten_value<'a>(
+    name: Option<&str>,
+    value: &'a serde_json::Value,
+    flatten: &CodecFlatten,
+    map: &DecodeMap,
+    values: &mut Vec<(String, &'a serde_json::Value)>,
+    warnings: &mut Vec<String>,
+) {
+    if let Some(name) = name {
+        if map.iter().any(|it| it.name == name && it.t.is_nested()) {
+            values.push((name.to_string(), value));
+            return
+        }
+    }
+    let children: Vec<(String, &serde_json::Value)> = match value {
+        serde_json::Value::Object(obj) => obj.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        serde_json::Value::Array(arr) => arr.iter().enumerate().map(|(i, v)| (i.to_string(), v)).collect(),
+        serde_json::Value::Null => return,
+        _ => {
+            if let Some(name) = name {
+                values.push((name.to_string(), value));
+            }
+            return
+        }
+    };
+    let depth = name.map(|name| name.split(flatten.separator.as_str()).count()).unwrap_or(0);
+    if depth >= flatten.depth as usize {
+        warnings.push(format!("{} is nested deeper than {}", name.unwrap_or_default(), flatten.depth));
+        return
+    }
+    for (key, child) in children {
+        let key = match name {
+            Some(name) => format!("{}{}{}", name, flatten.separator, key),
+            None => key,
+        };
+        flatten_value(Some(&key), child, flatten, map, values, warnings);
     }
 }
 
-struct JsManagerInner {
-    runtime: rquickjs::Runtime,
-    notify: Arc<AtomicBool>,
-    timeout: u64,
+/// Argument of a TTN codec function, `recvTime` is passed as a `Date`.
+struct CodecInput {
+    value: serde_json::Value,
+    recv_time: Option<Timestamp>,
 }
 
-pub struct JsManager {
-    inner: Arc<JsManagerInner>
+impl From<RawData> for CodecInput {
+    fn from(data: RawData) -> Self {
+        Self {
+            value: serde_json::json!({
+                "bytes": data.bytes,
+                "fPort": data.f_port,
+                "variables": data.variables.unwrap_or_default(),
+            }),
+            recv_time: Some(data.recv_time),
+        }
+    }
 }
 
-impl Clone for JsManager {
-    fn clone(&self) -> Self {
-        Self {
-            inner: Arc::clone(&self.inner)
+impl<'js> rquickjs::IntoJs<'js> for CodecInput {
+    fn into_js(self, ctx: rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
+        let value = ctx.json_parse(self.value.to_string())?;
+        if let (Some(obj), Some(recv_time)) = (value.as_object(), self.recv_time) {
+            let date: rquickjs::Function = ctx.globals().get("Date")?;
+            let date: rquickjs::Value = date.construct((recv_time.timestamp_millis() as f64,))?;
+            obj.set("recvTime", date)?;
         }
+        Ok(value)
     }
 }
 
+impl<'js> rquickjs::IntoJs<'js> for RawData {
+    fn into_js(self, ctx: rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
+        rquickjs::Object::new(ctx)
+            .and_then(|obj| {
+                obj.prop("bytes", self.bytes)
+                    .map(|_| obj)
+            }).into_js(ctx)
+    }
+}
+
+
 #[derive(Debug)]
 pub enum JsDecodeError {
     Unknown(String),
@@ -403,6 +576,7 @@ pub enum JsDecodeError {
     },
     Return(String),
     Export(String),
+    Memory,
 }
 
 impl From<rquickjs::Error> for JsDecodeError {
@@ -433,88 +607,277 @@ impl<'js> From<rquickjs::CaughtError<'js>> for JsDecodeError {
                             Self::TimeOut {
                                 stack: ex.stack()
                             }
+                        } else if s == "out of memory" {
+                            Self::Memory
                         } else {
                             Self::Unknown(s)
                         }
                     }
                 }
             }
+            // QuickJS throws null when not even the out of memory error can be allocated
+            rquickjs::CaughtError::Value(v) if v.is_null() => Self::Memory,
             rquickjs::CaughtError::Value(v) => {
                 Self::Unknown(format!("value {:?}", v))
             }
         }
     }
 }
-impl JsManager {
-    pub fn new() -> Self {
-        let runtime = rquickjs::Runtime::new().unwrap();
-        runtime.set_memory_limit(2_000_000);
-        let timeout = Arc::new(AtomicBool::new(false));
-        let notify = timeout.clone();
+/// Limits of the decoder runtimes.
+#[derive(Debug, Clone, Copy)]
+pub struct JsLimits {
+    pub workers: usize,
+    /// Time one compile or decode may run.
+    pub timeout: Duration,
+    /// Memory limit of each runtime.
+    pub memory: usize,
+}
+
+impl Default for JsLimits {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            timeout: Duration::from_millis(1000),
+            memory: 2_000_000,
+        }
+    }
+}
+
+enum JsJob {
+    Compile {
+        code: String,
+        tx: oneshot::Sender<Result<Vec<u8>, JsDecodeError>>,
+    },
+    Eval {
+        module: Arc<Vec<u8>>,
+        data: RawData,
+        tx: oneshot::Sender<Result<DecodeData, JsDecodeError>>,
+    },
+    Codec {
+        module: Arc<Vec<u8>>,
+        function: &'static str,
+        input: CodecInput,
+        tx: oneshot::Sender<Result<(serde_json::Value, usize), JsDecodeError>>,
+    },
+}
+
+/// A runtime owned by one blocking thread, with its own interrupt deadline and memory limit.
+struct JsWorker {
+    runtime: rquickjs::Runtime,
+    start: Instant,
+    /// Milliseconds since `start` after which the running script is interrupted.
+    deadline: Arc<AtomicU64>,
+    timeout: u64,
+    /// Bytes one job may allocate on top of what the runtime holds already.
+    memory: usize,
+}
+
+impl JsWorker {
+    fn new(limits: JsLimits) -> Result<Self, JsDecodeError> {
+        let runtime = rquickjs::Runtime::new()?;
+        runtime.set_memory_limit(limits.memory);
+        let start = Instant::now();
+        let deadline = Arc::new(AtomicU64::new(u64::MAX));
+        let interrupt = deadline.clone();
         runtime.set_interrupt_handler(
             Some(
                 Box::new(move || {
-                    timeout.swap(false, Ordering::Relaxed)
+                    start.elapsed().as_millis() as u64 > interrupt.load(Ordering::Relaxed)
                 })
             )
         );
-        Self {
-            inner: Arc::new(JsManagerInner {
-                runtime,
-                notify,
-                timeout: 1000
-            })
-        }
+        Ok(Self {
+            runtime,
+            start,
+            deadline,
+            timeout: limits.timeout.as_millis() as u64,
+            memory: limits.memory,
+        })
     }
 
-    fn ctx(&self) -> Result<JsContext, JsDecodeError> {
-        let ctx = rquickjs::Context::full(&self.inner.runtime)?;
-        let notify = self.inner.notify.clone();
-        let timeout = self.inner.timeout;
-        Ok(JsContext {
-            hand: tokio::spawn(async move {
-                tokio::time::sleep(Duration::from_millis(timeout)).await;
-                let s = notify.load(Ordering::Relaxed);
-                if !s {
-                    notify.store(true, Ordering::Relaxed)
+    fn run(self, jobs: Arc<Mutex<mpsc::Receiver<JsJob>>>) {
+        loop {
+            let job = match jobs.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            self.deadline.store(self.start.elapsed().as_millis() as u64 + self.timeout, Ordering::Relaxed);
+            match job {
+                JsJob::Compile { code, tx } => {
+                    let _ = tx.send(self.compile(&code));
                 }
-            }),
-            ctx,
-        })
+                JsJob::Eval { module, data, tx } => {
+                    let _ = tx.send(self.eval(&module, data));
+                }
+                JsJob::Codec { module, function, input, tx } => {
+                    let _ = tx.send(self.codec(&module, function, input));
+                }
+            }
+            self.deadline.store(u64::MAX, Ordering::Relaxed);
+            self.runtime.run_gc();
+        }
     }
 
-    pub fn test(&self, code: &str, data: RawData) -> Result<DecodeData, JsDecodeError> {
-        let module = self.compile(code)?;
-        self.eval(&module, data)
+    /// Runs `f` with the memory limit of one job, returns the bytes it allocated.
+    fn limited<T>(&self, f: impl FnOnce() -> Result<T, JsDecodeError>) -> (Result<T, JsDecodeError>, usize) {
+        let before = self.runtime.memory_usage().malloc_size.max(0) as usize;
+        self.runtime.set_memory_limit(before.saturating_add(self.memory));
+        let r = f();
+        let after = self.runtime.memory_usage().malloc_size.max(0) as usize;
+        (r, after.saturating_sub(before))
     }
-    pub fn eval(&self, module: &[u8], data: RawData) -> Result<DecodeData, JsDecodeError> {
-        let ctx = self.ctx()?;
-        ctx.ctx.with(|ctx| {
-            let m = rquickjs::Module::instantiate_read_object(ctx, module).catch(ctx)?;
-            let f: rquickjs::Function = m.get(JS_FUNCTION_NAME).map_err(|_|
-                JsDecodeError::Export(format!("most export {}", JS_FUNCTION_NAME))
-            )?;
-            let data: DecodeData = f.call((data,)).catch(ctx)?;
-            Ok(data)
-        })
+
+    fn compile(&self, code: &str) -> Result<Vec<u8>, JsDecodeError> {
+        self.limited(|| self.compile_script(code)).0
     }
 
-    pub fn compile(&self ,code: &str) -> Result<Vec<u8>, JsDecodeError> {
-        let ctx = self.ctx()?;
-        let r: Result<Vec<u8>, JsDecodeError> = ctx.ctx.with(|ctx| {
-            let b = unsafe { rquickjs::Module::unsafe_declare( ctx,"script", code) }.catch(ctx)?;
+    fn compile_script(&self, code: &str) -> Result<Vec<u8>, JsDecodeError> {
+        let ctx = rquickjs::Context::full(&self.runtime)?;
+        ctx.with(|ctx| {
+            let b = unsafe { rquickjs::Module::unsafe_declare(ctx, "script", code) }.catch(ctx)?;
             let byte = b.write_object(false)?;
             Ok(byte)
+        })
+    }
+
+    fn eval(&self, module: &[u8], data: RawData) -> Result<DecodeData, JsDecodeError> {
+        let (r, used) = self.limited(|| {
+            let ctx = rquickjs::Context::full(&self.runtime)?;
+            ctx.with(|ctx| {
+                let m = rquickjs::Module::instantiate_read_object(ctx, module).catch(ctx)?;
+                let f: rquickjs::Function = m.get(JS_FUNCTION_NAME).map_err(|_|
+                    JsDecodeError::Export(format!("most export {}", JS_FUNCTION_NAME))
+                )?;
+                let data: DecodeData = f.call((data,)).catch(ctx)?;
+                Ok(data)
+            })
+        });
+        debug!(used, "js decode memory");
+        r.map(|data| DecodeData { memory: Some(used), ..data })
+    }
+
+    /// Calls a TTN codec function, the returned object is taken over as JSON.
+    fn codec(&self, module: &[u8], function: &str, input: CodecInput) -> Result<(serde_json::Value, usize), JsDecodeError> {
+        let (r, used) = self.limited(|| {
+            let ctx = rquickjs::Context::full(&self.runtime)?;
+            ctx.with(|ctx| {
+                let m = rquickjs::Module::instantiate_read_object(ctx, module).catch(ctx)?;
+                let f = codec_function(&m, function)?;
+                let output: rquickjs::Value = f.call((input,)).catch(ctx)?;
+                let output = ctx.json_stringify(output)?
+                    .ok_or_else(|| JsDecodeError::Return(format!("{} most return a Object", function)))?
+                    .to_string()?;
+                serde_json::from_str(&output).map_err(|e| JsDecodeError::Return(e.to_string()))
+            })
         });
-        r
+        debug!(used, function, "js codec memory");
+        r.map(|output| (output, used))
+    }
+}
+
+/// Pool of QuickJS runtimes on dedicated threads, a slow script only holds up its own runtime.
+#[derive(Clone)]
+pub struct JsManager {
+    tx: mpsc::Sender<JsJob>,
+}
+
+impl JsManager {
+    pub fn new(limits: JsLimits) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..limits.workers.max(1) {
+            let rx = rx.clone();
+            std::thread::Builder::new()
+                .name(format!("js-decode-{}", i))
+                .spawn(move || {
+                    match JsWorker::new(limits) {
+                        Ok(worker) => worker.run(rx),
+                        Err(e) => error!("js runtime: {:?}", e),
+                    }
+                })
+                .unwrap();
+        }
+        Self { tx }
+    }
+
+    pub async fn test(&self, code: &str, data: RawData) -> Result<DecodeData, JsDecodeError> {
+        let module = self.compile(code).await?;
+        self.eval(Arc::new(module), data).await
+    }
+
+    pub async fn eval(&self, module: Arc<Vec<u8>>, data: RawData) -> Result<DecodeData, JsDecodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(JsJob::Eval { module, data, tx })?;
+        rx.await.map_err(|_| JsDecodeError::Unknown("js runtime closed".to_string()))?
+    }
+
+    /// Runs `decodeUplink` of a TTN / ChirpStack codec.
+    pub async fn decode_uplink(&self, module: Arc<Vec<u8>>, data: RawData) -> Result<CodecOutput, JsDecodeError> {
+        let (output, used) = self.codec(module, JS_FUNCTION_NAME, data.into()).await?;
+        let output: CodecOutput = serde_json::from_value(output).map_err(|e| JsDecodeError::Return(e.to_string()))?;
+        Ok(CodecOutput { memory: Some(used), ..output })
+    }
+
+    /// Runs `encodeDownlink`, `data` is the command sent by the user.
+    pub async fn encode_downlink(&self, module: Arc<Vec<u8>>, data: serde_json::Value, variables: Option<CodecVariables>) -> Result<CodecEncoded, JsDecodeError> {
+        let input = CodecInput {
+            value: serde_json::json!({
+                "data": data,
+                "variables": variables.unwrap_or_default(),
+            }),
+            recv_time: None,
+        };
+        let (output, _) = self.codec(module, JS_ENCODE_NAME, input).await?;
+        serde_json::from_value(output).map_err(|e| JsDecodeError::Return(e.to_string()))
+    }
+
+    /// Runs `decodeDownlink` on the bytes of a downlink, `None` when the codec has no such function.
+    pub async fn decode_downlink(&self, module: Arc<Vec<u8>>, bytes: &[u8], f_port: Option<u8>, variables: Option<CodecVariables>) -> Result<Option<CodecOutput>, JsDecodeError> {
+        let input = CodecInput {
+            value: serde_json::json!({
+                "bytes": bytes,
+                "fPort": f_port,
+                "variables": variables.unwrap_or_default(),
+            }),
+            recv_time: None,
+        };
+        let (output, used) = match self.codec(module, JS_DECODE_DOWN_NAME, input).await {
+            Ok(output) => output,
+            Err(JsDecodeError::Export(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let output: CodecOutput = serde_json::from_value(output).map_err(|e| JsDecodeError::Return(e.to_string()))?;
+        Ok(Some(CodecOutput { memory: Some(used), ..output }))
+    }
+
+    async fn codec(&self, module: Arc<Vec<u8>>, function: &'static str, input: CodecInput) -> Result<(serde_json::Value, usize), JsDecodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(JsJob::Codec { module, function, input, tx })?;
+        rx.await.map_err(|_| JsDecodeError::Unknown("js runtime closed".to_string()))?
+    }
+
+    /// Compiles a TTN / ChirpStack codec, its functions do not have to be exported.
+    pub async fn compile_codec(&self, code: &str) -> Result<Vec<u8>, JsDecodeError> {
+        self.compile(&codec_shim(code)).await
+    }
+
+    pub async fn compile(&self, code: &str) -> Result<Vec<u8>, JsDecodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(JsJob::Compile { code: code.to_string(), tx })?;
+        rx.await.map_err(|_| JsDecodeError::Unknown("js runtime closed".to_string()))?
+    }
+
+    fn send(&self, job: JsJob) -> Result<(), JsDecodeError> {
+        self.tx.send(job).map_err(|_| JsDecodeError::Unknown("js runtime closed".to_string()))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use common_define::Id;
-    use crate::decode::{up_data_decode, JsManager, RawData};
-    use crate::man::DecodeManager;
+    use std::sync::Arc;
+    use common_define::db::{CodeMapItem, CodecFlatten, CodecVariables, DecodeMap};
+    use common_define::decode::{DecodeDataType, GeoPoint, Value};
+    use crate::decode::{up_data_decode, JsDecodeError, JsLimits, JsManager, RawData};
 
     #[test]
     fn test_decode() {
@@ -524,8 +887,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_js_decode() {
-        let rt = JsManager::new();
-        let m = DecodeManager::new(rt.clone());
+        let rt = JsManager::new(JsLimits::default());
         let s = r#"export function decodeUplink(data) {
   return {
     data: [
@@ -534,7 +896,118 @@ mod tests {
     ]
   }
 }"#;
-        let d = m.decode_with_code(Id::new(1), s, RawData::new([1,2,3])).unwrap();
+        let d = rt.test(s, RawData::new([1,2,3])).await.unwrap();
         println!("{:?}", d);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_js_timeout() {
+        let rt = JsManager::new(JsLimits { workers: 2, ..Default::default() });
+        let slow = rt.test("export function decodeUplink(data) { while (true) {} }", RawData::new([1]));
+        let fast = rt.test("export function decodeUplink(data) { return { data: [{ data: 1, id: 1 }] } }", RawData::new([1]));
+        let (slow, fast) = tokio::join!(slow, fast);
+        assert!(matches!(slow, Err(JsDecodeError::TimeOut { .. })));
+        assert_eq!(fast.unwrap().data.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_js_memory() {
+        let rt = JsManager::new(JsLimits { workers: 1, ..Default::default() });
+        let big = rt.test("export function decodeUplink(data) { let a = []; while (true) { a.push(new Array(1000).fill(1)); } }", RawData::new([1])).await;
+        assert!(matches!(big, Err(JsDecodeError::Memory)), "{:?}", big);
+        let small = rt.test("export function decodeUplink(data) { return { data: [{ data: 1, id: 1 }] } }", RawData::new([1])).await.unwrap();
+        assert!(small.memory.is_some_and(|used| used < JsLimits::default().memory));
+    }
+
+    #[tokio::test]
+    async fn test_ttn_codec() {
+        let rt = JsManager::new(JsLimits::default());
+        let s = r#"export function decodeUplink(input) {
+  return {
+    data: {
+      temperature: input.bytes[0] / 10,
+      battery: { level: input.bytes[1], low: input.bytes[1] < 20 },
+      port: input.fPort,
+      scale: Number(input.variables.scale),
+      year: input.recvTime.getUTCFullYear(),
+      deep: { a: { b: { c: 1 } } },
+      version: "1.0.2",
+      location: { lat: 31.2, lon: 121.5 },
+      readings: [1, 2, 3]
+    },
+    warnings: ["low battery"]
+  };
+}"#;
+        let module = Arc::new(rt.compile(s).await.unwrap());
+        let variables = CodecVariables([("scale".to_string(), "2".to_string())].into());
+        let output = rt.decode_uplink(module, RawData::new([215, 10]).with_codec(5, Some(variables))).await.unwrap();
+        let item = |id, name: &str, t| CodeMapItem { id, name: name.to_string(), unit: String::new(), t };
+        let map = DecodeMap(vec![
+            item(1, "temperature", DecodeDataType::F64),
+            item(2, "battery.level", DecodeDataType::I32),
+            item(3, "battery.low", DecodeDataType::Bool),
+            item(4, "port", DecodeDataType::I32),
+            item(5, "scale", DecodeDataType::I32),
+            item(6, "deep.a.b", DecodeDataType::I32),
+            item(7, "version", DecodeDataType::String),
+            item(8, "location", DecodeDataType::GeoPoint),
+            item(9, "readings", DecodeDataType::Array),
+        ]);
+        let data = output.into_decode_data(&CodecFlatten::default(), &map);
+        let values: Vec<_> = data.data.iter().map(|it| (it.i, it.v.clone())).collect();
+        assert!(values.contains(&(1, Value::Float(21.5))));
+        assert!(values.contains(&(2, Value::Int(10))));
+        assert!(values.contains(&(3, Value::Bool(true))));
+        assert!(values.contains(&(4, Value::Int(5))));
+        assert!(values.contains(&(5, Value::Int(2))));
+        assert!(values.contains(&(7, "1.0.2".into())));
+        assert!(values.contains(&(8, GeoPoint { lat: 31.2, lon: 121.5, alt: None, accuracy: None }.into())));
+        assert!(values.contains(&(9, Value::Array(vec![1u8.into(), 2u8.into(), 3u8.into()]))));
+        assert_eq!(values.len(), 8);
+        assert_eq!(data.warnings.len(), 2);
+        assert!(data.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ttn_codec_global() {
+        let rt = JsManager::new(JsLimits::default());
+        let s = r#"function decodeUplink(input) {
+  return { data: { temperature: input.bytes[0] } };
+}
+function encodeDownlink(input) {
+  return { bytes: [input.data.level], fPort: 4 };
+}
+function decodeDownlink(input) {
+  return { data: { level: input.bytes[0], port: input.fPort } };
+}"#;
+        let module = Arc::new(rt.compile_codec(s).await.unwrap());
+        let output = rt.decode_uplink(module.clone(), RawData::new([7]).with_codec(1, None)).await.unwrap();
+        assert_eq!(output.data, serde_json::json!({"temperature": 7}));
+        let encoded = rt.encode_downlink(module.clone(), serde_json::json!({"level": 3}), None).await.unwrap();
+        assert_eq!(encoded.bytes, vec![3]);
+        let decoded = rt.decode_downlink(module, &encoded.bytes, encoded.f_port, None).await.unwrap().unwrap();
+        assert_eq!(decoded.data, serde_json::json!({"level": 3, "port": 4}));
+
+        let exported = Arc::new(rt.compile_codec("export function decodeUplink(input) { return { data: { a: 1 } }; }").await.unwrap());
+        assert_eq!(rt.decode_uplink(exported.clone(), RawData::new([1])).await.unwrap().data, serde_json::json!({"a": 1}));
+        assert!(rt.decode_downlink(exported, &[1], None, None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_encode_downlink() {
+        let rt = JsManager::new(JsLimits::default());
+        let s = r#"export function encodeDownlink(input) {
+  if (input.data.valve !== "open" && input.data.valve !== "close") {
+    return { errors: ["valve must be open or close"] };
+  }
+  return { bytes: [input.data.valve === "open" ? 1 : 0, input.data.duration], fPort: 10 };
+}"#;
+        let module = Arc::new(rt.compile(s).await.unwrap());
+        let encoded = rt.encode_downlink(module.clone(), serde_json::json!({"valve": "open", "duration": 30}), None).await.unwrap();
+        assert_eq!(encoded.bytes, vec![1, 30]);
+        assert_eq!(encoded.f_port, Some(10));
+        let encoded = rt.encode_downlink(module, serde_json::json!({"valve": "half"}), None).await.unwrap();
+        assert!(encoded.bytes.is_empty());
+        assert_eq!(encoded.errors.len(), 1);
+    }
+}
@@ -1,6 +1,6 @@
 use lorawan::parser::DataHeader;
 use redis::AsyncCommands;
-use common_define::event::lora_gateway::{GatewayEventType, GatewaySource};
+use common_define::event::lora_gateway::{GatewayConflict, GatewayEventType, GatewaySource};
 use common_define::{lorawan_bridge, Id};
 use common_define::lorawan_bridge::{GatewayUpData};
 use common_define::time::Timestamp;
@@ -12,6 +12,34 @@ use crate::protocol::lora::parse::LoraPhy;
 
 pub struct GatewayEvent;
 impl GatewayEvent {
+    pub(crate) async fn gateway_conflict(
+        gateway_id: Id,
+        state: &GatewayUpData,
+        previous: String,
+        previous_time: Timestamp,
+    ) -> DeviceResult {
+        let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
+        let resp = common_define::event::DeviceEvent {
+            device: gateway_id,
+            event: common_define::event::DeviceEventType::Gateway(
+                common_define::event::lora_gateway::GatewayEvent {
+                    eui: state.eui,
+                    time: Timestamp::from_timestamp_millis(state.time.timestamp_millis()).unwrap_or(Timestamp::now()),
+                    source: GatewaySource {
+                        ip: state.source.ip.map(|a|a.to_string()),
+                    },
+                    gateway_event: GatewayEventType::Conflict(GatewayConflict {
+                        previous,
+                        previous_time,
+                    }),
+                }
+            )
+        };
+        let resp = serde_json::to_string(&resp)?;
+        let _: () = conn.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
+        Ok(())
+    }
+
     pub(crate) async fn gateway_state(
         gateway_id: Id,
         state: GatewayUpData,
@@ -44,7 +72,7 @@ impl GatewayEvent {
                     )
                 };
                 let resp = serde_json::to_string(&resp)?;
-                conn.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
+                let _: () = conn.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
                 return Ok(());
             }
             lorawan_bridge::GatewayEventType::PushData(datas) => {
@@ -96,7 +124,7 @@ impl GatewayEvent {
                             }
                         };
                         let resp = serde_json::to_string(&resp)?;
-                        conn.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
+                        let _: () = conn.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
                     }
                 }
             }
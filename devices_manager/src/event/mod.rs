@@ -9,7 +9,9 @@ use common_define::db::LoRaAddr;
 use common_define::event::lora_node::GatewayRxStatus;
 use device_info::lorawan::NodeInfo;
 use utils::base64::EncodeBase64;
+use common_define::Id;
 use crate::DeviceResult;
+use crate::decode::DecodeData;
 use crate::man::data::DownloadData;
 use crate::man::lora::{LoRaNode};
 use crate::protocol::lora::payload::LoRaPayload;
@@ -35,7 +37,7 @@ impl LoRaNodeEvent {
             }
         )};
         let resp = serde_json::to_string(&resp)?;
-        conn.publish(
+        let _: () = conn.publish(
             common_define::event::DeviceEvent::KAFKA_TOPIC,
              resp
         ).await?;
@@ -45,6 +47,7 @@ impl LoRaNodeEvent {
     pub(crate) async fn join_accept(
         addr: LoRaAddr,
         device: &NodeInfo,
+        app_s_key: Option<String>,
         conn: &mut redis::aio::MultiplexedConnection,
     ) -> DeviceResult {
         let resp = common_define::event::DeviceEvent {
@@ -52,11 +55,12 @@ impl LoRaNodeEvent {
             event: common_define::event::DeviceEventType::JoinAccept(
             common_define::event::lora_node::JoinAccept {
                 dev_addr: addr,
+                app_s_key,
                 time: chrono::Utc::now().timestamp_millis(),
             }
         )};
         let resp = serde_json::to_string(&resp)?;
-        conn.publish(
+        let _: () = conn.publish(
             common_define::event::DeviceEvent::KAFKA_TOPIC,
             resp
         ).await?;
@@ -67,7 +71,7 @@ impl LoRaNodeEvent {
         header: &LoRaPayload,
         device: &LoRaNode,
         gateway: &PushData,
-        data: &[u8],
+        data: Option<&[u8]>,
         conn: &mut redis::aio::MultiplexedConnection,
     ) -> DeviceResult {
         let gateway = GatewayRxStatus {
@@ -86,13 +90,14 @@ impl LoRaNodeEvent {
                 f_port: header.f_port().unwrap_or_default() as i32,
                 f_cnt: header.fhdr().fcnt() as _ ,
                 payload: Some(header.as_bytes().encode_base64()),
-                decoded_payload: Some(data.encode_base64()),
+                decoded_payload: data.map(|data| data.encode_base64()),
+                frm_payload: data.is_none().then(|| header.frm_payload_bytes().encode_base64()),
                 gateway,
                 time: chrono::Utc::now().timestamp_millis(),
             }
         )};
         let resp = serde_json::to_string(&resp)?;
-        conn.publish(
+        let _: () = conn.publish(
             common_define::event::DeviceEvent::KAFKA_TOPIC,
             resp
         ).await?;
@@ -116,7 +121,41 @@ impl LoRaNodeEvent {
             }
         )};
         let resp = serde_json::to_string(&resp)?;
-        conn.publish(
+        let _: () = conn.publish(
+            common_define::event::DeviceEvent::KAFKA_TOPIC,
+            resp
+        ).await?;
+        Ok(())
+    }
+}
+
+pub struct DecodeEvent;
+
+impl DecodeEvent {
+
+    /// Keeps the warnings and errors of a TTN codec, nothing is published when there are none.
+    pub(crate) async fn codec(
+        device: Id,
+        script: Id,
+        f_port: u8,
+        data: &DecodeData,
+        conn: &mut redis::aio::MultiplexedConnection,
+    ) -> DeviceResult {
+        if data.warnings.is_empty() && data.errors.is_empty() {
+            return Ok(())
+        }
+        let resp = common_define::event::DeviceEvent {
+            device,
+            event: common_define::event::DeviceEventType::Codec(
+            common_define::event::CodecEvent {
+                script,
+                f_port,
+                warnings: data.warnings.clone(),
+                errors: data.errors.clone(),
+            }
+        )};
+        let resp = serde_json::to_string(&resp)?;
+        let _: () = conn.publish(
             common_define::event::DeviceEvent::KAFKA_TOPIC,
             resp
         ).await?;
@@ -21,7 +21,7 @@ pub async fn publish_log(mut rx: tokio::sync::mpsc::Receiver<String>) {
 async fn _send(rx: &mut tokio::sync::mpsc::Receiver<String>) -> DeviceResult {
     let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
     while let Some(log) = rx.recv().await {
-        conn.publish(PlatformLog::TOPIC, log).await?;
+        let _: () = conn.publish(PlatformLog::TOPIC, log).await?;
     }
     Ok(())
 }
@@ -1,6 +1,7 @@
 use serde_json::Value;
 use common_define::db::{Eui, LoRaAddr};
 use crate::DeviceResult;
+use crate::man::mqtt::SnapPublisher;
 use crate::man::data::ValueType;
 use crate::man::Id;
 
@@ -66,6 +67,40 @@ impl MqttMessage {
         })
     }
 
+    pub(crate) async fn publish(self) -> DeviceResult {
+        SnapPublisher::publish(self.topic, self.message).await
+    }
+
+    pub(crate) fn new_port_data(data: &MqttPortData, topic: &str, qos: i32) -> DeviceResult<Self> {
+        let message = serde_json::to_string(data)?;
+        let topic = format!("/v1/device/{}/{}", data.device, topic.trim_start_matches('/'));
+        Ok(Self {
+            message,
+            topic,
+            qos,
+        })
+    }
+
+    pub(crate) fn new_encrypted_data(data: &MqttEncryptedData, qos: i32) -> DeviceResult<Self> {
+        let message = serde_json::to_string(data)?;
+        let topic = format!("/v1/device/{}/encrypted", data.device);
+        Ok(Self {
+            message,
+            topic,
+            qos,
+        })
+    }
+
+    pub(crate) fn new_join_data(data: &MqttJoinData, qos: i32) -> DeviceResult<Self> {
+        let message = serde_json::to_string(data)?;
+        let topic = format!("/v1/device/{}/join", data.device);
+        Ok(Self {
+            message,
+            topic,
+            qos,
+        })
+    }
+
     pub(crate) fn new_decode_group_data(data: &MqttDecodeData, group_id: Id, qos: i32) -> DeviceResult<Self> {
         let message = serde_json::to_string(data)?;
         let topic = format!("/v1/group/{}/decode", group_id);
@@ -101,6 +136,33 @@ pub(crate) struct MqttRawData {
     pub(crate) bytes: String
 }
 
+#[derive(serde::Serialize)]
+pub(crate) struct MqttPortData {
+    pub(crate) device: Id,
+    pub(crate) f_port: u8,
+    pub(crate) bytes: String,
+    pub(crate) data: Vec<common_define::decode::DecodeData>,
+}
+
+/// Uplink of a node with application side encryption, `frm_payload` is encrypted with the AppSKey.
+#[derive(serde::Serialize)]
+pub(crate) struct MqttEncryptedData {
+    pub(crate) device: Id,
+    pub(crate) dev_addr: LoRaAddr,
+    pub(crate) f_port: u8,
+    pub(crate) f_cnt: u32,
+    pub(crate) frm_payload: String,
+}
+
+/// Session hand-off after a join, `app_s_key` is wrapped with the application key-encryption key.
+#[derive(serde::Serialize)]
+pub(crate) struct MqttJoinData {
+    pub(crate) device: Id,
+    pub(crate) dev_eui: Eui,
+    pub(crate) dev_addr: LoRaAddr,
+    pub(crate) app_s_key: String,
+}
+
 #[derive(serde::Serialize)]
 pub(crate) struct MqttDecodeData {
     pub(crate) device: Id,
@@ -114,9 +176,9 @@ pub(crate) struct MqttDecodeData {
 
 #[derive(serde::Serialize)]
 pub(crate) struct MqttDataItem {
-    pub(crate) data: serde_json::Value,
+    pub(crate) data: common_define::decode::Value,
     pub(crate) data_id: i32,
-    pub(crate) v_type: ValueType,
+    pub(crate) v_type: common_define::decode::DecodeDataType,
     pub(crate) v_name: Option<String>,
     pub(crate) v_unit: Option<String>,
 }
\ No newline at end of file
@@ -4,13 +4,14 @@
 use man::data::DataError;
 use once_cell::sync::Lazy;
 use tracing::{info, warn};
-use common_define::event::DeviceEvent;
-use crate::decode::JsManager;
-use crate::load::{store_config, State};
-use crate::man::{DecodeManager, DownlinkManager, Id, MQ};
+use common_define::event::{CalibrationEvent, DeviceEvent, ReDecodeJob, ScriptCall, ScriptEvent, VirtualPointEvent};
+use crate::decode::{JsDecodeError, JsManager};
+use crate::load::{load_config, store_config, State};
+use crate::man::{Calibrations, DecodeManager, DownlinkManager, Id, MQ, VirtualPoints};
 use crate::man::data::DownloadDataCache;
 use crate::man::mqtt::SnapSubscriber;
 use crate::man::redis_client::{RedisClient, RedisRecv};
+use crate::wasm::WasmManager;
 use crate::service::custom_gateway::start_process_snap;
 
 pub(crate) mod man;
@@ -19,6 +20,8 @@ pub(crate) mod protocol;
 pub(crate) mod load;
 pub(crate) mod mqtt;
 pub(crate) mod decode;
+pub(crate) mod binary;
+pub(crate) mod wasm;
 
 pub(crate) mod event;
 pub(crate) mod integration;
@@ -40,6 +43,8 @@ enum DeviceError {
     #[error("{0}")]
     Data(String),
     #[error("{0}")]
+    Timeout(String),
+    #[error("{0}")]
     Device(String),
     #[error("{0}")]
     Warn(String),
@@ -93,6 +98,15 @@ impl From<DataError> for DeviceError {
     }
 }
 
+impl From<JsDecodeError> for DeviceError {
+    fn from(value: JsDecodeError) -> Self {
+        match value {
+            JsDecodeError::TimeOut { .. } => Self::Timeout(format!("js decode: {:?}", value)),
+            _ => Self::Data(format!("js decode: {:?}", value)),
+        }
+    }
+}
+
 impl From<deadpool::managed::PoolError<deadpool_redis::redis::RedisError>> for DeviceError {
     fn from(value: deadpool::managed::PoolError<deadpool_redis::redis::RedisError>) -> Self {
         warn!("redis pool: {}", value);
@@ -107,6 +121,13 @@ impl From<sea_orm::DbErr> for DeviceError {
     }
 }
 
+impl From<common_define::telemetry::TelemetryError> for DeviceError {
+    fn from(value: common_define::telemetry::TelemetryError) -> Self {
+        warn!("telemetry store: {}", value);
+        Self::Error(value.to_string())
+    }
+}
+
 impl From<()> for DeviceError {
     fn from(_value: ()) -> Self {
         Self::Empty
@@ -121,10 +142,14 @@ static GLOBAL_TOPIC: Lazy<Topic> = Lazy::new(|| {
 });
 
 static GLOBAL_DEPEND: Lazy<DecodeManager> = Lazy::new(|| {
-    let rt = JsManager::new();
-    DecodeManager::new(rt.clone())
+    let config = &load_config().decode;
+    DecodeManager::new(JsManager::new(config.limits()), WasmManager::new(config.wasm_limits()))
 });
 
+static GLOBAL_VIRTUAL_POINTS: Lazy<VirtualPoints> = Lazy::new(VirtualPoints::default);
+
+static GLOBAL_CALIBRATIONS: Lazy<Calibrations> = Lazy::new(Calibrations::default);
+
 static GLOBAL_JS_RUNTIME: Lazy<DownloadDataCache> = Lazy::new(|| {
     DownloadDataCache::default()
 });
@@ -146,6 +171,7 @@ struct Topic {
 pub async fn run(config: String, env_prefix: String) {
     let config = store_config(config, env_prefix);
     snap_config::init_logging(config.log);
+    common_define::db::set_key_encryption_key(config.key.load().unwrap());
     GLOBAL_STATE.db.ping().await.unwrap();
     let redis_client = RedisClient::get_client();
     let recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
@@ -154,6 +180,31 @@ pub async fn run(config: String, env_prefix: String) {
     tokio::spawn(async move {
         DownlinkManager::new(consumer).start_downlink().await;
     });
+    let mut script_recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
+    script_recv.subscribe(ScriptEvent::TOPIC).await.unwrap();
+    tokio::spawn(async move {
+        GLOBAL_DEPEND.start_invalidate(script_recv).await;
+    });
+    let mut virtual_recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
+    virtual_recv.subscribe(VirtualPointEvent::TOPIC).await.unwrap();
+    tokio::spawn(async move {
+        GLOBAL_VIRTUAL_POINTS.start_invalidate(virtual_recv).await;
+    });
+    let mut calibration_recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
+    calibration_recv.subscribe(CalibrationEvent::TOPIC).await.unwrap();
+    tokio::spawn(async move {
+        GLOBAL_CALIBRATIONS.start_invalidate(calibration_recv).await;
+    });
+    let mut call_recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
+    call_recv.subscribe(ScriptCall::TOPIC).await.unwrap();
+    tokio::spawn(async move {
+        GLOBAL_DEPEND.start_call(call_recv).await;
+    });
+    let mut redecode_recv = RedisRecv::new(redis_client.get_pubsub().await.unwrap());
+    redecode_recv.subscribe(ReDecodeJob::TOPIC).await.unwrap();
+    tokio::spawn(async move {
+        GLOBAL_DEPEND.start_redecode(redecode_recv).await;
+    });
     info!(
         "push data topic: {}", GLOBAL_TOPIC.data
     );
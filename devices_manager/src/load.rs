@@ -1,11 +1,15 @@
 use std::sync::Arc;
+use std::time::Duration;
 use arc_swap::ArcSwap;
 use once_cell::sync::Lazy;
 use serde::Deserialize;
 use tracing::info;
 use snap_config::{DeviceTopicConfig, SnapConfig};
+use common_define::telemetry::TelemetryStore;
 
 use crate::Topic;
+use crate::decode::JsLimits;
+use crate::wasm::WasmLimits;
 use crate::protocol::lora::source::{listen_udp, LoRaUdp};
 
 
@@ -42,11 +46,17 @@ pub struct DeviceConfig {
     #[serde(default)]
     pub log: snap_config::LogLevelConfig,
     #[serde(default)]
+    pub key: snap_config::KeyEncryptionConfig,
+    #[serde(default)]
     pub device: DeviceConfigInner,
     #[serde(default)]
+    pub decode: DecodeConfig,
+    #[serde(default)]
     pub mqtt: Option<MqttConfig>,
     #[serde(default)]
-    pub snap: Option<SnapDeviceConfig>
+    pub snap: Option<SnapDeviceConfig>,
+    #[serde(default)]
+    pub telemetry: snap_config::TelemetryConfig,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -61,6 +71,9 @@ pub struct LoRaConfig {
     pub host: String,
     #[serde(default="_default_lora_port")]
     pub port: u16,
+    /// Seconds a gateway stays bound to the address it was last seen from.
+    #[serde(default="_default_conflict_window")]
+    pub conflict_window: u64,
 }
 
 impl Default for LoRaConfig {
@@ -68,6 +81,7 @@ impl Default for LoRaConfig {
         Self {
             host: _default_lora_host(),
             port: _default_lora_port(),
+            conflict_window: _default_conflict_window(),
         }
     }
 }
@@ -78,6 +92,73 @@ fn _default_lora_host() -> String {
 fn _default_lora_port() -> u16 {
     1700
 }
+fn _default_conflict_window() -> u64 {
+    30
+}
+
+/// Decoder runtime pool, see [`JsLimits`].
+#[derive(Deserialize, Debug)]
+pub struct DecodeConfig {
+    #[serde(default="_default_decode_workers")]
+    pub workers: usize,
+    /// Milliseconds one decode may run.
+    #[serde(default="_default_decode_timeout")]
+    pub timeout: u64,
+    /// Memory limit of each runtime in bytes.
+    #[serde(default="_default_decode_memory")]
+    pub memory: usize,
+    /// Fuel of one WebAssembly decode, see [`WasmLimits`].
+    #[serde(default="_default_wasm_fuel")]
+    pub wasm_fuel: u64,
+    /// Memory limit of each WebAssembly instance in bytes.
+    #[serde(default="_default_wasm_memory")]
+    pub wasm_memory: usize,
+}
+
+impl DecodeConfig {
+    pub fn limits(&self) -> JsLimits {
+        JsLimits {
+            workers: self.workers,
+            timeout: Duration::from_millis(self.timeout),
+            memory: self.memory,
+        }
+    }
+
+    pub fn wasm_limits(&self) -> WasmLimits {
+        WasmLimits {
+            fuel: self.wasm_fuel,
+            memory: self.wasm_memory,
+        }
+    }
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        Self {
+            workers: _default_decode_workers(),
+            timeout: _default_decode_timeout(),
+            memory: _default_decode_memory(),
+            wasm_fuel: _default_wasm_fuel(),
+            wasm_memory: _default_wasm_memory(),
+        }
+    }
+}
+
+fn _default_decode_workers() -> usize {
+    JsLimits::default().workers
+}
+fn _default_decode_timeout() -> u64 {
+    JsLimits::default().timeout.as_millis() as u64
+}
+fn _default_decode_memory() -> usize {
+    JsLimits::default().memory
+}
+fn _default_wasm_fuel() -> u64 {
+    WasmLimits::default().fuel
+}
+fn _default_wasm_memory() -> usize {
+    WasmLimits::default().memory
+}
 
 #[derive(Deserialize, Debug)]
 pub struct MqttConfig {
@@ -103,11 +184,14 @@ pub struct SnapDeviceConfig {
 pub struct State {
     pub db: sea_orm::DatabaseConnection,
     pub udp: LoRaUdp,
+    pub telemetry: Arc<dyn TelemetryStore>,
 }
 pub(crate) fn load_state() -> State {
     tokio::task::block_in_place(move || {
         tokio::runtime::Handle::current().block_on(async move {
             let db = load_db().await;
+            let telemetry = common_define::telemetry::open(&load_config().telemetry, db.clone());
+            telemetry.init().await.unwrap();
             let (forward, udp) = listen_udp().await.unwrap();
             tokio::spawn(async move {
                 forward.start().await;
@@ -115,6 +199,7 @@ pub(crate) fn load_state() -> State {
             State {
                 db,
                 udp,
+                telemetry,
             }
         })
     })
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+use common_define::db::{DataCalibrationColumn, DataCalibrationEntity};
+use common_define::decode::DecodeData;
+use common_define::event::CalibrationEvent;
+use common_define::unit::Calibration;
+use crate::man::Id;
+use crate::man::redis_client::RedisRecv;
+use crate::{DeviceResult, GLOBAL_STATE};
+
+/// Calibrations of a device by data ID.
+type Loaded = Arc<Vec<(u32, Calibration)>>;
+
+/// Calibrations of each device, loaded on first use and dropped on a [`CalibrationEvent`].
+#[derive(Clone, Default)]
+pub struct Calibrations {
+    map: Arc<Mutex<HashMap<Id, Loaded>>>,
+}
+
+impl Calibrations {
+
+    pub async fn load(&self, device: Id) -> DeviceResult<Loaded> {
+        if let Some(calibrations) = self.map.lock().unwrap().get(&device) {
+            return Ok(calibrations.clone())
+        }
+        let calibrations: Vec<_> = DataCalibrationEntity::find()
+            .filter(DataCalibrationColumn::DeviceId.eq(device))
+            .filter(DataCalibrationColumn::Calibration.is_not_null())
+            .all(&GLOBAL_STATE.db)
+            .await?
+            .into_iter()
+            .filter_map(|model| Some((model.data_id as u32, model.calibration?)))
+            .collect();
+        let calibrations = Arc::new(calibrations);
+        self.map.lock().unwrap().insert(device, calibrations.clone());
+        Ok(calibrations)
+    }
+
+    /// Calibrates the decoded data of an uplink of a device, before virtual data points are computed.
+    pub async fn apply(&self, device: Id, data: &mut [DecodeData]) -> DeviceResult {
+        let calibrations = self.load(device).await?;
+        Calibration::apply_all(&calibrations, data);
+        Ok(())
+    }
+
+    pub async fn start_invalidate(&self, mut recv: RedisRecv) {
+        let mut s = recv.message();
+        loop {
+            while let Some(msg) = s.next().await {
+                match serde_json::from_slice::<CalibrationEvent>(msg.get_payload_bytes()) {
+                    Ok(event) => {
+                        debug!(device = %event.device, "calibrations changed");
+                        self.map.lock().unwrap().remove(&event.device);
+                    }
+                    Err(e) => warn!("invalid calibration event: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -25,17 +25,21 @@ pub(crate) enum ValueType {
     U32,
 }
 
-impl From<DecodeDataType> for ValueType {
-    fn from(value: DecodeDataType) -> Self {
+impl TryFrom<DecodeDataType> for ValueType {
+    type Error = String;
+    fn try_from(value: DecodeDataType) -> Result<Self, Self::Error> {
         match value {
             DecodeDataType::I32 => {
-                Self::I32
+                Ok(Self::I32)
             }
             DecodeDataType::F64 => {
-                Self::F64
+                Ok(Self::F64)
             }
             DecodeDataType::Bool => {
-                Self::Bool
+                Ok(Self::Bool)
+            }
+            t => {
+                Err(format!("{} has no packet type", t.as_ref()))
             }
         }
     }
@@ -546,7 +550,9 @@ pub struct DownloadData {
     pub up_count: Option<u32>,
     pub bytes: Bytes,
     pub id: u32,
-    pub forward: Option<ClientId>
+    pub forward: Option<ClientId>,
+    /// Downlink FCnt the application encrypted `bytes` with, for application side encryption.
+    pub f_cnt: Option<u32>,
 }
 
 impl DownloadData {
@@ -559,7 +565,8 @@ impl DownloadData {
             up_count: None,
             port: 3,
             bytes,
-            forward: None
+            forward: None,
+            f_cnt: None,
         }
     }
     pub(crate) fn new_data<D: Into<Bytes>>(data: D) -> Self {
@@ -569,7 +576,8 @@ impl DownloadData {
             up_count: None,
             bytes,
             port: 2,
-            forward: None
+            forward: None,
+            f_cnt: None,
         }
     }
     pub(crate) fn new_data_with_id_and_forward<D: Into<Bytes>>(data: D, id: u32, forward: ClientId, port: u8) -> Self {
@@ -579,9 +587,15 @@ impl DownloadData {
             up_count: None,
             bytes,
             port,
-            forward: Some(forward)
+            forward: Some(forward),
+            f_cnt: None,
         }
     }
+
+    pub(crate) fn with_f_cnt(mut self, f_cnt: Option<u32>) -> Self {
+        self.f_cnt = f_cnt;
+        self
+    }
 }
 
 
@@ -1,37 +1,309 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use crate::decode::{DecodeData, RawData, JsDecodeError, JsManager};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+use common_define::binary::BinaryDecoder;
+use base64::Engine;
+use common_define::db::{CodecFlatten, CodecVariables, DbDecodeData, DecodeMap, DecodeScriptEntity, DecodeScriptVersionColumn, DecodeScriptVersionEntity};
+use common_define::decode::DecodeLang;
+use common_define::event::{ScriptCall, ScriptEvent, ScriptJob, ScriptPayload, ScriptReply, ScriptSource};
+use common_define::time::Timestamp;
+use crate::decode::{up_data_decode, DecodeData, RawData, JsManager};
 use crate::man::Id;
+use crate::man::redis_client::{RedisClient, RedisRecv};
+use crate::wasm::WasmManager;
+use crate::{DeviceError, DeviceResult, GLOBAL_STATE};
+
+enum DecodeCode {
+    Js(Arc<Vec<u8>>),
+    Ttn(Arc<Vec<u8>>),
+    Binary(BinaryDecoder),
+    Wasm(Arc<wasmi::Module>),
+}
 
 pub struct DecodeModule {
-    module: Vec<u8>,
-    time: chrono::DateTime<chrono::Utc>
+    code: DecodeCode,
+    /// Used to turn the object of a TTN codec into data points.
+    codec: CodecFlatten,
+    map: DecodeMap,
+    time: Timestamp
+}
+
+#[derive(Default)]
+struct DecodeCache {
+    modules: HashMap<Id, Arc<DecodeModule>>,
+    /// Latest change seen for each script, a module compiled from an older row is not cached.
+    changed: HashMap<Id, Timestamp>,
+    /// Pinned versions, a version never changes so these are only dropped with the script.
+    versions: HashMap<(Id, i32), Arc<DecodeModule>>,
 }
 
 #[derive(Clone)]
 pub struct DecodeManager {
-    map: Arc<Mutex<HashMap<Id, DecodeModule>>>,
-    rt: JsManager
+    map: Arc<Mutex<DecodeCache>>,
+    rt: JsManager,
+    wasm: WasmManager,
 }
 
 impl DecodeManager {
     
-    pub fn new(rt: JsManager) -> Self {
+    pub fn new(rt: JsManager, wasm: WasmManager) -> Self {
         Self {
             map: Default::default(),
-            rt
+            rt,
+            wasm,
+        }
+    }
+
+    async fn compile(&self, lang: DecodeLang, script: &str) -> DeviceResult<DecodeCode> {
+        let code = match lang {
+            DecodeLang::JS => DecodeCode::Js(Arc::new(self.rt.compile(script).await?)),
+            DecodeLang::TTN => DecodeCode::Ttn(Arc::new(self.rt.compile_codec(script).await?)),
+            DecodeLang::Binary => DecodeCode::Binary(BinaryDecoder::parse(script).map_err(DeviceError::data)?),
+            DecodeLang::Wasm => {
+                let bytes = base64::engine::general_purpose::STANDARD.decode(script.trim())?;
+                DecodeCode::Wasm(Arc::new(self.wasm.compile(&bytes)?))
+            }
+        };
+        Ok(code)
+    }
+
+    /// Returns the compiled script, the script is loaded and compiled on first use.
+    async fn load(&self, script: Id) -> DeviceResult<Option<Arc<DecodeModule>>> {
+        if let Some(module) = self.map.lock().unwrap().modules.get(&script).cloned() {
+            return Ok(Some(module))
+        }
+        let Some(model) = DecodeScriptEntity::find_by_id(script)
+            .one(&GLOBAL_STATE.db)
+            .await? else {
+            return Ok(None)
+        };
+        let module = Arc::new(DecodeModule {
+            code: self.compile(model.lang.parse().unwrap_or(DecodeLang::JS), &model.script).await?,
+            codec: model.codec,
+            map: model.map,
+            time: model.modify_time,
+        });
+        let mut map = self.map.lock().unwrap();
+        if map.changed.get(&script).is_none_or(|time| *time <= model.modify_time) {
+            debug!("cache script {}", script);
+            map.modules.insert(script, module.clone());
         }
+        Ok(Some(module))
     }
-    pub fn decode_with_id(&self, module_id: &Id, data: RawData) -> Option<Result<DecodeData, JsDecodeError>> {
-        let map = self.map.lock().unwrap();
-        let s = map.get(module_id)?;
-        Some(self.rt.eval(s.module.as_slice(), data))
+
+    /// Returns the compiled version of the script, the version is loaded and compiled on first use.
+    async fn load_version(&self, script: Id, version: i32) -> DeviceResult<Option<Arc<DecodeModule>>> {
+        if let Some(module) = self.map.lock().unwrap().versions.get(&(script, version)).cloned() {
+            return Ok(Some(module))
+        }
+        let Some(model) = DecodeScriptVersionEntity::find()
+            .filter(DecodeScriptVersionColumn::ScriptId.eq(script))
+            .filter(DecodeScriptVersionColumn::Version.eq(version))
+            .one(&GLOBAL_STATE.db)
+            .await? else {
+            return Ok(None)
+        };
+        let module = Arc::new(DecodeModule {
+            code: self.compile(model.lang.parse().unwrap_or(DecodeLang::JS), &model.script).await?,
+            codec: model.codec,
+            map: model.map,
+            time: model.create_time,
+        });
+        debug!("cache script {} version {}", script, version);
+        self.map.lock().unwrap().versions.insert((script, version), module.clone());
+        Ok(Some(module))
     }
 
-    pub fn decode_with_code(&self, id: Id, code: &str, data: RawData) -> Result<DecodeData, JsDecodeError> {
-        let module = self.rt.compile(code)?;
-        let data = self.rt.eval(module.as_slice(), data)?;
-        // self.map.lock().unwrap().insert(id, DecodeModule { module, time: chrono::Utc::now() });
+    /// Decodes with the compiled script, the latest version unless `version` pins one.
+    /// Returns `None` when the script or the version does not exist.
+    pub(crate) async fn decode(&self, script: Id, version: Option<i32>, data: RawData) -> DeviceResult<Option<DecodeData>> {
+        let module = match version {
+            Some(version) => self.load_version(script, version).await?,
+            None => self.load(script).await?,
+        };
+        let Some(module) = module else {
+            return Ok(None)
+        };
+        Ok(Some(self.run(&module, data).await?))
+    }
+
+    async fn run(&self, module: &DecodeModule, data: RawData) -> DeviceResult<DecodeData> {
+        let data = match &module.code {
+            DecodeCode::Js(code) => self.rt.eval(code.clone(), data).await?,
+            DecodeCode::Ttn(code) => self.rt.decode_uplink(code.clone(), data).await?
+                .into_decode_data(&module.codec, &module.map),
+            DecodeCode::Binary(decoder) => crate::binary::decode(decoder, data.bytes(), data.f_port()),
+            DecodeCode::Wasm(module) => self.wasm.decode(module.clone(), data).await?,
+        };
         Ok(data)
     }
-}
\ No newline at end of file
+
+    /// Compiles and runs a script draft, the draft is not cached.
+    async fn test(&self, lang: DecodeLang, script: &str, data: RawData) -> DeviceResult<DecodeData> {
+        let module = DecodeModule {
+            code: self.compile(lang, script).await?,
+            codec: Default::default(),
+            map: Default::default(),
+            time: Timestamp::now(),
+        };
+        self.run(&module, data).await
+    }
+
+    fn decoded(result: DeviceResult<DecodeData>) -> ScriptReply {
+        match result {
+            Ok(decoded) => ScriptReply::Decoded {
+                warnings: decoded.warnings.clone(),
+                errors: decoded.errors.clone(),
+                memory: decoded.memory,
+                data: DbDecodeData::from(decoded).0,
+            },
+            Err(DeviceError::Timeout(message)) => ScriptReply::Timeout { message },
+            Err(e) => ScriptReply::Error { message: e.to_string() },
+        }
+    }
+
+    /// Decodes the payloads one after another with the module, drafts are compiled once.
+    async fn batch(&self, source: ScriptSource, payloads: Vec<ScriptPayload>, variables: Option<CodecVariables>) -> ScriptReply {
+        let module = match source {
+            ScriptSource::Saved { script, version } => {
+                let module = match version {
+                    Some(version) => self.load_version(script, version).await,
+                    None => self.load(script).await,
+                };
+                match module {
+                    Ok(Some(module)) => module,
+                    Ok(None) => return ScriptReply::Error { message: format!("script {} not found", script) },
+                    Err(e) => return ScriptReply::Error { message: e.to_string() },
+                }
+            }
+            ScriptSource::Draft { lang, script } => match self.compile(lang, &script).await {
+                Ok(code) => Arc::new(DecodeModule {
+                    code,
+                    codec: Default::default(),
+                    map: Default::default(),
+                    time: Timestamp::now(),
+                }),
+                Err(e) => return ScriptReply::Error { message: e.to_string() },
+            },
+            ScriptSource::Builtin => {
+                let replies = payloads.into_iter()
+                    .take(ScriptCall::MAX_BATCH)
+                    .map(|payload| match up_data_decode(&payload.bytes) {
+                        Ok(decoded) => ScriptReply::Decoded { data: decoded.data, warnings: vec![], errors: vec![], memory: None },
+                        Err(e) => ScriptReply::Error { message: e.msg },
+                    })
+                    .collect();
+                return ScriptReply::Batch { replies }
+            }
+        };
+        let mut replies = Vec::with_capacity(payloads.len());
+        for payload in payloads.into_iter().take(ScriptCall::MAX_BATCH) {
+            let raw = RawData::new(payload.bytes).with_codec(payload.f_port, variables.clone());
+            replies.push(Self::decoded(self.run(&module, raw).await));
+        }
+        ScriptReply::Batch { replies }
+    }
+
+    async fn call(&self, job: ScriptJob) -> ScriptReply {
+        match job {
+            ScriptJob::Test { lang, script, bytes, f_port } => {
+                Self::decoded(self.test(lang, &script, RawData::new(bytes).with_codec(f_port, None)).await)
+            }
+            ScriptJob::Batch { source, payloads, variables } => self.batch(source, payloads, variables).await,
+            ScriptJob::Encode { script, data, variables } => {
+                let module = match self.load(script).await {
+                    Ok(Some(module)) => module,
+                    Ok(None) => return ScriptReply::Error { message: format!("script {} not found", script) },
+                    Err(e) => return ScriptReply::Error { message: e.to_string() },
+                };
+                let (DecodeCode::Js(code) | DecodeCode::Ttn(code)) = &module.code else {
+                    return ScriptReply::Error { message: format!("script {} can not encode", script) }
+                };
+                let encoded = match self.rt.encode_downlink(code.clone(), data, variables.clone()).await {
+                    Ok(encoded) if !encoded.errors.is_empty() => return ScriptReply::Invalid { errors: encoded.errors },
+                    Ok(encoded) => encoded,
+                    Err(e) => return ScriptReply::Error { message: format!("{:?}", e) },
+                };
+                // the bytes are read back with decodeDownlink when the codec has it
+                let mut warnings = encoded.warnings;
+                let decoded = match self.rt.decode_downlink(code.clone(), &encoded.bytes, encoded.f_port, variables).await {
+                    Ok(Some(decoded)) if !decoded.errors.is_empty() => return ScriptReply::Invalid { errors: decoded.errors },
+                    Ok(Some(decoded)) => {
+                        warnings.extend(decoded.warnings);
+                        Some(decoded.data)
+                    }
+                    Ok(None) => None,
+                    Err(e) => return ScriptReply::Error { message: format!("{:?}", e) },
+                };
+                ScriptReply::Encoded {
+                    bytes: encoded.bytes,
+                    f_port: encoded.f_port,
+                    warnings,
+                    decoded,
+                }
+            }
+        }
+    }
+
+    /// Answers script calls of snap_api, each reply is pushed to the list named by the call.
+    pub async fn start_call(&self, mut recv: RedisRecv) {
+        let mut s = recv.message();
+        loop {
+            while let Some(msg) = s.next().await {
+                let call = match serde_json::from_slice::<ScriptCall>(msg.get_payload_bytes()) {
+                    Ok(call) => call,
+                    Err(e) => {
+                        warn!("invalid script call: {}", e);
+                        continue
+                    }
+                };
+                let manager = self.clone();
+                tokio::spawn(async move {
+                    let reply = manager.call(call.job).await;
+                    if let Err(e) = Self::reply(&call.reply, &reply).await {
+                        warn!("script reply: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    async fn reply(key: &str, reply: &ScriptReply) -> DeviceResult {
+        let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
+        let reply = serde_json::to_string(reply)?;
+        let _: () = redis::pipe()
+            .rpush(key, reply)
+            .expire(key, ScriptCall::TIMEOUT as i64)
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the compiled script when it is older than the change.
+    pub fn invalidate(&self, event: &ScriptEvent) {
+        let mut map = self.map.lock().unwrap();
+        let time = event.modify_time.unwrap_or(Timestamp::now());
+        map.changed.insert(event.script, time);
+        if map.modules.get(&event.script).is_some_and(|it| event.modify_time.is_none_or(|time| it.time < time)) {
+            debug!("drop script {}", event.script);
+            map.modules.remove(&event.script);
+        }
+        if event.modify_time.is_none() {
+            map.versions.retain(|(script, _), _| *script != event.script);
+        }
+    }
+
+    pub async fn start_invalidate(&self, mut recv: RedisRecv) {
+        let mut s = recv.message();
+        loop {
+            while let Some(msg) = s.next().await {
+                match serde_json::from_slice::<ScriptEvent>(msg.get_payload_bytes()) {
+                    Ok(event) => self.invalidate(&event),
+                    Err(e) => warn!("invalid script event: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -42,10 +42,15 @@ impl DownlinkManager {
             DeviceType::LoRaNode => {
                 match LoRaNodeManager::get_node_by_eui(down.eui).await? {
                     Some(s) => {
+                        if s.info.is_app_encrypted() != down.f_cnt.is_some() {
+                            return Err(DeviceError::Device(format!("{} downlink fcnt is required only with application encryption", down.eui)))
+                        }
                         let data = base64::engine::general_purpose::STANDARD.decode(down.data.as_bytes())?;
                         info!("{}, down message", down.eui);
+                        let task = DownloadData::new_data_with_id_and_forward(data, 1, ClientId::next(), down.port)
+                            .with_f_cnt(down.f_cnt);
                         tokio::spawn(async move {
-                            if let Err(e) = s.dispatch_task_now(DownloadData::new_data_with_id_and_forward(data, 1, ClientId::next(), down.port)).await {
+                            if let Err(e) = s.dispatch_task_now(task).await {
                                 info!(
                                     device= down.eui.to_string(),
                                     "forward error: {}", e);
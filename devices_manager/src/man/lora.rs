@@ -5,26 +5,30 @@ use std::time::Duration;
 
 use common_define::db::{DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DevicesEntity, Eui, Key, LoRaAddr};
 use common_define::lora::LoRaRegion;
-use common_define::lorawan_bridge::{DownStream, GatewayToken};
+use common_define::lorawan_bridge::{DownStream, GatewaySignature, GatewaySource, GatewayToken};
 use common_define::time::Timestamp;
 use device_info::lorawan::{GatewayInfo, NodeInfo};
+use hmac::{Hmac, Mac};
 use lorawan::parser::DataHeader;
 use once_cell::sync::Lazy;
 use redis::AsyncCommands;
 use sea_orm::EntityTrait;
 use sea_orm::QueryFilter;
 use sea_orm::ColumnTrait;
+use sha2::Sha256;
 use tracing::{debug, error, info, instrument, warn};
 
 use super::Id;
 use crate::event::LoRaNodeEvent;
 use crate::man::data::DownloadData;
+use crate::integration::mqtt::{MqttJoinData, MqttMessage};
 use crate::protocol::lora::payload::LoRaPayload;
 use crate::{protocol::lora::{
     self,
     data::{JoinRespDataBuilder, RespDataBuilder, RespDataClassCBuilder},
 }, service::lorawan_node::PushData, DeviceError, DeviceResult, GLOBAL_DOWNLOAD, GLOBAL_STATE};
 use crate::man::redis_client::RedisClient;
+use crate::load::load_config;
 
 #[derive(
     derive_more::From,
@@ -77,7 +81,9 @@ static GATEWAY_TIME: Lazy<GatewayTimeline> = Lazy::new(|| GatewayTimeline(Defaul
     serde::Deserialize,
 )]
 pub(crate) struct LoRaOTAANodeInfo {
+    #[serde(with = "common_define::db::wrapped_key")]
     pub(crate) app_skey: Key,
+    #[serde(with = "common_define::db::wrapped_key")]
     pub(crate) nwk_skey: Key,
     pub(crate) dev_nonce: u16,
     pub(crate) app_nonce: u32,
@@ -146,21 +152,24 @@ impl LoRaNode {
                         task.bytes.as_ref()
                     );
                 let counter = GLOBAL_DOWNLOAD.insert(self.info.dev_eui, task.clone());
-                let device_addr = self.info.dev_addr;
-                let dev_eui = self.info.dev_eui;
-                tokio::spawn(async move {
-                    if let Err(e) =
-                        repetition_task(task, device_addr, gateway_eui, counter).await
-                    {
-                        warn!(
-                                target: "repetition_task",
-                                device_eui = dev_eui.to_string(),
-                                "{e}"
-                            )
-                    }
-                });
+                self.update_down_count_with(&task).await?;
+                // a frame encrypted by the application can not be sent again with a new FCnt
+                if task.f_cnt.is_none() {
+                    let device_addr = self.info.dev_addr;
+                    let dev_eui = self.info.dev_eui;
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            repetition_task(task, device_addr, gateway_eui, counter).await
+                        {
+                            warn!(
+                                    target: "repetition_task",
+                                    device_eui = dev_eui.to_string(),
+                                    "{e}"
+                                )
+                        }
+                    });
+                }
 
-                self.update_down_count().await?;
                 gateway.down_link(re_data).await?;
             }
         } else {
@@ -183,7 +192,7 @@ impl LoRaNode {
                         task.bytes.as_ref()
                     );
 
-                self.update_down_count().await?;
+                self.update_down_count_with(&task).await?;
                 gateway.down_link(re_data).await?;
                 return Ok(());
             }
@@ -200,7 +209,7 @@ impl LoRaNode {
          let active_key = LoRaNode::activate_key(self.info.dev_addr);
          let info: Option<LoRaOTAANodeInfo> =  self.conn.get(&active_key).await?;
          if info.is_some() {
-             self.conn.del(active_key).await?;
+             let _: () = self.conn.del(active_key).await?;
          }
          Ok(info)
     }
@@ -236,8 +245,9 @@ impl LoRaNode {
                 if let Some(pre_up_count) = task.up_count {
                     debug!("pull_task: get pre_up_count: {pre_up_count}, count: {count}");
                     self.wait().await;
-                    self.update_down_count().await?;
-                    let builder = RespDataBuilder::new(&self.info, push_data);
+                    self.update_down_count_with(&task).await?;
+                    let builder = RespDataBuilder::new(&self.info, push_data)
+                        .with_ack(header.is_confirmed());
                     if ack {
                         let ack = builder.build_ack(&[])?;
                         self.down_link(ack).await?;
@@ -287,6 +297,17 @@ impl LoRaNode {
             .await?;
         Ok(count)
     }
+    /// Moves the downlink counter past the FCnt `task` was sent with.
+    pub(crate) async fn update_down_count_with(&self, task: &DownloadData) -> DeviceResult<u32> {
+        match task.f_cnt {
+            Some(f_cnt) => {
+                let mut conn = self.conn.clone();
+                NodeInfo::update_by_addr(self.info.dev_addr, NodeInfo::down_count(), f_cnt + 1, &mut conn).await?;
+                Ok(f_cnt + 1)
+            }
+            None => self.update_down_count().await,
+        }
+    }
     pub(crate) async fn reset_down_count(&mut self) -> DeviceResult<u32> {
         NodeInfo::update_by_addr(self.info.dev_addr, NodeInfo::down_count(), 0, &mut self.conn).await?;
         self.info.down_count = 0;
@@ -328,7 +349,8 @@ impl LoRaNodeManager {
                     error!("dev_addr({}) in lora_node, but found in devices", dev_addr);
                     return Err(DeviceError::Empty);
                 }
-                NodeInfo::register_to_redis(node, devices.unwrap(), &mut conn).await?
+                let profile = node.load_profile(&GLOBAL_STATE.db).await?;
+                NodeInfo::register_to_redis(node, devices.unwrap(), profile, &mut conn).await?
             }
             Some(info) => info,
         };
@@ -368,7 +390,7 @@ impl LoRaNodeManager {
     pub(crate) async fn otaa_active(dev_addr: LoRaAddr) -> DeviceResult {
         let active_key = LoRaNode::activate_key(dev_addr);
         let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
-        conn.del(active_key).await?;
+        let _: () = conn.del(active_key).await?;
         Ok(())
     }
 
@@ -390,18 +412,35 @@ impl LoRaNodeManager {
         
         let active_key = LoRaNode::activate_key(info.dev_addr);
 
+        // with application side encryption the AppSKey is handed off wrapped and not kept
+        let (app_skey, app_s_key) = if info.is_app_encrypted() {
+            let kek = info.app_kek.as_ref()
+                .ok_or_else(|| DeviceError::device("application encryption without app kek"))?;
+            (Key::nil(), Some(keys.app_skey.wrap(kek.0.0)))
+        } else {
+            (keys.app_skey, None)
+        };
         let otaa_info = LoRaOTAANodeInfo {
             nwk_skey: keys.nwk_skey,
-            app_skey: keys.app_skey,
+            app_skey,
             dev_nonce,
             app_nonce,
             net_id,
         };
         let info_json = serde_json::to_string(&otaa_info)?;
 
-        conn.set(active_key, info_json).await?;
+        let _: () = conn.set(active_key, info_json).await?;
         gw.down_link(resp).await?;
-        LoRaNodeEvent::join_accept(info.dev_addr, &info, &mut conn).await?;
+        if let Some(ref app_s_key) = app_s_key {
+            let join = MqttJoinData {
+                device: info.device_id,
+                dev_eui: info.dev_eui,
+                dev_addr: info.dev_addr,
+                app_s_key: app_s_key.clone(),
+            };
+            MqttMessage::new_join_data(&join, 1)?.publish().await?;
+        }
+        LoRaNodeEvent::join_accept(info.dev_addr, &info, app_s_key, &mut conn).await?;
         debug!("Join Request");
         Ok(())
     }
@@ -412,9 +451,9 @@ impl LoRaNodeManager {
         let dev_addr: Option<LoRaAddr> = conn.get(&key).await?;
         if let Some(dev_addr) = dev_addr {
             let (dev_key, task_key) = LoRaNode::keys(dev_addr);
-            conn.del(dev_key).await?;
-            conn.del(task_key).await?;
-            conn.del(key).await?;
+            let _: () = conn.del(dev_key).await?;
+            let _: () = conn.del(task_key).await?;
+            let _: () = conn.del(key).await?;
         }
         Ok(())
     }
@@ -449,6 +488,28 @@ impl LoRaNodeManager {
 }
 
 
+/// Checks the MAC of a signed packet and that its counter is above `last`, the counter of the
+/// last accepted packet of the gateway.
+fn verify_signature(key: &Key, signature: &GatewaySignature, last: Option<u64>) -> Result<(), &'static str> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.0.0).map_err(|_| "invalid key")?;
+    mac.update(&signature.signed);
+    mac.verify_slice(&signature.mac).map_err(|_| "invalid signature")?;
+    if last.is_some_and(|last| signature.counter <= last) {
+        return Err("replayed");
+    }
+    Ok(())
+}
+
+/// Result of [`LoRaGate::check_source`] for a packet that passed the allow-list and signature checks.
+pub(crate) enum GatewaySourceCheck {
+    Accepted,
+    /// The gateway was seen from `previous` within the conflict window.
+    Conflict {
+        previous: String,
+        previous_time: Timestamp,
+    },
+}
+
 #[derive(Clone)]
 pub(crate) struct LoRaGate {
     key: String,
@@ -479,7 +540,7 @@ impl LoRaGate {
         if self.info.version != version {
             let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
             self.info.version = version;
-            conn.hset(self.key.as_str(), GatewayInfo::version(), version).await?;
+            let _: () = conn.hset(self.key.as_str(), GatewayInfo::version(), version).await?;
         }
         Ok(())
     }
@@ -492,7 +553,7 @@ impl LoRaGate {
         self.down = down;
         let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
 
-        conn.hset(self.key.as_str(), GatewayInfo::down(), &addr).await?;
+        let _: () = conn.hset(self.key.as_str(), GatewayInfo::down(), &addr).await?;
         Ok(())
     }
     #[instrument(skip(self))]
@@ -500,11 +561,51 @@ impl LoRaGate {
         self.info.tmst = tmst;
         let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
         let now = Timestamp::now();
-        conn.hset(self.key.as_str(), (GatewayInfo::tmst(), tmst), (GatewayInfo::time(), now))
+        let _: () = conn.hset(self.key.as_str(), (GatewayInfo::tmst(), tmst), (GatewayInfo::time(), now))
             .await?;
         Ok(())
     }
 
+    /// Checks a packet source against the allow-list and signing key of the gateway, and
+    /// against the address the gateway was last seen from. Packets from a second address are
+    /// reported as a conflict until the first address has been quiet for the conflict window.
+    #[instrument(skip(self, source))]
+    pub(crate) async fn check_source(&mut self, source: &GatewaySource) -> DeviceResult<GatewaySourceCheck> {
+        let ip = source.ip
+            .map(|addr| addr.ip())
+            .ok_or_else(|| DeviceError::Warn(format!("gateway {} without source address", self.eui)))?;
+        if let Some(allow_list) = &self.info.allow_list {
+            if !allow_list.allows(ip) {
+                return Err(DeviceError::Warn(format!("gateway {} source {} not allowed", self.eui, ip)));
+            }
+        }
+        let mut conn = RedisClient::get_client().get_multiplexed_conn().await?;
+        if let Some(key) = &self.info.hmac_key {
+            let signature = source.signature.as_ref()
+                .ok_or_else(|| DeviceError::Warn(format!("gateway {} packet from {} not signed", self.eui, ip)))?;
+            let last = GatewayInfo::load_counter(self.eui, &mut conn).await?;
+            verify_signature(key, signature, last)
+                .map_err(|e| DeviceError::Warn(format!("gateway {} packet from {} {}", self.eui, ip, e)))?;
+            if !GatewayInfo::advance_counter(self.eui, signature.counter, &mut conn).await? {
+                return Err(DeviceError::Warn(format!("gateway {} packet from {} replayed", self.eui, ip)));
+            }
+        }
+
+        let ip = ip.to_string();
+        let now = Timestamp::now();
+        if let (Some(previous), Some(previous_time)) = (&self.info.source, self.info.source_time) {
+            let window = chrono::Duration::seconds(load_config().device.lorawan.conflict_window as i64);
+            if *previous != ip && now - previous_time < window {
+                return Ok(GatewaySourceCheck::Conflict { previous: previous.clone(), previous_time });
+            }
+        }
+        let _: () = conn.hset(self.key.as_str(), (GatewayInfo::source(), &ip), (GatewayInfo::source_time(), now))
+            .await?;
+        self.info.source = Some(ip);
+        self.info.source_time = Some(now);
+        Ok(GatewaySourceCheck::Accepted)
+    }
+
     async fn down_link(&self, down: DownStream) -> DeviceResult {
         GLOBAL_STATE.udp.down(down, self.info.version, GatewayToken::random(), self.down)
             .await?;
@@ -544,7 +645,8 @@ impl LoRaGateManager {
                         DeviceError::device("gateway not register".to_string())
                     })?;
                 debug!("active gateway");
-                let info = GatewayInfo::new(device.device_id, 0, 2, Timestamp::now(), None, None);
+                let info = GatewayInfo::new(device.device_id, 0, 2, Timestamp::now(), None, None)
+                    .with_source(&device);
                 info.register(eui, &mut conn).await?;
                 info
             }
@@ -602,3 +704,43 @@ async fn repetition_task(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use common_define::db::Key;
+    use common_define::lorawan_bridge::GatewaySignature;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use super::verify_signature;
+
+    fn sign(key: &Key, packet: &[u8], counter: u64) -> Vec<u8> {
+        let mut s = packet.to_vec();
+        s.extend_from_slice(GatewaySignature::MAGIC);
+        s.extend_from_slice(&counter.to_be_bytes());
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key.0.0).unwrap();
+        mac.update(&s);
+        s.extend_from_slice(&mac.finalize().into_bytes());
+        s
+    }
+
+    #[test]
+    fn test_signature_replay() {
+        let key: Key = "00112233445566778899AABBCCDDEEFF".parse().unwrap();
+        let packet = [2, 0x12, 0x34, 2, 1, 2, 3, 4, 5, 6, 7, 8];
+        let signed = sign(&key, &packet, 1000);
+        let (rest, signature) = GatewaySignature::split(&signed, 12);
+        let signature = signature.unwrap();
+        assert_eq!(rest, packet);
+        assert_eq!(signature.counter, 1000);
+        assert_eq!(verify_signature(&key, &signature, None), Ok(()));
+        assert_eq!(verify_signature(&key, &signature, Some(999)), Ok(()));
+        // the same packet again, or an older one
+        assert_eq!(verify_signature(&key, &signature, Some(1000)), Err("replayed"));
+        assert_eq!(verify_signature(&key, &signature, Some(2000)), Err("replayed"));
+        // a newer counter without the key
+        let mut forged = signed.clone();
+        forged[packet.len() + 4..packet.len() + 12].copy_from_slice(&1001u64.to_be_bytes());
+        let (_, forged) = GatewaySignature::split(&forged, 12);
+        assert_eq!(verify_signature(&key, &forged.unwrap(), Some(1000)), Err("invalid signature"));
+    }
+}
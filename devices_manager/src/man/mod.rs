@@ -3,7 +3,10 @@ pub(crate) mod lora;
 pub(crate) mod data;
 mod mq;
 mod decode;
+mod redecode;
 mod downlink;
+mod virtual_point;
+mod calibration;
 pub mod redis_client;
 pub mod mqtt;
 
@@ -11,6 +14,8 @@ pub use downlink::DownlinkManager;
 
 pub use mq::MQ;
 pub use decode::DecodeManager;
+pub use virtual_point::VirtualPoints;
+pub use calibration::Calibrations;
 use common_define::product::ProductType;
 
 pub(crate) type Id = common_define::Id;
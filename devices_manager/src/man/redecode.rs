@@ -0,0 +1,228 @@
+use base64::Engine;
+use redis::AsyncCommands;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+use common_define::db::{DbDecodeData, DeviceDataColumn, DeviceDataEntity, DeviceDataModel, DevicesColumn, DevicesEntity, DevicesModel, Rollup};
+use common_define::decode::{DecodeData, LastDecodeData};
+use common_define::event::{ReDecodeDiff, ReDecodeJob, ReDecodeProgress, ReDecodeState};
+use common_define::last_device_data_key;
+use common_define::time::Timestamp;
+use common_define::virtual_point::VirtualPoint;
+use crate::decode::{up_data_decode, RawData};
+use crate::man::{DecodeManager, Id};
+use crate::man::redis_client::{RedisClient, RedisRecv};
+use crate::{DeviceError, DeviceResult, GLOBAL_CALIBRATIONS, GLOBAL_STATE, GLOBAL_VIRTUAL_POINTS};
+
+/// Rows decoded between progress reports and cancel checks.
+const BATCH: u64 = 100;
+
+impl DecodeManager {
+
+    pub async fn start_redecode(&self, mut recv: RedisRecv) {
+        let mut s = recv.message();
+        loop {
+            while let Some(msg) = s.next().await {
+                let job = match serde_json::from_slice::<ReDecodeJob>(msg.get_payload_bytes()) {
+                    Ok(job) => job,
+                    Err(e) => {
+                        warn!("invalid redecode job: {}", e);
+                        continue
+                    }
+                };
+                let manager = self.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = manager.redecode(job).await {
+                        warn!("redecode: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    async fn redecode(&self, job: ReDecodeJob) -> DeviceResult {
+        info!(job = job.id, devices = job.devices.len(), dry_run = job.dry_run, virtual_only = job.virtual_only, "start redecode");
+        let mut redis = RedisClient::get_client().get_multiplexed_conn().await?;
+        let mut progress = ReDecodeProgress::new(&job);
+        progress.state = ReDecodeState::Running;
+        match self.redecode_devices(&job, &mut progress, &mut redis).await {
+            Ok(true) => progress.state = ReDecodeState::Done,
+            Ok(false) => progress.state = ReDecodeState::Canceled,
+            Err(e) => {
+                progress.state = ReDecodeState::Failed;
+                progress.message = Some(e.to_string());
+            }
+        }
+        info!(job = job.id, state = ?progress.state, changed = progress.changed, "redecode finished");
+        Self::report(&job, &progress, &mut redis).await
+    }
+
+    /// Returns `false` when the job was canceled.
+    async fn redecode_devices<R: redis::aio::ConnectionLike + Send>(
+        &self,
+        job: &ReDecodeJob,
+        progress: &mut ReDecodeProgress,
+        redis: &mut R,
+    ) -> DeviceResult<bool> {
+        let db = &GLOBAL_STATE.db;
+        let devices = DevicesEntity::find()
+            .filter(DevicesColumn::Id.is_in(job.devices.clone()))
+            .all(db)
+            .await?;
+        progress.total = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.is_in(job.devices.clone()))
+            .filter(DeviceDataColumn::CreateTime.between(job.start, job.end))
+            .count(db)
+            .await?;
+        Self::report(job, progress, redis).await?;
+        let mut diffs = 0;
+        for device in devices {
+            let points = GLOBAL_VIRTUAL_POINTS.load(device.id).await?;
+            let mut prev = Self::stored_before(device.id, job.start).await?;
+            let mut cursor: Option<(Timestamp, Id)> = None;
+            let mut written = false;
+            loop {
+                if redis.exists(ReDecodeJob::cancel_key(&job.id)).await? {
+                    return Ok(false)
+                }
+                let mut query = DeviceDataEntity::find()
+                    .filter(DeviceDataColumn::DeviceId.eq(device.id))
+                    .filter(DeviceDataColumn::CreateTime.between(job.start, job.end));
+                if let Some((time, id)) = cursor {
+                    query = query.filter(
+                        Condition::any()
+                            .add(DeviceDataColumn::CreateTime.gt(time))
+                            .add(DeviceDataColumn::CreateTime.eq(time).and(DeviceDataColumn::Id.gt(id)))
+                    );
+                }
+                let rows = query
+                    .order_by_asc(DeviceDataColumn::CreateTime)
+                    .order_by_asc(DeviceDataColumn::Id)
+                    .limit(BATCH)
+                    .all(db)
+                    .await?;
+                let Some(row) = rows.last() else {
+                    break
+                };
+                cursor = Some((row.create_time, row.id));
+                for row in rows {
+                    progress.done += 1;
+                    let decoded = if job.virtual_only {
+                        let mut data = row.data.0.clone();
+                        VirtualPoint::strip(&points, &mut data);
+                        Ok(data)
+                    } else {
+                        self.decode_stored(&device, job, &row).await
+                    };
+                    let mut new = match decoded {
+                        Ok(new) => new,
+                        Err(e) => {
+                            debug!(row = %row.id, "redecode failed: {}", e);
+                            progress.failed += 1;
+                            prev = Some(LastDecodeData::new(row.data.0.clone(), row.create_time));
+                            continue
+                        }
+                    };
+                    VirtualPoint::compute(&points, &mut new, prev.as_ref(), row.create_time);
+                    prev = Some(LastDecodeData::new(new.clone(), row.create_time));
+                    if new == row.data.0 {
+                        continue
+                    }
+                    progress.changed += 1;
+                    if diffs < ReDecodeJob::MAX_DIFF {
+                        diffs += 1;
+                        let diff = ReDecodeDiff {
+                            row: row.id,
+                            device: device.id,
+                            time: row.create_time,
+                            old: row.data.0.clone(),
+                            new: new.clone(),
+                        };
+                        let _: () = redis.rpush(ReDecodeJob::diff_key(&job.id), diff).await?;
+                    }
+                    if !job.dry_run {
+                        let mut model = row.into_active_model();
+                        model.data = ActiveValue::Set(DbDecodeData(new));
+                        model.update(db).await?;
+                        written = true;
+                    }
+                }
+                Self::report(job, progress, redis).await?;
+            }
+            if written {
+                Self::refresh_last_data(device.id, redis).await?;
+                let txn = db.begin().await?;
+                for statement in Rollup::rebuild(job.start, job.end, Some(device.id)) {
+                    txn.execute(statement).await?;
+                }
+                txn.commit().await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decodes and calibrates a stored uplink the way it is on arrival, without the FPort which is not stored.
+    async fn decode_stored(&self, device: &DevicesModel, job: &ReDecodeJob, row: &DeviceDataModel) -> DeviceResult<Vec<DecodeData>> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(&row.bytes)?;
+        let script = match job.script {
+            Some(script) => Some((script, job.version)),
+            None => device.script.map(|script| (script, device.script_version)),
+        };
+        let mut data = match script {
+            Some((script, version)) => {
+                let variables = (!device.variables.is_empty()).then(|| device.variables.clone());
+                let raw = RawData::new(bytes)
+                    .with_codec(0, variables)
+                    .with_recv_time(row.create_time);
+                let data = self.decode(script, version, raw).await?
+                    .ok_or_else(|| DeviceError::data(format!("script {} not found", script)))?;
+                if !data.errors.is_empty() {
+                    return Err(DeviceError::data(data.errors.join("; ")));
+                }
+                DbDecodeData::from(data).0
+            }
+            None => match device.codec {
+                Some(codec) => codec.decode(&bytes).map_err(DeviceError::data)?,
+                None => up_data_decode(&bytes)?.data,
+            },
+        };
+        if data.is_empty() {
+            return Err(DeviceError::data("decoder returned no data"));
+        }
+        GLOBAL_CALIBRATIONS.apply(device.id, &mut data).await?;
+        Ok(data)
+    }
+
+    /// The last uplink of a device before `time`, the previous data of the first row of a job.
+    async fn stored_before(device: Id, time: Timestamp) -> DeviceResult<Option<LastDecodeData>> {
+        let row = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.eq(device))
+            .filter(DeviceDataColumn::CreateTime.lt(time))
+            .order_by_desc(DeviceDataColumn::CreateTime)
+            .one(&GLOBAL_STATE.db)
+            .await?;
+        Ok(row.map(|row| LastDecodeData::new(row.data.0, row.create_time)))
+    }
+
+    async fn report<R: redis::aio::ConnectionLike>(job: &ReDecodeJob, progress: &ReDecodeProgress, redis: &mut R) -> DeviceResult {
+        let _: () = redis::pipe()
+            .set_ex(ReDecodeJob::progress_key(&job.id), progress, ReDecodeJob::TTL)
+            .expire(ReDecodeJob::diff_key(&job.id), ReDecodeJob::TTL as i64)
+            .query_async(redis)
+            .await?;
+        Ok(())
+    }
+
+    async fn refresh_last_data<R: redis::aio::ConnectionLike + Send>(device: Id, redis: &mut R) -> DeviceResult {
+        let latest = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.eq(device))
+            .order_by_desc(DeviceDataColumn::CreateTime)
+            .one(&GLOBAL_STATE.db)
+            .await?;
+        if let Some(latest) = latest {
+            let last_data = LastDecodeData::new(latest.data.0, latest.create_time);
+            let _: () = redis.set(last_device_data_key(device), last_data).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use redis::AsyncCommands;
+use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+use common_define::decode::{DecodeData, LastDecodeData};
+use common_define::event::VirtualPointEvent;
+use common_define::last_device_data_key;
+use common_define::time::Timestamp;
+use common_define::virtual_point::VirtualPoint;
+use crate::man::Id;
+use crate::man::redis_client::RedisRecv;
+use crate::{DeviceResult, GLOBAL_STATE};
+
+type Loaded = (Instant, Arc<Vec<VirtualPoint>>);
+
+/// Virtual data points of each device, loaded on first use.
+///
+/// Every entry is dropped on a [`VirtualPointEvent`], and also after [`Self::TTL`]
+/// so a device moved to another product picks up its points.
+#[derive(Clone, Default)]
+pub struct VirtualPoints {
+    map: Arc<Mutex<HashMap<Id, Loaded>>>,
+}
+
+impl VirtualPoints {
+    const TTL: Duration = Duration::from_secs(60);
+
+    pub async fn load(&self, device: Id) -> DeviceResult<Arc<Vec<VirtualPoint>>> {
+        if let Some((time, points)) = self.map.lock().unwrap().get(&device) {
+            if time.elapsed() < Self::TTL {
+                return Ok(points.clone())
+            }
+        }
+        let points: Vec<_> = VirtualPoint::of_device(&GLOBAL_STATE.db, device)
+            .await?
+            .iter()
+            .filter_map(|model| match VirtualPoint::new(model) {
+                Ok(point) => Some(point),
+                Err(e) => {
+                    warn!(device = %device, point = %model.id, "invalid virtual point: {}", e);
+                    None
+                }
+            })
+            .collect();
+        let points = Arc::new(points);
+        self.map.lock().unwrap().insert(device, (Instant::now(), points.clone()));
+        Ok(points)
+    }
+
+    /// Adds the virtual data points of a device to a decoded uplink,
+    /// the previous uplink is the last data still in redis.
+    pub async fn compute<R: redis::aio::ConnectionLike + Send>(
+        &self,
+        device: Id,
+        data: &mut Vec<DecodeData>,
+        time: Timestamp,
+        redis: &mut R,
+    ) -> DeviceResult {
+        let points = self.load(device).await?;
+        if points.is_empty() {
+            return Ok(())
+        }
+        let prev: Option<LastDecodeData> = redis.get(last_device_data_key(device)).await?;
+        VirtualPoint::compute(&points, data, prev.as_ref(), time);
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.map.lock().unwrap().clear();
+    }
+
+    pub async fn start_invalidate(&self, mut recv: RedisRecv) {
+        let mut s = recv.message();
+        loop {
+            while let Some(msg) = s.next().await {
+                match serde_json::from_slice::<VirtualPointEvent>(msg.get_payload_bytes()) {
+                    Ok(event) => {
+                        debug!(scope = ?event.scope, target = %event.target, "virtual points changed");
+                        self.clear()
+                    }
+                    Err(e) => warn!("invalid virtual point event: {}", e),
+                }
+            }
+        }
+    }
+}
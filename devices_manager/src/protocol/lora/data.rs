@@ -7,13 +7,25 @@ use common_define::lorawan_bridge::{DownStream, RXPK, TXPK, UpMode};
 use device_info::lorawan::{GatewayInfo, NodeInfo};
 use crate::{DeviceResult, DeviceError};
 use crate::man::data::{DataError, DownloadData};
+use crate::protocol::lora::payload::{build_app_encrypted, down_fctrl};
 use crate::{man::{data::{CommandBuilder}}, service::lorawan_node::PushData};
 
 
 
+/// Frame of a downlink the application encrypted, the FCnt must not be used already.
+/// `ack` acknowledges the confirmed uplink the downlink replies to.
+fn build_app_encrypted_task(node: &NodeInfo, task: &DownloadData, f_cnt: u32, ack: bool) -> DeviceResult<Vec<u8>> {
+    if f_cnt < node.down_count {
+        return Err(DeviceError::Device(format!("downlink fcnt {} is used, next is {}", f_cnt, node.down_count)));
+    }
+    let fctrl = down_fctrl(node.adr, ack);
+    Ok(build_app_encrypted(node.dev_addr, f_cnt, task.port, task.bytes.as_ref(), &node.nwk_skey, fctrl))
+}
+
 pub(crate) struct RespDataBuilder<'a> {
     node: &'a NodeInfo,
     meta: &'a PushData,
+    ack: bool,
 }
 
 impl<'a> RespDataBuilder<'a> {
@@ -21,9 +33,21 @@ impl<'a> RespDataBuilder<'a> {
         node: &'a NodeInfo,
         meta: &'a PushData,
     ) -> Self {
-        Self { node, meta }
+        Self { node, meta, ack: false }
+    }
+    /// Acknowledge the uplink, it was a confirmed one.
+    pub fn with_ack(mut self, ack: bool) -> Self {
+        self.ack = ack;
+        self
     }
     pub(crate) fn build_with_task(&self, down: &DownloadData, token: u16, version: u8) -> DeviceResult<DownStream> {
+        if let Some(f_cnt) = down.f_cnt {
+            let r = build_app_encrypted_task(self.node, down, f_cnt, self.ack)?;
+            let len = r.len();
+            let data = base64::engine::general_purpose::STANDARD.encode(r);
+            let txpk = self.calc_args(data, Some(len as u32))?;
+            return Ok(DownStream::new(txpk));
+        }
         let data = &down.bytes;
         self.build(data, &[], Some(down.port))
     }
@@ -175,6 +199,13 @@ impl<'a> RespDataClassCBuilder<'a> {
         task: &DownloadData,
         token: u16, 
     ) -> DeviceResult<DownStream> {
+        if let Some(f_cnt) = task.f_cnt {
+            let r = build_app_encrypted_task(self.node, task, f_cnt, false)?;
+            let len = r.len();
+            let data = base64::engine::general_purpose::STANDARD.encode(r);
+            let txpk = self.calc_args(data, Some(len as u32))?;
+            return Ok(DownStream::new(txpk));
+        }
         self.build(task.bytes.as_ref(), task.port, token)
     }
     pub(crate) fn build_data<D: AsRef<[u8]>>(&self, data: D, _gateway: uuid::Uuid, token: u16) -> DeviceResult<DownStream> {
@@ -2,7 +2,7 @@ use std::ops::Deref;
 use std::sync::Arc;
 
 use lorawan::default_crypto::DefaultFactory;
-
+use lorawan::keys::{CryptoFactory, Mac};
 use lorawan::parser::{DataHeader, DataPayload, DecryptedDataPayload, EncryptedDataPayload};
 use common_define::db::{Key, LoRaAddr};
 
@@ -52,6 +52,94 @@ impl LoRaPayload {
             .map_err(|_| DataError::from("decrypt error, nwk_key or app_skey is invalid"))?;
         Ok(payload)
     }
+
+    /// Checks the MIC and decrypts the payload with the keys the network server holds.
+    /// Without `app_skey` the FRMPayload of an application port is left encrypted.
+    pub(crate) fn open(
+        &self,
+        nwk_skey: &Key,
+        app_skey: Option<&Key>,
+        fcnt: u32,
+    ) -> Result<NodePayload, DataError> {
+        match app_skey {
+            Some(app_skey) => self.decrypt_mic(nwk_skey, app_skey, fcnt).map(NodePayload::Decrypted),
+            None => {
+                let data = EncryptedDataPayload::new(self.inner.as_data_bytes().to_vec())?;
+                if !data.validate_mic(nwk_skey, fcnt) {
+                    return Err(DataError::from("mic error, nwk_skey is invalid"));
+                }
+                if self.f_port().is_some_and(|port| port != 0) {
+                    return Ok(NodePayload::AppEncrypted { fcnt });
+                }
+                let payload = data
+                    .decrypt(Some(nwk_skey), None, fcnt)
+                    .map_err(DataError::from)?;
+                Ok(NodePayload::Decrypted(payload))
+            }
+        }
+    }
+
+    /// FRMPayload as sent by the node, without decryption.
+    pub(crate) fn frm_payload_bytes(&self) -> &[u8] {
+        let data = self.inner.as_data_bytes();
+        let start = 1 + self.inner.fhdr_length() + 1;
+        let end = data.len() - 4;
+        if start < end {
+            &data[start..end]
+        } else {
+            &[]
+        }
+    }
+}
+
+/// Uplink payload after the network server checks.
+pub(crate) enum NodePayload {
+    Decrypted(DecryptedDataPayload<Vec<u8>>),
+    /// FRMPayload for the application, which holds the AppSKey.
+    AppEncrypted { fcnt: u32 },
+}
+
+/// FCtrl of a data down frame, `ack` is only set in the reply to a confirmed uplink.
+pub(crate) fn down_fctrl(adr: bool, ack: bool) -> u8 {
+    let mut fctrl = 0;
+    if adr {
+        fctrl |= 0x80;
+    }
+    if ack {
+        fctrl |= 0x20;
+    }
+    fctrl
+}
+
+/// Builds an unconfirmed data down frame from a FRMPayload the application already encrypted.
+pub(crate) fn build_app_encrypted(
+    dev_addr: LoRaAddr,
+    fcnt: u32,
+    port: u8,
+    frm_payload: &[u8],
+    nwk_skey: &Key,
+    fctrl: u8,
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(13 + frm_payload.len());
+    frame.push(0x60);
+    frame.extend_from_slice(&dev_addr.to_bytes());
+    frame.push(lorawan::parser::FCtrl::new(fctrl, false).raw_value());
+    frame.extend_from_slice(&(fcnt as u16).to_le_bytes());
+    frame.push(port);
+    frame.extend_from_slice(frm_payload);
+
+    let mut b0 = [0; 16];
+    b0[0] = 0x49;
+    b0[5] = 1;
+    b0[6..10].copy_from_slice(&frame[1..5]);
+    b0[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    b0[15] = frame.len() as u8;
+    let mut mac = DefaultFactory.new_mac(nwk_skey);
+    mac.input(&b0);
+    mac.input(&frame);
+    let mic = mac.result();
+    frame.extend_from_slice(&mic[..4]);
+    frame
 }
 
 impl Clone for LoRaPayload {
@@ -60,4 +148,36 @@ impl Clone for LoRaPayload {
             inner: Arc::clone(&self.inner)
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use lorawan::parser::DataHeader;
+    use common_define::db::{Key, LoRaAddr};
+    use super::{build_app_encrypted, down_fctrl};
+
+    #[test]
+    fn test_build_app_encrypted() {
+        let nwk_skey = Key::new([2; 16]);
+        let app_skey = Key::new([1; 16]);
+        let dev_addr = LoRaAddr::from([0x04, 0x03, 0x02, 0x01]);
+        for (adr, ack) in [(true, true), (false, false), (true, false), (false, true)] {
+            let fctrl = down_fctrl(adr, ack);
+            let mut phy = lorawan::creator::DataPayloadCreator::new();
+            phy.set_confirmed(false)
+                .set_uplink(false)
+                .set_f_port(10)
+                .set_dev_addr(&dev_addr.to_bytes())
+                .set_fctrl(&lorawan::parser::FCtrl::new(fctrl, false))
+                .set_fcnt(0x10003);
+            let expected = phy.build(b"hello", &[], &nwk_skey, &app_skey).unwrap().to_vec();
+            let frm_payload = &expected[9..expected.len() - 4];
+            let frame = build_app_encrypted(dev_addr, 0x10003, 10, frm_payload, &nwk_skey, fctrl);
+            assert_eq!(frame, expected);
+            let parsed = lorawan::parser::EncryptedDataPayload::new(frame).unwrap();
+            assert!(parsed.validate_mic(&nwk_skey, 0x10003));
+            assert_eq!(parsed.f_port(), Some(10));
+            assert_eq!(parsed.fhdr().fctrl().adr(), adr);
+            assert_eq!(parsed.fhdr().fctrl().ack(), ack);
+        }
+    }
+}
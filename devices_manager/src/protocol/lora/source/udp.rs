@@ -1,6 +1,6 @@
 use crate::{DeviceError, DeviceResult};
 use common_define::db::Eui;
-use common_define::lorawan_bridge::{DownStream, GatewayEventType, GatewaySource, GatewayToken, GatewayUpData, RXPK};
+use common_define::lorawan_bridge::{DownStream, GatewayEventType, GatewaySignature, GatewaySource, GatewayToken, GatewayUpData, RXPK};
 use common_define::time::Timestamp;
 use std::{net::SocketAddr, ops::Deref, sync::Arc};
 use serde::Deserialize;
@@ -65,6 +65,7 @@ impl UdpForward {
     }
     #[instrument(skip(self, s))]
     async fn decode(&mut self, s: &[u8], addr: SocketAddr) -> DeviceResult<GatewayUpData> {
+        let (s, signature) = GatewaySignature::split(s, 12);
         if s.len() < 12 {
             return Err(DeviceError::Data(format!(
                 "gateway receive invalid: {:X?}",
@@ -113,7 +114,7 @@ impl UdpForward {
             version,
             token,
             time: Timestamp::now(),
-            source: GatewaySource { ip: Some(addr) },
+            source: GatewaySource { ip: Some(addr), signature },
             event,
         };
         Ok(data)
@@ -3,17 +3,19 @@ use base64::Engine;
 use derive_new::new;
 use redis::aio::MultiplexedConnection;
 use redis::AsyncCommands;
-use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
-use common_define::db::{DbDecodeData, DeviceDataActiveModel, DevicesEntity, SnapDeviceColumn, SnapDeviceEntity};
+use common_define::db::{DbDecodeData, DevicesEntity, SnapDeviceColumn, SnapDeviceEntity};
 use common_define::decode::LastDecodeData;
 use common_define::last_device_data_key;
 use common_define::time::Timestamp;
 use device_info::snap::SnapDeviceInfo;
 use utils::base64::EncodeBase64;
-use crate::{DeviceError, DeviceResult, GLOBAL_DEPEND, GLOBAL_STATE};
+use crate::{DeviceError, DeviceResult, GLOBAL_DEPEND, GLOBAL_CALIBRATIONS, GLOBAL_STATE, GLOBAL_VIRTUAL_POINTS};
 use crate::decode::{up_data_decode, RawData};
+use crate::event::DecodeEvent;
+use crate::service::store_data;
 use crate::man::mqtt::{MqttMessage, SnapPublisher};
 use crate::man::redis_client::RedisClient;
 use crate::protocol::snap::{DownJson, DownloadData, UpData, UpJson};
@@ -86,7 +88,10 @@ impl NodeProcessor {
                 })?;
                 let down = self.topic.replace("up", "down");
                 
-                let info = SnapDeviceInfo::new(snap.device_id, snap.key, Some(Timestamp::now()), 1, Some(down), device.script, Some(self.pk.freq));
+                let mut info = SnapDeviceInfo::new(snap.device_id, snap.key, Some(Timestamp::now()), 1, Some(down), device.script, Some(self.pk.freq));
+                info.variables = (!device.variables.is_empty()).then_some(device.variables);
+                info.codec = device.codec;
+                info.script_version = device.script.and(device.script_version);
                 info.register(snap.eui, &mut self.redis).await?;
                 info
             }
@@ -106,7 +111,7 @@ impl NodeProcessor {
         let resp = serde_json::to_string(&resp)?;
         let now = Timestamp::now();
         SnapDeviceInfo::update_active_time(eui, now, &mut self.redis).await?;
-        self.redis.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
+        let _: () = self.redis.publish(common_define::event::DeviceEvent::KAFKA_TOPIC, resp).await?;
         
         if ack {
             match DownloadData::new_with_eui(eui)
@@ -139,51 +144,45 @@ impl NodeProcessor {
         }
         match snap_device.script {
             Some(o) => {
-                let script = common_define::db::DecodeScriptEntity::find_by_id(o)
-                    .one(&GLOBAL_STATE.db)
-                    .await?;
-                match script {
+                let bytes_b64 = payload.encode_base64();
+                let raw = RawData::new(payload).with_codec(0, snap_device.variables.clone());
+                match GLOBAL_DEPEND.decode(o, snap_device.script_version, raw).await? {
                     None => {
                         warn!("Not found Script");
                     }
-                    Some(script) => {
-                        let bytes_b64 = payload.encode_base64();
-                        let decodedata = GLOBAL_DEPEND.decode_with_code(o, script.script.as_str(), RawData::new(payload)).map_err(|e| DeviceError::data("js decode"))?;
+                    Some(decodedata) => {
+                        DecodeEvent::codec(snap_device.id, o, 0, &decodedata, &mut self.redis).await?;
                         if decodedata.data.is_empty() {
                             warn!("js return null");
                             return Ok(())
                         }
                         let last_key = last_device_data_key(snap_device.id);
-                        let data: DbDecodeData = decodedata.into();
+                        let mut data: DbDecodeData = decodedata.into();
                         let now = Timestamp::now();
+                        GLOBAL_CALIBRATIONS.apply(snap_device.id, &mut data.0).await?;
+                        GLOBAL_VIRTUAL_POINTS.compute(snap_device.id, &mut data.0, now, &mut self.redis).await?;
                         let last_data = LastDecodeData::new(data.0.clone(), now);
-                        self.redis.set(last_key, last_data).await?;
-                        let data = DeviceDataActiveModel {
-                            id: Default::default(),
-                            device_id: ActiveValue::Set(snap_device.id),
-                            data: ActiveValue::Set(data),
-                            bytes: ActiveValue::Set(bytes_b64),
-                            create_time: ActiveValue::Set(now),
-                        };
-                        data.insert(&GLOBAL_STATE.db).await?;
+                        let _: () = self.redis.set(last_key, last_data).await?;
+                        store_data(snap_device.id, data, bytes_b64, now).await?;
                     }
                 }
             }
             None => {
-                let decoded_data = up_data_decode(payload)?;
-                let last_data = LastDecodeData::new(decoded_data.data.clone(), now);
-                info!("decode {:?}", decoded_data);
+                let mut decoded_data = match snap_device.codec {
+                    Some(codec) => codec.decode(payload).map_err(DeviceError::data)?,
+                    None => {
+                        let decoded_data = up_data_decode(payload)?;
+                        info!("decode {:?}", decoded_data);
+                        decoded_data.data
+                    }
+                };
+                GLOBAL_CALIBRATIONS.apply(snap_device.id, &mut decoded_data).await?;
+                GLOBAL_VIRTUAL_POINTS.compute(snap_device.id, &mut decoded_data, now, &mut self.redis).await?;
+                let last_data = LastDecodeData::new(decoded_data.clone(), now);
                 let last_key = last_device_data_key(snap_device.id);
-                self.redis.set(last_key, last_data).await?;
+                let _: () = self.redis.set(last_key, last_data).await?;
                 let bytes_b64 = payload.encode_base64();
-                let data = DeviceDataActiveModel {
-                    id: Default::default(),
-                    device_id: ActiveValue::Set(snap_device.id),
-                    data: ActiveValue::Set(DbDecodeData(decoded_data.data)),
-                    bytes: ActiveValue::Set(bytes_b64),
-                    create_time: ActiveValue::Set(now),
-                };
-                data.insert(&GLOBAL_STATE.db).await?;
+                store_data(snap_device.id, DbDecodeData(decoded_data), bytes_b64, now).await?;
             }
         }
 
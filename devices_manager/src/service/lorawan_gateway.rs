@@ -2,9 +2,9 @@ use tracing::{instrument, warn};
 use tracing::log::debug;
 use common_define::event::lora_gateway::GatewayStatus;
 use common_define::lorawan_bridge::{GatewayEventType, GatewayUpData, GatewayUpDataHeader, RXPK};
-use crate::{man::lora::LoRaGateManager, DeviceResult};
+use crate::{man::lora::LoRaGateManager, DeviceError, DeviceResult};
 use crate::event::gateway::GatewayEvent;
-use crate::man::lora::LoRaGate;
+use crate::man::lora::{GatewaySourceCheck, LoRaGate};
 use crate::service::lorawan_node::{node_data, PushData};
 
 pub(crate) fn gateway_event(
@@ -28,6 +28,11 @@ async fn gateway_event_process(
     event: GatewayUpData,
 ) -> DeviceResult {
     let mut gw = LoRaGateManager::get_gate(event.eui).await?;
+    if let GatewaySourceCheck::Conflict { previous, previous_time } = gw.check_source(&event.source).await? {
+        let msg = format!("gateway {} seen from {:?} while active at {}", event.eui, event.source.ip, previous);
+        GatewayEvent::gateway_conflict(gw.id, &event, previous, previous_time).await?;
+        return Err(DeviceError::Warn(msg));
+    }
     gw.update_version(event.version).await?;
     GatewayEvent::gateway_state(gw.id, event.clone()).await?;
     
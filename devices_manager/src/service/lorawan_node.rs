@@ -1,14 +1,14 @@
 use crate::man::lora::{LoRaGate, LoRaNode, LoRaNodeManager};
 use crate::man::Id;
 use crate::protocol::lora;
-use crate::protocol::lora::payload::LoRaPayload;
-use crate::{decode, DeviceError, DeviceResult, GLOBAL_DEPEND, GLOBAL_STATE};
-use common_define::db::{DbDecodeData, DeviceDataActiveModel, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DevicesEntity, Eui, Key, LoRaAddr};
-use common_define::lora::LoRaJoinType;
+use crate::protocol::lora::payload::{LoRaPayload, NodePayload};
+use crate::{decode, DeviceError, DeviceResult, GLOBAL_DEPEND, GLOBAL_CALIBRATIONS, GLOBAL_STATE, GLOBAL_VIRTUAL_POINTS};
+use common_define::db::{DbDecodeData, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DevicesEntity, Eui, FPortRoute, FPortRoutes, LoRaAddr};
+use common_define::lora::{FCntPolicy, LoRaJoinType};
 use common_define::lorawan_bridge::{GatewayToken, RXPK};
 use common_define::time::Timestamp;
 use common_define::last_device_data_key;
-use lorawan::parser::{DataHeader, DecryptedDataPayload};
+use lorawan::parser::DataHeader;
 use once_cell::sync::Lazy;
 use tracing::instrument;
 use std::collections::HashMap;
@@ -22,15 +22,16 @@ use device_info::lorawan::NodeInfo;
 use utils::base64::EncodeBase64;
 
 use crate::decode::RawData;
-use crate::event::LoRaNodeEvent;
-use crate::integration::mqtt::{MqttMessage, MqttRawData};
+use crate::event::{DecodeEvent, LoRaNodeEvent};
+use crate::integration::mqtt::{MqttEncryptedData, MqttMessage, MqttPortData, MqttRawData};
 use crate::man::redis_client::RedisClient;
+use crate::service::store_data;
 use crate::protocol::lora::join_request::RequestJoin;
 
 struct DataItem {
     push: PushData,
     payload: LoRaPayload,
-    data: DecryptedDataPayload<Vec<u8>>,
+    data: NodePayload,
 }
 
 struct RequestCache {
@@ -88,7 +89,7 @@ impl DataCache {
         addr: LoRaAddr,
         push: PushData,
         payload: LoRaPayload,
-        data: DecryptedDataPayload<Vec<u8>>,
+        data: NodePayload,
     ) -> bool {
         let mut map = self.map.lock().unwrap();
         let m = Arc::clone(&self.map);
@@ -191,10 +192,9 @@ async fn decode_payload(
     push_data: &PushData,
     node: &mut LoRaNode,
     header: &LoRaPayload,
-    payload: DecryptedDataPayload<Vec<u8>>,
+    payload: NodePayload,
 ) -> DeviceResult {
     node.update_time().await?;
-    let conn = &GLOBAL_STATE.db;
     let mut redis = RedisClient::get_client().get_multiplexed_conn().await?;
     let all_data = MqttRawData {
         device: node.info.device_id,
@@ -203,70 +203,111 @@ async fn decode_payload(
     let msg = MqttMessage::new_row_data(&all_data, 1)?;
     node.update_gateway().await?;
 
-    for cmd in payload.fhdr().fopts() {
+    for cmd in header.fhdr().fopts() {
         warn!("fopt command: {:?}", cmd);
     }
 
+    let payload = match payload {
+        NodePayload::Decrypted(payload) => payload,
+        NodePayload::AppEncrypted { fcnt } => {
+            let frm_payload = header.frm_payload_bytes();
+            tracing::info!("UpLink encrypted: {:02X?}", frm_payload);
+            node.pull_task(frm_payload, push_data, header).await?;
+            LoRaNodeEvent::uplink(header, node, push_data, None, &mut redis).await?;
+            let encrypted = MqttEncryptedData {
+                device: node.info.device_id,
+                dev_addr: node.info.dev_addr,
+                f_port: header.f_port().unwrap_or_default(),
+                f_cnt: fcnt,
+                frm_payload: frm_payload.encode_base64(),
+            };
+            MqttMessage::new_encrypted_data(&encrypted, 1)?.publish().await?;
+            return Ok(());
+        }
+    };
     let payload = payload.frm_payload().map_err(DeviceError::data)?;
     match payload {
         lorawan::parser::FRMPayload::Data(data) => {
             tracing::info!("UpLink: {:02X?}", data);
             node.pull_task(data, push_data, header).await?;
-            LoRaNodeEvent::uplink(header, node, push_data, data, &mut redis).await?;
-            match node.info.script { 
+            LoRaNodeEvent::uplink(header, node, push_data, Some(data), &mut redis).await?;
+            let f_port = header.f_port().unwrap_or_default();
+            if FPortRoutes::is_reserved(f_port) {
+                info!("fport {} is reserved for application packages", f_port);
+                return Ok(());
+            }
+            let route = node.info.fport_routes.as_ref().and_then(|routes| routes.route(f_port));
+            let (script, version) = match route.and_then(|route| route.script) {
+                Some(script) => (Some(script), None),
+                None => (node.info.script, node.info.script_version),
+            };
+            let store = route.map(|route| route.store).unwrap_or(true);
+            let topic = route.and_then(|route| route.topic.clone());
+            let mut decoded: DbDecodeData = match script {
                 Some(o) => {
-                    let script = common_define::db::DecodeScriptEntity::find_by_id(o)
-                        .one(conn)
-                        .await?;
-                    match script {
+                    let raw = RawData::new(data).with_codec(f_port, node.info.variables.clone());
+                    match GLOBAL_DEPEND.decode(o, version, raw).await? {
                         None => {
                             warn!("Not found Script");
+                            return Ok(())
                         }
-                        Some(script) => {
-                            let bytes_b64 = data.encode_base64();
-                            let decodedata = GLOBAL_DEPEND.decode_with_code(o, script.script.as_str(), RawData::new(data)).map_err(|e| DeviceError::data("js decode"))?;
+                        Some(decodedata) => {
+                            DecodeEvent::codec(node.info.device_id, o, f_port, &decodedata, &mut redis).await?;
                             if decodedata.data.is_empty() {
                                 warn!("js return null");
                                 return Ok(())
                             }
-                            let last_key = last_device_data_key(node.info.device_id);
-                            let data: DbDecodeData = decodedata.into();
-                            let now = Timestamp::now();
-                            let last_data = LastDecodeData::new(data.0.clone(), now);
-                            redis.set(last_key, last_data).await?;
-                            let data = DeviceDataActiveModel {
-                                id: Default::default(),
-                                device_id: ActiveValue::Set(node.info.device_id),
-                                data: ActiveValue::Set(data),
-                                bytes: ActiveValue::Set(bytes_b64),
-                                create_time: ActiveValue::Set(now),
-                            };
-                            data.insert(conn).await?;
+                            decodedata.into()
                         }
                     }
                 }
-                None => {
+                None => if let Some(codec) = node.info.codec {
+                    DbDecodeData(codec.decode(data).map_err(DeviceError::data)?)
+                } else {
                     let decoded_data = decode::up_data_decode(data)?;
 
                     info!("decode {:?}", decoded_data);
-                    let bytes_b64 = data.encode_base64();
-                    let last_key = last_device_data_key(node.info.device_id);
-                    let now = Timestamp::now();
-                    let last_data = LastDecodeData::new(decoded_data.data.clone(), now);
-                    debug!("save last data");
-                    redis.set(last_key, last_data).await?;
-                    let data = DeviceDataActiveModel {
-                        id: Default::default(),
-                        device_id: ActiveValue::Set(node.info.device_id),
-                        data: ActiveValue::Set(DbDecodeData(decoded_data.data)),
-                        bytes: ActiveValue::Set(bytes_b64),
-                        create_time: ActiveValue::Set(Timestamp::now()),
-                    };
                     if let Some(status) = decoded_data.status {
                         debug!("change battery status");
-                        redis.hset(node.key.as_str(), (NodeInfo::battery(),status.battery), (NodeInfo::charge(), status.charge)).await?;
+                        let _: () = redis.hset(node.key.as_str(), (NodeInfo::battery(),status.battery), (NodeInfo::charge(), status.charge)).await?;
                     }
-                    data.insert(conn).await?;
+                    DbDecodeData(decoded_data.data)
+                }
+            };
+            let now = Timestamp::now();
+            GLOBAL_CALIBRATIONS.apply(node.info.device_id, &mut decoded.0).await?;
+            GLOBAL_VIRTUAL_POINTS.compute(node.info.device_id, &mut decoded.0, now, &mut redis).await?;
+            let bytes_b64 = data.encode_base64();
+            let topic = topic.filter(|topic| {
+                let valid = FPortRoute::is_valid_topic(topic);
+                if !valid {
+                    warn!("fport {} topic {} is invalid", f_port, topic);
+                }
+                valid
+            });
+            let port_data = topic.map(|topic| (topic, MqttPortData {
+                device: node.info.device_id,
+                f_port,
+                bytes: bytes_b64.clone(),
+                data: decoded.0.clone(),
+            }));
+            if store {
+                let last_key = last_device_data_key(node.info.device_id);
+                let last_data = LastDecodeData::new(decoded.0.clone(), now);
+                debug!("save last data");
+                let _: () = redis.set(last_key, last_data).await?;
+                store_data(node.info.device_id, decoded, bytes_b64, now).await?;
+            } else {
+                debug!("fport {} data is not stored", f_port);
+            }
+            if let Some((topic, port_data)) = port_data {
+                // the data is stored already, a broker failure must not drop it
+                let publish = match MqttMessage::new_port_data(&port_data, &topic, 1) {
+                    Ok(msg) => msg.publish().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = publish {
+                    warn!("publish fport {} data to {} failed: {}", f_port, topic, e);
                 }
             }
 
@@ -317,7 +358,8 @@ async fn request_warp(
                 error!("dev_eui({}) in lora_node, but found in devices", dev_eui);
                 return Err(DeviceError::Empty);
             }
-            NodeInfo::register_to_redis(node, devices.unwrap(), &mut redis_conn).await?
+            let profile = node.load_profile(&GLOBAL_STATE.db).await?;
+            NodeInfo::register_to_redis(node, devices.unwrap(), profile, &mut redis_conn).await?
         }
         Some(info) => info,
     };
@@ -387,6 +429,9 @@ async fn decode_enc_payload(
                         .arg(up_count)
                         .exec_async(&mut conn)
                         .await?;
+                    // a new session counts from zero again, its first frame is no replay
+                    node.info.up_count = 0;
+                    node.info.active_time = None;
                 }
                 Err(_) => {
                     warn!("otaa join decrypt mic failed");
@@ -455,26 +500,37 @@ async fn decode_node_payload(
 
 
 async fn payload_decode(node: &mut LoRaNode, payload: &LoRaPayload, current_up_count: u16)
-  -> DeviceResult<DecryptedDataPayload<Vec<u8>>>
+  -> DeviceResult<NodePayload>
 {
-    
+    // with application side encryption the network server only has the NwkSKey
+    let app_skey = (!node.info.is_app_encrypted()).then_some(node.info.app_skey);
     let pre_count = node.info.up_count as u16;
     let mut new_up_count = node.info.up_count;
     let up_count_diff = current_up_count.wrapping_sub(pre_count) as u32;
     info!("pre_count: {}, count: {}, up_count_diff: {}", pre_count, current_up_count, up_count_diff );
+    let strict = node.info.fcnt_policy == Some(FCntPolicy::Strict);
+    // a repeated counter is only valid for the very first frame of a session
+    if strict && up_count_diff == 0 && node.info.active_time.is_some() {
+        warn!("{} frame counter {} repeated under strict fcnt policy", node.info.dev_eui, current_up_count);
+        return Err(DeviceError::Warn(format!("frame counter {} rejected", current_up_count)));
+    }
     let decode = if up_count_diff < ( 1 << 15 ) {
         new_up_count = new_up_count.wrapping_add(up_count_diff);
-        payload.decrypt_mic(&node.info.nwk_skey, &node.info.app_skey, new_up_count)
+        payload.open(&node.info.nwk_skey, app_skey.as_ref(), new_up_count)
     } else {
         new_up_count = new_up_count.wrapping_add(0x10000).wrapping_add(up_count_diff);
-        payload.decrypt_mic(&node.info.nwk_skey, &node.info.app_skey, new_up_count)
+        payload.open(&node.info.nwk_skey, app_skey.as_ref(), new_up_count)
     };
     if let Ok(o) = decode {
         node.update_up_count(new_up_count).await?;
         return Ok(o)
     }
+    if strict {
+        warn!("{} frame counter {} rejected by strict fcnt policy", node.info.dev_eui, current_up_count);
+        return Err(DeviceError::Warn(format!("frame counter {} rejected", current_up_count)));
+    }
     // ABP device reset
-    let payload = payload.decrypt_mic(&node.info.nwk_skey, &node.info.app_skey, current_up_count as u32)?;
+    let payload = payload.open(&node.info.nwk_skey, app_skey.as_ref(), current_up_count as u32)?;
     node.update_up_count(current_up_count as u32).await?;
     node.reset_down_count().await?;
     Ok(payload)
@@ -5,3 +5,19 @@ pub(crate) mod device;
 pub(crate) mod data_decode;
 pub mod custom_gateway;
 
+
+use common_define::db::DbDecodeData;
+use common_define::time::Timestamp;
+use crate::{DeviceResult, GLOBAL_STATE};
+use crate::man::Id;
+
+/// Stores a decoded uplink in the configured telemetry store.
+pub(crate) async fn store_data(
+    device: Id,
+    data: DbDecodeData,
+    bytes: String,
+    time: Timestamp,
+) -> DeviceResult {
+    GLOBAL_STATE.telemetry.write(device, time, &data, &bytes).await?;
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! WebAssembly decoders.
+//!
+//! A decoder module exports:
+//! - `memory`, its linear memory;
+//! - `alloc(len: i32) -> i32`, returning space for `len` bytes the payload is written to;
+//! - `decode(ptr: i32, len: i32, f_port: i32) -> i64`, returning `ptr << 32 | len` of a UTF-8
+//!   JSON array in `memory`, the same `[{"i": id, "v": value}]` a JS `decodeUplink` returns.
+//!
+//! Modules have no imports, each decode runs in a new instance with limited fuel and memory.
+
+use std::sync::Arc;
+use common_define::decode::Value;
+use serde::Deserialize;
+use tokio::task;
+use crate::decode::{DecodeData, DecodeDataItem, JsDecodeError, RawData};
+
+/// Limits of one WebAssembly decode.
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel of one decode, roughly the number of executed instructions.
+    pub fuel: u64,
+    /// Memory limit of each instance in bytes.
+    pub memory: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 100_000_000,
+            memory: 16 << 20,
+        }
+    }
+}
+
+struct WasmState {
+    limits: wasmi::StoreLimits,
+}
+
+#[derive(Clone)]
+pub struct WasmManager {
+    engine: wasmi::Engine,
+    limits: WasmLimits,
+}
+
+impl From<wasmi::Error> for JsDecodeError {
+    fn from(value: wasmi::Error) -> Self {
+        match value.as_trap_code() {
+            Some(wasmi::core::TrapCode::OutOfFuel) => Self::TimeOut { stack: None },
+            _ => Self::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl WasmManager {
+    pub fn new(limits: WasmLimits) -> Self {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        Self {
+            engine: wasmi::Engine::new(&config),
+            limits,
+        }
+    }
+
+    /// Validates and compiles a module.
+    pub fn compile(&self, bytes: &[u8]) -> Result<wasmi::Module, JsDecodeError> {
+        Ok(wasmi::Module::new(&self.engine, bytes)?)
+    }
+
+    pub async fn decode(&self, module: Arc<wasmi::Module>, data: RawData) -> Result<DecodeData, JsDecodeError> {
+        let manager = self.clone();
+        task::spawn_blocking(move || manager.run(&module, data.bytes(), data.f_port()))
+            .await
+            .map_err(|e| JsDecodeError::Unknown(e.to_string()))?
+    }
+
+    fn run(&self, module: &wasmi::Module, bytes: &[u8], f_port: u8) -> Result<DecodeData, JsDecodeError> {
+        let limits = wasmi::StoreLimitsBuilder::new()
+            .memory_size(self.limits.memory)
+            .build();
+        let mut store = wasmi::Store::new(&self.engine, WasmState { limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel).map_err(|e| JsDecodeError::Unknown(e.to_string()))?;
+        let linker = wasmi::Linker::<WasmState>::new(&self.engine);
+        let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or_else(|| JsDecodeError::Export("most export memory".to_string()))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_| JsDecodeError::Export("most export alloc(i32) -> i32".to_string()))?;
+        let decode = instance.get_typed_func::<(i32, i32, i32), i64>(&store, "decode")
+            .map_err(|_| JsDecodeError::Export("most export decode(i32, i32, i32) -> i64".to_string()))?;
+
+        let ptr = alloc.call(&mut store, bytes.len() as i32)?;
+        memory.write(&mut store, ptr as u32 as usize, bytes)
+            .map_err(|_| JsDecodeError::Memory)?;
+        let output = decode.call(&mut store, (ptr, bytes.len() as i32, f_port as i32))?;
+        // the output is read in place, its length is checked against the memory of the instance
+        let (start, len) = ((output >> 32) as u32 as usize, output as u32 as usize);
+        let json = start.checked_add(len)
+            .filter(|_| len <= self.limits.memory)
+            .and_then(|end| memory.data(&store).get(start..end))
+            .ok_or_else(|| JsDecodeError::Return("output is out of memory".to_string()))?;
+
+        #[derive(Deserialize)]
+        struct Item {
+            i: i32,
+            v: Value,
+        }
+        let items: Vec<Item> = serde_json::from_slice(json)
+            .map_err(|e| JsDecodeError::Return(e.to_string()))?;
+        Ok(DecodeData {
+            data: items.into_iter().map(|it| DecodeDataItem { v: it.v, i: it.i }).collect(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use common_define::decode::Value;
+    use crate::decode::{JsDecodeError, RawData};
+    use super::{WasmLimits, WasmManager};
+
+    /// Returns the first payload byte as data point 1, the JSON is built in memory at 1024.
+    const DECODER: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 1024) "[{\"i\":1,\"v\":0}]")
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "decode") (param $ptr i32) (param $len i32) (param $port i32) (result i64)
+    (i32.store8 (i32.const 1036) (i32.add (i32.const 48) (i32.load8_u (local.get $ptr))))
+    (i64.or (i64.shl (i64.const 1024) (i64.const 32)) (i64.const 15)))
+)"#;
+
+    const LOOP: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "decode") (param i32 i32 i32) (result i64)
+    (loop $l (br $l))
+    (i64.const 0))
+)"#;
+
+    /// Claims an output of 4 GiB at the end of its single page.
+    const HUGE: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "decode") (param i32 i32 i32) (result i64)
+    (i64.or (i64.shl (i64.const 65000) (i64.const 32)) (i64.const 0xFFFFFFFF)))
+)"#;
+
+    #[tokio::test]
+    async fn test_wasm_decode() {
+        let manager = WasmManager::new(WasmLimits { fuel: 100_000, ..Default::default() });
+        let module = manager.compile(&wat::parse_str(DECODER).unwrap()).unwrap();
+        let data = manager.decode(Arc::new(module), RawData::new([7])).await.unwrap();
+        assert_eq!(data.data.len(), 1);
+        assert_eq!(data.data[0].i, 1);
+        assert_eq!(data.data[0].v, Value::Int(7));
+
+        let module = manager.compile(&wat::parse_str(LOOP).unwrap()).unwrap();
+        let r = manager.decode(Arc::new(module), RawData::new([7])).await;
+        assert!(matches!(r, Err(JsDecodeError::TimeOut { .. })));
+        let module = manager.compile(&wat::parse_str(HUGE).unwrap()).unwrap();
+        let r = manager.decode(Arc::new(module), RawData::new([7])).await;
+        assert!(matches!(r, Err(JsDecodeError::Return(_))));
+        assert!(manager.compile(b"not wasm").is_err());
+    }
+}
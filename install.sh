@@ -4,6 +4,7 @@ install_dir=$(pwd)/snapemu
 db_password=''
 web_domain=''
 jwt_key=$(head /dev/urandom | tr -dc A-Za-z0-9 | head -c 16)
+kek=$(head -c 16 /dev/urandom | od -An -tx1 | tr -d ' \n')
 
 RED='\e[31m'
 RESET='\e[0m'
@@ -212,6 +213,8 @@ db:
   username: postgres
   db: snapemu
 jwt_key: ${jwt_key}
+key:
+  kek: ${kek}
 EOF
 }
 
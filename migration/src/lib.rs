@@ -1,6 +1,21 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20240904_020441_create_table;
+mod m20261019_082311_device_profile;
+mod m20261019_094502_fport_route;
+mod m20261019_121530_payload_encryption;
+mod m20261019_140210_gateway_source;
+mod m20261019_163045_decode_codec;
+mod m20261019_181207_downlink_encoder;
+mod m20261019_203518_device_codec;
+mod m20261019_221406_decode_script_version;
+mod m20261019_235830_decode_test_vector;
+mod m20261020_013204_device_data_rollup;
+mod m20261020_041755_data_retention;
+mod m20261020_062318_virtual_point;
+mod m20261020_083541_data_calibration;
+mod m20261020_101226_user_time_zone;
+mod m20261020_120000_wrap_keys;
 
 pub struct Migrator;
 
@@ -9,6 +24,21 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(m20240904_020441_create_table::Migration),
+            Box::new(m20261019_082311_device_profile::Migration),
+            Box::new(m20261019_094502_fport_route::Migration),
+            Box::new(m20261019_121530_payload_encryption::Migration),
+            Box::new(m20261019_140210_gateway_source::Migration),
+            Box::new(m20261019_163045_decode_codec::Migration),
+            Box::new(m20261019_181207_downlink_encoder::Migration),
+            Box::new(m20261019_203518_device_codec::Migration),
+            Box::new(m20261019_221406_decode_script_version::Migration),
+            Box::new(m20261019_235830_decode_test_vector::Migration),
+            Box::new(m20261020_013204_device_data_rollup::Migration),
+            Box::new(m20261020_041755_data_retention::Migration),
+            Box::new(m20261020_062318_virtual_point::Migration),
+            Box::new(m20261020_083541_data_calibration::Migration),
+            Box::new(m20261020_101226_user_time_zone::Migration),
+            Box::new(m20261020_120000_wrap_keys::Migration),
         ]
     }
 }
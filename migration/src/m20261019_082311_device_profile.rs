@@ -0,0 +1,105 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDeviceProfile::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapDeviceProfile::Id))
+                    .col(big_integer(SnapDeviceProfile::Owner))
+                    .col(text(SnapDeviceProfile::Name))
+                    .col(text(SnapDeviceProfile::Description))
+                    .col(text(SnapDeviceProfile::MacVersion))
+                    .col(text(SnapDeviceProfile::RegParamsRevision))
+                    .col(text(SnapDeviceProfile::Region))
+                    .col(boolean(SnapDeviceProfile::ClassB))
+                    .col(boolean(SnapDeviceProfile::ClassC))
+                    .col(boolean(SnapDeviceProfile::Adr))
+                    .col(small_integer(SnapDeviceProfile::Rx1Delay))
+                    .col(small_integer(SnapDeviceProfile::Rx1Dro))
+                    .col(small_integer(SnapDeviceProfile::Rx2Dr))
+                    .col(integer(SnapDeviceProfile::Rx2Freq))
+                    .col(integer(SnapDeviceProfile::Dutycyle))
+                    .col(small_integer(SnapDeviceProfile::DRetry))
+                    .col(small_integer(SnapDeviceProfile::CRetry))
+                    .col(text(SnapDeviceProfile::FcntPolicy))
+                    .col(big_integer_null(SnapDeviceProfile::Script))
+                    .col(timestamp_with_time_zone(SnapDeviceProfile::CreateTime).default(Expr::current_timestamp()))
+                    .col(timestamp_with_time_zone(SnapDeviceProfile::ModifyTime).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .add_column_if_not_exists(big_integer_null(SnapDeviceLoraNode::Profile))
+                    .add_column_if_not_exists(json(SnapDeviceLoraNode::Overrides).default(Expr::cust("'{}'::json")))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("lora-node-profile-idx")
+                    .table(SnapDeviceLoraNode::Table)
+                    .col(SnapDeviceLoraNode::Profile)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .drop_column(SnapDeviceLoraNode::Profile)
+                    .drop_column(SnapDeviceLoraNode::Overrides)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table(Table::drop().table(SnapDeviceProfile::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceProfile {
+    Table,
+    Id,
+    Owner,
+    Name,
+    Description,
+    MacVersion,
+    RegParamsRevision,
+    Region,
+    ClassB,
+    ClassC,
+    Adr,
+    Rx1Delay,
+    Rx1Dro,
+    Rx2Dr,
+    Rx2Freq,
+    Dutycyle,
+    DRetry,
+    CRetry,
+    FcntPolicy,
+    Script,
+    CreateTime,
+    ModifyTime,
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceLoraNode {
+    Table,
+    Profile,
+    Overrides,
+}
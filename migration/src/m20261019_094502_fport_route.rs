@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceProfile::Table)
+                    .add_column_if_not_exists(json(SnapDeviceProfile::FportRoutes).default(Expr::cust("'[]'::json")))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .add_column_if_not_exists(json(SnapDeviceLoraNode::FportRoutes).default(Expr::cust("'[]'::json")))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .drop_column(SnapDeviceLoraNode::FportRoutes)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceProfile::Table)
+                    .drop_column(SnapDeviceProfile::FportRoutes)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceProfile {
+    Table,
+    FportRoutes,
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceLoraNode {
+    Table,
+    FportRoutes,
+}
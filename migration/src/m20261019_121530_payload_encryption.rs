@@ -0,0 +1,38 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .add_column_if_not_exists(text(SnapDeviceLoraNode::PayloadEncryption).default("Server"))
+                    .add_column_if_not_exists(text_null(SnapDeviceLoraNode::AppKek))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraNode::Table)
+                    .drop_column(SnapDeviceLoraNode::PayloadEncryption)
+                    .drop_column(SnapDeviceLoraNode::AppKek)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceLoraNode {
+    Table,
+    PayloadEncryption,
+    AppKek,
+}
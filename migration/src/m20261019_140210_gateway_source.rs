@@ -0,0 +1,38 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraGate::Table)
+                    .add_column_if_not_exists(json(SnapDeviceLoraGate::AllowList).default(Expr::cust("'[]'::json")))
+                    .add_column_if_not_exists(text_null(SnapDeviceLoraGate::HmacKey))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDeviceLoraGate::Table)
+                    .drop_column(SnapDeviceLoraGate::AllowList)
+                    .drop_column(SnapDeviceLoraGate::HmacKey)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceLoraGate {
+    Table,
+    AllowList,
+    HmacKey,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDecodeScript::Table)
+                    .add_column_if_not_exists(json(SnapDecodeScript::Codec).default(Expr::cust("'{}'::json")))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .add_column_if_not_exists(json(SnapDevices::Variables).default(Expr::cust("'{}'::json")))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDecodeScript::Table)
+                    .drop_column(SnapDecodeScript::Codec)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .drop_column(SnapDevices::Variables)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDecodeScript {
+    Table,
+    Codec,
+}
+
+#[derive(DeriveIden)]
+enum SnapDevices {
+    Table,
+    Variables,
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .add_column_if_not_exists(big_integer_null(SnapDevices::Encoder))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapProductInfo::Table)
+                    .add_column_if_not_exists(big_integer_null(SnapProductInfo::Encoder))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDownlink::Table)
+                    .add_column_if_not_exists(json_null(SnapDownlink::Json))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .drop_column(SnapDevices::Encoder)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapProductInfo::Table)
+                    .drop_column(SnapProductInfo::Encoder)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDownlink::Table)
+                    .drop_column(SnapDownlink::Json)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDevices {
+    Table,
+    Encoder,
+}
+
+#[derive(DeriveIden)]
+enum SnapProductInfo {
+    Table,
+    Encoder,
+}
+
+#[derive(DeriveIden)]
+enum SnapDownlink {
+    Table,
+    Json,
+}
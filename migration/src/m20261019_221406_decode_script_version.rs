@@ -0,0 +1,137 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDecodeScriptVersion::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapDecodeScriptVersion::Id))
+                    .col(big_integer(SnapDecodeScriptVersion::ScriptId))
+                    .col(integer(SnapDecodeScriptVersion::Version))
+                    .col(text(SnapDecodeScriptVersion::Script))
+                    .col(text(SnapDecodeScriptVersion::Lang))
+                    .col(json(SnapDecodeScriptVersion::Map))
+                    .col(json(SnapDecodeScriptVersion::Codec).default(Expr::cust("'{}'::json")))
+                    .col(big_integer(SnapDecodeScriptVersion::Author))
+                    .col(text(SnapDecodeScriptVersion::Changelog))
+                    .col(timestamp_with_time_zone(SnapDecodeScriptVersion::CreateTime).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("decode-script-version-idx")
+                    .table(SnapDecodeScriptVersion::Table)
+                    .col(SnapDecodeScriptVersion::ScriptId)
+                    .col(SnapDecodeScriptVersion::Version)
+                    .unique()
+                    .to_owned()
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDecodeScript::Table)
+                    .add_column_if_not_exists(integer(SnapDecodeScript::Version).default(1))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .add_column_if_not_exists(integer_null(SnapDevices::ScriptVersion))
+                    .to_owned(),
+            )
+            .await?;
+        // existing scripts become their version 1
+        let select = Query::select()
+            .column(SnapDecodeScript::Id)
+            .expr(Expr::val(1))
+            .columns([SnapDecodeScript::Script, SnapDecodeScript::Lang, SnapDecodeScript::Map, SnapDecodeScript::Codec, SnapDecodeScript::Owner])
+            .expr(Expr::val(""))
+            .column(SnapDecodeScript::ModifyTime)
+            .from(SnapDecodeScript::Table)
+            .to_owned();
+        let insert = Query::insert()
+            .into_table(SnapDecodeScriptVersion::Table)
+            .columns([
+                SnapDecodeScriptVersion::ScriptId,
+                SnapDecodeScriptVersion::Version,
+                SnapDecodeScriptVersion::Script,
+                SnapDecodeScriptVersion::Lang,
+                SnapDecodeScriptVersion::Map,
+                SnapDecodeScriptVersion::Codec,
+                SnapDecodeScriptVersion::Author,
+                SnapDecodeScriptVersion::Changelog,
+                SnapDecodeScriptVersion::CreateTime,
+            ])
+            .select_from(select)
+            .map_err(|e| DbErr::Migration(e.to_string()))?
+            .to_owned();
+        manager.exec_stmt(insert).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDevices::Table)
+                    .drop_column(SnapDevices::ScriptVersion)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapDecodeScript::Table)
+                    .drop_column(SnapDecodeScript::Version)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table(Table::drop().table(SnapDecodeScriptVersion::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDecodeScriptVersion {
+    Table,
+    Id,
+    ScriptId,
+    Version,
+    Script,
+    Lang,
+    Map,
+    Codec,
+    Author,
+    Changelog,
+    CreateTime,
+}
+
+#[derive(DeriveIden)]
+enum SnapDecodeScript {
+    Table,
+    Id,
+    Script,
+    Lang,
+    Owner,
+    Map,
+    Codec,
+    Version,
+    ModifyTime,
+}
+
+#[derive(DeriveIden)]
+enum SnapDevices {
+    Table,
+    ScriptVersion,
+}
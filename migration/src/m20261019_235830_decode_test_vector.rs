@@ -0,0 +1,52 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDecodeTestVector::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapDecodeTestVector::Id))
+                    .col(big_integer(SnapDecodeTestVector::ScriptId))
+                    .col(text(SnapDecodeTestVector::Name))
+                    .col(text(SnapDecodeTestVector::Bytes))
+                    .col(small_integer(SnapDecodeTestVector::FPort))
+                    .col(json(SnapDecodeTestVector::Expected))
+                    .col(timestamp_with_time_zone(SnapDecodeTestVector::CreateTime).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("decode-test-vector-script-idx")
+                    .table(SnapDecodeTestVector::Table)
+                    .col(SnapDecodeTestVector::ScriptId)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(SnapDecodeTestVector::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDecodeTestVector {
+    Table,
+    Id,
+    ScriptId,
+    Name,
+    Bytes,
+    FPort,
+    Expected,
+    CreateTime,
+}
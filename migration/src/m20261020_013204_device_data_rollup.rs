@@ -0,0 +1,66 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDeviceDataRollup::Table)
+                    .if_not_exists()
+                    .col(big_integer(SnapDeviceDataRollup::DeviceId))
+                    .col(big_integer(SnapDeviceDataRollup::DataId))
+                    .col(integer(SnapDeviceDataRollup::Period))
+                    .col(timestamp_with_time_zone(SnapDeviceDataRollup::Bucket))
+                    .col(big_integer(SnapDeviceDataRollup::Count))
+                    .col(big_integer(SnapDeviceDataRollup::NumCount))
+                    .col(double_null(SnapDeviceDataRollup::Min))
+                    .col(double_null(SnapDeviceDataRollup::Max))
+                    .col(double_null(SnapDeviceDataRollup::Sum))
+                    .col(json_null(SnapDeviceDataRollup::Last))
+                    .col(timestamp_with_time_zone(SnapDeviceDataRollup::LastTime))
+                    .primary_key(
+                        Index::create()
+                            .col(SnapDeviceDataRollup::DeviceId)
+                            .col(SnapDeviceDataRollup::DataId)
+                            .col(SnapDeviceDataRollup::Period)
+                            .col(SnapDeviceDataRollup::Bucket)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("device-data-rollup-bucket-idx")
+                    .table(SnapDeviceDataRollup::Table)
+                    .col(SnapDeviceDataRollup::Bucket)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(SnapDeviceDataRollup::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceDataRollup {
+    Table,
+    DeviceId,
+    DataId,
+    Period,
+    Bucket,
+    Count,
+    NumCount,
+    Min,
+    Max,
+    Sum,
+    Last,
+    LastTime,
+}
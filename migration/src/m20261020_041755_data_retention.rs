@@ -0,0 +1,67 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDataRetention::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapDataRetention::Id))
+                    .col(text(SnapDataRetention::Scope))
+                    .col(big_integer(SnapDataRetention::TargetId))
+                    .col(integer_null(SnapDataRetention::RawDays))
+                    .col(integer_null(SnapDataRetention::RollupDays))
+                    .col(timestamp_with_time_zone(SnapDataRetention::ModifyTime).default(Expr::current_timestamp()))
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("data-retention-target-idx")
+                            .col(SnapDataRetention::Scope)
+                            .col(SnapDataRetention::TargetId)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // pruning and range queries look up the uplinks of a device by time
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("device-data-device-time-idx")
+                    .table(SnapDeviceData::Table)
+                    .col(SnapDeviceData::DeviceId)
+                    .col(SnapDeviceData::CreateTime)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("device-data-device-time-idx").table(SnapDeviceData::Table).to_owned()).await?;
+        manager.drop_table(Table::drop().table(SnapDataRetention::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDataRetention {
+    Table,
+    Id,
+    Scope,
+    TargetId,
+    RawDays,
+    RollupDays,
+    ModifyTime,
+}
+
+#[derive(DeriveIden)]
+enum SnapDeviceData {
+    Table,
+    DeviceId,
+    CreateTime,
+}
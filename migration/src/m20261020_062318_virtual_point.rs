@@ -0,0 +1,52 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapVirtualPoint::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapVirtualPoint::Id))
+                    .col(text(SnapVirtualPoint::Scope))
+                    .col(big_integer(SnapVirtualPoint::TargetId))
+                    .col(big_integer(SnapVirtualPoint::DataId))
+                    .col(text(SnapVirtualPoint::Name))
+                    .col(text(SnapVirtualPoint::Unit))
+                    .col(text(SnapVirtualPoint::Expression))
+                    .col(timestamp_with_time_zone(SnapVirtualPoint::ModifyTime).default(Expr::current_timestamp()))
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("virtual-point-target-idx")
+                            .col(SnapVirtualPoint::Scope)
+                            .col(SnapVirtualPoint::TargetId)
+                            .col(SnapVirtualPoint::DataId)
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(SnapVirtualPoint::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapVirtualPoint {
+    Table,
+    Id,
+    Scope,
+    TargetId,
+    DataId,
+    Name,
+    Unit,
+    Expression,
+    ModifyTime,
+}
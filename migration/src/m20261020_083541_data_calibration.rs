@@ -0,0 +1,69 @@
+use sea_orm_migration::{prelude::*, schema::*};
+use crate::m20240904_020441_create_table::big_key_auto;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SnapDataCalibration::Table)
+                    .if_not_exists()
+                    .col(big_key_auto(SnapDataCalibration::Id))
+                    .col(big_integer(SnapDataCalibration::DeviceId))
+                    .col(big_integer(SnapDataCalibration::DataId))
+                    .col(json_null(SnapDataCalibration::Calibration))
+                    .col(small_integer_null(SnapDataCalibration::Precision))
+                    .col(timestamp_with_time_zone(SnapDataCalibration::ModifyTime).default(Expr::current_timestamp()))
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("data-calibration-device-idx")
+                            .col(SnapDataCalibration::DeviceId)
+                            .col(SnapDataCalibration::DataId)
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapUsers::Table)
+                    .add_column_if_not_exists(json(SnapUsers::Units).default(Expr::cust("'{}'::json")))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SnapUsers::Table)
+                    .drop_column(SnapUsers::Units)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table(Table::drop().table(SnapDataCalibration::Table).to_owned()).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SnapDataCalibration {
+    Table,
+    Id,
+    DeviceId,
+    DataId,
+    Calibration,
+    Precision,
+    ModifyTime,
+}
+
+#[derive(DeriveIden)]
+enum SnapUsers {
+    Table,
+    Units,
+}
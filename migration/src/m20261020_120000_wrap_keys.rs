@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::TransactionTrait;
+
+/// Wraps the keys stored as plain hex when a key-encryption key is configured, wrapped keys are left
+/// for `snap-api rewrap-keys`. Without a key-encryption key, as in the standalone migration binary,
+/// nothing changes and a warning counts the plain keys, snap-api wraps them on every start with one.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        if common_define::db::key_encryption_key().is_none() {
+            let plain = common_define::db::plain_key_rows(conn).await?;
+            if plain > 0 {
+                tracing::warn!(
+                    "{} rows keep keys as plain hex: no key-encryption key is loaded, \
+                    snap-api wraps them when it starts with one configured",
+                    plain
+                );
+            }
+            return Ok(());
+        }
+        let txn = conn.begin().await?;
+        common_define::db::wrap_plain_keys(&txn).await?;
+        txn.commit().await
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // wrapped keys are read as well as plain ones
+        Ok(())
+    }
+}
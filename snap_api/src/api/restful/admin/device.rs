@@ -307,7 +307,8 @@ async fn get_device_info(
                             warn!("Failed to find device with id {}", id);
                             ApiError::User("Device not found".into())
                         })?;
-                    DeviceTypeInfoBody::LoRaNode(NodeInfo::register_to_redis(node, device.clone(), conn).await?)
+                    let profile = node.load_profile(&state.db).await?;
+                    DeviceTypeInfoBody::LoRaNode(NodeInfo::register_to_redis(node, device.clone(), profile, conn).await?)
                 }
                 Some(info) => DeviceTypeInfoBody::LoRaNode(info)
             }
@@ -335,7 +336,10 @@ async fn get_device_info(
                             warn!("Failed to find device with id {}", id);
                             ApiError::User("Device not found".into())
                         })?;
-                    let snap = SnapDeviceInfo::new(device.id, snap.key, Some(Timestamp::now()), 0, None, device.script, None);
+                    let mut snap = SnapDeviceInfo::new(device.id, snap.key, Some(Timestamp::now()), 0, None, device.script, None);
+                    snap.variables = (!device.variables.is_empty()).then(|| device.variables.clone());
+                    snap.codec = device.codec;
+                    snap.script_version = device.script.and(device.script_version);
                     snap.register(device.eui, conn).await?;
                     DeviceTypeInfoBody::Snap(snap)
                 }
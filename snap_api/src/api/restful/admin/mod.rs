@@ -27,6 +27,7 @@ mod group;
 mod config;
 mod log;
 mod product;
+mod retention;
 
 #[derive(OpenApi)]
 #[openapi(
@@ -42,6 +43,7 @@ mod product;
         (path = "/admin", api = config::ConfigApi),
         (path = "/admin", api = log::LogApi),
         (path = "/admin", api = product::ProductApi),
+        (path = "/admin", api = retention::RetentionApi),
     ),
     security(
         ("Authorization" = []),
@@ -80,6 +82,7 @@ pub(crate) fn router() -> Router<AppState> {
         .nest("/config", config::router())
         .nest("/log", log::router())
         .nest("/product", product::router())
+        .nest("/retention", retention::router())
         .layer(middleware::from_fn(auth));
 
     if config.api.openapi {
@@ -1,6 +1,6 @@
 use axum::extract::{Multipart, Query, State};
 use axum::Router;
-use axum::routing::{get, post};
+use axum::routing::{delete, get};
 use futures_util::FutureExt;
 use sea_orm::{ActiveModelTrait, ActiveValue, EntityTrait};
 use serde::Serialize;
@@ -9,12 +9,17 @@ use common_define::db::{SnapProductInfoActiveModel, SnapProductInfoEntity};
 use common_define::Id;
 use common_define::time::Timestamp;
 use crate::{tt, AppState};
+use crate::api::{SnJson, SnPath};
 use crate::error::{ApiError, ApiResponseResult};
+use crate::service::data::DataService;
+use crate::service::data::virtual_point::{VirtualPointRequest, VirtualPointResponse};
 use crate::service::user::{save_picture, Picture};
 
 pub(crate) fn router() -> Router<AppState> {
     Router::new()
         .route("/", get(get_all_product).post(post_product))
+        .route("/:id/virtual", get(get_virtual_points).put(put_virtual_point))
+        .route("/:id/virtual/:data_id", delete(delete_virtual_point))
 }
 
 #[derive(Serialize)]
@@ -31,12 +36,14 @@ struct ProductInfoItem {
     name: String,
     image: String,
     description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoder: Option<Id>,
     create_time: Timestamp,
 }
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(get_all_product,post_product),
+    paths(get_all_product, post_product, get_virtual_points, put_virtual_point, delete_virtual_point),
     tags((name = "product", description = "Device Product Info control api")),
 
 )]
@@ -64,6 +71,7 @@ async fn get_all_product(
             sku: item.sku,
             name: item.name,
             description: item.description,
+            encoder: item.encoder,
             create_time: item.create_time,
         })
         .collect();
@@ -80,6 +88,8 @@ struct UpProduct {
     name: String,
     description: String,
     image: Vec<u8>,
+    /// Encoder script id for the downlink commands of the product devices.
+    encoder: Option<String>,
 }
 
 ///
@@ -100,6 +110,7 @@ async fn post_product(
     let mut name: Option<String> = None;
     let mut describption: Option<String> = None;
     let mut product_image: Option<_> = None;
+    let mut encoder: Option<Id> = None;
     
     while let Some(mut field) = multipart.next_field().await.map_err(|e| ApiError::User(e.to_string().into()))? {
         if let Some(field_name) = field.name() {
@@ -116,6 +127,10 @@ async fn post_product(
                 "image" => {
                     product_image = field.bytes().await.ok()
                 }
+                "encoder" => {
+                    encoder = field.text().await.ok()
+                        .and_then(|it| it.parse().ok())
+                }
                 el => {
                     return Err(ApiError::User(format!("unsupported field: {:?}", el).into()))
                 }
@@ -149,6 +164,7 @@ async fn post_product(
         name: ActiveValue::Set(name),
         description: ActiveValue::Set(description),
         image: ActiveValue::Set(product_url),
+        encoder: ActiveValue::Set(encoder),
         create_time: ActiveValue::Set(now)
     };
     let s = product.insert(&state.db).await.map(|item| ProductInfoItem {
@@ -157,7 +173,75 @@ async fn post_product(
         name: item.name,
         image: item.image,
         description: item.description,
+        encoder: item.encoder,
         create_time: item.create_time,
     })?;
     Ok(s.into())
-}
\ No newline at end of file
+}
+///
+/// Get the virtual data points of a product, computed for all its devices
+#[utoipa::path(
+    get,
+    path = "/product/{id}/virtual",
+    params(
+        ("id", description = "Product id"),
+    ),
+    responses(
+            (status = 0, description = "virtual data points"),
+    )
+)]
+async fn get_virtual_points(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+) -> ApiResponseResult<Vec<VirtualPointResponse>> {
+    Ok(DataService::product_virtual_points(id, &state.db).await?.into())
+}
+
+///
+/// Create or replace a virtual data point of a product, a device point with the same data id replaces it
+#[utoipa::path(
+    put,
+    path = "/product/{id}/virtual",
+    params(
+        ("id", description = "Product id"),
+    ),
+    request_body(content = String, description = "Virtual data point", content_type = "application/json", example = json!({
+        "data_id": 1001,
+        "name": "Dew point",
+        "unit": "°C",
+        "expression": "v1 - (100 - v2) / 5"
+    })),
+    responses(
+            (status = 0, description = "virtual data point"),
+    )
+)]
+async fn put_virtual_point(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    SnJson(req): SnJson<VirtualPointRequest>,
+) -> ApiResponseResult<VirtualPointResponse> {
+    let redis = &mut state.redis.get().await?;
+    Ok(DataService::set_product_virtual_point(id, req, redis, &state.db).await?.into())
+}
+
+///
+/// Remove a virtual data point of a product
+#[utoipa::path(
+    delete,
+    path = "/product/{id}/virtual/{data_id}",
+    params(
+        ("id", description = "Product id"),
+        ("data_id", description = "Data id of the point"),
+    ),
+    responses(
+            (status = 0, description = "removed"),
+    )
+)]
+async fn delete_virtual_point(
+    State(state): State<AppState>,
+    SnPath((id, data_id)): SnPath<(Id, u32)>,
+) -> ApiResponseResult {
+    let redis = &mut state.redis.get().await?;
+    DataService::delete_product_virtual_point(id, data_id, redis, &state.db).await?;
+    Ok(().into())
+}
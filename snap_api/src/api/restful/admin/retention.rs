@@ -0,0 +1,84 @@
+use axum::extract::State;
+use axum::Router;
+use axum::routing::get;
+use utoipa::OpenApi;
+use crate::api::SnJson;
+use crate::AppState;
+use crate::error::ApiResponseResult;
+use crate::man::{RedisClient, RetentionManager};
+use crate::service::data::DataService;
+use crate::service::data::retention::{RetentionPolicy, RetentionPreview, RetentionResponse, RetentionRun};
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_retention).put(put_retention))
+        .route("/preview", get(preview_retention))
+        .route("/runs", get(get_retention_runs))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_retention, put_retention, preview_retention, get_retention_runs),
+    tags((name = "retention", description = "data retention api")),
+)]
+pub struct RetentionApi;
+
+///
+/// Get the system data retention, users and devices without their own use it
+#[utoipa::path(
+    get,
+    path = "/retention",
+    responses(
+            (status = 0, description = "system data retention"),
+    )
+)]
+async fn get_retention(State(state): State<AppState>) -> ApiResponseResult<RetentionResponse> {
+    Ok(DataService::system_retention(&state.db).await?.into())
+}
+
+///
+/// Set the system data retention
+#[utoipa::path(
+    put,
+    path = "/retention",
+    request_body(content = String, description = "Days raw data and rollups are kept, 0 keeps them forever", content_type = "application/json", example = json!({
+        "raw_days": 90,
+        "rollup_days": 730
+    })),
+    responses(
+            (status = 0, description = "system data retention"),
+    )
+)]
+async fn put_retention(
+    State(state): State<AppState>,
+    SnJson(policy): SnJson<RetentionPolicy>,
+) -> ApiResponseResult<RetentionResponse> {
+    Ok(DataService::set_system_retention(policy, &state.db).await?.into())
+}
+
+///
+/// Count the data the next retention run would delete
+#[utoipa::path(
+    get,
+    path = "/retention/preview",
+    responses(
+            (status = 0, description = "data that would be deleted"),
+    )
+)]
+async fn preview_retention(State(state): State<AppState>) -> ApiResponseResult<RetentionPreview> {
+    Ok(DataService::preview_retention(&state.db).await?.into())
+}
+
+///
+/// Statistics of the last retention runs, newest first
+#[utoipa::path(
+    get,
+    path = "/retention/runs",
+    responses(
+            (status = 0, description = "retention runs"),
+    )
+)]
+async fn get_retention_runs() -> ApiResponseResult<Vec<RetentionRun>> {
+    let mut redis = RedisClient::get_client().get_multiplexed_conn().await?;
+    Ok(RetentionManager::runs(&mut redis).await?.into())
+}
@@ -1,22 +1,50 @@
-use crate::error::{ApiResponseResult};
+use crate::error::{ApiError, ApiResponseResult};
 use crate::service::data::query::{DataDeviceOneResponseWrap, DataDuration, DataResponseWrap};
 use crate::service::data::DataService;
+use crate::service::data::last::{BatchLastRequest, BatchLastResponse};
+use crate::service::data::range::{RangeQuery, RangeResponse};
+use crate::service::data::retention::{RetentionPolicy, RetentionResponse};
+use crate::service::data::export::{ExportJob, ExportRequest};
+use crate::service::data::import::{ImportFormat, ImportKind, ImportOptions, ImportReport, MAX_BODY};
+use crate::service::data::calibration::{CalibrationRequest, CalibrationResponse};
+use crate::service::data::virtual_point::{VirtualPointRequest, VirtualPointResponse};
+use axum::body::Body;
 use axum::routing::get;
-use axum::extract::State;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use tokio_util::io::ReaderStream;
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
 use common_define::Id;
-use crate::api::SnPath;
+use common_define::time::{Timestamp, Tz};
+use common_define::unit::UnitPreference;
+use crate::api::{SnJson, SnPath};
 use crate::{get_current_user, AppState};
 use crate::service::device::DeviceService;
 
 pub(crate) fn router() -> OpenApiRouter<AppState> {
+    let import = OpenApiRouter::new()
+        .routes(routes!(import_data))
+        .layer(axum::extract::DefaultBodyLimit::max(MAX_BODY));
     OpenApiRouter::new()
         .routes(routes!(get_hour_data))
         .routes(routes!(get_day_data))
         .routes(routes!(get_week_data))
         .routes(routes!(get_last_data))
-        // .route("/:device/range", get(get_range_data))
+        .routes(routes!(post_last_batch))
+        .routes(routes!(get_range_data))
+        .routes(routes!(get_user_retention, put_user_retention, delete_user_retention))
+        .routes(routes!(get_device_retention, put_device_retention, delete_device_retention))
+        .routes(routes!(get_virtual_points, put_virtual_point))
+        .routes(routes!(delete_virtual_point))
+        .routes(routes!(get_calibrations, put_calibration))
+        .routes(routes!(delete_calibration))
+        .routes(routes!(get_units, put_units))
+        .routes(routes!(post_export))
+        .routes(routes!(get_export))
+        .routes(routes!(download_export))
+        .merge(import)
 }
 
 /// Get 1 hour of data
@@ -40,16 +68,26 @@ async fn get_hour_data(
 ) -> ApiResponseResult<DataResponseWrap> {
     let user = get_current_user();
     let device_db = DeviceService::query_one(user.id, device, &state.db).await?;
-    let data = DataService::query_duration_data(device, device_db.script, DataDuration::Hour, &state).await?;
+    let mut data = DataService::query_duration_data(device, device_db.script, DataDuration::Hour, Tz::UTC, &state).await?;
+    let display = DataService::data_display(&user, device, &state.db).await?;
+    data.data.iter_mut().for_each(|it| display.apply_series(it));
     Ok(data.into())
 }
 
-/// Get 1 day of data
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TimeZoneParams {
+    /// IANA time zone such as Europe/Berlin, the one of the user or of the device when not set.
+    tz: Option<String>,
+}
+
+/// Get the data of the current day, from midnight in the time zone
 #[utoipa::path(
     method(get),
     path = "/{id}/day",
     params(
-            ("id" = i32, Path, description = "Todo database id")
+            ("id" = i32, Path, description = "Todo database id"),
+            TimeZoneParams
     ),
     security(
         (), // <-- make optional authentication
@@ -62,19 +100,24 @@ async fn get_hour_data(
 async fn get_day_data(
     State(state): State<AppState>,
     SnPath(device): SnPath<Id>,
+    Query(params): Query<TimeZoneParams>,
 ) -> ApiResponseResult<DataResponseWrap> {
     let user = get_current_user();
     let device_db = DeviceService::query_one(user.id, device, &state.db).await?;
-    let data = DataService::query_duration_data(device, device_db.script, DataDuration::Day, &state).await?;
+    let tz = DataService::time_zone(&user, device, params.tz.as_deref(), &state.db).await?;
+    let mut data = DataService::query_duration_data(device, device_db.script, DataDuration::Day, tz, &state).await?;
+    let display = DataService::data_display(&user, device, &state.db).await?;
+    data.data.iter_mut().for_each(|it| display.apply_series(it));
     Ok(data.into())
 }
 
-/// Get 1 week of data
+/// Get the data of the current week, from midnight on Monday in the time zone
 #[utoipa::path(
     method(get),
     path = "/{id}/week",
     params(
-            ("id" = i32, Path, description = "Todo database id")
+            ("id" = i32, Path, description = "Todo database id"),
+            TimeZoneParams
     ),
     security(
         (), // <-- make optional authentication
@@ -87,10 +130,14 @@ async fn get_day_data(
 async fn get_week_data(
     State(state): State<AppState>,
     SnPath(device): SnPath<Id>,
+    Query(params): Query<TimeZoneParams>,
 ) -> ApiResponseResult<DataResponseWrap> {
     let user = get_current_user();
     let device_db = DeviceService::query_one(user.id, device, &state.db).await?;
-    let data = DataService::query_duration_data(device, device_db.script, DataDuration::Week, &state).await?;
+    let tz = DataService::time_zone(&user, device, params.tz.as_deref(), &state.db).await?;
+    let mut data = DataService::query_duration_data(device, device_db.script, DataDuration::Week, tz, &state).await?;
+    let display = DataService::data_display(&user, device, &state.db).await?;
+    data.data.iter_mut().for_each(|it| display.apply_series(it));
     Ok(data.into())
 }
 
@@ -116,41 +163,462 @@ async fn get_last_data(
     let user = get_current_user();
     
     let device_db = DeviceService::query_one(user.id, device, &state.db).await?;
-    let data = DataService::query_last(&device_db, &state).await?;
+    let mut data = DataService::query_last(&device_db, &state).await?;
+    let display = DataService::data_display(&user, device, &state.db).await?;
+    data.data.iter_mut().for_each(|it| display.apply_one(it));
     Ok(data.into())
 }
 
-#[derive(Debug, serde::Deserialize)]
+/// Get the last data of many devices, by ID, by group or all devices of the user
+#[utoipa::path(
+    method(post),
+    path = "/last",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn post_last_batch(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<BatchLastRequest>,
+) -> ApiResponseResult<BatchLastResponse> {
+    let user = get_current_user();
+    Ok(DataService::query_last_batch(&user, req, &state).await?.into())
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 struct QueryRangeParams {
-    s: u64,
-    e: u64,
-    id: Option<u64>,
-    c: Option<u64>
-}
-// 
-// async fn get_range_data(
-//     mut conn: DatabaseConnection,
-//     Query(params): Query<QueryRangeParams>,
-//     Path(device): Path<Id>,
-// ) -> ApiResponseResult<DataResponseWrap> {
-//     let user = get_current_user();
-//     
-//     snap_log::log::warn!("{:?}", params);
-//     if params.s > params.e {
-//         return Err(ApiError::User(
-//             t!("messages.user.data.time_start_end", locale = user.lang.as_ref())
-//         ))
-//     }
-//     if (params.e - params.s) > (60 * 60 * 24 * 8) {
-//         return Err(ApiError::User(
-//             t!("messages.user.data.time_range", locale = user.lang.as_ref())
-//         ))
-//     }
-//     let start = Time::form_sec(params.s);
-//     let end = Time::form_sec(params.e);
-//     let count = params.c.unwrap_or(24);
-//     let data_id = params.id.map(|id| id as i32);
-//     let device_db = DeviceService::query_one(user.id, device, conn.as_mut()).await?;
-//     let data = DataService::query_range_data(&user, device, device_db.script, data_id, start,  end, count, conn.as_mut()).await?;
-//     Ok(data.into())
-// }
\ No newline at end of file
+    /// Start in milliseconds, inclusive.
+    #[param(value_type = u64)]
+    start: Timestamp,
+    /// End in milliseconds, exclusive.
+    #[param(value_type = u64)]
+    end: Timestamp,
+    /// Comma separated data ids, all data ids when not set.
+    ids: Option<String>,
+    /// Seconds of a bucket, 1/200 of the range when not set.
+    interval: Option<u64>,
+    /// Comma separated aggregations out of avg, min, max, sum, count, first and last, avg when not set.
+    agg: Option<String>,
+    /// Buckets by local day, week or month instead of interval.
+    calendar: Option<String>,
+    /// IANA time zone of the calendar buckets, the one of the user or of the device when not set.
+    tz: Option<String>,
+}
+
+/// Get data in a time range, bucketed and aggregated
+#[utoipa::path(
+    method(get),
+    path = "/{id}/range",
+    params(
+        ("id" = i32, Path, description = "Device id"),
+        QueryRangeParams
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_range_data(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+    Query(params): Query<QueryRangeParams>,
+) -> ApiResponseResult<RangeResponse> {
+    let user = get_current_user();
+    let query = RangeQuery::new(params.start, params.end, params.ids.as_deref(), params.interval, params.agg.as_deref())?;
+    let device_db = DeviceService::query_one(user.id, device, &state.db).await?;
+    let query = match params.calendar {
+        Some(calendar) => {
+            let tz = DataService::time_zone(&user, device, params.tz.as_deref(), &state.db).await?;
+            query.with_calendar(&calendar, tz)?
+        }
+        None => query,
+    };
+    let mut data = DataService::query_range(device, device_db.script, query, &state.db).await?;
+    let display = DataService::data_display(&user, device, &state.db).await?;
+    data.apply_display(&display);
+    Ok(data.into())
+}
+
+/// Get the data retention of the user
+#[utoipa::path(
+    method(get),
+    path = "/retention",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_user_retention(
+    State(state): State<AppState>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::user_retention(&user, &state.db).await?.into())
+}
+
+/// Set how many days the data of the devices of the user is kept
+#[utoipa::path(
+    method(put),
+    path = "/retention",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn put_user_retention(
+    State(state): State<AppState>,
+    SnJson(policy): SnJson<RetentionPolicy>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::set_user_retention(&user, policy, &state.db).await?.into())
+}
+
+/// Remove the data retention of the user, the system one applies
+#[utoipa::path(
+    method(delete),
+    path = "/retention",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn delete_user_retention(
+    State(state): State<AppState>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::delete_user_retention(&user, &state.db).await?.into())
+}
+
+/// Get the data retention of a device
+#[utoipa::path(
+    method(get),
+    path = "/{id}/retention",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_device_retention(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::device_retention(&user, device, &state.db).await?.into())
+}
+
+/// Set how many days the data of a device is kept, only by its owner
+#[utoipa::path(
+    method(put),
+    path = "/{id}/retention",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn put_device_retention(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+    SnJson(policy): SnJson<RetentionPolicy>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::set_device_retention(&user, device, policy, &state.db).await?.into())
+}
+
+/// Remove the data retention of a device, the one of its owner applies
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/retention",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn delete_device_retention(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+) -> ApiResponseResult<RetentionResponse> {
+    let user = get_current_user();
+    Ok(DataService::delete_device_retention(&user, device, &state.db).await?.into())
+}
+
+/// Start an export of the data of devices or of a group as CSV, JSON Lines or Parquet
+#[utoipa::path(
+    method(post),
+    path = "/export",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn post_export(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<ExportRequest>,
+) -> ApiResponseResult<ExportJob> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    Ok(DataService::start_export(&user, req, redis, &state.db).await?.into())
+}
+
+/// Get the state of an export
+#[utoipa::path(
+    method(get),
+    path = "/export/{id}",
+    params(
+        ("id" = String, Path, description = "Export id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_export(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<String>,
+) -> ApiResponseResult<ExportJob> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    Ok(DataService::export_job(&user, &id, redis).await?.into())
+}
+
+/// Download the file of a finished export
+#[utoipa::path(
+    method(get),
+    path = "/export/{id}/download",
+    params(
+        ("id" = String, Path, description = "Export id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn download_export(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<String>,
+) -> Result<Response, ApiError> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    let export = DataService::export_file(&user, &id, redis).await?;
+    let file = tokio::fs::File::open(&export.path).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, export.content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", export.name)),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    ).into_response())
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportParams {
+    /// csv or jsonl.
+    #[param(value_type = String)]
+    format: ImportFormat,
+    /// values or bytes, values when not set.
+    #[param(value_type = Option<String>)]
+    #[serde(default)]
+    kind: ImportKind,
+    /// FPort the payloads are decoded with, 0 when not set.
+    #[serde(default)]
+    f_port: u8,
+}
+
+/// Import the history of a device from CSV or JSON Lines, only by its owner
+#[utoipa::path(
+    method(post),
+    path = "/{id}/import",
+    params(
+        ("id" = i32, Path, description = "Device id"),
+        ImportParams
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn import_data(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+    Query(params): Query<ImportParams>,
+    body: String,
+) -> ApiResponseResult<ImportReport> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    let options = ImportOptions { format: params.format, kind: params.kind, f_port: params.f_port };
+    let report = DataService::import(&user, device, options, &body, redis, &state.db).await?;
+    Ok(report.into())
+}
+
+/// Get the virtual data points of a device, with the ones of its product
+#[utoipa::path(
+    method(get),
+    path = "/{id}/virtual",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_virtual_points(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+) -> ApiResponseResult<Vec<VirtualPointResponse>> {
+    let user = get_current_user();
+    Ok(DataService::device_virtual_points(&user, device, &state.db).await?.into())
+}
+
+/// Create or replace a virtual data point of a device, computed from the other values of each uplink
+#[utoipa::path(
+    method(put),
+    path = "/{id}/virtual",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn put_virtual_point(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+    SnJson(req): SnJson<VirtualPointRequest>,
+) -> ApiResponseResult<VirtualPointResponse> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    Ok(DataService::set_device_virtual_point(&user, device, req, redis, &state.db).await?.into())
+}
+
+/// Remove a virtual data point of a device, the stored values are kept
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/virtual/{data_id}",
+    params(
+        ("id" = i32, Path, description = "Device id"),
+        ("data_id" = u32, Path, description = "Data id of the point")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn delete_virtual_point(
+    State(state): State<AppState>,
+    SnPath((device, data_id)): SnPath<(Id, u32)>,
+) -> ApiResponseResult {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    DataService::delete_device_virtual_point(&user, device, data_id, redis, &state.db).await?;
+    Ok(().into())
+}
+
+/// Get the calibrations and display precisions of the data points of a device
+#[utoipa::path(
+    method(get),
+    path = "/{id}/calibration",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_calibrations(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+) -> ApiResponseResult<Vec<CalibrationResponse>> {
+    let user = get_current_user();
+    Ok(DataService::device_calibrations(&user, device, &state.db).await?.into())
+}
+
+/// Set the calibration and display precision of a data point of a device, only by its owner
+#[utoipa::path(
+    method(put),
+    path = "/{id}/calibration",
+    params(
+        ("id" = i32, Path, description = "Device id")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn put_calibration(
+    State(state): State<AppState>,
+    SnPath(device): SnPath<Id>,
+    SnJson(req): SnJson<CalibrationRequest>,
+) -> ApiResponseResult<CalibrationResponse> {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    Ok(DataService::set_device_calibration(&user, device, req, redis, &state.db).await?.into())
+}
+
+/// Remove the calibration of a data point of a device, the stored values are kept
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/calibration/{data_id}",
+    params(
+        ("id" = i32, Path, description = "Device id"),
+        ("data_id" = u32, Path, description = "Data id of the point")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn delete_calibration(
+    State(state): State<AppState>,
+    SnPath((device, data_id)): SnPath<(Id, u32)>,
+) -> ApiResponseResult {
+    let user = get_current_user();
+    let redis = &mut state.redis.get().await?;
+    DataService::delete_device_calibration(&user, device, data_id, redis, &state.db).await?;
+    Ok(().into())
+}
+
+/// Get the units the data is shown in to the user
+#[utoipa::path(
+    method(get),
+    path = "/units",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn get_units(
+    State(state): State<AppState>,
+) -> ApiResponseResult<UnitPreference> {
+    let user = get_current_user();
+    Ok(DataService::user_units(&user, &state.db).await?.into())
+}
+
+/// Set the units the data is shown in to the user
+#[utoipa::path(
+    method(put),
+    path = "/units",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DATA_TAG
+)]
+async fn put_units(
+    State(state): State<AppState>,
+    SnJson(units): SnJson<UnitPreference>,
+) -> ApiResponseResult<UnitPreference> {
+    let user = get_current_user();
+    Ok(DataService::set_user_units(&user, units, &state.db).await?.into())
+}
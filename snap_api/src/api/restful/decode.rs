@@ -1,18 +1,33 @@
 use axum::{Router};
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::routing::{delete, post};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
+use serde::Deserialize;
+use common_define::event::{ReDecodeDiff, ReDecodeProgress};
 use common_define::Id;
 use crate::api::{SnJson, SnPath};
 use crate::error::ApiResponseResult;
 use crate::{get_current_user, AppState};
-use crate::service::decode::{DecodeService, ScriptRequest};
+use crate::service::decode::{CompareRequest, CompareResponse, DecodeRequest, DecodeResponse, DecodeService, ReDecodeRequest, ReDecodeStarted, RollbackRequest, ScriptDevice, ScriptDiff, ScriptRequest, ScriptVersion, TestVector, VectorRequest, VectorResult, VectorRunRequest};
 
 pub(crate) fn router() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(new_script, list_script))
         .routes(routes!(delete_script))
+        .routes(routes!(test_script))
+        .routes(routes!(compare_script))
+        .routes(routes!(start_redecode))
+        .routes(routes!(redecode_progress, cancel_redecode))
+        .routes(routes!(redecode_diff))
+        .routes(routes!(script_versions))
+        .routes(routes!(script_version))
+        .routes(routes!(rollback_script))
+        .routes(routes!(diff_script))
+        .routes(routes!(script_devices))
+        .routes(routes!(list_vectors, new_vector))
+        .routes(routes!(delete_vector))
+        .routes(routes!(run_vectors))
 }
 
 /// Create JS script
@@ -29,9 +44,11 @@ async fn new_script(
     SnJson(req): SnJson<ScriptRequest>
 ) -> ApiResponseResult<ScriptRequest> {
     let user = get_current_user();
+    let mut redis = state.redis.get().await?;
     let script = DecodeService::insert_script(
         &user,
         req,
+        &mut redis,
         &state.db
     ).await?;
     Ok(script.into())
@@ -73,6 +90,316 @@ async fn delete_script(
 ) -> ApiResponseResult<String> {
     let user = get_current_user();
     
-    DecodeService::delete_script(&user, id, &state.db).await?;
+    let mut redis = state.redis.get().await?;
+    DecodeService::delete_script(&user, id, &mut redis, &state.db).await?;
     Ok(String::new().into())
-}
\ No newline at end of file
+}
+
+/// Decode one payload with a script draft
+#[utoipa::path(
+    method(post),
+    path = "/test",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn test_script(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<DecodeRequest>
+) -> ApiResponseResult<DecodeResponse> {
+    let mut redis = state.redis.get().await?;
+    let response = DecodeService::test(req, &mut redis).await?;
+    Ok(response.into())
+}
+
+/// Decode the latest uplinks of a device with its decoder and a script draft
+#[utoipa::path(
+    method(post),
+    path = "/compare",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn compare_script(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<CompareRequest>
+) -> ApiResponseResult<CompareResponse> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let response = DecodeService::compare(&user, req, &mut redis, &state.db).await?;
+    Ok(response.into())
+}
+
+/// Re-decode stored uplinks of devices in a time range
+#[utoipa::path(
+    method(post),
+    path = "/redecode",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn start_redecode(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<ReDecodeRequest>
+) -> ApiResponseResult<ReDecodeStarted> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let started = DecodeService::start_redecode(&user, req, &mut redis, &state.db).await?;
+    Ok(started.into())
+}
+
+/// Get the progress of a re-decode job
+#[utoipa::path(
+    method(get),
+    path = "/redecode/{id}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn redecode_progress(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<String>
+) -> ApiResponseResult<ReDecodeProgress> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let progress = DecodeService::redecode_progress(&user, &id, &mut redis).await?;
+    Ok(progress.into())
+}
+
+/// Cancel a re-decode job
+#[utoipa::path(
+    method(delete),
+    path = "/redecode/{id}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn cancel_redecode(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<String>
+) -> ApiResponseResult<String> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    DecodeService::cancel_redecode(&user, &id, &mut redis).await?;
+    Ok(String::new().into())
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DiffQuery {
+    #[param(value_type = Option<usize>, example = 0, minimum = 0, default = 0)]
+    offset: Option<usize>,
+    #[param(value_type = Option<usize>, example = 20, minimum = 1, maximum = 100, default = 20)]
+    limit: Option<usize>,
+}
+
+/// Get the rows a re-decode job changed
+#[utoipa::path(
+    method(get),
+    path = "/redecode/{id}/diff",
+    params(DiffQuery),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn redecode_diff(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<String>,
+    Query(query): Query<DiffQuery>,
+) -> ApiResponseResult<Vec<ReDecodeDiff>> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let diff = DecodeService::redecode_diff(
+        &user,
+        &id,
+        query.offset.unwrap_or(0),
+        query.limit.unwrap_or(20),
+        &mut redis
+    ).await?;
+    Ok(diff.into())
+}
+
+/// Get the versions of a script, newest first
+#[utoipa::path(
+    method(get),
+    path = "/{id}/versions",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn script_versions(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>
+) -> ApiResponseResult<Vec<ScriptVersion>> {
+    let user = get_current_user();
+    let versions = DecodeService::versions(&user, id, &state.db).await?;
+    Ok(versions.into())
+}
+
+/// Get one version of a script with its content
+#[utoipa::path(
+    method(get),
+    path = "/{id}/versions/{version}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn script_version(
+    State(state): State<AppState>,
+    SnPath((id, version)): SnPath<(Id, i32)>
+) -> ApiResponseResult<ScriptVersion> {
+    let user = get_current_user();
+    let version = DecodeService::version(&user, id, version, &state.db).await?;
+    Ok(version.into())
+}
+
+/// Make an old version of a script the latest one
+#[utoipa::path(
+    method(post),
+    path = "/{id}/rollback",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn rollback_script(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    SnJson(req): SnJson<RollbackRequest>
+) -> ApiResponseResult<ScriptVersion> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let version = DecodeService::rollback(&user, id, req, &mut redis, &state.db).await?;
+    Ok(version.into())
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct VersionDiffQuery {
+    #[param(value_type = i32, example = 1)]
+    from: i32,
+    #[param(value_type = i32, example = 2)]
+    to: i32,
+}
+
+/// Line diff between two versions of a script
+#[utoipa::path(
+    method(get),
+    path = "/{id}/diff",
+    params(VersionDiffQuery),
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn diff_script(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    Query(query): Query<VersionDiffQuery>,
+) -> ApiResponseResult<ScriptDiff> {
+    let user = get_current_user();
+    let diff = DecodeService::diff(&user, id, query.from, query.to, &state.db).await?;
+    Ok(diff.into())
+}
+
+/// Get the devices decoding with a script and the version each one runs
+#[utoipa::path(
+    method(get),
+    path = "/{id}/devices",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn script_devices(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>
+) -> ApiResponseResult<Vec<ScriptDevice>> {
+    let user = get_current_user();
+    let devices = DecodeService::script_devices(&user, id, &state.db).await?;
+    Ok(devices.into())
+}
+
+/// Get the test vectors of a script
+#[utoipa::path(
+    method(get),
+    path = "/{id}/vectors",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn list_vectors(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>
+) -> ApiResponseResult<Vec<TestVector>> {
+    let user = get_current_user();
+    let vectors = DecodeService::vectors(&user, id, &state.db).await?;
+    Ok(vectors.into())
+}
+
+/// Add a test vector to a script, the expected output is the output of the saved script when not given
+#[utoipa::path(
+    method(post),
+    path = "/{id}/vectors",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn new_vector(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    SnJson(req): SnJson<VectorRequest>
+) -> ApiResponseResult<TestVector> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let vector = DecodeService::insert_vector(&user, id, req, &mut redis, &state.db).await?;
+    Ok(vector.into())
+}
+
+/// Delete a test vector
+#[utoipa::path(
+    method(delete),
+    path = "/{id}/vectors/{vector}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn delete_vector(
+    State(state): State<AppState>,
+    SnPath((id, vector)): SnPath<(Id, Id)>
+) -> ApiResponseResult<String> {
+    let user = get_current_user();
+    DecodeService::delete_vector(&user, id, vector, &state.db).await?;
+    Ok(String::new().into())
+}
+
+/// Run the test vectors of a script with a draft of it
+#[utoipa::path(
+    method(post),
+    path = "/{id}/vectors/run",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DECODE_TAG
+)]
+async fn run_vectors(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    SnJson(req): SnJson<VectorRunRequest>
+) -> ApiResponseResult<Vec<VectorResult>> {
+    let user = get_current_user();
+    let mut redis = state.redis.get().await?;
+    let results = DecodeService::run_vectors(&user, id, req, &mut redis, &state.db).await?;
+    Ok(results.into())
+}
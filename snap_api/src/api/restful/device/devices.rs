@@ -220,7 +220,7 @@ async fn put_device(
         .transaction::<_, _, ApiError>(|ctx| {
             Box::pin(async move {
                 let mut redis = redis.get().await?;
-                DeviceService::update_info(device_with_auth, req, &mut redis, ctx).await?;
+                DeviceService::update_info(&user, device_with_auth, req, &mut redis, ctx).await?;
                 DeviceCache::delete_by_user_id(user.id, &mut redis).await?;
                 Ok(())
             })
@@ -1,19 +1,20 @@
 use crate::api::{SnJson, SnPath};
-use crate::error::{ApiError, ApiResponseResult};
+use crate::error::{ApiError, ApiResponseResult, ApiResult};
+use crate::service::decode::{DecodeService, Encoded};
 use crate::service::device::device::DeviceWithAuth;
 use crate::service::device::DeviceService;
-use crate::{get_current_user, AppState};
+use crate::{get_current_user, tt, AppState};
 use axum::extract::State;
 use axum::routing::{delete, get, post};
 use axum::Router;
 use base64::Engine;
-use common_define::db::{SnapDownLinkActiveModel, SnapDownLinkColumn, SnapDownLinkEntity};
+use common_define::db::{DevicesModel, SnapDownLinkActiveModel, SnapDownLinkColumn, SnapDownLinkEntity, SnapProductInfoEntity};
 use common_define::event::{DeviceEvent, DownEvent};
 use common_define::product::DeviceType;
 use common_define::time::Timestamp;
 use common_define::Id;
 use redis::AsyncCommands;
-use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, ModelTrait, QueryFilter};
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, ModelTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
@@ -28,7 +29,18 @@ pub(crate) fn router() -> OpenApiRouter<AppState> {
 #[derive(Deserialize)]
 struct DownData {
     port: Option<u8>,
-    data: String,
+    data: Option<String>,
+    /// Command encoded by the encoder script of the device, used instead of `data`.
+    json: Option<serde_json::Value>,
+    /// Downlink counter the data was encrypted with, for application encrypted nodes.
+    f_cnt: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct DownResp {
+    /// The JSON command as the `decodeDownlink` of the codec reads the sent bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -36,6 +48,8 @@ struct DownTemplateItem {
     id: Id,
     name: String,
     data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json: Option<serde_json::Value>,
     port: i32,
 }
 
@@ -47,10 +61,39 @@ struct DownTemplate {
 #[derive(Deserialize, Serialize)]
 struct DownTempleBody {
     name: String,
+    #[serde(default)]
     data: String,
+    /// Encoded when the template is saved, `data` and `port` are taken from the encoder.
+    json: Option<serde_json::Value>,
     port: i32,
 }
 
+/// Encodes a JSON command with the encoder of the device, its built-in codec,
+/// or else the encoder of its product.
+async fn encode_json<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+    device: &DevicesModel,
+    json: serde_json::Value,
+    redis: &mut R,
+    conn: &C,
+) -> ApiResult<Encoded> {
+    if let (None, Some(codec)) = (device.encoder, device.codec) {
+        let bytes = codec.encode(&json)
+            .map_err(|e| ApiError::User(tt!("messages.device.decode.encoder_invalid", errors = e)))?;
+        return Ok(Encoded { bytes, f_port: None, decoded: None });
+    }
+    let encoder = match (device.encoder, device.product_id) {
+        (Some(encoder), _) => Some(encoder),
+        (None, Some(product)) => SnapProductInfoEntity::find_by_id(product)
+            .one(conn)
+            .await?
+            .and_then(|product| product.encoder),
+        (None, None) => None,
+    };
+    let encoder = encoder.ok_or_else(|| ApiError::User(tt!("messages.device.decode.encoder_missing")))?;
+    let variables = (!device.variables.is_empty()).then(|| device.variables.clone());
+    DecodeService::encode(encoder, json, variables, redis).await
+}
+
 /// Send data to the device
 #[utoipa::path(
     method(post),
@@ -64,42 +107,55 @@ async fn post_down(
     State(state): State<AppState>,
     SnPath(id): SnPath<Id>,
     SnJson(data): SnJson<DownData>,
-) -> ApiResponseResult {
-    base64::engine::general_purpose::STANDARD
-        .decode(data.data.as_bytes())
-        .map_err(|e| ApiError::User("invalid data".into()))?;
+) -> ApiResponseResult<DownResp> {
+    if let Some(bytes) = &data.data {
+        base64::engine::general_purpose::STANDARD
+            .decode(bytes.as_bytes())
+            .map_err(|_| ApiError::User(tt!("messages.device.decode.down_data_invalid")))?;
+    }
     let user = get_current_user();
     let conn = &state.db;
     let DeviceWithAuth { auth, device } =
         DeviceService::query_one_with_auth(user.id, id, conn).await?;
+    let (port, bytes, decoded) = match (data.json, data.data) {
+        (Some(json), _) => {
+            let mut redis = state.redis.get().await?;
+            let encoded = encode_json(&device, json, &mut redis, conn).await?;
+            (data.port.or(encoded.f_port), base64::engine::general_purpose::STANDARD.encode(encoded.bytes), encoded.decoded)
+        }
+        (None, Some(bytes)) => (data.port, bytes, None),
+        (None, None) => return Err(ApiError::User(tt!("messages.device.decode.down_data_missing"))),
+    };
     match device.device_type {
         DeviceType::Snap => {
             let event = DownEvent {
                 device: common_define::event::DeviceType::Snap,
                 eui: device.eui,
-                port: data.port.unwrap_or(2),
-                data: data.data,
+                port: port.unwrap_or(2),
+                data: bytes,
+                f_cnt: None,
             };
             let data = serde_json::to_string(&event)?;
             let mut conn = state.redis.get().await?;
-            conn.publish(DeviceEvent::DOWN_TOPIC, data).await?;
+            let _: () = conn.publish(DeviceEvent::DOWN_TOPIC, data).await?;
         }
         DeviceType::LoRaNode => {
             let event = DownEvent {
                 device: common_define::event::DeviceType::LoRaNode,
                 eui: device.eui,
-                port: data.port.unwrap_or(2),
-                data: data.data,
+                port: port.unwrap_or(2),
+                data: bytes,
+                f_cnt: data.f_cnt,
             };
             let data = serde_json::to_string(&event)?;
             let mut conn = state.redis.get().await?;
-            conn.publish(DeviceEvent::DOWN_TOPIC, data).await?;
+            let _: () = conn.publish(DeviceEvent::DOWN_TOPIC, data).await?;
         }
         _ => {
             return Err(ApiError::User("unsupport device type".into()));
         }
     }
-    Ok(().into())
+    Ok(DownResp { decoded }.into())
 }
 
 /// Get the template for sending data
@@ -132,6 +188,7 @@ async fn get_template(
             id: link.id,
             name: link.name,
             data: link.data,
+            json: link.json,
             port: link.port,
         })
         .collect();
@@ -155,15 +212,25 @@ async fn post_template(
     let user = get_current_user();
     let DeviceWithAuth { auth, device } =
         DeviceService::query_one_with_auth(user.id, id, &state.db).await?;
+    let (data, port) = match &template.json {
+        Some(json) => {
+            let mut redis = state.redis.get().await?;
+            let encoded = encode_json(&device, json.clone(), &mut redis, &state.db).await?;
+            let port = encoded.f_port.map(i32::from).unwrap_or(template.port);
+            (base64::engine::general_purpose::STANDARD.encode(encoded.bytes), port)
+        }
+        None => (template.data, template.port),
+    };
 
     let model = SnapDownLinkActiveModel {
         id: Default::default(),
         device_id: ActiveValue::Set(id),
         user_id: ActiveValue::Set(user.id),
         name: ActiveValue::Set(template.name),
-        data: ActiveValue::Set(template.data),
+        data: ActiveValue::Set(data),
+        json: ActiveValue::Set(template.json),
         order: ActiveValue::Set(0),
-        port: ActiveValue::Set(template.port),
+        port: ActiveValue::Set(port),
         create_time: ActiveValue::Set(Timestamp::now()),
     };
     let ok = model.insert(&state.db).await?;
@@ -171,6 +238,7 @@ async fn post_template(
         id: ok.id,
         name: ok.name,
         data: ok.data,
+        json: ok.json,
         port: ok.port,
     }
     .into())
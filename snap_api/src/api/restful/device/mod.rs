@@ -15,6 +15,7 @@ mod io;
 mod log;
 mod map;
 mod product;
+mod profile;
 // mod model;
 
 pub(crate) fn router() -> OpenApiRouter<AppState> {
@@ -29,6 +30,7 @@ pub(crate) fn router() -> OpenApiRouter<AppState> {
         .nest("/query", query::router())
     // .nest("/lorawan", lorawan::router())
     .nest("/product", product::router())
+    .nest("/profile", profile::router())
 }
 
 #[derive(OpenApi)]
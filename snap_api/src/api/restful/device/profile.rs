@@ -0,0 +1,114 @@
+use axum::extract::State;
+use common_define::Id;
+use sea_orm::TransactionTrait;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+use crate::api::{SnJson, SnPath};
+use crate::cache::DeviceCache;
+use crate::error::{ApiError, ApiResponseResult};
+use crate::service::lorawan::{DeviceProfileResp, DeviceProfileService, ReqDeviceProfile};
+use crate::{get_current_user, tt, AppState, AppString};
+
+pub(crate) fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(post_profile, all_profile))
+        .routes(routes!(get_profile, put_profile, delete_profile))
+}
+
+/// Create a device profile
+#[utoipa::path(
+    method(post),
+    path = "",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DEVICE_TAG
+)]
+async fn post_profile(
+    State(state): State<AppState>,
+    SnJson(req): SnJson<ReqDeviceProfile>,
+) -> ApiResponseResult<DeviceProfileResp> {
+    let user = get_current_user();
+    let profile = DeviceProfileService::create(&user, req, &state.db).await?;
+    Ok(DeviceProfileResp::from(profile).into())
+}
+
+/// Get all device profiles
+#[utoipa::path(
+    method(get),
+    path = "",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DEVICE_TAG
+)]
+async fn all_profile(State(state): State<AppState>) -> ApiResponseResult<Vec<DeviceProfileResp>> {
+    let user = get_current_user();
+    let profiles = DeviceProfileService::list(&user, &state.db).await?;
+    Ok(profiles.into_iter().map(DeviceProfileResp::from).collect::<Vec<_>>().into())
+}
+
+/// Get a device profile
+#[utoipa::path(
+    method(get),
+    path = "/{id}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DEVICE_TAG
+)]
+async fn get_profile(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+) -> ApiResponseResult<DeviceProfileResp> {
+    let user = get_current_user();
+    let profile = DeviceProfileService::get(&user, id, &state.db).await?;
+    Ok(DeviceProfileResp::from(profile).into())
+}
+
+/// Modify a device profile, nodes using it pick up the new settings
+#[utoipa::path(
+    method(put),
+    path = "/{id}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DEVICE_TAG
+)]
+async fn put_profile(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+    SnJson(req): SnJson<ReqDeviceProfile>,
+) -> ApiResponseResult<AppString> {
+    let mut redis = state.redis.get().await?;
+    state
+        .db
+        .transaction::<_, _, ApiError>(|ctx| {
+            Box::pin(async move {
+                let user = get_current_user();
+                DeviceProfileService::update(&user, id, req, &mut redis, ctx).await?;
+                DeviceCache::delete_by_user_id(user.id, &mut redis).await?;
+                Ok(())
+            })
+        })
+        .await?;
+    Ok(tt!("messages.device.profile.update_success").into())
+}
+
+/// Delete a device profile
+#[utoipa::path(
+    method(delete),
+    path = "/{id}",
+    responses(
+        (status = OK, description = "Success", body = str)
+    ),
+    tag = crate::DEVICE_TAG
+)]
+async fn delete_profile(
+    State(state): State<AppState>,
+    SnPath(id): SnPath<Id>,
+) -> ApiResponseResult<AppString> {
+    let user = get_current_user();
+    DeviceProfileService::delete(&user, id, &state.db).await?;
+    Ok(tt!("messages.device.profile.delete_success").into())
+}
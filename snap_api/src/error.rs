@@ -160,6 +160,42 @@ impl From<serde_json::Error> for ApiError {
     }
 }
 
+impl From<common_define::telemetry::TelemetryError> for ApiError {
+    fn from(value: common_define::telemetry::TelemetryError) -> Self {
+        Self::Server {
+            case: "telemetry",
+            msg: value.to_string().into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Server {
+            case: "io",
+            msg: value.to_string().into(),
+        }
+    }
+}
+
+impl From<parquet::errors::ParquetError> for ApiError {
+    fn from(value: parquet::errors::ParquetError) -> Self {
+        Self::Server {
+            case: "parquet",
+            msg: value.to_string().into(),
+        }
+    }
+}
+
+impl From<arrow_schema::ArrowError> for ApiError {
+    fn from(value: arrow_schema::ArrowError) -> Self {
+        Self::Server {
+            case: "arrow",
+            msg: value.to_string().into(),
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         self.response().into_response()
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use std::sync::Arc;
 use crate::api::restful;
 use async_graphql::http::GraphiQLSource;
 
@@ -15,13 +16,14 @@ use sea_orm::EntityTrait;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 use utoipa::{Modify, OpenApi};
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_scalar::{Scalar, Servable};
 use common_define::db::{SnapProductInfoEntity};
 use common_define::event::DeviceEvent;
+use common_define::telemetry::{TelemetryResult, TelemetryStore};
 use snap_config::SnapConfig;
 
 
@@ -64,6 +66,21 @@ static MODEL_MAP: Lazy<snap_model::ModelMap> = Lazy::new(|| {
 });
 
 
+static TELEMETRY: once_cell::sync::OnceCell<Arc<dyn TelemetryStore>> = once_cell::sync::OnceCell::new();
+
+/// The store of device data, opened by [`run`] before any request is served.
+fn telemetry() -> &'static dyn TelemetryStore {
+    TELEMETRY.get().expect("telemetry store is not opened").as_ref()
+}
+
+/// Opens and initializes the configured store of device data for [`telemetry`].
+async fn open_telemetry(config: &snap_config::TelemetryConfig, db: &sea_orm::DatabaseConnection) -> TelemetryResult {
+    let telemetry = common_define::telemetry::open(config, db.clone());
+    telemetry.init().await?;
+    let _ = TELEMETRY.set(telemetry);
+    Ok(())
+}
+
 #[derive(Clone)]
 struct AppState {
     db: sea_orm::DatabaseConnection,
@@ -142,10 +159,21 @@ fn load_redis(config: &SnapConfig) -> deadpool_redis::Pool  {
 pub async fn run(config_path: String, env_prefix: String) {
     let config = store_config(config_path, env_prefix);
     snap_config::init_logging(config.log);
+    common_define::db::set_key_encryption_key(config.key.load().unwrap());
     let redis_pool = RedisClient::get_client();
     let db = load_db().await;
 
     migration::Migrator::up(&db, None).await.unwrap();
+    if common_define::db::key_encryption_key().is_some() {
+        // keys stored while no key-encryption key was configured
+        let txn = sea_orm::TransactionTrait::begin(&db).await.unwrap();
+        let wrapped = common_define::db::wrap_plain_keys(&txn).await.unwrap();
+        txn.commit().await.unwrap();
+        if wrapped > 0 {
+            info!("wrap {} plain keys", wrapped);
+        }
+    }
+    open_telemetry(&config.telemetry, &db).await.unwrap();
     let redis: RedisClient = RedisClient::get_client();
     let mut consumer = RedisRecv::new(redis.get_pubsub().await.unwrap());
     consumer.subscribe(DeviceEvent::KAFKA_TOPIC).await.unwrap();
@@ -167,6 +195,7 @@ pub async fn run(config_path: String, env_prefix: String) {
             }
         }
     });
+    man::RetentionManager::start(db.clone());
     let state = AppState {
         db,
         redis: redis_pool.clone()
@@ -214,6 +243,80 @@ pub async fn run(config_path: String, env_prefix: String) {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Wraps every stored device key with the configured key-encryption key.
+/// `old_kek` is the key the rows were wrapped with before a rotation.
+pub async fn rewrap_keys(config_path: String, env_prefix: String, old_kek: Option<String>, old_kek_file: Option<String>) {
+    let config = store_config(config_path, env_prefix);
+    snap_config::init_logging(config.log);
+    let old = snap_config::KeyEncryptionConfig { kek: old_kek, kek_file: old_kek_file };
+    let old_kek = old.load().unwrap();
+    let kek = config.key.load().unwrap();
+    if kek.is_none() {
+        warn!("no key encryption key configured, keys are stored as plain hex");
+    }
+    common_define::db::set_key_encryption_key(kek);
+    let db = load_db().await;
+    migration::Migrator::up(&db, None).await.unwrap();
+    let mut redis = RedisClient::get_client().get().await.unwrap();
+    let count = service::lorawan::KeyService::rewrap(old_kek, &db, &mut redis).await.unwrap();
+    info!("rewrap {} keys", count);
+}
+
+/// Recomputes the hourly and daily rollups from the stored uplinks, `start` and `end` are unix millis.
+pub async fn backfill_rollups(config_path: String, env_prefix: String, device: Option<u64>, start: Option<u64>, end: Option<u64>) {
+    let config = store_config(config_path, env_prefix);
+    snap_config::init_logging(config.log);
+    let db = load_db().await;
+    migration::Migrator::up(&db, None).await.unwrap();
+    let timestamp = |millis: u64| common_define::time::Timestamp::from_timestamp_millis(millis).unwrap();
+    let days = service::data::DataService::backfill_rollups(
+        device.map(common_define::Id),
+        start.map(timestamp),
+        end.map(timestamp),
+        &db,
+    ).await.unwrap();
+    info!("backfill rollups of {} days", days);
+}
+
+/// Imports the history of a device from a `.csv`, `.jsonl` or `.ndjson` file.
+pub async fn import_data(config_path: String, env_prefix: String, device: u64, file: String, bytes: bool, f_port: u8) {
+    let config = store_config(config_path, env_prefix);
+    snap_config::init_logging(config.log);
+    let db = load_db().await;
+    migration::Migrator::up(&db, None).await.unwrap();
+    let mut redis = RedisClient::get_client().get().await.unwrap();
+    let kind = if bytes { service::data::import::ImportKind::Bytes } else { service::data::import::ImportKind::Values };
+    match import_file(&config.telemetry, common_define::Id(device), &file, kind, f_port, &mut redis, &db).await {
+        Ok(report) => {
+            for row in &report.rows {
+                warn!(line = row.line, "reject: {}", row.reason);
+            }
+            info!("import {} uplinks with {} values, reject {} rows", report.uplinks, report.values, report.rejected);
+        }
+        Err(e) => error!("import {}: {}", file, e),
+    }
+}
+
+/// [`import_data`] once the configuration is loaded, the command opens the telemetry store itself.
+async fn import_file<R: redis::aio::ConnectionLike>(
+    telemetry: &snap_config::TelemetryConfig,
+    device: common_define::Id,
+    file: &str,
+    kind: service::data::import::ImportKind,
+    f_port: u8,
+    redis: &mut R,
+    db: &sea_orm::DatabaseConnection,
+) -> error::ApiResult<service::data::import::ImportReport> {
+    use service::data::import::{ImportFormat, ImportOptions};
+    open_telemetry(telemetry, db).await?;
+    let format = ImportFormat::from_path(file)
+        .ok_or_else(|| error::ApiError::User(tt!("messages.user.data.import_format")))?;
+    let text = tokio::fs::read_to_string(file).await?;
+    let device = common_define::db::DevicesEntity::find_by_id(device).one(db).await?
+        .ok_or_else(|| error::ApiError::Device { device_id: device, msg: tt!("messages.device.common.device_missing", device_id = device) })?;
+    service::data::DataService::import_device(&device, ImportOptions { format, kind, f_port }, &text, redis, db).await
+}
+
 async fn accept_language(request: Request<axum::body::Body>, next: Next) -> Response {
     let lang = request.headers()
         .get(http::header::ACCEPT_LANGUAGE)
@@ -222,4 +325,84 @@ async fn accept_language(request: Request<axum::body::Body>, next: Next) -> Resp
     run_with_lang(lang, next.run(request)).await
 }
 
+#[cfg(test)]
+mod tests {
+    use sea_orm::{ActiveModelTrait, ActiveValue, EntityTrait};
+    use migration::MigratorTrait;
+    use common_define::db::{CodeMapItem, DecodeMap, DecodeScriptActiveModel, DecodeScriptEntity, DevicesActiveModel, DevicesEntity, Eui};
+    use common_define::decode::DecodeDataType;
+    use common_define::Id;
+    use common_define::product::DeviceType;
+    use common_define::time::Timestamp;
+    use crate::service::data::import::ImportKind;
 
+    /// Fails every command, importing values never reaches redis.
+    struct NoRedis;
+
+    impl redis::aio::ConnectionLike for NoRedis {
+        fn req_packed_command<'a>(&'a mut self, _cmd: &'a redis::Cmd) -> redis::RedisFuture<'a, redis::Value> {
+            Box::pin(async { Err((redis::ErrorKind::IoError, "no redis").into()) })
+        }
+
+        fn req_packed_commands<'a>(&'a mut self, _cmd: &'a redis::Pipeline, _offset: usize, _count: usize) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            Box::pin(async { Err((redis::ErrorKind::IoError, "no redis").into()) })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in SNAPEMU_TEST_DB"]
+    async fn test_import_file() {
+        let db = sea_orm::Database::connect(std::env::var("SNAPEMU_TEST_DB").unwrap()).await.unwrap();
+        migration::Migrator::up(&db, None).await.unwrap();
+        let now = Timestamp::now();
+        let map = DecodeMap(vec![CodeMapItem { id: 1, name: "t".to_string(), unit: String::new(), t: DecodeDataType::F64 }]);
+        let script = DecodeScriptActiveModel {
+            id: Default::default(),
+            script: ActiveValue::Set(String::new()),
+            lang: ActiveValue::Set("JS".to_string()),
+            owner: ActiveValue::Set(Id(0)),
+            name: ActiveValue::Set("import".to_string()),
+            map: ActiveValue::Set(map),
+            codec: Default::default(),
+            version: ActiveValue::Set(1),
+            create_time: ActiveValue::Set(now),
+            modify_time: ActiveValue::Set(now),
+        }.insert(&db).await.unwrap();
+        let device = DevicesActiveModel {
+            id: Default::default(),
+            eui: ActiveValue::Set(Eui::new(uuid::Uuid::new_v4().as_u64_pair().0)),
+            name: ActiveValue::Set("import".to_string()),
+            description: ActiveValue::Set(String::new()),
+            creator: ActiveValue::Set(Id(0)),
+            enable: ActiveValue::Set(true),
+            online: ActiveValue::Set(false),
+            script: ActiveValue::Set(Some(script.id)),
+            script_version: ActiveValue::Set(None),
+            encoder: ActiveValue::Set(None),
+            codec: ActiveValue::Set(None),
+            data_id: Default::default(),
+            product_id: Default::default(),
+            device_type: ActiveValue::Set(DeviceType::Snap),
+            active_time: ActiveValue::Set(None),
+            create_time: ActiveValue::Set(now),
+            variables: Default::default(),
+        }.insert(&db).await.unwrap();
+        let file = std::env::temp_dir().join(format!("import-{}.csv", uuid::Uuid::new_v4()));
+        tokio::fs::write(&file, "time,data_id,value\n1000,1,2.5\n2000,1,true\n").await.unwrap();
+
+        // the command opens the store itself, nothing else in this process did
+        let report = super::import_file(&Default::default(), device.id, file.to_str().unwrap(), ImportKind::Values, 0, &mut NoRedis, &db).await.unwrap();
+        assert_eq!((report.uplinks, report.values, report.rejected), (1, 1, 1));
+        let time = |millis| Timestamp::from_timestamp_millis(millis).unwrap();
+        assert_eq!(super::telemetry().range(device.id, time(0), time(10_000)).await.unwrap().len(), 1);
+
+        super::telemetry().delete(&[device.id]).await.unwrap();
+        DevicesEntity::delete_by_id(device.id).exec(&db).await.unwrap();
+        DecodeScriptEntity::delete_by_id(script.id).exec(&db).await.unwrap();
+        tokio::fs::remove_file(file).await.unwrap();
+    }
+}
@@ -14,8 +14,16 @@ pub struct AppConfig {
     pub log: snap_config::LogLevelConfig,
     pub jwt_key: String,
     #[serde(default)]
+    pub key: snap_config::KeyEncryptionConfig,
+    #[serde(default)]
     pub concat_email: Option<String>,
     pub api: ApiConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub telemetry: snap_config::TelemetryConfig,
 }
 
 impl Default for AppConfig {
@@ -25,6 +33,7 @@ impl Default for AppConfig {
             redis: Default::default(),
             log: Default::default(),
             jwt_key: "".to_string(),
+            key: Default::default(),
             concat_email: None,
             api: ApiConfig {
                 predefine: None,
@@ -38,6 +47,9 @@ impl Default for AppConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
             },
+            retention: Default::default(),
+            export: Default::default(),
+            telemetry: Default::default(),
         }
     }
 }
@@ -79,6 +91,68 @@ pub struct ModelConfig {
     pub path: String,
 }
 
+/// Pruning of data older than the retention policies allow, the policies themselves are stored in the database.
+#[derive(Debug, Deserialize)]
+pub struct RetentionConfig {
+    #[serde(default="_default_retention_enable")]
+    pub enable: bool,
+    /// Seconds between two runs.
+    #[serde(default="_default_retention_interval")]
+    pub interval: u64,
+    /// Rows deleted by one statement.
+    #[serde(default="_default_retention_batch")]
+    pub batch: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enable: _default_retention_enable(),
+            interval: _default_retention_interval(),
+            batch: _default_retention_batch(),
+        }
+    }
+}
+
+fn _default_retention_enable() -> bool {
+    true
+}
+
+fn _default_retention_interval() -> u64 {
+    3600
+}
+
+fn _default_retention_batch() -> u64 {
+    5000
+}
+
+/// Files of data export jobs, the directory must be shared by every instance of the API.
+#[derive(Debug, Deserialize)]
+pub struct ExportConfig {
+    #[serde(default="_default_export_dir")]
+    pub dir: String,
+    /// Seconds an export can be downloaded.
+    #[serde(default="_default_export_ttl")]
+    pub ttl: u64,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            dir: _default_export_dir(),
+            ttl: _default_export_ttl(),
+        }
+    }
+}
+
+fn _default_export_dir() -> String {
+    "exports".to_string()
+}
+
+fn _default_export_ttl() -> u64 {
+    24 * 3600
+}
+
 fn _default_email_port() -> u32 {
     465
 }
@@ -1,4 +1,4 @@
-use snap_api::run;
+use snap_api::{backfill_rollups, import_data, rewrap_keys, run};
 
 use clap::{Command, FromArgMatches as _, Parser, Subcommand as _};
 
@@ -10,6 +10,49 @@ enum Subcommands {
         #[arg(short, long, default_value="SNAPEMU_API_", env="SNAPEMU_API_ENV_PREFIX")]
         env_prefix: String,
     },
+    /// Wrap stored device keys with the configured key encryption key,
+    /// unwrapping with the old key first when rotating
+    RewrapKeys {
+        #[arg(short, long, default_value="/etc/snapemu/config.yaml", env="SNAPEMU_CONFIG")]
+        config: String,
+        #[arg(short, long, default_value="SNAPEMU_API_", env="SNAPEMU_API_ENV_PREFIX")]
+        env_prefix: String,
+        #[arg(long)]
+        old_kek: Option<String>,
+        #[arg(long)]
+        old_kek_file: Option<String>,
+    },
+    /// Recompute the hourly and daily rollups of device data from the stored uplinks,
+    /// from the oldest uplink until now unless a range in unix milliseconds is given
+    BackfillRollups {
+        #[arg(short, long, default_value="/etc/snapemu/config.yaml", env="SNAPEMU_CONFIG")]
+        config: String,
+        #[arg(short, long, default_value="SNAPEMU_API_", env="SNAPEMU_API_ENV_PREFIX")]
+        env_prefix: String,
+        #[arg(long)]
+        device: Option<u64>,
+        #[arg(long)]
+        start: Option<u64>,
+        #[arg(long)]
+        end: Option<u64>,
+    },
+    /// Import the history of a device from a .csv, .jsonl or .ndjson file of
+    /// `time, data_id, value` rows, or of `time, bytes` rows with --bytes
+    ImportData {
+        #[arg(short, long, default_value="/etc/snapemu/config.yaml", env="SNAPEMU_CONFIG")]
+        config: String,
+        #[arg(short, long, default_value="SNAPEMU_API_", env="SNAPEMU_API_ENV_PREFIX")]
+        env_prefix: String,
+        #[arg(long)]
+        device: u64,
+        #[arg(long)]
+        file: String,
+        /// Rows hold base64 payloads decoded with the decode script of the device
+        #[arg(long)]
+        bytes: bool,
+        #[arg(long, default_value_t = 0)]
+        f_port: u8,
+    },
 }
 
 fn cmd() -> Command {
@@ -29,6 +72,15 @@ async fn main() {
                 Subcommands::Run { config, env_prefix } => {
                     run(config, env_prefix).await;
                 }
+                Subcommands::RewrapKeys { config, env_prefix, old_kek, old_kek_file } => {
+                    rewrap_keys(config, env_prefix, old_kek, old_kek_file).await;
+                }
+                Subcommands::BackfillRollups { config, env_prefix, device, start, end } => {
+                    backfill_rollups(config, env_prefix, device, start, end).await;
+                }
+                Subcommands::ImportData { config, env_prefix, device, file, bytes, f_port } => {
+                    import_data(config, env_prefix, device, file, bytes, f_port).await;
+                }
             }
         }
         Err(_) => {
@@ -5,6 +5,7 @@ mod redis_client;
 mod user_manager;
 
 mod device_predefine;
+mod retention;
 pub mod user_status;
 pub mod sync_device;
 
@@ -14,6 +15,7 @@ pub use redis_client::{RedisClient, RedisRecv};
 pub use node_event::NodeEventManager;
 
 pub use email::EmailManager;
+pub(crate) use retention::RetentionManager;
 
 
 
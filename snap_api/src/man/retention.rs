@@ -0,0 +1,81 @@
+use std::time::Duration;
+use sea_orm::DatabaseConnection;
+use tracing::{info, warn};
+use common_define::time::Timestamp;
+use crate::error::ApiResult;
+use crate::load::load_config;
+use crate::man::RedisClient;
+use crate::service::data::DataService;
+use crate::service::data::retention::RetentionRun;
+
+/// Prunes device data older than the retention policies allow, in every instance of the API
+/// but only one at a time.
+pub(crate) struct RetentionManager;
+
+impl RetentionManager {
+    const LOCK_KEY: &'static str = "retention:lock";
+    const RUNS_KEY: &'static str = "retention:runs";
+    /// Runs kept for the admin API, newest first.
+    const MAX_RUNS: isize = 50;
+
+    pub(crate) fn start(db: DatabaseConnection) {
+        let config = load_config();
+        if !config.retention.enable {
+            info!("data retention is disabled");
+            return;
+        }
+        let interval = Duration::from_secs(config.retention.interval.max(60));
+        let batch = config.retention.batch;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = Self::run(batch, interval, &db).await {
+                    warn!("data retention: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn run(batch: u64, interval: Duration, db: &DatabaseConnection) -> ApiResult {
+        let mut redis = RedisClient::get_client().get_multiplexed_conn().await?;
+        // expires before the next tick so the instance running next is not fixed
+        let locked: Option<String> = redis::cmd("SET")
+            .arg(Self::LOCK_KEY)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg((interval.as_secs() / 2).max(1))
+            .query_async(&mut redis)
+            .await?;
+        if locked.is_none() {
+            return Ok(());
+        }
+        let mut run = RetentionRun {
+            start_time: Some(Timestamp::now()),
+            ..Default::default()
+        };
+        if let Err(e) = DataService::prune_expired(batch, &mut run, db).await {
+            run.error = Some(e.to_string());
+        }
+        run.end_time = Some(Timestamp::now());
+        info!(
+            devices = run.devices,
+            raw = run.raw,
+            rollups = run.rollups,
+            orphans = run.orphans,
+            error = ?run.error,
+            "data retention run"
+        );
+        let _: () = redis::pipe()
+            .lpush(Self::RUNS_KEY, run)
+            .ltrim(Self::RUNS_KEY, 0, Self::MAX_RUNS - 1)
+            .query_async(&mut redis)
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn runs<R: redis::aio::ConnectionLike>(redis: &mut R) -> ApiResult<Vec<RetentionRun>> {
+        Ok(redis::cmd("LRANGE").arg(Self::RUNS_KEY).arg(0).arg(-1).query_async(redis).await?)
+    }
+}
@@ -52,7 +52,8 @@ pub async fn sync_node<C: ConnectionTrait>(
     for (device, node) in nodes {
         let exist = NodeInfo::check_eui(node.dev_eui, redis_conn).await?;
         if !exist {
-            NodeInfo::register_to_redis(node, device, redis_conn).await?;
+            let profile = node.load_profile(conn).await?;
+            NodeInfo::register_to_redis(node, device, profile, redis_conn).await?;
         }
     }
     Ok(())
@@ -30,7 +30,7 @@ impl EmailCount {
         
         let count = if self.count >= Self::MAX_COUNT  {  Self::MAX_COUNT } else { self.count + 1 };
         
-        redis::cmd("SET")
+        let _: () = redis::cmd("SET")
             .arg(&self.key)
             .arg(count)
             .arg("EX")
@@ -77,7 +77,7 @@ impl UserStatus {
         }
         let code = hex::encode(code);
         let k = Self::code(email, &code);
-        redis::cmd("SET")
+        let _: () = redis::cmd("SET")
             .arg(&k)
             .arg("")
             .arg("EX")
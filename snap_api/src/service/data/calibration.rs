@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, QueryFilter, QueryOrder, TryIntoModel};
+use serde::{Deserialize, Serialize};
+use common_define::db::{DataCalibrationActiveModel, DataCalibrationColumn, DataCalibrationEntity, DataCalibrationModel, UsersEntity};
+use common_define::decode::Value;
+use common_define::event::CalibrationEvent;
+use common_define::Id;
+use common_define::time::Timestamp;
+use common_define::unit::{Calibration, UnitPreference};
+use crate::{tt, CurrentUser};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::data::query::{DataDeviceOneResponse, DataResponse};
+use crate::service::device::DeviceService;
+
+/// Most decimal places a data point can be displayed with.
+const MAX_PRECISION: u8 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CalibrationRequest {
+    data_id: u32,
+    /// Applied to new uplinks, re-decode the history to apply it to stored data.
+    #[serde(default)]
+    calibration: Option<Calibration>,
+    /// Decimal places the value is displayed with.
+    #[serde(default)]
+    precision: Option<u8>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CalibrationResponse {
+    data_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calibration: Option<Calibration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    precision: Option<u8>,
+    modify_time: Timestamp,
+}
+
+impl From<DataCalibrationModel> for CalibrationResponse {
+    fn from(value: DataCalibrationModel) -> Self {
+        Self {
+            data_id: value.data_id as u32,
+            calibration: value.calibration,
+            precision: value.precision.map(|it| it as u8),
+            modify_time: value.modify_time,
+        }
+    }
+}
+
+/// How the data of a device is shown to a user: in the units of the user
+/// and with the precision set for each data point of the device.
+#[derive(Default)]
+pub(crate) struct DataDisplay {
+    units: UnitPreference,
+    precision: HashMap<u32, u8>,
+}
+
+impl DataDisplay {
+    /// Converts `value` from `unit` to the unit of the user, `None` when it is kept.
+    pub(crate) fn convert(&self, unit: &str, value: &Value) -> Option<Value> {
+        self.units.convert_value(unit, value).map(|(value, _)| value)
+    }
+
+    /// Converts a number from `unit` to the unit of the user, kept when the unit is kept.
+    pub(crate) fn convert_number(&self, unit: &str, value: f64) -> f64 {
+        self.units.convert(unit, value).map_or(value, |(value, _)| value)
+    }
+
+    /// The unit of the user for `unit`, `None` when it is kept.
+    pub(crate) fn unit(&self, unit: &str) -> Option<&'static str> {
+        self.units.unit_of(unit)
+    }
+
+    pub(crate) fn precision(&self, data_id: u32) -> Option<u8> {
+        self.precision.get(&data_id).copied()
+    }
+
+    pub(crate) fn apply_one(&self, resp: &mut DataDeviceOneResponse) {
+        resp.precision = self.precision(resp.data_id);
+        let Some(unit) = self.unit(&resp.unit) else {
+            return
+        };
+        if let Some(value) = self.convert(&resp.unit, &resp.data.data) {
+            resp.data.data = value;
+        }
+        resp.source_unit = Some(std::mem::replace(&mut resp.unit, unit.to_string()));
+    }
+
+    pub(crate) fn apply_series(&self, resp: &mut DataResponse) {
+        resp.precision = self.precision(resp.data_id);
+        let Some(unit) = self.unit(&resp.unit) else {
+            return
+        };
+        for item in resp.data.iter_mut() {
+            if let Some(value) = self.convert(&resp.unit, &item.data) {
+                item.data = value;
+            }
+        }
+        resp.source_unit = Some(std::mem::replace(&mut resp.unit, unit.to_string()));
+    }
+
+    /// Converts a sum of `count` numbers, the offset of a conversion is added once per number.
+    pub(crate) fn convert_sum(&self, unit: &str, sum: f64, count: i64) -> f64 {
+        match (self.units.convert(unit, 0.0), self.units.convert(unit, 1.0)) {
+            (Some((zero, _)), Some((one, _))) => sum * (one - zero) + zero * count as f64,
+            _ => sum,
+        }
+    }
+}
+
+impl DataService {
+
+    async fn check_calibration_owner<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult {
+        let device = DeviceService::query_one_with_auth(user.id, device, conn).await?;
+        if !device.auth.owner {
+            return Err(ApiError::User(tt!("messages.user.data.calibration_owner")));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn device_calibrations<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult<Vec<CalibrationResponse>> {
+        DeviceService::query_one(user.id, device, conn).await?;
+        let calibrations = DataCalibrationEntity::find()
+            .filter(DataCalibrationColumn::DeviceId.eq(device))
+            .order_by_asc(DataCalibrationColumn::DataId)
+            .all(conn)
+            .await?;
+        Ok(calibrations.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn set_device_calibration<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        device: Id,
+        req: CalibrationRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<CalibrationResponse> {
+        Self::check_calibration_owner(user, device, conn).await?;
+        if let Some(calibration) = &req.calibration {
+            calibration.check()
+                .map_err(|e| ApiError::User(tt!("messages.user.data.calibration", error = e)))?;
+        }
+        if req.precision.is_some_and(|it| it > MAX_PRECISION) {
+            return Err(ApiError::User(tt!("messages.user.data.precision", max = MAX_PRECISION)));
+        }
+        let current = DataCalibrationEntity::find()
+            .filter(DataCalibrationColumn::DeviceId.eq(device))
+            .filter(DataCalibrationColumn::DataId.eq(req.data_id as i64))
+            .one(conn)
+            .await?;
+        let mut model = match current {
+            Some(current) => current.into_active_model(),
+            None => DataCalibrationActiveModel {
+                id: Default::default(),
+                device_id: ActiveValue::Set(device),
+                data_id: ActiveValue::Set(req.data_id as i64),
+                ..Default::default()
+            },
+        };
+        model.calibration = ActiveValue::Set(req.calibration);
+        model.precision = ActiveValue::Set(req.precision.map(|it| it as i16));
+        model.modify_time = ActiveValue::Set(Timestamp::now());
+        let model = model.save(conn).await?.try_into_model()?;
+        Self::publish_calibration(device, redis).await?;
+        Ok(model.into())
+    }
+
+    pub(crate) async fn delete_device_calibration<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        device: Id,
+        data_id: u32,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult {
+        Self::check_calibration_owner(user, device, conn).await?;
+        let current = DataCalibrationEntity::find()
+            .filter(DataCalibrationColumn::DeviceId.eq(device))
+            .filter(DataCalibrationColumn::DataId.eq(data_id as i64))
+            .one(conn)
+            .await?;
+        if let Some(current) = current {
+            current.delete(conn).await?;
+            Self::publish_calibration(device, redis).await?;
+        }
+        Ok(())
+    }
+
+    /// Tells devices_manager to load the calibrations of the device again.
+    async fn publish_calibration<R: redis::aio::ConnectionLike>(device: Id, redis: &mut R) -> ApiResult {
+        let event = serde_json::to_string(&CalibrationEvent { device })?;
+        let _: () = redis::cmd("PUBLISH").arg(CalibrationEvent::TOPIC).arg(event).query_async(redis).await?;
+        Ok(())
+    }
+
+    /// Removes the calibrations of deleted devices.
+    pub(crate) async fn delete_device_calibrations<C: ConnectionTrait>(devices: &[Id], conn: &C) -> ApiResult {
+        DataCalibrationEntity::delete_many()
+            .filter(DataCalibrationColumn::DeviceId.is_in(devices))
+            .exec(conn)
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn user_units<C: ConnectionTrait>(user: &CurrentUser, conn: &C) -> ApiResult<UnitPreference> {
+        let user = UsersEntity::find_by_id(user.id)
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.user.info.not_found")))?;
+        Ok(user.units)
+    }
+
+    pub(crate) async fn set_user_units<C: ConnectionTrait>(user: &CurrentUser, units: UnitPreference, conn: &C) -> ApiResult<UnitPreference> {
+        let user = UsersEntity::find_by_id(user.id)
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.user.info.not_found")))?;
+        let mut model = user.into_active_model();
+        model.units = ActiveValue::Set(units);
+        model.update(conn).await?;
+        Ok(units)
+    }
+
+    /// Units of the user and precisions of the device the data of `device` is shown with.
+    pub(crate) async fn data_display<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult<DataDisplay> {
+        let mut displays = Self::data_displays(user, &[device], conn).await?;
+        Ok(displays.remove(&device).unwrap_or_default())
+    }
+
+    /// [`Self::data_display`] of many devices.
+    pub(crate) async fn data_displays<C: ConnectionTrait>(user: &CurrentUser, devices: &[Id], conn: &C) -> ApiResult<HashMap<Id, DataDisplay>> {
+        let units = Self::user_units(user, conn).await?;
+        let mut displays: HashMap<Id, DataDisplay> = devices.iter()
+            .map(|device| (*device, DataDisplay { units, precision: HashMap::new() }))
+            .collect();
+        let precisions = DataCalibrationEntity::find()
+            .filter(DataCalibrationColumn::DeviceId.is_in(devices.iter().copied()))
+            .filter(DataCalibrationColumn::Precision.is_not_null())
+            .all(conn)
+            .await?;
+        for model in precisions {
+            if let (Some(display), Some(precision)) = (displays.get_mut(&model.device_id), model.precision) {
+                display.precision.insert(model.data_id as u32, precision as u8);
+            }
+        }
+        Ok(displays)
+    }
+}
@@ -0,0 +1,795 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use redis_macros::{FromRedisValue, ToRedisArgs};
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, QuerySelect, Statement};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::{info, warn};
+use common_define::db::{CodeMapItem, DecodeScriptEntity, DeviceDataColumn, DeviceDataEntity, DeviceDataModel, DeviceGroupColumn, DeviceGroupEntity, DevicesEntity, DevicesModel};
+use common_define::decode::Value;
+use common_define::Id;
+use common_define::time::Timestamp;
+use crate::{get_lang, tt, CurrentUser, MODEL_MAP, SEA_ORMDB_BACKEND};
+use crate::error::{ApiError, ApiResult};
+use crate::load::load_config;
+use crate::man::RedisClient;
+use crate::service::data::DataService;
+use crate::service::device::DeviceService;
+
+/// Data IDs stored in a range, `{devices}` is filled in per export.
+const DATA_IDS_SQL: &str = r"SELECT DISTINCT (e->>'i')::bigint AS data_id
+FROM snap_device_data d
+CROSS JOIN LATERAL json_array_elements(CASE WHEN json_typeof(d.data) = 'array' THEN d.data ELSE '[]'::json END) AS e
+WHERE d.device_id IN ({devices}) AND d.create_time >= $1 AND d.create_time < $2
+ORDER BY 1";
+
+/// Most devices of one export.
+const MAX_DEVICES: usize = 500;
+/// Uplinks read per query and between progress reports.
+const BATCH: u64 = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    Csv,
+    /// One JSON object per uplink and line.
+    Jsonl,
+    /// One row per value, numbers and booleans in `value`, everything else in `text`.
+    Parquet,
+}
+
+/// Columns of a CSV export, Parquet is always long.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CsvLayout {
+    /// One row per uplink, one column per data ID.
+    #[default]
+    Wide,
+    /// One row per value.
+    Long,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ExportRequest {
+    #[serde(default)]
+    devices: Vec<Id>,
+    /// Exports the devices of the group, added to `devices`.
+    group: Option<Id>,
+    start: Timestamp,
+    end: Timestamp,
+    /// All data IDs when not set.
+    ids: Option<Vec<u32>>,
+    format: ExportFormat,
+    #[serde(default)]
+    layout: CsvLayout,
+    /// Adds the payload of each uplink in base64.
+    #[serde(default)]
+    bytes: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportState {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, FromRedisValue, ToRedisArgs)]
+pub(crate) struct ExportJob {
+    id: String,
+    owner: Id,
+    state: ExportState,
+    format: ExportFormat,
+    /// Uplinks written so far.
+    rows: u64,
+    /// Bytes of the file once done.
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    create_time: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_time: Option<Timestamp>,
+}
+
+/// File of a finished export.
+pub(crate) struct ExportFile {
+    pub(crate) path: PathBuf,
+    pub(crate) name: String,
+    pub(crate) content_type: &'static str,
+}
+
+#[derive(FromQueryResult)]
+struct DataIdRow {
+    data_id: i64,
+}
+
+/// A device with the names and units of its data IDs, from its virtual points,
+/// its decode map or the model map.
+struct ExportDevice {
+    id: Id,
+    name: String,
+    map: Option<HashMap<u32, CodeMapItem>>,
+    points: HashMap<u32, CodeMapItem>,
+}
+
+impl ExportDevice {
+    fn entry(&self, data_id: u32, lang: &str) -> (&str, &str) {
+        if let Some(point) = self.points.get(&data_id) {
+            return (point.name.as_str(), point.unit.as_str())
+        }
+        match &self.map {
+            Some(map) => map.get(&data_id)
+                .map(|it| (it.name.as_str(), it.unit.as_str()))
+                .unwrap_or_default(),
+            None => {
+                let entry = MODEL_MAP.get_entry(data_id, lang);
+                (entry.name, entry.unit)
+            }
+        }
+    }
+}
+
+/// Quotes a CSV field when it holds a separator, a quote or a line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// A value in one CSV field, arrays, objects and positions as JSON.
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Int(v) => v.to_string(),
+        Value::Float(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::String(v) => v.clone(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+fn csv_line<'a>(fields: impl IntoIterator<Item = Cow<'a, str>>) -> String {
+    let mut line = fields.into_iter()
+        .map(|it| csv_field(&it).into_owned())
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push('\n');
+    line
+}
+
+/// Column name of a data ID in a wide export.
+fn column_name(name: &str, unit: &str, data_id: u32) -> String {
+    match (name.is_empty(), unit.is_empty()) {
+        (true, _) => data_id.to_string(),
+        (false, true) => name.to_string(),
+        (false, false) => format!("{} ({})", name, unit),
+    }
+}
+
+/// Column name of a data ID across devices, the distinct names joined.
+fn union_column_name(devices: &[ExportDevice], data_id: u32, lang: &str) -> String {
+    let mut names: Vec<String> = vec![];
+    for device in devices {
+        let (name, unit) = device.entry(data_id, lang);
+        if name.is_empty() {
+            continue
+        }
+        let name = column_name(name, unit, data_id);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return data_id.to_string()
+    }
+    names.join(" / ")
+}
+
+/// Numeric value of a Parquet row, text otherwise.
+fn parquet_value(value: &Value) -> (Option<f64>, Option<String>) {
+    match value {
+        Value::Int(v) => (Some(*v as f64), None),
+        Value::Float(v) => (Some(*v), None),
+        Value::Bool(v) => (Some(if *v { 1.0 } else { 0.0 }), None),
+        other => (None, Some(csv_value(other))),
+    }
+}
+
+enum ExportSink {
+    Text(BufWriter<tokio::fs::File>),
+    Parquet(Box<ArrowWriter<std::fs::File>>),
+}
+
+struct ExportWriter {
+    sink: ExportSink,
+    format: ExportFormat,
+    layout: CsvLayout,
+    bytes: bool,
+    /// Data IDs written, every one when not set.
+    ids: Option<BTreeSet<u32>>,
+    /// Data ID of each value column of a wide export.
+    columns: Vec<u32>,
+    lang: &'static str,
+}
+
+impl ExportWriter {
+
+    async fn create(path: &Path, req: &ExportRequest, columns: Vec<u32>, lang: &'static str) -> ApiResult<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        let sink = match req.format {
+            ExportFormat::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let schema = Self::parquet_schema(req.bytes);
+                ExportSink::Parquet(Box::new(ArrowWriter::try_new(file.into_std().await, schema, Some(props))?))
+            }
+            _ => ExportSink::Text(BufWriter::new(file)),
+        };
+        Ok(Self {
+            sink,
+            format: req.format,
+            layout: req.layout,
+            bytes: req.bytes,
+            ids: req.ids.as_ref().map(|ids| ids.iter().copied().collect()),
+            columns,
+            lang,
+        })
+    }
+
+    fn parquet_schema(bytes: bool) -> SchemaRef {
+        let mut fields = vec![
+            Field::new("time", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+            Field::new("device_id", DataType::Utf8, false),
+            Field::new("device_name", DataType::Utf8, false),
+            Field::new("data_id", DataType::UInt32, false),
+            Field::new("name", DataType::Utf8, false),
+            Field::new("unit", DataType::Utf8, false),
+            Field::new("value", DataType::Float64, true),
+            Field::new("text", DataType::Utf8, true),
+        ];
+        if bytes {
+            fields.push(Field::new("bytes", DataType::Utf8, false));
+        }
+        Arc::new(Schema::new(fields))
+    }
+
+    /// Header of a CSV export, wide columns are named from every device.
+    fn header(&self, devices: &[ExportDevice]) -> Option<String> {
+        let mut fields: Vec<Cow<str>> = vec!["time".into(), "device_id".into(), "device_name".into()];
+        match (self.format, self.layout) {
+            (ExportFormat::Jsonl | ExportFormat::Parquet, _) => return None,
+            (ExportFormat::Csv, CsvLayout::Wide) => {
+                fields.extend(self.columns.iter().map(|id| union_column_name(devices, *id, self.lang).into()));
+            }
+            (ExportFormat::Csv, CsvLayout::Long) => {
+                fields.extend(["data_id", "name", "unit", "value"].map(Cow::from));
+            }
+        }
+        if self.bytes {
+            fields.push("bytes".into());
+        }
+        Some(csv_line(fields))
+    }
+
+    /// Lines of one uplink.
+    fn lines(&self, device: &ExportDevice, row: &DeviceDataModel) -> String {
+        let data = row.data.0.iter()
+            .filter(|it| self.ids.as_ref().is_none_or(|ids| ids.contains(&it.i)));
+        let time = row.create_time.to_rfc3339();
+        let device_id = device.id.to_string();
+        let bytes = self.bytes.then_some(row.bytes.as_str());
+        match (self.format, self.layout) {
+            (ExportFormat::Parquet, _) => String::new(),
+            (ExportFormat::Jsonl, _) => {
+                let data: Vec<_> = data.map(|it| {
+                    let (name, unit) = device.entry(it.i, self.lang);
+                    serde_json::json!({ "id": it.i, "name": name, "unit": unit, "value": it.v })
+                }).collect();
+                let mut line = serde_json::json!({
+                    "time": row.create_time,
+                    "device_id": device.id,
+                    "device_name": device.name,
+                    "data": data,
+                });
+                if let Some(bytes) = bytes {
+                    line["bytes"] = bytes.into();
+                }
+                let mut line = line.to_string();
+                line.push('\n');
+                line
+            }
+            (ExportFormat::Csv, CsvLayout::Wide) => {
+                let values: HashMap<u32, &Value> = data.map(|it| (it.i, &it.v)).collect();
+                let mut fields: Vec<Cow<str>> = vec![time.into(), device_id.into(), device.name.as_str().into()];
+                fields.extend(self.columns.iter().map(|id| values.get(id).map(|v| csv_value(v)).unwrap_or_default().into()));
+                fields.extend(bytes.map(Cow::from));
+                csv_line(fields)
+            }
+            (ExportFormat::Csv, CsvLayout::Long) => {
+                data.map(|it| {
+                    let (name, unit) = device.entry(it.i, self.lang);
+                    let mut fields: Vec<Cow<str>> = vec![
+                        time.as_str().into(),
+                        device_id.as_str().into(),
+                        device.name.as_str().into(),
+                        it.i.to_string().into(),
+                        name.into(),
+                        unit.into(),
+                        csv_value(&it.v).into(),
+                    ];
+                    fields.extend(bytes.map(Cow::from));
+                    csv_line(fields)
+                }).collect()
+            }
+        }
+    }
+
+    /// Parquet rows of a batch of uplinks, one per value.
+    fn batch(&self, device: &ExportDevice, rows: &[DeviceDataModel]) -> ApiResult<RecordBatch> {
+        let mut time = vec![];
+        let mut data_id = vec![];
+        let mut name = vec![];
+        let mut unit = vec![];
+        let mut value = vec![];
+        let mut text = vec![];
+        let mut bytes = vec![];
+        for row in rows {
+            let data = row.data.0.iter()
+                .filter(|it| self.ids.as_ref().is_none_or(|ids| ids.contains(&it.i)));
+            for it in data {
+                let (n, u) = device.entry(it.i, self.lang);
+                let (v, t) = parquet_value(&it.v);
+                time.push(row.create_time.timestamp_millis() as i64);
+                data_id.push(it.i);
+                name.push(n);
+                unit.push(u);
+                value.push(v);
+                text.push(t);
+                bytes.push(row.bytes.as_str());
+            }
+        }
+        let device_id = device.id.to_string();
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMillisecondArray::from(time).with_timezone("UTC")),
+            Arc::new(StringArray::from(vec![device_id.as_str(); data_id.len()])),
+            Arc::new(StringArray::from(vec![device.name.as_str(); data_id.len()])),
+            Arc::new(UInt32Array::from(data_id)),
+            Arc::new(StringArray::from(name)),
+            Arc::new(StringArray::from(unit)),
+            Arc::new(Float64Array::from(value)),
+            Arc::new(StringArray::from(text)),
+        ];
+        if self.bytes {
+            columns.push(Arc::new(StringArray::from(bytes)));
+        }
+        Ok(RecordBatch::try_new(Self::parquet_schema(self.bytes), columns)?)
+    }
+
+    async fn write_header(&mut self, devices: &[ExportDevice]) -> ApiResult {
+        let header = self.header(devices);
+        if let (ExportSink::Text(file), Some(header)) = (&mut self.sink, header) {
+            file.write_all(header.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_rows(&mut self, device: &ExportDevice, rows: &[DeviceDataModel]) -> ApiResult {
+        match self.format {
+            ExportFormat::Parquet => {
+                let batch = self.batch(device, rows)?;
+                if let ExportSink::Parquet(writer) = &mut self.sink {
+                    writer.write(&batch)?;
+                }
+            }
+            ExportFormat::Csv | ExportFormat::Jsonl => {
+                let lines: String = rows.iter().map(|row| self.lines(device, row)).collect();
+                if let ExportSink::Text(file) = &mut self.sink {
+                    file.write_all(lines.as_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> ApiResult {
+        match self.sink {
+            ExportSink::Text(mut file) => file.flush().await?,
+            ExportSink::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+impl ExportJob {
+    fn key(id: &str) -> String {
+        format!("export:{}", id)
+    }
+
+    fn path(id: &str, format: ExportFormat) -> PathBuf {
+        Path::new(&load_config().export.dir).join(format!("{}.{}", id, format.extension()))
+    }
+
+    async fn save<R: redis::aio::ConnectionLike>(&self, redis: &mut R) -> ApiResult {
+        let ttl = load_config().export.ttl;
+        let _: () = redis::cmd("SET").arg(Self::key(&self.id)).arg(self).arg("EX").arg(ttl).query_async(redis).await?;
+        Ok(())
+    }
+}
+
+/// Removes the files of exports that can no longer be downloaded.
+async fn remove_expired(dir: &str, ttl: u64) -> std::io::Result<()> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let modified = entry.metadata().await?.modified()?;
+        if modified.elapsed().unwrap_or_default() > Duration::from_secs(ttl) {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+impl DataService {
+
+    async fn export_devices<C: ConnectionTrait>(
+        user: &CurrentUser,
+        devices: &[Id],
+        group: Option<Id>,
+        conn: &C,
+    ) -> ApiResult<Vec<DevicesModel>> {
+        let mut models = Vec::with_capacity(devices.len());
+        for device in devices {
+            models.push(DeviceService::query_one(user.id, *device, conn).await?);
+        }
+        if let Some(group) = group {
+            let (_, devices) = DeviceGroupEntity::find_by_id(group)
+                .filter(DeviceGroupColumn::Owner.eq(user.id))
+                .find_with_related(DevicesEntity)
+                .all(conn)
+                .await?
+                .pop()
+                .ok_or_else(|| ApiError::User(tt!("messages.user.data.export_group")))?;
+            models.extend(devices);
+        }
+        models.sort_by_key(|it| it.id);
+        models.dedup_by_key(|it| it.id);
+        if models.is_empty() {
+            return Err(ApiError::User(tt!("messages.user.data.export_devices")));
+        }
+        if models.len() > MAX_DEVICES {
+            return Err(ApiError::User(tt!("messages.user.data.export_limit", max = MAX_DEVICES)));
+        }
+        Ok(models)
+    }
+
+    /// Starts an export in the background, the file is written as the uplinks are read.
+    pub(crate) async fn start_export<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        req: ExportRequest,
+        redis: &mut R,
+        conn: &DatabaseConnection,
+    ) -> ApiResult<ExportJob> {
+        if req.start >= req.end {
+            return Err(ApiError::User(tt!("messages.user.data.time_start_end")));
+        }
+        let models = Self::export_devices(user, &req.devices, req.group, conn).await?;
+        let mut devices = Vec::with_capacity(models.len());
+        for model in models {
+            let map = match model.script {
+                Some(script) => DecodeScriptEntity::find_by_id(script)
+                    .one(conn)
+                    .await?
+                    .map(|script| script.map.0.into_iter().map(|it| (it.id, it)).collect()),
+                None => None,
+            };
+            let points = Self::virtual_point_map(model.id, conn).await?;
+            devices.push(ExportDevice { id: model.id, name: model.name, map, points });
+        }
+        let job = ExportJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            owner: user.id,
+            state: ExportState::Running,
+            format: req.format,
+            rows: 0,
+            size: 0,
+            message: None,
+            create_time: Timestamp::now(),
+            finish_time: None,
+        };
+        job.save(redis).await?;
+        let lang = get_lang().as_static_str();
+        let conn = conn.clone();
+        let id = job.id.clone();
+        tokio::spawn(async move {
+            let mut job = match Self::run_export(&id, req, devices, lang, &conn).await {
+                Ok(job) => job,
+                Err(e) => {
+                    warn!(export = id, "export: {}", e);
+                    return;
+                }
+            };
+            job.finish_time = Some(Timestamp::now());
+            info!(export = id, state = ?job.state, rows = job.rows, size = job.size, "export finished");
+            if let Err(e) = async { job.save(&mut RedisClient::get_client().get_multiplexed_conn().await?).await }.await {
+                warn!(export = id, "save export: {}", e);
+            }
+        });
+        Ok(job)
+    }
+
+    async fn run_export(
+        id: &str,
+        req: ExportRequest,
+        devices: Vec<ExportDevice>,
+        lang: &'static str,
+        conn: &DatabaseConnection,
+    ) -> ApiResult<ExportJob> {
+        let mut redis = RedisClient::get_client().get_multiplexed_conn().await?;
+        let mut job: ExportJob = redis::cmd("GET").arg(ExportJob::key(id)).query_async(&mut redis).await?;
+        let config = load_config();
+        if let Err(e) = remove_expired(&config.export.dir, config.export.ttl).await {
+            warn!("remove expired exports: {}", e);
+        }
+        let path = ExportJob::path(id, req.format);
+        match Self::write_export(&mut job, &req, &devices, lang, &path, &mut redis, conn).await {
+            Ok(size) => {
+                job.state = ExportState::Done;
+                job.size = size;
+            }
+            Err(e) => {
+                job.state = ExportState::Failed;
+                job.message = Some(e.to_string());
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+        Ok(job)
+    }
+
+    /// Writes the uplinks of every device in time order, returns the size of the file.
+    async fn write_export<R: redis::aio::ConnectionLike>(
+        job: &mut ExportJob,
+        req: &ExportRequest,
+        devices: &[ExportDevice],
+        lang: &'static str,
+        path: &Path,
+        redis: &mut R,
+        conn: &DatabaseConnection,
+    ) -> ApiResult<u64> {
+        let columns = match (&req.ids, req.format, req.layout) {
+            (Some(ids), _, _) => ids.clone(),
+            (None, ExportFormat::Csv, CsvLayout::Wide) => {
+                let devices = itertools::join(devices.iter().map(|it| it.id), ",");
+                DataIdRow::find_by_statement(Statement::from_sql_and_values(
+                    SEA_ORMDB_BACKEND,
+                    DATA_IDS_SQL.replace("{devices}", &devices),
+                    [req.start.into(), req.end.into()],
+                ))
+                    .all(conn)
+                    .await?
+                    .into_iter()
+                    .map(|it| it.data_id as u32)
+                    .collect()
+            }
+            (None, _, _) => vec![],
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut writer = ExportWriter::create(path, req, columns, lang).await?;
+        writer.write_header(devices).await?;
+        for device in devices {
+            let mut cursor: Option<(Timestamp, Id)> = None;
+            loop {
+                let mut query = DeviceDataEntity::find()
+                    .filter(DeviceDataColumn::DeviceId.eq(device.id))
+                    .filter(DeviceDataColumn::CreateTime.gte(req.start))
+                    .filter(DeviceDataColumn::CreateTime.lt(req.end));
+                if let Some((time, id)) = cursor {
+                    query = query.filter(
+                        Condition::any()
+                            .add(DeviceDataColumn::CreateTime.gt(time))
+                            .add(DeviceDataColumn::CreateTime.eq(time).and(DeviceDataColumn::Id.gt(id)))
+                    );
+                }
+                let rows = query
+                    .order_by_asc(DeviceDataColumn::CreateTime)
+                    .order_by_asc(DeviceDataColumn::Id)
+                    .limit(BATCH)
+                    .all(conn)
+                    .await?;
+                let Some(last) = rows.last() else {
+                    break
+                };
+                cursor = Some((last.create_time, last.id));
+                writer.write_rows(device, &rows).await?;
+                job.rows += rows.len() as u64;
+                job.save(redis).await?;
+            }
+        }
+        writer.finish().await?;
+        Ok(tokio::fs::metadata(path).await?.len())
+    }
+
+    pub(crate) async fn export_job<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: &str,
+        redis: &mut R,
+    ) -> ApiResult<ExportJob> {
+        let job: Option<ExportJob> = redis::cmd("GET").arg(ExportJob::key(id)).query_async(redis).await?;
+        job.filter(|it| it.owner == user.id)
+            .ok_or_else(|| ApiError::User(tt!("messages.user.data.export_not_found")))
+    }
+
+    pub(crate) async fn export_file<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: &str,
+        redis: &mut R,
+    ) -> ApiResult<ExportFile> {
+        let job = Self::export_job(user, id, redis).await?;
+        if job.state != ExportState::Done {
+            return Err(ApiError::User(tt!("messages.user.data.export_not_done")));
+        }
+        Ok(ExportFile {
+            path: ExportJob::path(&job.id, job.format),
+            name: format!("export-{}.{}", job.id, job.format.extension()),
+            content_type: job.format.content_type(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::db::{DbDecodeData, DeviceDataModel};
+    use common_define::decode::{DecodeData, DecodeDataType, Value};
+    use common_define::Id;
+    use common_define::time::Timestamp;
+    use common_define::db::CodeMapItem;
+    use super::{csv_field, CsvLayout, ExportDevice, ExportFormat, ExportSink};
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_export_lines() {
+        let device = ExportDevice { id: Id(7), name: "node, 1".to_string(), map: Some(Default::default()), points: Default::default() };
+        let row = DeviceDataModel {
+            id: Id(1),
+            device_id: Id(7),
+            data: DbDecodeData(vec![
+                DecodeData { i: 1, v: Value::Float(21.5) },
+                DecodeData { i: 3, v: Value::Bool(true) },
+            ]),
+            bytes: "AQI=".to_string(),
+            create_time: Timestamp::from_timestamp_millis(0).unwrap(),
+        };
+        let mut writer = super::ExportWriter {
+            sink: ExportSink::Text(tokio::io::BufWriter::new(tokio::fs::File::from_std(tempfile()))),
+            format: ExportFormat::Csv,
+            layout: CsvLayout::Wide,
+            bytes: true,
+            ids: None,
+            columns: vec![1, 2, 3],
+            lang: "en",
+        };
+        assert_eq!(writer.header(std::slice::from_ref(&device)).unwrap(), "time,device_id,device_name,1,2,3,bytes\n");
+        assert_eq!(writer.lines(&device, &row), "1970-01-01T00:00:00+00:00,0000000000000007,\"node, 1\",21.5,,true,AQI=\n");
+        writer.layout = CsvLayout::Long;
+        writer.bytes = false;
+        writer.ids = Some([3].into());
+        assert_eq!(writer.lines(&device, &row), "1970-01-01T00:00:00+00:00,0000000000000007,\"node, 1\",3,,,true\n");
+    }
+
+    #[test]
+    fn test_export_header_union() {
+        let map = |name: &str| Some([(2, CodeMapItem { id: 2, name: name.to_string(), unit: "C".to_string(), t: DecodeDataType::F64 })].into());
+        let devices = [
+            ExportDevice { id: Id(1), name: "a".to_string(), map: Some(Default::default()), points: Default::default() },
+            ExportDevice { id: Id(2), name: "b".to_string(), map: map("temp"), points: Default::default() },
+            ExportDevice { id: Id(3), name: "c".to_string(), map: map("air"), points: Default::default() },
+            ExportDevice { id: Id(4), name: "d".to_string(), map: map("temp"), points: Default::default() },
+        ];
+        let writer = super::ExportWriter {
+            sink: ExportSink::Text(tokio::io::BufWriter::new(tokio::fs::File::from_std(tempfile()))),
+            format: ExportFormat::Csv,
+            layout: CsvLayout::Wide,
+            bytes: false,
+            ids: None,
+            columns: vec![1, 2],
+            lang: "en",
+        };
+        assert_eq!(writer.header(&devices).unwrap(), "time,device_id,device_name,1,temp (C) / air (C)\n");
+    }
+
+    #[test]
+    fn test_export_parquet() {
+        use arrow_array::{Array, Float64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        let device = ExportDevice { id: Id(7), name: "node".to_string(), map: Some(Default::default()), points: Default::default() };
+        let row = DeviceDataModel {
+            id: Id(1),
+            device_id: Id(7),
+            data: DbDecodeData(vec![
+                DecodeData { i: 1, v: Value::Float(21.5) },
+                DecodeData { i: 2, v: Value::String("on".to_string()) },
+                DecodeData { i: 3, v: Value::Bool(true) },
+            ]),
+            bytes: "AQI=".to_string(),
+            create_time: Timestamp::from_timestamp_millis(1000).unwrap(),
+        };
+        let path = std::env::temp_dir().join(format!("export-test-{}.parquet", uuid::Uuid::new_v4()));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = super::ExportWriter {
+            sink: ExportSink::Parquet(Box::new(parquet::arrow::ArrowWriter::try_new(file, super::ExportWriter::parquet_schema(true), None).unwrap())),
+            format: ExportFormat::Parquet,
+            layout: CsvLayout::Wide,
+            bytes: true,
+            ids: None,
+            columns: vec![],
+            lang: "en",
+        };
+        assert!(writer.header(std::slice::from_ref(&device)).is_none());
+        let batch = writer.batch(&device, &[row]).unwrap();
+        if let ExportSink::Parquet(w) = &mut writer.sink {
+            w.write(&batch).unwrap();
+        }
+        let ExportSink::Parquet(w) = writer.sink else { unreachable!() };
+        w.close().unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        let value = batch.column_by_name("value").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(value.value(0), 21.5);
+        assert!(value.is_null(1));
+        assert_eq!(value.value(2), 1.0);
+        let text = batch.column_by_name("text").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(text.value(1), "on");
+        let device_id = batch.column_by_name("device_id").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(device_id.value(0), "0000000000000007");
+    }
+
+    fn tempfile() -> std::fs::File {
+        let path = std::env::temp_dir().join(format!("export-test-{}", uuid::Uuid::new_v4()));
+        let file = std::fs::File::create(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+}
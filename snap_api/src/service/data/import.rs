@@ -0,0 +1,520 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use base64::Engine;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use common_define::codec::PayloadCodec;
+use common_define::db::{CodecVariables, DecodeScriptEntity, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DevicesModel};
+use common_define::decode::{DecodeData, DecodeDataType, Value};
+use common_define::event::{ScriptCall, ScriptPayload, ScriptSource};
+use common_define::lora::ValueType;
+use common_define::Id;
+use common_define::telemetry::TelemetryRow;
+use common_define::time::Timestamp;
+use crate::{telemetry, tt, CurrentUser, MODEL_MAP};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::decode::{DecodeOutcome, DecodeService};
+use crate::service::device::DeviceService;
+
+/// Largest request body of an import, bigger files go through the CLI.
+pub(crate) const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Rejected rows listed in a report, the rest are only counted.
+const MAX_REJECTED: usize = 100;
+/// Uplinks checked against the stored ones per query.
+const BATCH: usize = 1000;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImportFormat {
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+impl ImportFormat {
+    /// From the extension of a file, `.csv`, `.jsonl` or `.ndjson`.
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        match std::path::Path::new(path).extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// What each row holds.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImportKind {
+    /// `time, data_id, value`, values of the same time make one uplink.
+    #[default]
+    Values,
+    /// `time, bytes` with the payload in base64, decoded like an uplink of the device on the import FPort.
+    Bytes,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ImportOptions {
+    pub(crate) format: ImportFormat,
+    pub(crate) kind: ImportKind,
+    /// FPort the payloads are decoded with.
+    pub(crate) f_port: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct RejectedRow {
+    /// Line in the file, starting at 1.
+    pub(crate) line: usize,
+    pub(crate) reason: String,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub(crate) struct ImportReport {
+    /// Uplinks written.
+    pub(crate) uplinks: u64,
+    /// Values of the uplinks written.
+    pub(crate) values: u64,
+    pub(crate) rejected: u64,
+    /// The first rejected rows.
+    pub(crate) rows: Vec<RejectedRow>,
+}
+
+impl ImportReport {
+    fn reject(&mut self, line: usize, reason: impl Into<String>) {
+        self.rejected += 1;
+        if self.rows.len() < MAX_REJECTED {
+            self.rows.push(RejectedRow { line, reason: reason.into() });
+        }
+    }
+}
+
+/// A value before its type is known, CSV fields are text and JSON Lines fields are JSON.
+#[derive(Debug)]
+enum RawValue {
+    Text(String),
+    Json(serde_json::Value),
+}
+
+#[derive(Debug)]
+enum ImportRecord {
+    Value { time: Timestamp, id: u32, value: RawValue },
+    Bytes { time: Timestamp, bytes: Vec<u8> },
+}
+
+#[derive(Deserialize)]
+struct JsonRecord {
+    time: serde_json::Value,
+    data_id: Option<u32>,
+    value: Option<serde_json::Value>,
+    bytes: Option<String>,
+}
+
+/// Splits CSV text into records with the line each starts on, quoted fields may hold line breaks.
+fn csv_records(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                let record = std::mem::take(&mut fields);
+                if record.iter().any(|it| !it.is_empty()) {
+                    records.push((start, record));
+                }
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    fields.push(field);
+    if fields.iter().any(|it| !it.is_empty()) {
+        records.push((start, fields));
+    }
+    records
+}
+
+/// Unix milliseconds or RFC 3339.
+fn parse_time(time: &str) -> Option<Timestamp> {
+    let time = time.trim();
+    match time.parse::<u64>() {
+        Ok(millis) => Timestamp::from_timestamp_millis(millis),
+        Err(_) => chrono::DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&chrono::Utc).into()),
+    }
+}
+
+fn parse_json_time(time: &serde_json::Value) -> Option<Timestamp> {
+    match time {
+        serde_json::Value::Number(millis) => millis.as_u64().and_then(Timestamp::from_timestamp_millis),
+        serde_json::Value::String(time) => parse_time(time),
+        _ => None,
+    }
+}
+
+fn parse_record(format: ImportFormat, kind: ImportKind, fields: &[String]) -> Result<ImportRecord, String> {
+    let time = |time: Option<Timestamp>| time.ok_or_else(|| "invalid time".to_string());
+    match format {
+        ImportFormat::Csv => match (kind, fields) {
+            (ImportKind::Values, [t, id, value]) => Ok(ImportRecord::Value {
+                time: time(parse_time(t))?,
+                id: id.trim().parse().map_err(|_| format!("invalid data id: {}", id))?,
+                value: RawValue::Text(value.clone()),
+            }),
+            (ImportKind::Bytes, [t, bytes]) => Ok(ImportRecord::Bytes {
+                time: time(parse_time(t))?,
+                bytes: base64::engine::general_purpose::STANDARD.decode(bytes.trim()).map_err(|e| e.to_string())?,
+            }),
+            (ImportKind::Values, _) => Err("expected time, data_id, value".to_string()),
+            (ImportKind::Bytes, _) => Err("expected time, bytes".to_string()),
+        },
+        ImportFormat::Jsonl => {
+            let record: JsonRecord = serde_json::from_str(&fields[0]).map_err(|e| e.to_string())?;
+            match (kind, record) {
+                (ImportKind::Values, JsonRecord { time: t, data_id: Some(id), value: Some(value), .. }) => Ok(ImportRecord::Value {
+                    time: time(parse_json_time(&t))?,
+                    id,
+                    value: RawValue::Json(value),
+                }),
+                (ImportKind::Bytes, JsonRecord { time: t, bytes: Some(bytes), .. }) => Ok(ImportRecord::Bytes {
+                    time: time(parse_json_time(&t))?,
+                    bytes: base64::engine::general_purpose::STANDARD.decode(bytes.trim()).map_err(|e| e.to_string())?,
+                }),
+                (ImportKind::Values, _) => Err("expected time, data_id and value".to_string()),
+                (ImportKind::Bytes, _) => Err("expected time and bytes".to_string()),
+            }
+        }
+    }
+}
+
+/// Converts a value to the type of its data ID, integers are taken as floats.
+fn coerce(value: RawValue, t: DecodeDataType) -> Result<Value, String> {
+    let value = match value {
+        RawValue::Json(value) => Value::from_json(value),
+        RawValue::Text(text) => {
+            let text = text.trim();
+            match t {
+                DecodeDataType::I32 => text.parse().ok().map(Value::Int),
+                DecodeDataType::F64 => text.parse().ok().map(Value::Float),
+                DecodeDataType::Bool => text.parse().ok().map(Value::Bool),
+                DecodeDataType::String => Some(Value::String(text.to_string())),
+                DecodeDataType::Array | DecodeDataType::Object | DecodeDataType::GeoPoint => serde_json::from_str(text).ok(),
+            }
+        }
+    };
+    match (value, t) {
+        (Some(Value::Int(v)), DecodeDataType::F64) => Ok(Value::Float(v as f64)),
+        (Some(v), t) if v.data_type() == t => Ok(v),
+        _ => Err(format!("expected a value of type {}", t.as_ref())),
+    }
+}
+
+fn value_type(t: ValueType) -> DecodeDataType {
+    match t {
+        ValueType::Array => DecodeDataType::Array,
+        ValueType::F64 | ValueType::F32 => DecodeDataType::F64,
+        ValueType::Bool => DecodeDataType::Bool,
+        ValueType::I8 | ValueType::U8 | ValueType::I16 | ValueType::U16 | ValueType::I32 | ValueType::U32 => DecodeDataType::I32,
+    }
+}
+
+impl DataService {
+
+    /// Imports the history of a device, only by its owner.
+    pub(crate) async fn import<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        device: Id,
+        options: ImportOptions,
+        text: &str,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<ImportReport> {
+        let device = DeviceService::query_one_with_auth(user.id, device, conn).await?;
+        if !device.auth.owner {
+            return Err(ApiError::User(tt!("messages.user.data.import_owner")));
+        }
+        Self::import_device(&device.device, options, text, redis, conn).await
+    }
+
+    /// Writes the rows of a file as uplinks with their original time, rows that fail to parse,
+    /// do not match the data model or whose time already has an uplink are rejected.
+    pub(crate) async fn import_device<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        device: &DevicesModel,
+        options: ImportOptions,
+        text: &str,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<ImportReport> {
+        let mut report = ImportReport::default();
+        let ImportOptions { format, kind, f_port } = options;
+        let lines = match format {
+            ImportFormat::Csv => {
+                let mut records = csv_records(text);
+                // A header is any first record without a valid time.
+                if records.first().is_some_and(|(_, fields)| parse_time(&fields[0]).is_none()) {
+                    records.remove(0);
+                }
+                records
+            }
+            ImportFormat::Jsonl => text.lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| (i + 1, vec![line.to_string()]))
+                .collect(),
+        };
+        let mut records = Vec::with_capacity(lines.len());
+        for (line, fields) in lines {
+            match parse_record(format, kind, &fields) {
+                Ok(record) => records.push((line, record)),
+                Err(reason) => report.reject(line, reason),
+            }
+        }
+        let uplinks = match kind {
+            ImportKind::Values => Self::import_values(device, records, &mut report, conn).await?,
+            ImportKind::Bytes => Self::import_bytes(device, records, f_port, &mut report, redis, conn).await?,
+        };
+        Self::insert_uplinks(device.id, uplinks, &mut report).await?;
+        Ok(report)
+    }
+
+    /// Checks values against the virtual points and the decode map of the device, or the model map without a script.
+    async fn import_values<C: ConnectionTrait>(
+        device: &DevicesModel,
+        records: Vec<(usize, ImportRecord)>,
+        report: &mut ImportReport,
+        conn: &C,
+    ) -> ApiResult<BTreeMap<Timestamp, ImportUplink>> {
+        let map: Option<HashMap<u32, DecodeDataType>> = match device.script {
+            Some(script) => DecodeScriptEntity::find_by_id(script)
+                .one(conn)
+                .await?
+                .map(|script| script.map.0.into_iter().map(|it| (it.id, it.t)).collect()),
+            None => None,
+        };
+        let points = Self::virtual_point_map(device.id, conn).await?;
+        let mut uplinks: BTreeMap<Timestamp, ImportUplink> = BTreeMap::new();
+        for (line, record) in records {
+            let ImportRecord::Value { time, id, value } = record else {
+                continue
+            };
+            let t = match (points.get(&id), &map) {
+                (Some(point), _) => Some(point.t),
+                (None, Some(map)) => map.get(&id).copied(),
+                (None, None) => MODEL_MAP.get_entry(id, "en").v_type.map(value_type),
+            };
+            let Some(t) = t else {
+                report.reject(line, format!("data id {} is not in the data model", id));
+                continue
+            };
+            let value = match coerce(value, t) {
+                Ok(value) => value,
+                Err(reason) => {
+                    report.reject(line, reason);
+                    continue
+                }
+            };
+            let uplink = uplinks.entry(time).or_default();
+            if uplink.data.iter().any(|it| it.i == id) {
+                report.reject(line, format!("data id {} is repeated at this time", id));
+                continue
+            }
+            uplink.lines.push(line);
+            uplink.data.push(DecodeData::new(id, value));
+        }
+        Ok(uplinks)
+    }
+
+    /// Decoder of an uplink on the FPort, in the order devices_manager picks it:
+    /// the script of the FPort route, the script of the device, its codec, then the built-in format.
+    async fn import_decoder<C: ConnectionTrait>(device: &DevicesModel, f_port: u8, conn: &C) -> ApiResult<ImportDecoder> {
+        let node = DeviceLoraNodeEntity::find()
+            .filter(DeviceLoraNodeColumn::DeviceId.eq(device.id))
+            .one(conn)
+            .await?;
+        let route_script = match node {
+            Some(node) => {
+                let node = match node.load_profile(conn).await? {
+                    Some(profile) => node.apply_profile(&profile),
+                    None => node,
+                };
+                node.fport_routes.route(f_port).and_then(|route| route.script)
+            }
+            None => None,
+        };
+        Ok(match (route_script, device.script, device.codec) {
+            (Some(script), _, _) => ImportDecoder::Script(ScriptSource::Saved { script, version: None }),
+            (None, Some(script), _) => ImportDecoder::Script(ScriptSource::Saved { script, version: device.script_version }),
+            (None, None, Some(codec)) => ImportDecoder::Codec(codec),
+            (None, None, None) => ImportDecoder::Script(ScriptSource::Builtin),
+        })
+    }
+
+    /// Decodes payloads with the decoder of the device, scripts and the built-in format run in devices_manager.
+    async fn import_bytes<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        device: &DevicesModel,
+        records: Vec<(usize, ImportRecord)>,
+        f_port: u8,
+        report: &mut ImportReport,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<BTreeMap<Timestamp, ImportUplink>> {
+        let decoder = Self::import_decoder(device, f_port, conn).await?;
+        let variables: Option<CodecVariables> = (!device.variables.is_empty()).then(|| device.variables.clone());
+        let records: Vec<_> = records.into_iter()
+            .filter_map(|(line, record)| match record {
+                ImportRecord::Bytes { time, bytes } => Some((line, time, bytes)),
+                ImportRecord::Value { .. } => None,
+            })
+            .collect();
+        let mut uplinks: BTreeMap<Timestamp, ImportUplink> = BTreeMap::new();
+        for chunk in records.chunks(ScriptCall::MAX_BATCH) {
+            let outcomes: Vec<Result<Vec<DecodeData>, String>> = match &decoder {
+                ImportDecoder::Script(source) => {
+                    let payloads = chunk.iter()
+                        .map(|(_, _, bytes)| ScriptPayload { bytes: bytes.clone(), f_port })
+                        .collect();
+                    DecodeService::batch(source.clone(), payloads, variables.clone(), redis).await?
+                        .into_iter()
+                        .map(|outcome| match outcome {
+                            DecodeOutcome::Ok { data, .. } => Ok(data),
+                            DecodeOutcome::Error { message } | DecodeOutcome::Timeout { message } => Err(message),
+                        })
+                        .collect()
+                }
+                ImportDecoder::Codec(codec) => chunk.iter().map(|(_, _, bytes)| codec.decode(bytes)).collect(),
+            };
+            for ((line, time, bytes), outcome) in chunk.iter().zip(outcomes) {
+                let data = match outcome {
+                    Ok(data) if data.is_empty() => {
+                        report.reject(*line, "no values decoded");
+                        continue
+                    }
+                    Ok(data) => data,
+                    Err(message) => {
+                        report.reject(*line, message);
+                        continue
+                    }
+                };
+                if uplinks.contains_key(time) {
+                    report.reject(*line, "repeated time");
+                    continue
+                }
+                uplinks.insert(*time, ImportUplink {
+                    lines: vec![*line],
+                    data,
+                    bytes: base64::engine::general_purpose::STANDARD.encode(bytes),
+                });
+            }
+        }
+        Ok(uplinks)
+    }
+
+    /// Writes the uplinks whose time is not stored yet in one batch of the telemetry store,
+    /// which also brings the rollups of the days written up to date.
+    async fn insert_uplinks(
+        device: Id,
+        uplinks: BTreeMap<Timestamp, ImportUplink>,
+        report: &mut ImportReport,
+    ) -> ApiResult {
+        let uplinks: Vec<_> = uplinks.into_iter().collect();
+        let mut rows = Vec::with_capacity(uplinks.len());
+        for chunk in uplinks.chunks(BATCH) {
+            let (Some((start, _)), Some((end, _))) = (chunk.first(), chunk.last()) else {
+                continue
+            };
+            let stored: HashSet<Timestamp> = telemetry().range(device, *start, *end + chrono::Duration::milliseconds(1)).await?
+                .into_iter()
+                .map(|row| row.time)
+                .collect();
+            for (time, uplink) in chunk {
+                if stored.contains(time) {
+                    for line in &uplink.lines {
+                        report.reject(*line, "an uplink is already stored at this time");
+                    }
+                    continue
+                }
+                rows.push(TelemetryRow {
+                    time: *time,
+                    data: uplink.data.clone(),
+                    bytes: uplink.bytes.clone(),
+                });
+            }
+        }
+        telemetry().write_batch(device, &rows).await?;
+        report.uplinks += rows.len() as u64;
+        report.values += rows.iter().map(|row| row.data.len() as u64).sum::<u64>();
+        Ok(())
+    }
+}
+
+/// Decoder of imported payloads.
+enum ImportDecoder {
+    Script(ScriptSource),
+    Codec(PayloadCodec),
+}
+
+/// Data of one time, with the lines it was read from.
+#[derive(Default)]
+struct ImportUplink {
+    lines: Vec<usize>,
+    data: Vec<DecodeData>,
+    bytes: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::decode::{DecodeDataType, Value};
+    use super::{coerce, csv_records, parse_record, parse_time, ImportFormat, ImportKind, ImportRecord, RawValue};
+
+    #[test]
+    fn test_csv_records() {
+        let records = csv_records("time,data_id,value\r\n1000,1,\"a,\"\"b\"\"\nc\"\n\n2000,2,3");
+        assert_eq!(records, vec![
+            (1, vec!["time".to_string(), "data_id".to_string(), "value".to_string()]),
+            (2, vec!["1000".to_string(), "1".to_string(), "a,\"b\"\nc".to_string()]),
+            (5, vec!["2000".to_string(), "2".to_string(), "3".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn test_import_values() {
+        assert_eq!(parse_time("1000").unwrap().timestamp_millis(), 1000);
+        assert_eq!(parse_time("1970-01-01T00:00:01.5+00:00").unwrap().timestamp_millis(), 1500);
+        assert!(parse_time("time").is_none());
+
+        let fields = ["1000".to_string(), "7".to_string(), "21".to_string()];
+        let Ok(ImportRecord::Value { id, value, .. }) = parse_record(ImportFormat::Csv, ImportKind::Values, &fields) else {
+            panic!("csv value")
+        };
+        assert_eq!(id, 7);
+        assert_eq!(coerce(value, DecodeDataType::F64), Ok(Value::Float(21.0)));
+        assert!(parse_record(ImportFormat::Csv, ImportKind::Bytes, &fields).is_err());
+
+        let line = [r#"{"time":"1970-01-01T00:00:01Z","data_id":3,"value":true}"#.to_string()];
+        let Ok(ImportRecord::Value { value, .. }) = parse_record(ImportFormat::Jsonl, ImportKind::Values, &line) else {
+            panic!("json value")
+        };
+        assert!(coerce(value, DecodeDataType::I32).is_err());
+        assert_eq!(coerce(RawValue::Text("x".to_string()), DecodeDataType::String), Ok(Value::String("x".to_string())));
+        assert!(coerce(RawValue::Text("1.5".to_string()), DecodeDataType::I32).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use common_define::db::{DeviceGroupColumn, DeviceGroupEntity, DevicesEntity};
+use common_define::Id;
+use common_define::product::DeviceType;
+use common_define::time::Timestamp;
+use crate::{tt, AppState, CurrentUser};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::data::query::DataDeviceOneResponse;
+use crate::service::device::DeviceService;
+
+#[derive(Deserialize, Default)]
+pub(crate) struct BatchLastRequest {
+    #[serde(default)]
+    devices: Vec<Id>,
+    /// Adds the devices of the group to `devices`.
+    group: Option<Id>,
+    /// Every device the user can see, `devices` and `group` are ignored.
+    #[serde(default)]
+    all: bool,
+    /// All data IDs when not set.
+    ids: Option<Vec<u32>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DeviceLastData {
+    device: Id,
+    name: String,
+    data: Vec<DataDeviceOneResponse>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BatchLastResponse {
+    counts: i64,
+    data: Vec<DeviceLastData>,
+    update: Timestamp,
+}
+
+impl DataService {
+
+    /// Last data of many devices in one request, read from redis in one pipeline.
+    /// Only devices the user has an authority on are returned, gateways have no data and are left out.
+    pub(crate) async fn query_last_batch(
+        user: &CurrentUser,
+        req: BatchLastRequest,
+        state: &AppState,
+    ) -> ApiResult<BatchLastResponse> {
+        let conn = &state.db;
+        let redis = &mut state.redis.get().await?;
+        let authorized = DeviceService::query_all(user.id, redis, conn).await?;
+        let mut devices = if req.all {
+            authorized
+        } else {
+            let mut wanted: HashSet<Id> = HashSet::with_capacity(req.devices.len());
+            for device in &req.devices {
+                if !authorized.iter().any(|it| it.id == *device) {
+                    return Err(ApiError::Device { device_id: *device, msg: tt!("messages.device.common.device_missing", device_id = *device) });
+                }
+                wanted.insert(*device);
+            }
+            if let Some(group) = req.group {
+                wanted.extend(Self::group_devices(user, group, conn).await?);
+            }
+            if wanted.is_empty() {
+                return Err(ApiError::User(tt!("messages.user.data.last_devices")));
+            }
+            authorized.into_iter().filter(|it| wanted.contains(&it.id)).collect()
+        };
+        devices.retain(|it| it.device_type != DeviceType::LoRaGate);
+        devices.sort_by_key(|it| it.id);
+
+        let mut last_data = DeviceService::query_last_data(&devices, state).await?;
+        let device_ids: Vec<Id> = devices.iter().map(|it| it.id).collect();
+        let displays = Self::data_displays(user, &device_ids, conn).await?;
+        let data: Vec<_> = devices.into_iter()
+            .map(|device| {
+                let mut data = last_data.remove(&device.id).unwrap_or_default();
+                if let Some(ids) = &req.ids {
+                    data.retain(|it| ids.contains(&it.data_id));
+                }
+                if let Some(display) = displays.get(&device.id) {
+                    data.iter_mut().for_each(|it| display.apply_one(it));
+                }
+                DeviceLastData { device: device.id, name: device.name, data }
+            })
+            .collect();
+        Ok(BatchLastResponse {
+            counts: data.len() as i64,
+            data,
+            update: Timestamp::now(),
+        })
+    }
+
+    async fn group_devices<C: ConnectionTrait>(user: &CurrentUser, group: Id, conn: &C) -> ApiResult<Vec<Id>> {
+        let (_, devices) = DeviceGroupEntity::find_by_id(group)
+            .filter(DeviceGroupColumn::Owner.eq(user.id))
+            .find_with_related(DevicesEntity)
+            .all(conn)
+            .await?
+            .pop()
+            .ok_or_else(|| ApiError::User(tt!("messages.user.data.last_group")))?;
+        Ok(devices.into_iter().map(|it| it.id).collect())
+    }
+}
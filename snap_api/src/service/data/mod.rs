@@ -2,3 +2,11 @@ pub(crate) struct DataService;
 
 pub(crate) mod query;
 pub(crate) mod update;
+pub(crate) mod range;
+pub(crate) mod retention;
+pub(crate) mod export;
+pub(crate) mod import;
+pub(crate) mod virtual_point;
+pub(crate) mod calibration;
+pub(crate) mod last;
+pub(crate) mod zone;
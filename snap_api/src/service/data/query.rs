@@ -1,18 +1,17 @@
 use std::collections::{BTreeMap, HashMap};
-use std::ops::Sub;
 use crate::error::{ApiResult};
 use crate::service::data::DataService;
-use crate::{get_lang, AppState, MODEL_MAP};
+use crate::{get_lang, telemetry, AppState, MODEL_MAP};
 
 use derive_new::new;
 use redis::AsyncCommands;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use sea_orm::EntityTrait;
 use serde::{Deserialize, Serialize};
-use common_define::db::{DecodeScriptEntity, DeviceDataColumn, DeviceDataEntity, DevicesModel};
-use common_define::decode::{LastDecodeData, Value};
+use common_define::db::{DecodeScriptEntity, DevicesModel};
+use common_define::decode::{DecodeDataType, LastDecodeData, Value};
 use common_define::{last_device_data_key, Id};
 use common_define::product::DeviceType;
-use common_define::time::Timestamp;
+use common_define::time::{Calendar, Timestamp, Tz};
 
 #[derive(Deserialize, Serialize, Clone, new)]
 pub(crate) struct TimeDate {
@@ -25,6 +24,15 @@ pub(crate) struct DataResponse {
     pub counts: i32,
     pub data_id: u32,
     pub unit: String,
+    /// Type of the first value, absent in responses cached before it was added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_type: Option<DecodeDataType>,
+    /// Unit the values are stored in, when they were converted to `unit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_unit: Option<String>,
+    /// Decimal places the values are displayed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<u8>,
     pub data: Vec<TimeDate>,
 }
 
@@ -33,6 +41,14 @@ pub(crate) struct DataDeviceOneResponse {
     pub(crate) name: String,
     pub(crate) data_id: u32,
     pub(crate) unit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) data_type: Option<DecodeDataType>,
+    /// Unit the value is stored in, when it was converted to `unit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source_unit: Option<String>,
+    /// Decimal places the value is displayed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) precision: Option<u8>,
     pub(crate) data: TimeDate,
 }
 #[derive(Deserialize, Serialize, Clone, redis_macros::FromRedisValue, redis_macros::ToRedisArgs)]
@@ -47,7 +63,10 @@ pub(crate) struct DataDeviceOneResponseWrap {
 pub(crate) struct DataResponseWrap {
     pub counts: i64,
     pub data: Vec<DataResponse>,
-    pub update: Timestamp
+    pub update: Timestamp,
+    /// Time zone a day or week starts at midnight in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -59,23 +78,12 @@ pub enum DataDuration {
 
 
 impl  DataDuration {
-    fn duration(&self) -> chrono::Duration {
+    /// Start of the data up to `now`: the last hour, or the current day or week in `tz`.
+    pub(crate) fn start(&self, now: Timestamp, tz: Tz) -> Timestamp {
         match self {
-            DataDuration::Hour => { chrono::Duration::hours(1) }
-            DataDuration::Day => { chrono::Duration::days(1) }
-            DataDuration::Week => { chrono::Duration::weeks(1) }
-        }
-    }
-}
-
-impl Sub<DataDuration> for Timestamp {
-    type Output = Timestamp;
-
-    fn sub(self, rhs: DataDuration) -> Self::Output {
-        match rhs {
-            DataDuration::Hour => { self - chrono::Duration::hours(1) }
-            DataDuration::Day => { self - chrono::Duration::days(1) }
-            DataDuration::Week => { self - chrono::Duration::weeks(1) }
+            DataDuration::Hour => { now - chrono::Duration::hours(1) }
+            DataDuration::Day => { Calendar::Day.floor(now, tz) }
+            DataDuration::Week => { Calendar::Week.floor(now, tz) }
         }
     }
 }
@@ -208,12 +216,12 @@ impl DataService {
     //     })
     // }
     
-    fn device_duration_key(device: Id, data_duration: DataDuration) -> String {
+    fn device_duration_key(device: Id, data_duration: DataDuration, tz: Tz) -> String {
         let lang = get_lang().as_static_str();
         match data_duration {
             DataDuration::Hour => { format!("data:hour:{}:{}", lang, device) }
-            DataDuration::Day => { format!("data:day:{}:{}", lang, device) }
-            DataDuration::Week => { format!("data:week:{}:{}", lang, device) }
+            DataDuration::Day => { format!("data:day:{}:{}:{}", lang, tz.name(), device) }
+            DataDuration::Week => { format!("data:week:{}:{}:{}", lang, tz.name(), device) }
         }
     }
 
@@ -225,9 +233,10 @@ impl DataService {
         device: Id,
         script_id: Option<Id>,
         data_duration: DataDuration,
+        tz: Tz,
         state: &AppState,
     ) -> ApiResult<DataResponseWrap> {
-        let key = Self::device_duration_key(device, data_duration);
+        let key = Self::device_duration_key(device, data_duration, tz);
         let lang = get_lang().as_static_str();
         let mut redis_conn = state.redis.get().await?;
         let data_resp: Option<DataResponseWrap> = redis_conn.get(&key).await?;
@@ -237,42 +246,48 @@ impl DataService {
             }
         }
         
-        let start = Timestamp::now() - data_duration;
+        let now = Timestamp::now();
         let conn = &state.db;
 
-        let data_all = DeviceDataEntity::find()
-            .filter(DeviceDataColumn::DeviceId.eq(device).and(DeviceDataColumn::CreateTime.gt(start)))
-            .order_by_asc(DeviceDataColumn::Id)
-            .all(conn)
-            .await?;
+        let data_all = telemetry().range(device, data_duration.start(now, tz), now).await?;
+        let points = Self::virtual_point_map(device, conn).await?;
 
         let mut data_map: BTreeMap<u32, DataResponse> = BTreeMap::new();
         
         match script_id {
             None => {
                 for data in data_all {
-                    for x in data.data.0 {
+                    for x in data.data {
                         match data_map.get_mut(&x.i) {
                             Some(d) => {
                                 d.counts += 1;
                                 d.data.push(
                                     TimeDate {
-                                        time: data.create_time,
+                                        time: data.time,
                                         data: x.v
                                     }
                                 )
                             }
                             None => {
-                                let data_name = MODEL_MAP.get_entry(x.i, lang);
+                                let (name, unit) = match points.get(&x.i) {
+                                    Some(point) => (point.name.clone(), point.unit.clone()),
+                                    None => {
+                                        let data_name = MODEL_MAP.get_entry(x.i, lang);
+                                        (data_name.name.to_string(), data_name.unit.to_string())
+                                    }
+                                };
                                 let res=
                                     DataResponse {
-                                        name: data_name.name.to_string(),
+                                        name,
                                         counts: 1,
                                         data_id: x.i,
-                                        unit: data_name.unit.to_string(),
+                                        unit,
+                                        data_type: Some(x.v.data_type()),
+                                        source_unit: None,
+                                        precision: None,
                                         data: vec![
                                             TimeDate {
-                                                time: data.create_time,
+                                                time: data.time,
                                                 data: x.v,
                                             }
                                         ],
@@ -286,15 +301,18 @@ impl DataService {
             Some(script_id) => {
                 let map = DecodeScriptEntity::find_by_id(script_id).one(conn).await?;
                 if let Some(script) = map {
-                    let map: HashMap<_, _> = script.map.iter().map(|it| (it.id, it)).collect();
+                    let map: HashMap<_, _> = script.map.iter()
+                        .chain(points.values())
+                        .map(|it| (it.id, it))
+                        .collect();
                     for data in data_all {
-                        for x in data.data.0 {
+                        for x in data.data {
                             match data_map.get_mut(&x.i) {
                                 Some(d) => {
                                     d.counts += 1;
                                     d.data.push(
                                         TimeDate {
-                                            time: data.create_time,
+                                            time: data.time,
                                             data: x.v
                                         }
                                     )
@@ -307,9 +325,12 @@ impl DataService {
                                                 counts: 1,
                                                 data_id: x.i,
                                                 unit: m.unit.clone(),
+                                                data_type: Some(x.v.data_type()),
+                                                source_unit: None,
+                                                precision: None,
                                                 data: vec![
                                                     TimeDate {
-                                                        time: data.create_time,
+                                                        time: data.time,
                                                         data: x.v,
                                                     }
                                                 ],
@@ -330,10 +351,14 @@ impl DataService {
         let resp = DataResponseWrap {
             counts: data.len() as i64,
             data,
-            update: Timestamp::now()
+            update: Timestamp::now(),
+            time_zone: match data_duration {
+                DataDuration::Hour => None,
+                DataDuration::Day | DataDuration::Week => Some(tz.name().to_string()),
+            },
         };
         
-        redis_conn.set(&key, &resp).await?;
+        let _: () = redis_conn.set(&key, &resp).await?;
         
         Ok(resp)
     }
@@ -362,7 +387,10 @@ impl DataService {
         }
 
         let mut redis_conn = state.redis.get().await?;
-        let last_data: Option<LastDecodeData> = redis_conn.get(last_device_data_key(device.id)).await?;
+        let last_data: Option<LastDecodeData> = match redis_conn.get(last_device_data_key(device.id)).await? {
+            Some(last) => Some(last),
+            None => telemetry().last(device.id).await?.map(|row| LastDecodeData::new(row.data, row.time)),
+        };
         
         match last_data {
             None => {
@@ -372,7 +400,9 @@ impl DataService {
                     update: Timestamp::now(),
                 })
             }
-            Some(data) => {
+            Some(mut data) => {
+                let points = Self::virtual_point_map(device.id, conn).await?;
+                let virtual_resp = Self::take_virtual_data(&points, &mut data);
                 let mut resp = vec![];
                 match script_id {
                     None => {
@@ -382,6 +412,9 @@ impl DataService {
                                 name: data_name.name.to_string(),
                                 data_id: d.i,
                                 unit: data_name.unit.to_string(),
+                                data_type: Some(d.v.data_type()),
+                                source_unit: None,
+                                precision: None,
                                 data: TimeDate {
                                     time: data.t,
                                     data: d.v
@@ -408,6 +441,9 @@ impl DataService {
                                             name: map.name.to_string(),
                                             data_id: d.i,
                                             unit: map.unit.to_string(),
+                                            data_type: Some(d.v.data_type()),
+                                            source_unit: None,
+                                            precision: None,
                                             data: TimeDate {
                                                 time: data.t,
                                                 data: d.v
@@ -421,6 +457,7 @@ impl DataService {
                     }
                 }
 
+                resp.extend(virtual_resp);
                 resp.sort_by(|pre, cur| pre.data_id.cmp(&cur.data_id));
                 let resp = DataDeviceOneResponseWrap {
                     counts: resp.len() as i64,
@@ -428,7 +465,7 @@ impl DataService {
                     update: Timestamp::now(),
                 };
 
-                redis_conn.set(&key, &resp).await?;
+                let _: () = redis_conn.set(&key, &resp).await?;
                 Ok(resp)
             }
         }
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use sea_orm::{ConnectionTrait, EntityTrait, FromQueryResult, Statement};
+use serde::Serialize;
+use common_define::db::{CodeMapItem, DecodeScriptEntity, Rollup};
+use common_define::decode::{DecodeDataType, Value};
+use common_define::Id;
+use common_define::time::{Calendar, Timestamp, Tz};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::data::calibration::DataDisplay;
+use crate::{get_lang, tt, MODEL_MAP, SEA_ORMDB_BACKEND};
+
+/// Values of one data ID bucketed by `create_time`, `{bucket}`, `{aggregates}` and `{ids}` are filled in per request.
+/// Only JSON numbers count for avg, min, max and sum, every value counts for count, first and last.
+/// A sum comes with `num_count`, the numbers it adds up.
+const RANGE_SQL: &str = r"SELECT (e->>'i')::bigint AS data_id,
+       {bucket} AS bucket,
+       {aggregates}
+FROM snap_device_data d
+CROSS JOIN LATERAL json_array_elements(CASE WHEN json_typeof(d.data) = 'array' THEN d.data ELSE '[]'::json END) AS e
+CROSS JOIN LATERAL (SELECT CASE WHEN json_typeof(e->'v') = 'number' THEN (e->>'v')::float8 END AS n) AS x
+WHERE d.device_id = $4 AND d.create_time >= $1 AND d.create_time < $2 {ids}
+GROUP BY 1, 2
+ORDER BY 1, 2";
+
+/// Same columns as [`RANGE_SQL`], from the rollups of period `$5` between `$6` and `$7`
+/// and from the stored uplinks for the rest of the range.
+const ROLLUP_RANGE_SQL: &str = r"WITH p AS (
+    SELECT data_id, bucket AS time, count, num_count, min, max, sum, last, last_time
+    FROM snap_device_data_rollup
+    WHERE device_id = $4 AND period = $5 AND bucket >= $6 AND bucket < $7 {rollup_ids}
+    UNION ALL
+    SELECT (e->>'i')::bigint, d.create_time, 1, (x.n IS NOT NULL)::int, x.n, x.n, x.n, e->'v', d.create_time
+    FROM snap_device_data d
+    CROSS JOIN LATERAL json_array_elements(CASE WHEN json_typeof(d.data) = 'array' THEN d.data ELSE '[]'::json END) AS e
+    CROSS JOIN LATERAL (SELECT CASE WHEN json_typeof(e->'v') = 'number' THEN (e->>'v')::float8 END AS n) AS x
+    WHERE d.device_id = $4 AND (d.create_time >= $1 AND d.create_time < $6 OR d.create_time >= $7 AND d.create_time < $2) {ids}
+)
+SELECT p.data_id,
+       {bucket} AS bucket,
+       {aggregates}
+FROM p
+GROUP BY 1, 2
+ORDER BY 1, 2";
+
+/// Most buckets of one data ID.
+const MAX_BUCKETS: i64 = 10_000;
+/// Buckets of one data ID when no interval is given.
+const DEFAULT_BUCKETS: i64 = 200;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, strum::EnumString, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum Aggregate {
+    Avg,
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregate {
+    const ALL: [Aggregate; 7] = [Self::Avg, Self::Min, Self::Max, Self::Sum, Self::Count, Self::First, Self::Last];
+
+    /// Column of the aggregate, `NULL` when it was not asked for.
+    /// Over the rows of [`ROLLUP_RANGE_SQL`] when `rollup` is set, which have no first value.
+    fn column(self, enabled: bool, rollup: bool) -> String {
+        let expr = match (self, enabled && !(rollup && self == Self::First)) {
+            (Self::Avg, true) if rollup => "sum(p.sum) / nullif(sum(p.num_count), 0)".to_string(),
+            (Self::Min | Self::Max | Self::Sum, true) if rollup => format!("{0}(p.{0})", self.as_ref()),
+            (Self::Count, true) if rollup => "sum(p.count)::bigint".to_string(),
+            (Self::Last, true) if rollup => "(array_agg(p.last ORDER BY p.last_time DESC))[1]".to_string(),
+            (Self::Avg | Self::Min | Self::Max | Self::Sum, true) => format!("{}(x.n)", self.as_ref()),
+            (Self::Count, true) => "count(*)".to_string(),
+            (Self::First, true) => "(array_agg(e->'v' ORDER BY d.create_time))[1]".to_string(),
+            (Self::Last, true) => "(array_agg(e->'v' ORDER BY d.create_time DESC))[1]".to_string(),
+            (Self::Count, false) => "NULL::bigint".to_string(),
+            (Self::First | Self::Last, false) => "NULL::json".to_string(),
+            (_, false) => "NULL::float8".to_string(),
+        };
+        format!("{} AS {}", expr, self.as_ref())
+    }
+}
+
+pub(crate) struct RangeQuery {
+    pub(crate) start: Timestamp,
+    pub(crate) end: Timestamp,
+    /// All data IDs when empty.
+    pub(crate) ids: Vec<u32>,
+    /// Seconds of a bucket, the range over [`DEFAULT_BUCKETS`] when not set.
+    pub(crate) interval: Option<u64>,
+    pub(crate) aggregates: Vec<Aggregate>,
+    /// Buckets by local day, week or month in `tz` instead of `interval`.
+    pub(crate) calendar: Option<Calendar>,
+    pub(crate) tz: Tz,
+}
+
+impl RangeQuery {
+    /// Parses comma separated data IDs and aggregates, avg when none is given.
+    pub(crate) fn new(start: Timestamp, end: Timestamp, ids: Option<&str>, interval: Option<u64>, aggregates: Option<&str>) -> ApiResult<Self> {
+        let split = |s: Option<&str>| s.unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|it| !it.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let ids = split(ids).iter()
+            .map(|id| id.parse::<u32>().map_err(|_| ApiError::User(tt!("messages.user.data.data_id", id = id))))
+            .collect::<ApiResult<Vec<_>>>()?;
+        let mut aggregates = split(aggregates).iter()
+            .map(|it| Aggregate::from_str(it).map_err(|_| ApiError::User(tt!("messages.user.data.aggregate", name = it))))
+            .collect::<ApiResult<Vec<_>>>()?;
+        if aggregates.is_empty() {
+            aggregates.push(Aggregate::Avg);
+        }
+        aggregates.sort();
+        aggregates.dedup();
+        Ok(Self { start, end, ids, interval, aggregates, calendar: None, tz: Tz::UTC })
+    }
+
+    /// Buckets by `calendar` in `tz`, `interval` is ignored then.
+    pub(crate) fn with_calendar(mut self, calendar: &str, tz: Tz) -> ApiResult<Self> {
+        let calendar = calendar.trim();
+        self.calendar = Some(Calendar::from_str(calendar)
+            .map_err(|_| ApiError::User(tt!("messages.user.data.calendar", name = calendar)))?);
+        self.tz = tz;
+        Ok(self)
+    }
+
+    /// Seconds of a bucket, `None` for calendar buckets, checked against [`MAX_BUCKETS`].
+    fn interval(&self) -> ApiResult<Option<i64>> {
+        if self.start >= self.end {
+            return Err(ApiError::User(tt!("messages.user.data.time_start_end")));
+        }
+        let range = (self.end - self.start).num_seconds().max(1);
+        if let Some(calendar) = self.calendar {
+            if range / calendar.min_seconds() > MAX_BUCKETS {
+                return Err(ApiError::User(tt!("messages.user.data.buckets", max = MAX_BUCKETS)));
+            }
+            return Ok(None);
+        }
+        let interval = match self.interval {
+            Some(interval) => interval.max(1) as i64,
+            None => (range + DEFAULT_BUCKETS - 1) / DEFAULT_BUCKETS,
+        };
+        if range / interval > MAX_BUCKETS {
+            return Err(ApiError::User(tt!("messages.user.data.buckets", max = MAX_BUCKETS)));
+        }
+        Ok(Some(interval))
+    }
+
+    /// The coarsest rollup period the buckets are made of and the part of the range it covers,
+    /// `None` when the buckets are not whole rollup buckets or first values are asked for.
+    fn rollup(&self, interval: Option<i64>) -> Option<(i64, Timestamp, Timestamp)> {
+        if self.aggregates.contains(&Aggregate::First) {
+            return None;
+        }
+        let periods: Vec<i64> = match interval {
+            Some(interval) => Rollup::PERIODS.iter().rev().copied().filter(|period| interval % period == 0).collect(),
+            None => Rollup::calendar_period(self.tz, self.start, self.end).into_iter().collect(),
+        };
+        periods.into_iter()
+            .find_map(|period| {
+                let millis = period as u64 * 1000;
+                let start = Timestamp::from_timestamp_millis(self.start.timestamp_millis().div_ceil(millis) * millis)?;
+                let end = Timestamp::from_timestamp_millis(self.end.timestamp_millis() / millis * millis)?;
+                (start < end).then_some((period, start, end))
+            })
+    }
+
+    /// Start of the bucket of a row, `$3` is the interval or the time zone of the calendar.
+    fn bucket(&self, time: &str) -> String {
+        match self.calendar {
+            Some(calendar) => format!("date_trunc('{}', {}, $3)", calendar.as_ref(), time),
+            None => format!("to_timestamp(floor(extract(epoch FROM {0}) / $3) * $3)", time),
+        }
+    }
+
+    fn sql(&self, rollup: bool) -> String {
+        let num_count = match (self.aggregates.contains(&Aggregate::Sum), rollup) {
+            (false, _) => "NULL::bigint",
+            (true, false) => "count(x.n)",
+            (true, true) => "sum(p.num_count)::bigint",
+        };
+        let aggregates = Aggregate::ALL.iter()
+            .map(|it| it.column(self.aggregates.contains(it), rollup))
+            .chain([format!("{} AS num_count", num_count)])
+            .collect::<Vec<_>>()
+            .join(",\n       ");
+        let (ids, rollup_ids) = if self.ids.is_empty() {
+            (String::new(), String::new())
+        } else {
+            let ids = itertools::join(&self.ids, ",");
+            (format!("AND (e->>'i')::bigint IN ({})", ids), format!("AND data_id IN ({})", ids))
+        };
+        let (sql, bucket) = if rollup {
+            (ROLLUP_RANGE_SQL, self.bucket("p.time"))
+        } else {
+            (RANGE_SQL, self.bucket("d.create_time"))
+        };
+        sql.replace("{bucket}", &bucket)
+            .replace("{aggregates}", &aggregates)
+            .replace("{rollup_ids}", &rollup_ids)
+            .replace("{ids}", &ids)
+    }
+}
+
+#[derive(FromQueryResult)]
+struct RangeRow {
+    data_id: i64,
+    bucket: Timestamp,
+    avg: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: Option<f64>,
+    count: Option<i64>,
+    num_count: Option<i64>,
+    first: Option<serde_json::Value>,
+    last: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RangePoint {
+    time: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<i64>,
+    /// Numbers added up in `sum`, to convert it.
+    #[serde(skip)]
+    num_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last: Option<Value>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RangeSeries {
+    data_id: u32,
+    name: String,
+    unit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_type: Option<DecodeDataType>,
+    /// Unit the values are stored in, when they were converted to `unit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_unit: Option<String>,
+    /// Decimal places the values are displayed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    precision: Option<u8>,
+    points: Vec<RangePoint>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RangeResponse {
+    start: Timestamp,
+    end: Timestamp,
+    /// Seconds of a bucket, a point is at the start of its bucket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<i64>,
+    /// Local day, week or month of a bucket, instead of `interval`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calendar: Option<Calendar>,
+    /// Time zone the calendar buckets start at midnight in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_zone: Option<&'static str>,
+    /// Seconds of the rollup most of the range was read from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rollup: Option<i64>,
+    data: Vec<RangeSeries>,
+}
+
+impl RangeResponse {
+    /// Converts the aggregates to the units of the user, count is kept.
+    pub(crate) fn apply_display(&mut self, display: &DataDisplay) {
+        for series in self.data.iter_mut() {
+            series.precision = display.precision(series.data_id);
+            let Some(unit) = display.unit(&series.unit) else {
+                continue
+            };
+            let convert = |value: f64| display.convert_number(&series.unit, value);
+            for point in series.points.iter_mut() {
+                point.avg = point.avg.map(convert);
+                point.min = point.min.map(convert);
+                point.max = point.max.map(convert);
+                point.sum = point.sum.map(|sum| display.convert_sum(&series.unit, sum, point.num_count.unwrap_or_default()));
+                for value in [&mut point.first, &mut point.last].into_iter().flatten() {
+                    if let Some(converted) = display.convert(&series.unit, value) {
+                        *value = converted;
+                    }
+                }
+            }
+            series.source_unit = Some(std::mem::replace(&mut series.unit, unit.to_string()));
+        }
+    }
+}
+
+impl DataService {
+
+    /// Buckets the data of a device in SQL, only the aggregates asked for are computed.
+    /// Whole rollup buckets are read from the coarsest rollup that fits, the rest from the stored uplinks.
+    pub(crate) async fn query_range<C: ConnectionTrait>(
+        device: Id,
+        script_id: Option<Id>,
+        query: RangeQuery,
+        conn: &C,
+    ) -> ApiResult<RangeResponse> {
+        let interval = query.interval()?;
+        let rollup = query.rollup(interval);
+        let bucket = match interval {
+            Some(interval) => (interval as f64).into(),
+            None => query.tz.name().into(),
+        };
+        let mut values = vec![query.start.into(), query.end.into(), bucket, device.into()];
+        if let Some((period, start, end)) = rollup {
+            values.extend([(period as i32).into(), start.into(), end.into()]);
+        }
+        let rows = RangeRow::find_by_statement(Statement::from_sql_and_values(
+            SEA_ORMDB_BACKEND,
+            query.sql(rollup.is_some()),
+            values,
+        ))
+            .all(conn)
+            .await?;
+
+        let script_map: Option<HashMap<u32, CodeMapItem>> = match script_id {
+            Some(script_id) => DecodeScriptEntity::find_by_id(script_id)
+                .one(conn)
+                .await?
+                .map(|script| script.map.0.into_iter().map(|it| (it.id, it)).collect()),
+            None => None,
+        };
+        let points = Self::virtual_point_map(device, conn).await?;
+        let lang = get_lang().as_static_str();
+        let mut series: BTreeMap<u32, RangeSeries> = BTreeMap::new();
+        for row in rows {
+            let data_id = row.data_id as u32;
+            let Some(entry) = series.get_mut(&data_id) else {
+                let entry = match (points.get(&data_id), &script_map) {
+                    (Some(point), _) => Some((point.name.clone(), point.unit.clone(), Some(point.t))),
+                    (None, Some(map)) => map.get(&data_id).map(|it| (it.name.clone(), it.unit.clone(), Some(it.t))),
+                    (None, None) => {
+                        let entry = MODEL_MAP.get_entry(data_id, lang);
+                        Some((entry.name.to_string(), entry.unit.to_string(), None))
+                    }
+                };
+                if let Some((name, unit, data_type)) = entry {
+                    series.insert(data_id, RangeSeries { data_id, name, unit, data_type, source_unit: None, precision: None, points: vec![row.into()] });
+                }
+                continue
+            };
+            entry.points.push(row.into());
+        }
+        Ok(RangeResponse {
+            start: query.start,
+            end: query.end,
+            interval,
+            calendar: query.calendar,
+            time_zone: query.calendar.map(|_| query.tz.name()),
+            rollup: rollup.map(|(period, ..)| period),
+            data: series.into_values().collect(),
+        })
+    }
+}
+
+impl From<RangeRow> for RangePoint {
+    fn from(row: RangeRow) -> Self {
+        Self {
+            time: row.bucket,
+            avg: row.avg,
+            min: row.min,
+            max: row.max,
+            sum: row.sum,
+            count: row.count,
+            num_count: row.num_count,
+            first: row.first.and_then(Value::from_json),
+            last: row.last.and_then(Value::from_json),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::db::Rollup;
+    use common_define::time::{self, Timestamp, Tz};
+    use super::{Aggregate, RangeQuery};
+
+    #[test]
+    fn test_range_query() {
+        let start = Timestamp::from_timestamp_millis(0).unwrap();
+        let end = Timestamp::from_timestamp_millis(7 * 24 * 3600 * 1000).unwrap();
+        let query = RangeQuery::new(start, end, Some("1, 2"), None, Some("max,avg,max")).unwrap();
+        assert_eq!(query.ids, vec![1, 2]);
+        assert_eq!(query.aggregates, vec![Aggregate::Avg, Aggregate::Max]);
+        assert_eq!(query.interval().unwrap(), Some(3024));
+        assert!(query.rollup(Some(3024)).is_none());
+        let sql = query.sql(false);
+        assert!(sql.contains("avg(x.n) AS avg"));
+        assert!(sql.contains("NULL::float8 AS min"));
+        assert!(sql.contains("IN (1,2)"));
+        assert!(sql.contains("NULL::bigint AS num_count"));
+        let sql = query.sql(true);
+        assert!(sql.contains("sum(p.sum) / nullif(sum(p.num_count), 0) AS avg"));
+        assert!(sql.contains("AND data_id IN (1,2)"));
+        let sum = RangeQuery::new(start, end, None, None, Some("sum")).unwrap();
+        assert!(sum.sql(false).contains("count(x.n) AS num_count"));
+        assert!(sum.sql(true).contains("sum(p.num_count)::bigint AS num_count"));
+
+        let day = Rollup::DAY as u64 * 1000;
+        let query = RangeQuery::new(start, end, None, Some(2 * Rollup::DAY as u64), Some("max,last")).unwrap();
+        let (period, inner_start, inner_end) = query.rollup(query.interval().unwrap()).unwrap();
+        assert_eq!((period, inner_start, inner_end), (Rollup::DAY, start, end));
+        let offset = Timestamp::from_timestamp_millis(day / 2).unwrap();
+        let query = RangeQuery::new(offset, end, None, Some(2 * Rollup::HOUR as u64), None).unwrap();
+        let (period, inner_start, _) = query.rollup(query.interval().unwrap()).unwrap();
+        assert_eq!((period, inner_start), (Rollup::HOUR, offset));
+        let query = RangeQuery::new(offset, end, None, Some(Rollup::DAY as u64), Some("first")).unwrap();
+        assert!(query.rollup(Some(Rollup::DAY)).is_none());
+
+        let query = RangeQuery::new(start, end, None, Some(1), None).unwrap();
+        assert!(query.interval().is_err());
+        assert!(RangeQuery::new(start, end, None, None, Some("median")).is_err());
+
+        let berlin = time::time_zone("Europe/Berlin").unwrap();
+        let query = RangeQuery::new(offset, end, None, None, Some("max")).unwrap()
+            .with_calendar("day", berlin).unwrap();
+        assert_eq!(query.interval().unwrap(), None);
+        let (period, inner_start, _) = query.rollup(None).unwrap();
+        assert_eq!((period, inner_start), (Rollup::HOUR, offset));
+        assert!(query.sql(true).contains("date_trunc('day', p.time, $3) AS bucket"));
+        let query = RangeQuery::new(start, end, None, None, None).unwrap()
+            .with_calendar("week", Tz::UTC).unwrap();
+        assert_eq!(query.rollup(None).unwrap().0, Rollup::DAY);
+        let kolkata = time::time_zone("Asia/Kolkata").unwrap();
+        let query = RangeQuery::new(start, end, None, None, None).unwrap()
+            .with_calendar("month", kolkata).unwrap();
+        assert!(query.rollup(None).is_none());
+        assert!(RangeQuery::new(start, end, None, None, None).unwrap().with_calendar("year", berlin).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use std::time::Duration;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, FromQueryResult, IntoActiveModel, ModelTrait, QueryFilter, Statement};
+use redis_macros::{FromRedisValue, ToRedisArgs};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use common_define::db::{DataRetentionActiveModel, DataRetentionColumn, DataRetentionEntity, DeviceAuthorityColumn, DeviceAuthorityEntity, RetentionScope};
+use common_define::Id;
+use common_define::product::ShareType;
+use common_define::time::Timestamp;
+use crate::{telemetry, tt, CurrentUser, SEA_ORMDB_BACKEND};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::device::DeviceService;
+
+/// Retention of every device in days, 0 when the data is kept forever. `$1` is the owner share type.
+const TARGETS_SQL: &str = r"WITH t AS (
+    SELECT d.id AS device_id,
+           coalesce(dp.raw_days, up.raw_days, sp.raw_days, 0) AS raw_days,
+           coalesce(dp.rollup_days, up.rollup_days, sp.rollup_days, 0) AS rollup_days
+    FROM snap_devices d
+    LEFT JOIN LATERAL (SELECT share_id FROM snap_device_authority a WHERE a.device_id = d.id AND a.owner AND a.share_type = $1 LIMIT 1) o ON true
+    LEFT JOIN snap_data_retention dp ON dp.scope = 'device' AND dp.target_id = d.id
+    LEFT JOIN snap_data_retention up ON up.scope = 'user' AND up.target_id = o.share_id
+    LEFT JOIN snap_data_retention sp ON sp.scope = 'system'
+)
+SELECT t.device_id, t.raw_days, t.rollup_days{counts}
+FROM t
+WHERE t.raw_days > 0 OR t.rollup_days > 0
+ORDER BY 1";
+
+/// Rows [`TARGETS_SQL`] would prune at `$2`.
+const COUNTS_SQL: &str = r",
+       (SELECT count(*) FROM snap_device_data x WHERE t.raw_days > 0 AND x.device_id = t.device_id AND x.create_time < $2 - make_interval(days => t.raw_days)) AS raw,
+       (SELECT count(*) FROM snap_device_data_rollup x WHERE t.rollup_days > 0 AND x.device_id = t.device_id AND x.bucket + make_interval(secs => x.period) <= $2 - make_interval(days => t.rollup_days)) AS rollups";
+
+/// Deletes at most `$3` rollup buckets of device `$1` that ended before `$2`.
+const PRUNE_ROLLUP_SQL: &str = r"DELETE FROM snap_device_data_rollup WHERE (device_id, data_id, period, bucket) IN (
+    SELECT device_id, data_id, period, bucket FROM snap_device_data_rollup
+    WHERE device_id = $1 AND bucket + make_interval(secs => period) <= $2 LIMIT $3
+)";
+
+/// Deletes at most `$1` uplinks of devices that no longer exist.
+const PRUNE_ORPHAN_SQL: &str = r"DELETE FROM snap_device_data WHERE id IN (
+    SELECT d.id FROM snap_device_data d WHERE NOT EXISTS (SELECT 1 FROM snap_devices v WHERE v.id = d.device_id) LIMIT $1
+)";
+
+const PRUNE_ORPHAN_ROLLUP_SQL: &str = r"DELETE FROM snap_device_data_rollup WHERE (device_id, data_id, period, bucket) IN (
+    SELECT r.device_id, r.data_id, r.period, r.bucket FROM snap_device_data_rollup r
+    WHERE NOT EXISTS (SELECT 1 FROM snap_devices v WHERE v.id = r.device_id) LIMIT $1
+)";
+
+const COUNT_ORPHAN_SQL: &str = r"SELECT count(*) AS count FROM snap_device_data d
+WHERE NOT EXISTS (SELECT 1 FROM snap_devices v WHERE v.id = d.device_id)";
+
+/// Longest retention a policy can set, 100 years.
+const MAX_DAYS: u32 = 36_500;
+/// Pause between two deleted batches so other queries get the table.
+const BATCH_PAUSE: Duration = Duration::from_millis(50);
+
+/// Days data is kept, inherited from the level above when not set and kept forever when 0.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RetentionPolicy {
+    #[serde(default)]
+    raw_days: Option<u32>,
+    #[serde(default)]
+    rollup_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RetentionResponse {
+    /// Policy set at this level.
+    policy: RetentionPolicy,
+    /// Policy with the levels above applied, a day count of 0 keeps data forever.
+    effective: RetentionPolicy,
+}
+
+#[derive(FromQueryResult)]
+struct RetentionTarget {
+    device_id: i64,
+    raw_days: i32,
+    rollup_days: i32,
+}
+
+#[derive(FromQueryResult, Serialize)]
+pub(crate) struct DevicePreview {
+    device_id: i64,
+    raw_days: i32,
+    rollup_days: i32,
+    /// Stored uplinks that would be deleted.
+    raw: i64,
+    /// Rollup buckets that would be deleted.
+    rollups: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RetentionPreview {
+    time: Timestamp,
+    raw: i64,
+    rollups: i64,
+    /// Stored uplinks of deleted devices.
+    orphans: i64,
+    /// Devices with something to delete.
+    devices: Vec<DevicePreview>,
+}
+
+#[derive(FromQueryResult)]
+struct Count {
+    count: i64,
+}
+
+/// Statistics of one pruning run.
+#[derive(Serialize, Deserialize, Debug, Default, FromRedisValue, ToRedisArgs)]
+pub(crate) struct RetentionRun {
+    pub(crate) start_time: Option<Timestamp>,
+    pub(crate) end_time: Option<Timestamp>,
+    /// Devices with a retention policy that deletes something.
+    pub(crate) devices: u64,
+    pub(crate) raw: u64,
+    pub(crate) rollups: u64,
+    /// Stored uplinks and rollup buckets of deleted devices.
+    pub(crate) orphans: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl RetentionPolicy {
+    fn check(&self) -> ApiResult {
+        for days in [self.raw_days, self.rollup_days].into_iter().flatten() {
+            if days > MAX_DAYS {
+                return Err(ApiError::User(tt!("messages.user.data.retention_days", max = MAX_DAYS)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the days not set with the ones of the level above.
+    fn or(self, above: Self) -> Self {
+        Self {
+            raw_days: self.raw_days.or(above.raw_days),
+            rollup_days: self.rollup_days.or(above.rollup_days),
+        }
+    }
+
+    /// Days of the last level, data is kept forever when no level sets them.
+    fn effective(self) -> Self {
+        self.or(Self { raw_days: Some(0), rollup_days: Some(0) })
+    }
+}
+
+impl DataService {
+
+    async fn retention_policy<C: ConnectionTrait>(scope: RetentionScope, target: Id, conn: &C) -> ApiResult<RetentionPolicy> {
+        let policy = DataRetentionEntity::find()
+            .filter(DataRetentionColumn::Scope.eq(scope))
+            .filter(DataRetentionColumn::TargetId.eq(target))
+            .one(conn)
+            .await?;
+        Ok(policy.map(|it| RetentionPolicy {
+            raw_days: it.raw_days.map(|days| days as u32),
+            rollup_days: it.rollup_days.map(|days| days as u32),
+        }).unwrap_or_default())
+    }
+
+    async fn set_retention_policy<C: ConnectionTrait>(scope: RetentionScope, target: Id, policy: RetentionPolicy, conn: &C) -> ApiResult {
+        policy.check()?;
+        let current = DataRetentionEntity::find()
+            .filter(DataRetentionColumn::Scope.eq(scope))
+            .filter(DataRetentionColumn::TargetId.eq(target))
+            .one(conn)
+            .await?;
+        let mut model = match current {
+            Some(current) => current.into_active_model(),
+            None => DataRetentionActiveModel {
+                id: Default::default(),
+                scope: ActiveValue::Set(scope),
+                target_id: ActiveValue::Set(target),
+                ..Default::default()
+            },
+        };
+        model.raw_days = ActiveValue::Set(policy.raw_days.map(|days| days as i32));
+        model.rollup_days = ActiveValue::Set(policy.rollup_days.map(|days| days as i32));
+        model.modify_time = ActiveValue::Set(Timestamp::now());
+        model.save(conn).await?;
+        Ok(())
+    }
+
+    async fn delete_retention_policy<C: ConnectionTrait>(scope: RetentionScope, target: Id, conn: &C) -> ApiResult {
+        let current = DataRetentionEntity::find()
+            .filter(DataRetentionColumn::Scope.eq(scope))
+            .filter(DataRetentionColumn::TargetId.eq(target))
+            .one(conn)
+            .await?;
+        if let Some(current) = current {
+            current.delete(conn).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn system_retention<C: ConnectionTrait>(conn: &C) -> ApiResult<RetentionResponse> {
+        let policy = Self::retention_policy(RetentionScope::System, Id(0), conn).await?;
+        Ok(RetentionResponse { policy, effective: policy.effective() })
+    }
+
+    pub(crate) async fn set_system_retention<C: ConnectionTrait>(policy: RetentionPolicy, conn: &C) -> ApiResult<RetentionResponse> {
+        Self::set_retention_policy(RetentionScope::System, Id(0), policy, conn).await?;
+        Self::system_retention(conn).await
+    }
+
+    pub(crate) async fn user_retention<C: ConnectionTrait>(user: &CurrentUser, conn: &C) -> ApiResult<RetentionResponse> {
+        let policy = Self::retention_policy(RetentionScope::User, user.id, conn).await?;
+        let system = Self::retention_policy(RetentionScope::System, Id(0), conn).await?;
+        Ok(RetentionResponse { policy, effective: policy.or(system).effective() })
+    }
+
+    pub(crate) async fn set_user_retention<C: ConnectionTrait>(user: &CurrentUser, policy: RetentionPolicy, conn: &C) -> ApiResult<RetentionResponse> {
+        Self::set_retention_policy(RetentionScope::User, user.id, policy, conn).await?;
+        Self::user_retention(user, conn).await
+    }
+
+    pub(crate) async fn delete_user_retention<C: ConnectionTrait>(user: &CurrentUser, conn: &C) -> ApiResult<RetentionResponse> {
+        Self::delete_retention_policy(RetentionScope::User, user.id, conn).await?;
+        Self::user_retention(user, conn).await
+    }
+
+    /// The policy of the device and the one of its owner apply, the device can be shared with the user.
+    pub(crate) async fn device_retention<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult<RetentionResponse> {
+        DeviceService::query_one(user.id, device, conn).await?;
+        let owner = DeviceAuthorityEntity::find()
+            .filter(DeviceAuthorityColumn::DeviceId.eq(device))
+            .filter(DeviceAuthorityColumn::Owner.eq(true))
+            .filter(DeviceAuthorityColumn::ShareType.eq(ShareType::User.as_ref()))
+            .one(conn)
+            .await?;
+        let policy = Self::retention_policy(RetentionScope::Device, device, conn).await?;
+        let owner = match owner {
+            Some(owner) => Self::retention_policy(RetentionScope::User, owner.share_id, conn).await?,
+            None => RetentionPolicy::default(),
+        };
+        let system = Self::retention_policy(RetentionScope::System, Id(0), conn).await?;
+        Ok(RetentionResponse { policy, effective: policy.or(owner).or(system).effective() })
+    }
+
+    async fn check_device_owner<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult {
+        let device = DeviceService::query_one_with_auth(user.id, device, conn).await?;
+        if !device.auth.owner {
+            return Err(ApiError::User(tt!("messages.user.data.retention_owner")));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn set_device_retention<C: ConnectionTrait>(user: &CurrentUser, device: Id, policy: RetentionPolicy, conn: &C) -> ApiResult<RetentionResponse> {
+        Self::check_device_owner(user, device, conn).await?;
+        Self::set_retention_policy(RetentionScope::Device, device, policy, conn).await?;
+        Self::device_retention(user, device, conn).await
+    }
+
+    pub(crate) async fn delete_device_retention<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult<RetentionResponse> {
+        Self::check_device_owner(user, device, conn).await?;
+        Self::delete_retention_policy(RetentionScope::Device, device, conn).await?;
+        Self::device_retention(user, device, conn).await
+    }
+
+    /// Removes the policies of deleted devices.
+    pub(crate) async fn delete_device_retention_policies<C: ConnectionTrait>(devices: &[Id], conn: &C) -> ApiResult {
+        DataRetentionEntity::delete_many()
+            .filter(DataRetentionColumn::Scope.eq(RetentionScope::Device))
+            .filter(DataRetentionColumn::TargetId.is_in(devices))
+            .exec(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Counts what pruning now would delete, without deleting.
+    pub(crate) async fn preview_retention<C: ConnectionTrait>(conn: &C) -> ApiResult<RetentionPreview> {
+        let time = Timestamp::now();
+        let devices: Vec<DevicePreview> = DevicePreview::find_by_statement(Statement::from_sql_and_values(
+            SEA_ORMDB_BACKEND,
+            TARGETS_SQL.replace("{counts}", COUNTS_SQL),
+            [ShareType::User.as_ref().into(), time.into()],
+        ))
+            .all(conn)
+            .await?
+            .into_iter()
+            .filter(|it| it.raw > 0 || it.rollups > 0)
+            .collect();
+        let orphans = Count::find_by_statement(Statement::from_string(SEA_ORMDB_BACKEND, COUNT_ORPHAN_SQL))
+            .one(conn)
+            .await?
+            .map(|it| it.count)
+            .unwrap_or_default();
+        Ok(RetentionPreview {
+            time,
+            raw: devices.iter().map(|it| it.raw).sum(),
+            rollups: devices.iter().map(|it| it.rollups).sum(),
+            orphans,
+            devices,
+        })
+    }
+
+    /// Deletes expired data device by device, `batch` rows per statement so no lock is held for long.
+    pub(crate) async fn prune_expired<C: ConnectionTrait>(batch: u64, run: &mut RetentionRun, conn: &C) -> ApiResult {
+        let now = Timestamp::now();
+        let targets = RetentionTarget::find_by_statement(Statement::from_sql_and_values(
+            SEA_ORMDB_BACKEND,
+            TARGETS_SQL.replace("{counts}", ""),
+            [ShareType::User.as_ref().into()],
+        ))
+            .all(conn)
+            .await?;
+        run.devices = targets.len() as u64;
+        for target in targets {
+            let device = Id(target.device_id as u64);
+            if target.raw_days > 0 {
+                let cutoff = now - chrono::Duration::days(target.raw_days as i64);
+                run.raw += Self::prune_raw(device, cutoff, batch).await?;
+            }
+            if target.rollup_days > 0 {
+                let cutoff = now - chrono::Duration::days(target.rollup_days as i64);
+                run.rollups += Self::prune_batches(PRUNE_ROLLUP_SQL, Some((device, cutoff)), batch, conn).await?;
+            }
+        }
+        run.orphans += Self::prune_batches(PRUNE_ORPHAN_SQL, None, batch, conn).await?;
+        run.orphans += Self::prune_batches(PRUNE_ORPHAN_ROLLUP_SQL, None, batch, conn).await?;
+        Ok(())
+    }
+
+    /// Prunes uplinks through the telemetry store a batch at a time, returns the uplinks deleted.
+    async fn prune_raw(device: Id, cutoff: Timestamp, batch: u64) -> ApiResult<u64> {
+        let batch = batch.max(1);
+        let mut deleted = 0;
+        loop {
+            let rows = telemetry().prune(device, cutoff, batch).await?;
+            deleted += rows;
+            if rows < batch {
+                break
+            }
+            tokio::time::sleep(BATCH_PAUSE).await;
+        }
+        if deleted > 0 {
+            debug!(device = %device, deleted, "pruned expired uplinks");
+        }
+        Ok(deleted)
+    }
+
+    /// Runs a delete until it deletes less than a batch, returns the rows deleted.
+    async fn prune_batches<C: ConnectionTrait>(sql: &str, target: Option<(Id, Timestamp)>, batch: u64, conn: &C) -> ApiResult<u64> {
+        let batch = batch.max(1);
+        let mut deleted = 0;
+        loop {
+            let values = match target {
+                Some((device, cutoff)) => vec![device.into(), cutoff.into(), (batch as i64).into()],
+                None => vec![(batch as i64).into()],
+            };
+            let rows = conn.execute(Statement::from_sql_and_values(SEA_ORMDB_BACKEND, sql, values)).await?
+                .rows_affected();
+            deleted += rows;
+            if rows < batch {
+                break
+            }
+            tokio::time::sleep(BATCH_PAUSE).await;
+        }
+        if deleted > 0 {
+            debug!(device = ?target.map(|it| it.0), deleted, "pruned expired data");
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QueryOrder, TransactionTrait};
+    use migration::MigratorTrait;
+    use common_define::db::{DbDecodeData, DeviceDataRollupColumn, DeviceDataRollupEntity, Rollup};
+    use common_define::decode::{DecodeData, Value};
+    use common_define::Id;
+    use common_define::telemetry::{PostgresStore, TelemetryStore};
+    use common_define::time::Timestamp;
+    use super::RetentionPolicy;
+
+    #[test]
+    fn test_retention_policy() {
+        let device = RetentionPolicy { raw_days: Some(7), rollup_days: None };
+        let user = RetentionPolicy { raw_days: Some(30), rollup_days: None };
+        let system = RetentionPolicy { raw_days: Some(90), rollup_days: Some(730) };
+        assert_eq!(device.or(user).or(system).effective(), RetentionPolicy { raw_days: Some(7), rollup_days: Some(730) });
+        assert_eq!(RetentionPolicy::default().effective(), RetentionPolicy { raw_days: Some(0), rollup_days: Some(0) });
+        assert!(RetentionPolicy { raw_days: Some(40_000), rollup_days: None }.check().is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL database in SNAPEMU_TEST_DB"]
+    async fn test_rebuild_after_prune() {
+        let db = sea_orm::Database::connect(std::env::var("SNAPEMU_TEST_DB").unwrap()).await.unwrap();
+        migration::Migrator::up(&db, None).await.unwrap();
+        let store = PostgresStore::new(db.clone());
+        let device = Id(u32::MAX as u64 + std::process::id() as u64);
+        store.delete(&[device]).await.unwrap();
+        let day = |day: i64, hour: i64| Timestamp::from_timestamp_millis((day * Rollup::DAY + hour * Rollup::HOUR) as u64 * 1000).unwrap();
+        let times = [day(1, 1), day(1, 2), day(3, 1), day(3, 5), day(4, 1)];
+        for time in times {
+            store.write(device, time, &DbDecodeData(vec![DecodeData::new(1, Value::Int(1))]), "").await.unwrap();
+        }
+        // retention deletes day 1 and the first uplink of day 3
+        assert_eq!(store.prune(device, day(3, 3), 100).await.unwrap(), 3);
+        let txn = db.begin().await.unwrap();
+        for statement in Rollup::rebuild(day(0, 0), day(5, 0), Some(device)) {
+            txn.execute(statement).await.unwrap();
+        }
+        txn.commit().await.unwrap();
+        let days: Vec<_> = DeviceDataRollupEntity::find()
+            .filter(DeviceDataRollupColumn::DeviceId.eq(device))
+            .filter(DeviceDataRollupColumn::Period.eq(Rollup::DAY as i32))
+            .order_by_asc(DeviceDataRollupColumn::Bucket)
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|it| (it.bucket, it.count))
+            .collect();
+        assert_eq!(days, [(day(1, 0), 2), (day(3, 0), 2), (day(4, 0), 1)]);
+        store.delete(&[device]).await.unwrap();
+    }
+}
@@ -1,22 +1,59 @@
-use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
-use common_define::db::{DeviceDataColumn, DeviceDataEntity};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect, TransactionTrait};
+use sea_orm::sea_query::Expr;
+use tracing::debug;
+use common_define::db::{DeviceDataColumn, DeviceDataEntity, Rollup};
 use common_define::Id;
+use common_define::time::Timestamp;
 use crate::error::ApiResult;
+use crate::telemetry;
 use crate::service::data::DataService;
 
 impl DataService {
 
-    pub(crate) async fn delete_by_device_id<C: ConnectionTrait>(device: Id, conn: &C) -> ApiResult {
-        DeviceDataEntity::delete_many()
-            .filter(DeviceDataColumn::DeviceId.eq(device))
-            .exec(conn).await?;
-        Ok(())
+    pub(crate) async fn delete_by_device_id(device: Id) -> ApiResult {
+        Ok(telemetry().delete(&[device]).await?)
     }
 
-    pub(crate) async fn delete_by_device_id_array<C: ConnectionTrait>(devices: &[Id], conn: &C) -> ApiResult {
-        DeviceDataEntity::delete_many()
-            .filter(DeviceDataColumn::DeviceId.is_in(devices))
-            .exec(conn).await?;
-        Ok(())
+    pub(crate) async fn delete_by_device_id_array(devices: &[Id]) -> ApiResult {
+        Ok(telemetry().delete(devices).await?)
     }
-}
\ No newline at end of file
+
+    /// Recomputes the rollups from the stored uplinks one day at a time, of one device or of every device.
+    /// The range defaults to the oldest stored uplink until now, returns the days recomputed.
+    pub(crate) async fn backfill_rollups<C: ConnectionTrait + TransactionTrait>(
+        device: Option<Id>,
+        start: Option<Timestamp>,
+        end: Option<Timestamp>,
+        conn: &C,
+    ) -> ApiResult<u64> {
+        let start = match start {
+            Some(start) => start,
+            None => {
+                let mut query = DeviceDataEntity::find()
+                    .select_only()
+                    .column_as(Expr::col(DeviceDataColumn::CreateTime).min(), "start");
+                if let Some(device) = device {
+                    query = query.filter(DeviceDataColumn::DeviceId.eq(device));
+                }
+                match query.into_tuple::<Option<Timestamp>>().one(conn).await?.flatten() {
+                    Some(start) => start,
+                    None => return Ok(0),
+                }
+            }
+        };
+        let end = end.unwrap_or_else(Timestamp::now);
+        let (mut day, end) = Rollup::align(start, end, Rollup::DAY);
+        let mut days = 0;
+        while day < end {
+            let txn = conn.begin().await?;
+            for statement in Rollup::rebuild(day, day, device) {
+                txn.execute(statement).await?;
+            }
+            txn.commit().await?;
+            debug!("backfill rollups of {}", day.to_rfc3339());
+            day = day + chrono::Duration::seconds(Rollup::DAY);
+            days += 1;
+        }
+        Ok(days)
+    }
+}
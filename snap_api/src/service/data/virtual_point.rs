@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, QueryFilter, QueryOrder, TryIntoModel};
+use serde::{Deserialize, Serialize};
+use common_define::db::{CodeMapItem, DevicesModel, SnapProductInfoEntity, VirtualPointActiveModel, VirtualPointColumn, VirtualPointEntity, VirtualPointModel, VirtualPointScope};
+use common_define::decode::{DecodeDataType, LastDecodeData};
+use common_define::event::VirtualPointEvent;
+use common_define::Id;
+use common_define::time::Timestamp;
+use common_define::virtual_point::{Expr, VirtualPoint};
+use crate::{tt, CurrentUser};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+use crate::service::data::query::{DataDeviceOneResponse, TimeDate};
+use crate::service::device::DeviceService;
+
+/// Longest expression of a virtual data point.
+const MAX_EXPRESSION: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct VirtualPointRequest {
+    data_id: u32,
+    name: String,
+    #[serde(default)]
+    unit: String,
+    /// See [`common_define::virtual_point`] for the syntax.
+    expression: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct VirtualPointResponse {
+    scope: VirtualPointScope,
+    data_id: u32,
+    name: String,
+    unit: String,
+    expression: String,
+    modify_time: Timestamp,
+}
+
+impl From<VirtualPointModel> for VirtualPointResponse {
+    fn from(value: VirtualPointModel) -> Self {
+        Self {
+            scope: value.scope,
+            data_id: value.data_id as u32,
+            name: value.name,
+            unit: value.unit,
+            expression: value.expression,
+            modify_time: value.modify_time,
+        }
+    }
+}
+
+impl VirtualPointRequest {
+    fn check(&self) -> ApiResult {
+        if self.data_id == 0 {
+            return Err(ApiError::User(tt!("messages.user.data.data_id", id = self.data_id)));
+        }
+        if self.name.trim().is_empty() {
+            return Err(ApiError::User(tt!("messages.user.data.virtual_name")));
+        }
+        if self.expression.len() > MAX_EXPRESSION {
+            return Err(ApiError::User(tt!("messages.user.data.virtual_expression_length", max = MAX_EXPRESSION)));
+        }
+        let expr = Expr::parse(&self.expression)
+            .map_err(|e| ApiError::User(tt!("messages.user.data.virtual_expression", error = e)))?;
+        if expr.data_ids().contains(&self.data_id) {
+            return Err(ApiError::User(tt!("messages.user.data.virtual_itself", id = self.data_id)));
+        }
+        Ok(())
+    }
+}
+
+impl DataService {
+
+    async fn virtual_points<C: ConnectionTrait>(scope: VirtualPointScope, target: Id, conn: &C) -> ApiResult<Vec<VirtualPointModel>> {
+        let points = VirtualPointEntity::find()
+            .filter(VirtualPointColumn::Scope.eq(scope))
+            .filter(VirtualPointColumn::TargetId.eq(target))
+            .order_by_asc(VirtualPointColumn::DataId)
+            .all(conn)
+            .await?;
+        Ok(points)
+    }
+
+    async fn set_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        scope: VirtualPointScope,
+        target: Id,
+        req: VirtualPointRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<VirtualPointResponse> {
+        req.check()?;
+        let current = VirtualPointEntity::find()
+            .filter(VirtualPointColumn::Scope.eq(scope))
+            .filter(VirtualPointColumn::TargetId.eq(target))
+            .filter(VirtualPointColumn::DataId.eq(req.data_id as i64))
+            .one(conn)
+            .await?;
+        let mut model = match current {
+            Some(current) => current.into_active_model(),
+            None => VirtualPointActiveModel {
+                id: Default::default(),
+                scope: ActiveValue::Set(scope),
+                target_id: ActiveValue::Set(target),
+                data_id: ActiveValue::Set(req.data_id as i64),
+                ..Default::default()
+            },
+        };
+        model.name = ActiveValue::Set(req.name);
+        model.unit = ActiveValue::Set(req.unit);
+        model.expression = ActiveValue::Set(req.expression);
+        model.modify_time = ActiveValue::Set(Timestamp::now());
+        let model = model.save(conn).await?.try_into_model()?;
+        Self::publish_virtual_point(scope, target, redis).await?;
+        Ok(model.into())
+    }
+
+    async fn delete_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        scope: VirtualPointScope,
+        target: Id,
+        data_id: u32,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult {
+        let current = VirtualPointEntity::find()
+            .filter(VirtualPointColumn::Scope.eq(scope))
+            .filter(VirtualPointColumn::TargetId.eq(target))
+            .filter(VirtualPointColumn::DataId.eq(data_id as i64))
+            .one(conn)
+            .await?;
+        if let Some(current) = current {
+            current.delete(conn).await?;
+            Self::publish_virtual_point(scope, target, redis).await?;
+        }
+        Ok(())
+    }
+
+    /// Tells devices_manager to load the points again, new uplinks use them from then on.
+    async fn publish_virtual_point<R: redis::aio::ConnectionLike>(scope: VirtualPointScope, target: Id, redis: &mut R) -> ApiResult {
+        let event = serde_json::to_string(&VirtualPointEvent { scope, target })?;
+        let _: () = redis::cmd("PUBLISH").arg(VirtualPointEvent::TOPIC).arg(event).query_async(redis).await?;
+        Ok(())
+    }
+
+    /// Points computed for a device, its own and the ones of its product it does not replace.
+    pub(crate) async fn device_virtual_points<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult<Vec<VirtualPointResponse>> {
+        DeviceService::query_one(user.id, device, conn).await?;
+        let points = VirtualPoint::of_device(conn, device).await?;
+        Ok(points.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn set_device_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        device: Id,
+        req: VirtualPointRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<VirtualPointResponse> {
+        Self::check_virtual_point_owner(user, device, conn).await?;
+        Self::set_virtual_point(VirtualPointScope::Device, device, req, redis, conn).await
+    }
+
+    pub(crate) async fn delete_device_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        device: Id,
+        data_id: u32,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult {
+        Self::check_virtual_point_owner(user, device, conn).await?;
+        Self::delete_virtual_point(VirtualPointScope::Device, device, data_id, redis, conn).await
+    }
+
+    async fn check_virtual_point_owner<C: ConnectionTrait>(user: &CurrentUser, device: Id, conn: &C) -> ApiResult {
+        let device = DeviceService::query_one_with_auth(user.id, device, conn).await?;
+        if !device.auth.owner {
+            return Err(ApiError::User(tt!("messages.user.data.virtual_owner")));
+        }
+        Ok(())
+    }
+
+    async fn check_product<C: ConnectionTrait>(product: Id, conn: &C) -> ApiResult {
+        SnapProductInfoEntity::find_by_id(product)
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.user.data.virtual_product")))?;
+        Ok(())
+    }
+
+    pub(crate) async fn product_virtual_points<C: ConnectionTrait>(product: Id, conn: &C) -> ApiResult<Vec<VirtualPointResponse>> {
+        Self::check_product(product, conn).await?;
+        let points = Self::virtual_points(VirtualPointScope::Product, product, conn).await?;
+        Ok(points.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn set_product_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        product: Id,
+        req: VirtualPointRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<VirtualPointResponse> {
+        Self::check_product(product, conn).await?;
+        Self::set_virtual_point(VirtualPointScope::Product, product, req, redis, conn).await
+    }
+
+    pub(crate) async fn delete_product_virtual_point<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        product: Id,
+        data_id: u32,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult {
+        Self::check_product(product, conn).await?;
+        Self::delete_virtual_point(VirtualPointScope::Product, product, data_id, redis, conn).await
+    }
+
+    /// Removes the points of deleted devices.
+    pub(crate) async fn delete_device_virtual_points<C: ConnectionTrait>(devices: &[Id], conn: &C) -> ApiResult {
+        VirtualPointEntity::delete_many()
+            .filter(VirtualPointColumn::Scope.eq(VirtualPointScope::Device))
+            .filter(VirtualPointColumn::TargetId.is_in(devices))
+            .exec(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Names and units of the virtual points of a device, looked up before the decoder map.
+    pub(crate) async fn virtual_point_map<C: ConnectionTrait>(device: Id, conn: &C) -> ApiResult<HashMap<u32, CodeMapItem>> {
+        let points = VirtualPoint::of_device(conn, device).await?;
+        Ok(points.iter().map(|it| (it.data_id as u32, Self::virtual_map_item(it))).collect())
+    }
+
+    /// [`Self::virtual_point_map`] of many devices, devices without points are left out.
+    pub(crate) async fn virtual_point_maps<C: ConnectionTrait>(devices: &[DevicesModel], conn: &C) -> ApiResult<HashMap<Id, HashMap<u32, CodeMapItem>>> {
+        let device_ids: Vec<Id> = devices.iter().map(|it| it.id).collect();
+        let product_ids: Vec<Id> = devices.iter().filter_map(|it| it.product_id).collect();
+        let points = VirtualPointEntity::find()
+            .filter(Condition::any()
+                .add(VirtualPointColumn::Scope.eq(VirtualPointScope::Device).and(VirtualPointColumn::TargetId.is_in(device_ids)))
+                .add(VirtualPointColumn::Scope.eq(VirtualPointScope::Product).and(VirtualPointColumn::TargetId.is_in(product_ids))))
+            .all(conn)
+            .await?;
+        let mut maps = HashMap::new();
+        for device in devices {
+            // points of the device come last and replace the ones of its product
+            let map: HashMap<_, _> = points.iter()
+                .filter(|it| it.scope == VirtualPointScope::Product && Some(it.target_id) == device.product_id)
+                .chain(points.iter().filter(|it| it.scope == VirtualPointScope::Device && it.target_id == device.id))
+                .map(|it| (it.data_id as u32, Self::virtual_map_item(it)))
+                .collect();
+            if !map.is_empty() {
+                maps.insert(device.id, map);
+            }
+        }
+        Ok(maps)
+    }
+
+    fn virtual_map_item(model: &VirtualPointModel) -> CodeMapItem {
+        CodeMapItem {
+            id: model.data_id as u32,
+            name: model.name.clone(),
+            unit: model.unit.clone(),
+            t: DecodeDataType::F64,
+        }
+    }
+
+    /// Takes the values of virtual points out of the last data of a device, named from `points`.
+    pub(crate) fn take_virtual_data(points: &HashMap<u32, CodeMapItem>, data: &mut LastDecodeData) -> Vec<DataDeviceOneResponse> {
+        let mut resp = vec![];
+        data.v.retain(|it| {
+            let Some(point) = points.get(&it.i) else {
+                return true
+            };
+            resp.push(DataDeviceOneResponse {
+                name: point.name.clone(),
+                data_id: it.i,
+                unit: point.unit.clone(),
+                data_type: Some(it.v.data_type()),
+                source_unit: None,
+                precision: None,
+                data: TimeDate {
+                    time: data.t,
+                    data: it.v.clone(),
+                },
+            });
+            false
+        });
+        resp
+    }
+}
@@ -0,0 +1,41 @@
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect};
+use common_define::db::{DeviceLoraNodeColumn, DeviceLoraNodeEntity, UsersColumn, UsersEntity};
+use common_define::Id;
+use common_define::time::{self, Tz};
+use crate::{tt, CurrentUser};
+use crate::error::{ApiError, ApiResult};
+use crate::service::data::DataService;
+
+impl DataService {
+
+    /// Time zone the days, weeks and months of the data of a device start at midnight in:
+    /// `name` when given, else the one of the user, else the offset the device reports, else UTC.
+    pub(crate) async fn time_zone<C: ConnectionTrait>(
+        user: &CurrentUser,
+        device: Id,
+        name: Option<&str>,
+        conn: &C,
+    ) -> ApiResult<Tz> {
+        if let Some(name) = name.map(str::trim).filter(|it| !it.is_empty()) {
+            return time::time_zone(name)
+                .ok_or_else(|| ApiError::User(tt!("messages.user.info.time_zone", name = name)));
+        }
+        let user_zone: Option<Option<String>> = UsersEntity::find_by_id(user.id)
+            .select_only()
+            .column(UsersColumn::TimeZone)
+            .into_tuple()
+            .one(conn)
+            .await?;
+        if let Some(tz) = user_zone.flatten().as_deref().and_then(time::time_zone) {
+            return Ok(tz);
+        }
+        let device_zone: Option<i32> = DeviceLoraNodeEntity::find()
+            .filter(DeviceLoraNodeColumn::DeviceId.eq(device))
+            .select_only()
+            .column(DeviceLoraNodeColumn::TimeZone)
+            .into_tuple()
+            .one(conn)
+            .await?;
+        Ok(device_zone.and_then(time::offset_time_zone).unwrap_or(Tz::UTC))
+    }
+}
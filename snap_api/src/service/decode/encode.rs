@@ -0,0 +1,60 @@
+use common_define::db::CodecVariables;
+use common_define::event::{ScriptCall, ScriptJob, ScriptReply};
+use common_define::Id;
+use crate::error::{ApiError, ApiResult};
+use crate::service::decode::DecodeService;
+use crate::tt;
+
+pub(crate) struct Encoded {
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) f_port: Option<u8>,
+    /// The bytes read back by `decodeDownlink` of the codec.
+    pub(crate) decoded: Option<serde_json::Value>,
+}
+
+impl DecodeService {
+
+    /// Runs a job in devices_manager and waits for its reply.
+    pub(crate) async fn call<R: redis::aio::ConnectionLike>(
+        job: ScriptJob,
+        redis: &mut R,
+    ) -> ApiResult<ScriptReply> {
+        let timeout = match job {
+            ScriptJob::Batch { .. } => ScriptCall::BATCH_TIMEOUT,
+            _ => ScriptCall::TIMEOUT,
+        };
+        let reply = format!("script:reply:{}", uuid::Uuid::new_v4());
+        let call = serde_json::to_string(&ScriptCall {
+            reply: reply.clone(),
+            job,
+        })?;
+        let receivers: u32 = redis::cmd("PUBLISH").arg(ScriptCall::TOPIC).arg(call).query_async(redis).await?;
+        if receivers == 0 {
+            return Err(ApiError::User(tt!("messages.device.decode.script_unavailable")));
+        }
+        let reply: Option<(String, String)> = redis::cmd("BLPOP").arg(&reply).arg(timeout).query_async(redis).await?;
+        let (_, reply) = reply.ok_or_else(|| ApiError::User(tt!("messages.device.decode.script_timeout")))?;
+        Ok(serde_json::from_str(&reply)?)
+    }
+
+    /// Encodes a JSON command with the encoder script, the script is run by devices_manager.
+    pub(crate) async fn encode<R: redis::aio::ConnectionLike>(
+        script: Id,
+        data: serde_json::Value,
+        variables: Option<CodecVariables>,
+        redis: &mut R,
+    ) -> ApiResult<Encoded> {
+        match Self::call(ScriptJob::Encode { script, data, variables }, redis).await? {
+            ScriptReply::Encoded { bytes, f_port, decoded, .. } => Ok(Encoded { bytes, f_port, decoded }),
+            ScriptReply::Invalid { errors } => Err(ApiError::User(
+                tt!("messages.device.decode.encoder_invalid", errors = errors.join("; "))
+            )),
+            ScriptReply::Error { message } | ScriptReply::Timeout { message } => Err(ApiError::User(
+                tt!("messages.device.decode.encoder_error", error = message)
+            )),
+            ScriptReply::Decoded { .. } | ScriptReply::Batch { .. } => Err(ApiError::User(
+                tt!("messages.device.decode.encoder_error", error = "unexpected reply")
+            )),
+        }
+    }
+}
@@ -1,6 +1,15 @@
 mod script;
 mod test;
+mod encode;
+mod redecode;
+mod version;
+mod vector;
 
 pub(crate) use script::ScriptRequest;
+pub(crate) use encode::Encoded;
+pub(crate) use test::{CompareRequest, CompareResponse, DecodeOutcome, DecodeRequest, DecodeResponse};
+pub(crate) use redecode::{ReDecodeRequest, ReDecodeStarted};
+pub(crate) use version::{RollbackRequest, ScriptDevice, ScriptDiff, ScriptVersion};
+pub(crate) use vector::{TestVector, VectorRequest, VectorResult, VectorRunRequest};
 
 pub(crate) struct DecodeService;
\ No newline at end of file
@@ -0,0 +1,188 @@
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use common_define::binary::BinaryDecoder;
+use common_define::db::{DecodeScriptColumn, DecodeScriptEntity, DecodeScriptVersionColumn, DecodeScriptVersionEntity};
+use common_define::decode::DecodeLang;
+use common_define::event::{ReDecodeDiff, ReDecodeJob, ReDecodeProgress};
+use common_define::Id;
+use common_define::time::Timestamp;
+use crate::{CurrentUser, tt};
+use crate::error::{ApiError, ApiResult};
+use crate::service::decode::DecodeService;
+use crate::service::device::DeviceService;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct ReDecodeRequest {
+    devices: Vec<Id>,
+    start: Timestamp,
+    end: Timestamp,
+    /// Decoder to run, the decoder of each device when not set.
+    script: Option<Id>,
+    /// Version of `script`, the latest version when not set.
+    version: Option<i32>,
+    #[serde(default)]
+    dry_run: bool,
+    /// Only computes the virtual data points again, `script` and `version` are not used.
+    #[serde(default)]
+    virtual_only: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct ReDecodeStarted {
+    id: String,
+}
+
+/// Whether the output of a decoder depends on the FPort, which stored uplinks do not keep.
+fn port_dependent(lang: DecodeLang, script: &str) -> bool {
+    match lang {
+        DecodeLang::Binary => BinaryDecoder::parse(script).is_ok_and(|decoder| {
+            !decoder.f_ports.is_empty() || decoder.fields.iter().any(|field| field.f_port.is_some())
+        }),
+        DecodeLang::TTN => script.contains("fPort"),
+        DecodeLang::JS | DecodeLang::Wasm => false,
+    }
+}
+
+impl DecodeService {
+
+    /// Rejects decoders that read the FPort, they would decode stored uplinks as FPort 0.
+    async fn check_port_independent<C: ConnectionTrait>(script: Id, version: Option<i32>, conn: &C) -> ApiResult {
+        let source = match version {
+            Some(version) => DecodeScriptVersionEntity::find()
+                .filter(DecodeScriptVersionColumn::ScriptId.eq(script))
+                .filter(DecodeScriptVersionColumn::Version.eq(version))
+                .one(conn)
+                .await?
+                .map(|it| (it.lang, it.script)),
+            None => DecodeScriptEntity::find_by_id(script)
+                .one(conn)
+                .await?
+                .map(|it| (it.lang, it.script)),
+        };
+        if let Some((lang, source)) = source {
+            if port_dependent(lang.parse().unwrap_or(DecodeLang::JS), &source) {
+                return Err(ApiError::User(tt!("messages.device.decode.redecode_fport", script = script)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a re-decode job in devices_manager.
+    pub(crate) async fn start_redecode<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        req: ReDecodeRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<ReDecodeStarted> {
+        if req.devices.is_empty() {
+            return Err(ApiError::User(tt!("messages.device.decode.redecode_devices")));
+        }
+        if req.start >= req.end {
+            return Err(ApiError::User(tt!("messages.device.decode.redecode_range")));
+        }
+        let mut scripts = Vec::new();
+        for device in &req.devices {
+            let device = DeviceService::query_one_with_auth(user.id, *device, conn).await?;
+            if !device.auth.owner {
+                return Err(ApiError::User(tt!("messages.device.decode.redecode_owner")));
+            }
+            if let Some(script) = device.device.script {
+                scripts.push((script, device.device.script_version));
+            }
+        }
+        match (req.script, req.version) {
+            _ if req.virtual_only => {}
+            (Some(script), Some(version)) => Self::check_version(user, script, version, conn).await?,
+            (Some(script), None) => {
+                DecodeScriptEntity::find_by_id(script)
+                    .filter(DecodeScriptColumn::Owner.eq(user.id))
+                    .one(conn)
+                    .await?
+                    .ok_or_else(|| ApiError::User(tt!("messages.device.decode.not_found_script")))?;
+            }
+            (None, _) => {}
+        }
+        if !req.virtual_only {
+            if let Some(script) = req.script {
+                scripts = vec![(script, req.version)];
+            }
+            scripts.sort_unstable();
+            scripts.dedup();
+            for (script, version) in scripts {
+                Self::check_port_independent(script, version, conn).await?;
+            }
+        }
+        let job = ReDecodeJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            owner: user.id,
+            devices: req.devices,
+            start: req.start,
+            end: req.end,
+            script: req.script.filter(|_| !req.virtual_only),
+            version: req.script.and(req.version).filter(|_| !req.virtual_only),
+            dry_run: req.dry_run,
+            virtual_only: req.virtual_only,
+        };
+        let key = ReDecodeJob::progress_key(&job.id);
+        let _: () = redis::cmd("SET").arg(&key).arg(ReDecodeProgress::new(&job)).arg("EX").arg(ReDecodeJob::TTL).query_async(redis).await?;
+        let receivers: u32 = redis::cmd("PUBLISH").arg(ReDecodeJob::TOPIC).arg(serde_json::to_string(&job)?).query_async(redis).await?;
+        if receivers == 0 {
+            let _: () = redis::cmd("DEL").arg(&key).query_async(redis).await?;
+            return Err(ApiError::User(tt!("messages.device.decode.script_unavailable")));
+        }
+        Ok(ReDecodeStarted { id: job.id })
+    }
+
+    pub(crate) async fn redecode_progress<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: &str,
+        redis: &mut R,
+    ) -> ApiResult<ReDecodeProgress> {
+        let progress: Option<ReDecodeProgress> = redis::cmd("GET").arg(ReDecodeJob::progress_key(id)).query_async(redis).await?;
+        progress
+            .filter(|progress| progress.owner == user.id)
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.redecode_not_found")))
+    }
+
+    /// Rows whose decoded data changed, at most [`ReDecodeJob::MAX_DIFF`] are kept.
+    pub(crate) async fn redecode_diff<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: &str,
+        offset: usize,
+        limit: usize,
+        redis: &mut R,
+    ) -> ApiResult<Vec<ReDecodeDiff>> {
+        Self::redecode_progress(user, id, redis).await?;
+        let end = offset + limit.clamp(1, 100) - 1;
+        let diff: Vec<ReDecodeDiff> = redis::cmd("LRANGE").arg(ReDecodeJob::diff_key(id)).arg(offset).arg(end).query_async(redis).await?;
+        Ok(diff)
+    }
+
+    /// Asks devices_manager to stop the job, rows already written stay written.
+    pub(crate) async fn cancel_redecode<R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: &str,
+        redis: &mut R,
+    ) -> ApiResult {
+        Self::redecode_progress(user, id, redis).await?;
+        let _: () = redis::cmd("SET").arg(ReDecodeJob::cancel_key(id)).arg(1).arg("EX").arg(ReDecodeJob::TTL).query_async(redis).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::decode::DecodeLang;
+    use super::port_dependent;
+
+    #[test]
+    fn test_port_dependent() {
+        let binary = r#"{"f_ports": [2], "fields": [{"name": "t", "type": "U8", "offset": 0, "id": 1}]}"#;
+        assert!(port_dependent(DecodeLang::Binary, binary));
+        let any_port = binary.replace(r#""f_ports": [2], "#, "");
+        assert!(!port_dependent(DecodeLang::Binary, &any_port));
+        assert!(port_dependent(DecodeLang::Binary, &any_port.replace(r#""id": 1"#, r#""id": 1, "f_port": 3"#)));
+        assert!(port_dependent(DecodeLang::TTN, "function decodeUplink(input) { return { data: { p: input.fPort } }; }"));
+        assert!(!port_dependent(DecodeLang::TTN, "function decodeUplink(input) { return { data: {} }; }"));
+        assert!(!port_dependent(DecodeLang::JS, "fPort"));
+    }
+}
@@ -1,7 +1,10 @@
-use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, PaginatorTrait, QueryFilter};
-use common_define::db::{CodeMapItem, DecodeMap as DbDecodeMap, DecodeScriptActiveModel, DecodeScriptColumn, DecodeScriptEntity, DevicesColumn, DevicesEntity};
+use base64::Engine;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, PaginatorTrait, QueryFilter, QuerySelect, TransactionTrait};
+use common_define::binary::BinaryDecoder;
+use common_define::db::{CodecFlatten, CodeMapItem, DecodeMap as DbDecodeMap, DecodeScriptActiveModel, DecodeScriptColumn, DecodeScriptEntity, DecodeScriptVersionColumn, DecodeScriptVersionEntity, DecodeTestVectorColumn, DecodeTestVectorEntity, DevicesColumn, DevicesEntity};
 use common_define::decode::{DecodeDataType, DecodeLang};
 use common_define::Id;
+use common_define::event::ScriptEvent;
 use common_define::time::Timestamp;
 use crate::{CurrentUser, tt};
 use crate::error::{ApiError, ApiResult};
@@ -23,7 +26,16 @@ pub(crate) struct ScriptRequest {
     name: String,
     lang: DecodeLang,
     script: String,
-    map: Vec<DecodeMap>
+    map: Vec<DecodeMap>,
+    /// Flattening of the object returned by a TTN codec.
+    #[serde(default)]
+    codec: CodecFlatten,
+    /// Latest version, each save adds one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<i32>,
+    /// What changed in this save, kept with the new version.
+    #[serde(default, skip_serializing)]
+    changelog: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -36,9 +48,34 @@ pub(crate) struct ScriptResponse {
 
 impl DecodeService {
 
-    pub(crate) async fn update_script<C: ConnectionTrait>(
+    /// A binary decoder is checked here and its data points come from its fields,
+    /// a WebAssembly module is only checked to be one.
+    pub(super) fn script_map(lang: DecodeLang, script: &str, map: Vec<DecodeMap>) -> ApiResult<DbDecodeMap> {
+        if matches!(lang, DecodeLang::Binary) {
+            let decoder = BinaryDecoder::parse(script)
+                .map_err(|e| ApiError::User(tt!("messages.device.decode.invalid_binary", error = e)))?;
+            return Ok(decoder.map());
+        }
+        if matches!(lang, DecodeLang::Wasm) {
+            let wasm = base64::engine::general_purpose::STANDARD.decode(script.trim()).unwrap_or_default();
+            if !wasm.starts_with(b"\0asm") {
+                return Err(ApiError::User(tt!("messages.device.decode.invalid_wasm")));
+            }
+        }
+        Ok(DbDecodeMap(map.into_iter().map(|it| CodeMapItem {
+            id: it.d_id,
+            name: it.d_name,
+            unit: it.d_unit,
+            t: it.d_type,
+        }).collect()))
+    }
+
+    /// The script and its new version are written in one transaction, the script row is locked
+    /// so concurrent updates get consecutive versions.
+    pub(crate) async fn update_script<C: ConnectionTrait + TransactionTrait, R: redis::aio::ConnectionLike>(
         user: &CurrentUser,
         req: ScriptRequest,
+        redis: &mut R,
         conn: &C
     ) -> ApiResult<ScriptRequest> {
         let script_id = req.id.ok_or_else(|| {
@@ -46,25 +83,30 @@ impl DecodeService {
                tt!("messages.device.decode.id_missing")
            )
         })?;
+        Self::owned_script(user, script_id, conn).await?;
+
+        let map = Self::script_map(req.lang, &req.script, req.map)?;
+        Self::check_vectors(script_id, req.lang, &req.script, redis, conn).await?;
+        let txn = conn.begin().await?;
         let script = DecodeScriptEntity::find_by_id(script_id)
             .filter(DecodeScriptColumn::Owner.eq(user.id))
-            .one(conn)
+            .lock_exclusive()
+            .one(&txn)
             .await?.ok_or(
             ApiError::User(tt!("messages.device.decode.not_found_script"))
         )?;
-
-        let map = DbDecodeMap(req.map.into_iter().map(|it| CodeMapItem {
-            id: it.d_id,
-            name: it.d_name,
-            unit: it.d_unit,
-            t: it.d_type,
-        }).collect());
-        let mut model = script.into_active_model();
+        let mut model = script.clone().into_active_model();
         model.script = ActiveValue::Set(req.script);
         model.name = ActiveValue::Set(req.name);
         model.map = ActiveValue::Set(map);
+        model.lang = ActiveValue::Set(req.lang.as_ref().to_string());
+        model.codec = ActiveValue::Set(req.codec);
+        model.version = ActiveValue::Set(script.version + 1);
         model.modify_time = ActiveValue::Set(Timestamp::now());
-        let item = model.update(conn).await?;
+        let item = model.update(&txn).await?;
+        Self::insert_version(&item, user.id, req.changelog.unwrap_or_default(), &txn).await?;
+        txn.commit().await?;
+        Self::publish_script_event(item.id, Some(item.modify_time), redis).await?;
         let map = item.map.0.into_iter().map(|m| DecodeMap {
             d_name: m.name,
             d_unit: m.unit,
@@ -74,15 +116,19 @@ impl DecodeService {
         Ok(ScriptRequest {
             id: Some(item.id),
             name: item.name,
-            lang: DecodeLang::JS,
+            lang: item.lang.parse().unwrap_or(DecodeLang::JS),
             script: item.script,
             map,
+            codec: item.codec,
+            version: Some(item.version),
+            changelog: None,
         }.into())
     }
 
-    pub(crate) async fn delete_script<C: ConnectionTrait>(
+    pub(crate) async fn delete_script<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
         user: &CurrentUser,
         script: Id,
+        redis: &mut R,
         conn: &C
     ) -> ApiResult {
         let count = DevicesEntity::find()
@@ -99,8 +145,29 @@ impl DecodeService {
             ApiError::User(tt!("messages.device.decode.not_found_script"))
         )?;
         
+        let script_id = script.id;
         script.delete(conn).await?;
+        DecodeScriptVersionEntity::delete_many()
+            .filter(DecodeScriptVersionColumn::ScriptId.eq(script_id))
+            .exec(conn)
+            .await?;
+        DecodeTestVectorEntity::delete_many()
+            .filter(DecodeTestVectorColumn::ScriptId.eq(script_id))
+            .exec(conn)
+            .await?;
+        Self::publish_script_event(script_id, None, redis).await?;
+
+        Ok(())
+    }
 
+    /// Tells devices_manager to drop its compiled copy of the script.
+    pub(super) async fn publish_script_event<R: redis::aio::ConnectionLike>(
+        script: Id,
+        modify_time: Option<Timestamp>,
+        redis: &mut R,
+    ) -> ApiResult {
+        let event = serde_json::to_string(&ScriptEvent { script, modify_time })?;
+        let _: () = redis::cmd("PUBLISH").arg(ScriptEvent::TOPIC).arg(event).query_async(redis).await?;
         Ok(())
     }
 
@@ -108,27 +175,38 @@ impl DecodeService {
         user_id: Id,
         conn: &C,
     ) -> ApiResult {
+        let scripts = DecodeScriptEntity::find()
+            .select_only()
+            .column(DecodeScriptColumn::Id)
+            .filter(DecodeScriptColumn::Owner.eq(user_id))
+            .into_tuple::<Id>()
+            .all(conn)
+            .await?;
+        DecodeScriptVersionEntity::delete_many()
+            .filter(DecodeScriptVersionColumn::ScriptId.is_in(scripts.clone()))
+            .exec(conn)
+            .await?;
+        DecodeTestVectorEntity::delete_many()
+            .filter(DecodeTestVectorColumn::ScriptId.is_in(scripts))
+            .exec(conn)
+            .await?;
         DecodeScriptEntity::delete_many()
             .filter(DecodeScriptColumn::Owner.eq(user_id))
             .exec(conn)
             .await?;
         Ok(())
     }
-    pub(crate) async fn insert_script<C: ConnectionTrait>(
+    pub(crate) async fn insert_script<C: ConnectionTrait + TransactionTrait, R: redis::aio::ConnectionLike>(
         user: &CurrentUser,
         script: ScriptRequest,
+        redis: &mut R,
         conn: &C
     ) -> ApiResult<ScriptRequest> {
         if script.id.is_some() {
-            return Self::update_script(user, script, conn).await;
+            return Self::update_script(user, script, redis, conn).await;
         }
 
-        let map = DbDecodeMap(script.map.into_iter().map(|it| CodeMapItem {
-            id: it.d_id,
-            name: it.d_name,
-            unit: it.d_unit,
-            t: it.d_type,
-        }).collect());
+        let map = Self::script_map(script.lang, &script.script, script.map)?;
         let now = Timestamp::now();
         let model = DecodeScriptActiveModel {
             id: Default::default(),
@@ -137,10 +215,15 @@ impl DecodeService {
             owner: ActiveValue::Set(user.id),
             name: ActiveValue::Set(script.name),
             map: ActiveValue::Set(map),
+            codec: ActiveValue::Set(script.codec),
+            version: ActiveValue::Set(1),
             create_time: ActiveValue::Set(now),
             modify_time: ActiveValue::Set(now),
         };
-        let item = model.insert(conn).await?;
+        let txn = conn.begin().await?;
+        let item = model.insert(&txn).await?;
+        Self::insert_version(&item, user.id, script.changelog.unwrap_or_default(), &txn).await?;
+        txn.commit().await?;
         let map = item.map.0.into_iter().map(|m| DecodeMap {
             d_name: m.name,
             d_unit: m.unit,
@@ -150,9 +233,12 @@ impl DecodeService {
         Ok(ScriptRequest {
             id: Some(item.id),
             name: item.name,
-            lang: DecodeLang::JS,
+            lang: item.lang.parse().unwrap_or(DecodeLang::JS),
             script: item.script,
             map,
+            codec: item.codec,
+            version: Some(item.version),
+            changelog: None,
         }.into())
     }
     pub(crate) async fn list<C: ConnectionTrait>(
@@ -173,9 +259,12 @@ impl DecodeService {
             ScriptRequest {
                 id: Some(item.id),
                 name: item.name,
-                lang: DecodeLang::JS,
+                lang: item.lang.parse().unwrap_or(DecodeLang::JS),
                 script: item.script,
                 map,
+                codec: item.codec,
+                version: Some(item.version),
+                changelog: None,
             }
         }).collect();
 
@@ -1,4 +1,16 @@
-use common_define::decode::DecodeLang;
+use std::collections::BTreeMap;
+use base64::Engine;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use common_define::db::{CodecVariables, DeviceDataColumn, DeviceDataEntity};
+use common_define::decode::{DecodeData, DecodeLang};
+use common_define::event::{ScriptCall, ScriptJob, ScriptPayload, ScriptReply, ScriptSource};
+use common_define::Id;
+use common_define::time::Timestamp;
+use crate::CurrentUser;
+use crate::error::{ApiError, ApiResult};
+use crate::service::decode::DecodeService;
+use crate::service::device::DeviceService;
+use crate::tt;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct DecodeResponse {
@@ -9,7 +21,233 @@ pub struct DecodeResponse {
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct DecodeRequest {
     pub(crate) script: String,
+    /// Payload in hex.
     pub(crate) bytes: String,
-    pub(crate) lang: DecodeLang
+    pub(crate) lang: DecodeLang,
+    #[serde(default)]
+    pub(crate) f_port: u8,
 }
 
+/// Uplinks of a device decoded with its current decoder and a script draft.
+#[derive(serde::Deserialize)]
+pub(crate) struct CompareRequest {
+    device: Id,
+    lang: DecodeLang,
+    script: String,
+    /// FPort the payloads are decoded with, the FPort of stored uplinks is not kept.
+    #[serde(default)]
+    f_port: u8,
+    /// Number of latest uplinks, 10 when not set.
+    limit: Option<usize>,
+}
+
+/// Output of one decoder for one payload.
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub(crate) enum DecodeOutcome {
+    Ok {
+        data: Vec<DecodeData>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+        /// Bytes the JS runtime allocated for the decode.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        memory: Option<usize>,
+    },
+    Error {
+        message: String,
+    },
+    Timeout {
+        message: String,
+    },
+}
+
+impl From<ScriptReply> for DecodeOutcome {
+    fn from(value: ScriptReply) -> Self {
+        match value {
+            ScriptReply::Decoded { data, warnings, errors, memory } if errors.is_empty() => Self::Ok { data, warnings, memory },
+            ScriptReply::Decoded { errors, .. } | ScriptReply::Invalid { errors } => Self::Error { message: errors.join("; ") },
+            ScriptReply::Error { message } => Self::Error { message },
+            ScriptReply::Timeout { message } => Self::Timeout { message },
+            ScriptReply::Encoded { .. } | ScriptReply::Batch { .. } => Self::Error { message: "unexpected reply".to_string() },
+        }
+    }
+}
+
+impl DecodeOutcome {
+    pub(crate) fn data(&self) -> Option<&[DecodeData]> {
+        match self {
+            Self::Ok { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+}
+
+/// Data IDs that differ between two outputs.
+#[derive(serde::Serialize, Default, Debug, PartialEq)]
+pub(crate) struct DataDiff {
+    /// Only in the new output.
+    pub(crate) added: Vec<u32>,
+    /// Only in the old output.
+    pub(crate) removed: Vec<u32>,
+    /// In both with different values.
+    pub(crate) changed: Vec<u32>,
+}
+
+impl DataDiff {
+    pub(crate) fn new(old: &[DecodeData], new: &[DecodeData]) -> Self {
+        let old: BTreeMap<_, _> = old.iter().map(|it| (it.i, &it.v)).collect();
+        let new: BTreeMap<_, _> = new.iter().map(|it| (it.i, &it.v)).collect();
+        Self {
+            added: new.keys().filter(|i| !old.contains_key(i)).copied().collect(),
+            removed: old.keys().filter(|i| !new.contains_key(i)).copied().collect(),
+            changed: old.iter().filter(|(i, v)| new.get(i).is_some_and(|n| n != *v)).map(|(i, _)| *i).collect(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct CompareRow {
+    id: Id,
+    time: Timestamp,
+    /// Payload in hex.
+    bytes: String,
+    current: DecodeOutcome,
+    draft: DecodeOutcome,
+    /// Set when both decoders succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<DataDiff>,
+    /// The outputs differ, or one of the decoders failed.
+    changed: bool,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct CompareResponse {
+    rows: Vec<CompareRow>,
+    changed: usize,
+    /// Rows the draft failed or timed out on.
+    failed: usize,
+}
+
+impl DecodeService {
+
+    /// Decodes the latest stored uplinks of a device with its current decoder and a script draft.
+    /// Without a device script the stored data is the current output.
+    pub(crate) async fn compare<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        req: CompareRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<CompareResponse> {
+        let device = DeviceService::query_one_with_auth(user.id, req.device, conn).await?.device;
+        let rows = DeviceDataEntity::find()
+            .filter(DeviceDataColumn::DeviceId.eq(device.id))
+            .order_by_desc(DeviceDataColumn::CreateTime)
+            .limit(req.limit.unwrap_or(10).clamp(1, ScriptCall::MAX_BATCH) as u64)
+            .all(conn)
+            .await?;
+        let rows: Vec<_> = rows.into_iter()
+            .filter_map(|row| {
+                let bytes = base64::engine::general_purpose::STANDARD.decode(&row.bytes).ok()?;
+                Some((row, bytes))
+            })
+            .collect();
+        let payloads: Vec<_> = rows.iter()
+            .map(|(_, bytes)| ScriptPayload { bytes: bytes.clone(), f_port: req.f_port })
+            .collect();
+        let variables = (!device.variables.is_empty()).then(|| device.variables.clone());
+        let draft = Self::batch(ScriptSource::Draft { lang: req.lang, script: req.script }, payloads.clone(), variables.clone(), redis).await?;
+        let current = match device.script {
+            Some(script) => {
+                let source = ScriptSource::Saved { script, version: device.script_version };
+                Self::batch(source, payloads, variables, redis).await?
+            }
+            None => rows.iter()
+                .map(|(row, _)| DecodeOutcome::Ok { data: row.data.0.clone(), warnings: vec![], memory: None })
+                .collect(),
+        };
+        let mut response = CompareResponse { rows: Vec::with_capacity(rows.len()), changed: 0, failed: 0 };
+        for (((row, bytes), current), draft) in rows.into_iter().zip(current).zip(draft) {
+            let diff = match (current.data(), draft.data()) {
+                (Some(current), Some(draft)) => Some(DataDiff::new(current, draft)),
+                _ => None,
+            };
+            let changed = diff.as_ref().is_none_or(|diff| !diff.is_empty());
+            response.changed += changed as usize;
+            response.failed += draft.data().is_none() as usize;
+            response.rows.push(CompareRow {
+                id: row.id,
+                time: row.create_time,
+                bytes: hex::encode(bytes),
+                current,
+                draft,
+                diff,
+                changed,
+            });
+        }
+        Ok(response)
+    }
+
+    /// Decodes payloads in one call, a script that can not be loaded or compiled fails every payload.
+    pub(crate) async fn batch<R: redis::aio::ConnectionLike>(
+        source: ScriptSource,
+        payloads: Vec<ScriptPayload>,
+        variables: Option<CodecVariables>,
+        redis: &mut R,
+    ) -> ApiResult<Vec<DecodeOutcome>> {
+        let len = payloads.len();
+        let outcomes = match Self::call(ScriptJob::Batch { source, payloads, variables }, redis).await? {
+            ScriptReply::Batch { replies } => replies.into_iter().map(Into::into).collect(),
+            reply => {
+                let outcome = DecodeOutcome::from(reply);
+                let message = match outcome {
+                    DecodeOutcome::Error { message } | DecodeOutcome::Timeout { message } => message,
+                    DecodeOutcome::Ok { .. } => "unexpected reply".to_string(),
+                };
+                (0..len).map(|_| DecodeOutcome::Error { message: message.clone() }).collect()
+            }
+        };
+        Ok(outcomes)
+    }
+
+    /// Decodes one payload with a script draft, `result` holds the decoded data
+    /// or why the script failed.
+    pub(crate) async fn test<R: redis::aio::ConnectionLike>(
+        req: DecodeRequest,
+        redis: &mut R,
+    ) -> ApiResult<DecodeResponse> {
+        let bytes = hex::decode(req.bytes.trim())
+            .map_err(|_| ApiError::User(tt!("messages.device.decode.invalid_bytes")))?;
+        let job = ScriptJob::Test {
+            lang: req.lang,
+            script: req.script,
+            bytes,
+            f_port: req.f_port,
+        };
+        let (result, state) = match Self::call(job, redis).await? {
+            ScriptReply::Decoded { data, errors, .. } if errors.is_empty() => (serde_json::to_string(&data)?, true),
+            ScriptReply::Decoded { errors, .. } | ScriptReply::Invalid { errors } => (errors.join("; "), false),
+            ScriptReply::Error { message } | ScriptReply::Timeout { message } => (message, false),
+            ScriptReply::Encoded { .. } | ScriptReply::Batch { .. } => ("unexpected reply".to_string(), false),
+        };
+        Ok(DecodeResponse { result, state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_define::decode::{DecodeData, Value};
+    use super::DataDiff;
+
+    #[test]
+    fn test_data_diff() {
+        let old = [DecodeData::new(1, Value::Int(1)), DecodeData::new(2, Value::Int(2))];
+        let new = [DecodeData::new(2, Value::Int(3)), DecodeData::new(4, Value::Int(4))];
+        let diff = DataDiff::new(&old, &new);
+        assert_eq!(diff, DataDiff { added: vec![4], removed: vec![1], changed: vec![2] });
+        assert!(DataDiff::new(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder};
+use common_define::db::{DbDecodeData, DecodeTestVectorActiveModel, DecodeTestVectorColumn, DecodeTestVectorEntity, DecodeTestVectorModel};
+use common_define::decode::{DecodeData, DecodeLang};
+use common_define::event::{ScriptCall, ScriptPayload, ScriptSource};
+use common_define::Id;
+use common_define::time::Timestamp;
+use crate::{CurrentUser, tt};
+use crate::error::{ApiError, ApiResult};
+use crate::service::decode::DecodeService;
+use crate::service::decode::test::{DataDiff, DecodeOutcome};
+
+/// A payload with the output a script must decode it to before the script can be saved.
+#[derive(serde::Deserialize)]
+pub(crate) struct VectorRequest {
+    name: String,
+    /// Payload in hex.
+    bytes: String,
+    #[serde(default)]
+    f_port: u8,
+    /// Output of the saved script when not set.
+    expected: Option<Vec<DecodeData>>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct TestVector {
+    id: Id,
+    name: String,
+    bytes: String,
+    f_port: u8,
+    expected: Vec<DecodeData>,
+    create_time: Timestamp,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct VectorRunRequest {
+    lang: DecodeLang,
+    script: String,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct VectorResult {
+    id: Id,
+    name: String,
+    /// Every expected data point was decoded with the expected value, extra data points are allowed.
+    passed: bool,
+    actual: DecodeOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<DataDiff>,
+}
+
+impl From<DecodeTestVectorModel> for TestVector {
+    fn from(value: DecodeTestVectorModel) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            bytes: value.bytes,
+            f_port: value.f_port as u8,
+            expected: value.expected.0,
+            create_time: value.create_time,
+        }
+    }
+}
+
+impl DecodeService {
+
+    pub(crate) async fn vectors<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        conn: &C,
+    ) -> ApiResult<Vec<TestVector>> {
+        Self::owned_script(user, script, conn).await?;
+        let vectors = DecodeTestVectorEntity::find()
+            .filter(DecodeTestVectorColumn::ScriptId.eq(script))
+            .order_by_asc(DecodeTestVectorColumn::Id)
+            .all(conn)
+            .await?;
+        Ok(vectors.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn insert_vector<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        script: Id,
+        req: VectorRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<TestVector> {
+        Self::owned_script(user, script, conn).await?;
+        let count = DecodeTestVectorEntity::find()
+            .filter(DecodeTestVectorColumn::ScriptId.eq(script))
+            .count(conn)
+            .await?;
+        if count as usize >= ScriptCall::MAX_BATCH {
+            return Err(ApiError::User(tt!("messages.device.decode.vector_limit", max = ScriptCall::MAX_BATCH)));
+        }
+        let bytes = hex::decode(req.bytes.trim())
+            .map_err(|_| ApiError::User(tt!("messages.device.decode.invalid_bytes")))?;
+        let expected = match req.expected {
+            Some(expected) => expected,
+            None => {
+                let source = ScriptSource::Saved { script, version: None };
+                let payload = ScriptPayload { bytes: bytes.clone(), f_port: req.f_port };
+                match Self::batch(source, vec![payload], None, redis).await?.pop() {
+                    Some(DecodeOutcome::Ok { data, .. }) => data,
+                    Some(DecodeOutcome::Error { message } | DecodeOutcome::Timeout { message }) => {
+                        return Err(ApiError::User(tt!("messages.device.decode.vector_expected", error = message)))
+                    }
+                    None => return Err(ApiError::User(tt!("messages.device.decode.vector_expected", error = "no reply"))),
+                }
+            }
+        };
+        let model = DecodeTestVectorActiveModel {
+            id: Default::default(),
+            script_id: ActiveValue::Set(script),
+            name: ActiveValue::Set(req.name),
+            bytes: ActiveValue::Set(hex::encode(bytes)),
+            f_port: ActiveValue::Set(req.f_port as i16),
+            expected: ActiveValue::Set(DbDecodeData(expected)),
+            create_time: ActiveValue::Set(Timestamp::now()),
+        }.insert(conn).await?;
+        Ok(model.into())
+    }
+
+    pub(crate) async fn delete_vector<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        vector: Id,
+        conn: &C,
+    ) -> ApiResult {
+        Self::owned_script(user, script, conn).await?;
+        let vector = DecodeTestVectorEntity::find_by_id(vector)
+            .filter(DecodeTestVectorColumn::ScriptId.eq(script))
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.vector_not_found")))?;
+        vector.delete(conn).await?;
+        Ok(())
+    }
+
+    /// Runs the test vectors of a script with a draft of it.
+    pub(crate) async fn run_vectors<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        script: Id,
+        req: VectorRunRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<Vec<VectorResult>> {
+        Self::owned_script(user, script, conn).await?;
+        Self::test_vectors(script, req.lang, &req.script, redis, conn).await
+    }
+
+    async fn test_vectors<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        script_id: Id,
+        lang: DecodeLang,
+        script: &str,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<Vec<VectorResult>> {
+        let vectors = DecodeTestVectorEntity::find()
+            .filter(DecodeTestVectorColumn::ScriptId.eq(script_id))
+            .order_by_asc(DecodeTestVectorColumn::Id)
+            .all(conn)
+            .await?;
+        if vectors.is_empty() {
+            return Ok(vec![]);
+        }
+        let payloads = vectors.iter()
+            .map(|it| ScriptPayload {
+                bytes: hex::decode(&it.bytes).unwrap_or_default(),
+                f_port: it.f_port as u8,
+            })
+            .collect();
+        let source = ScriptSource::Draft { lang, script: script.to_string() };
+        let outcomes = Self::batch(source, payloads, None, redis).await?;
+        Ok(vectors.into_iter().zip(outcomes).map(|(vector, actual)| {
+            let diff = actual.data().map(|data| DataDiff::new(&vector.expected.0, data));
+            let passed = diff.as_ref().is_some_and(|diff| diff.removed.is_empty() && diff.changed.is_empty());
+            VectorResult {
+                id: vector.id,
+                name: vector.name,
+                passed,
+                actual,
+                diff,
+            }
+        }).collect())
+    }
+
+    /// Fails when the draft does not pass every test vector of the script.
+    pub(super) async fn check_vectors<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        script_id: Id,
+        lang: DecodeLang,
+        script: &str,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult {
+        let failed: Vec<_> = Self::test_vectors(script_id, lang, script, redis, conn).await?
+            .into_iter()
+            .filter(|it| !it.passed)
+            .map(|it| it.name)
+            .collect();
+        if !failed.is_empty() {
+            return Err(ApiError::User(tt!("messages.device.decode.vector_failed", names = failed.join(", "))));
+        }
+        Ok(())
+    }
+}
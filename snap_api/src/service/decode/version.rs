@@ -0,0 +1,334 @@
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use common_define::db::{DecodeScriptColumn, DecodeScriptEntity, DecodeScriptModel, DecodeScriptVersionActiveModel, DecodeScriptVersionColumn, DecodeScriptVersionEntity, DecodeScriptVersionModel, DeviceAuthorityColumn, DeviceAuthorityEntity, DevicesColumn, DevicesEntity};
+use common_define::decode::DecodeLang;
+use common_define::Id;
+use common_define::product::ShareType;
+use common_define::time::Timestamp;
+use crate::{CurrentUser, tt};
+use crate::error::{ApiError, ApiResult};
+use crate::service::decode::DecodeService;
+use crate::service::decode::script::DecodeMap;
+
+#[derive(serde::Serialize)]
+pub(crate) struct ScriptVersion {
+    version: i32,
+    lang: DecodeLang,
+    author: Id,
+    changelog: String,
+    create_time: Timestamp,
+    /// Only set when one version is requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    map: Option<Vec<DecodeMap>>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct RollbackRequest {
+    version: i32,
+    changelog: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DiffOp {
+    Same,
+    Add,
+    Remove,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub(crate) struct DiffLine {
+    op: DiffOp,
+    line: String,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct ScriptDiff {
+    from: i32,
+    to: i32,
+    lines: Vec<DiffLine>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct ScriptDevice {
+    id: Id,
+    name: String,
+    /// Version the device is pinned to, it follows the latest version when not set.
+    pinned: Option<i32>,
+    /// Version the device decodes with.
+    version: i32,
+}
+
+impl From<DecodeScriptVersionModel> for ScriptVersion {
+    fn from(value: DecodeScriptVersionModel) -> Self {
+        Self {
+            version: value.version,
+            lang: value.lang.parse().unwrap_or(DecodeLang::JS),
+            author: value.author,
+            changelog: value.changelog,
+            create_time: value.create_time,
+            script: None,
+            map: None,
+        }
+    }
+}
+
+/// Changed lines of each script the longest common subsequence is searched in,
+/// longer changes are shown as removed and added as a whole.
+const MAX_DIFF_LINES: usize = 1000;
+
+/// Line diff of two scripts, from the longest common subsequence of their lines.
+fn diff_lines(from: &str, to: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = from.lines().collect();
+    let b: Vec<&str> = to.lines().collect();
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let line = |op, line: &str| DiffLine { op, line: line.to_string() };
+    let mut lines = Vec::with_capacity(a.len().max(b.len()));
+    lines.extend(a[..prefix].iter().map(|it| line(DiffOp::Same, it)));
+    if a_mid.len() > MAX_DIFF_LINES || b_mid.len() > MAX_DIFF_LINES {
+        lines.extend(a_mid.iter().map(|it| line(DiffOp::Remove, it)));
+        lines.extend(b_mid.iter().map(|it| line(DiffOp::Add, it)));
+    } else {
+        diff_lcs(a_mid, b_mid, &mut lines);
+    }
+    lines.extend(a[a.len() - suffix..].iter().map(|it| line(DiffOp::Same, it)));
+    lines
+}
+
+fn diff_lcs(a: &[&str], b: &[&str], lines: &mut Vec<DiffLine>) {
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let line = |op, line: &str| DiffLine { op, line: line.to_string() };
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            lines.push(line(DiffOp::Same, a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(line(DiffOp::Remove, a[i]));
+            i += 1;
+        } else {
+            lines.push(line(DiffOp::Add, b[j]));
+            j += 1;
+        }
+    }
+    lines.extend(a[i..].iter().map(|it| line(DiffOp::Remove, it)));
+    lines.extend(b[j..].iter().map(|it| line(DiffOp::Add, it)));
+}
+
+impl DecodeService {
+
+    /// Keeps the current content of the script as its version `script.version`.
+    pub(super) async fn insert_version<C: ConnectionTrait>(
+        script: &DecodeScriptModel,
+        author: Id,
+        changelog: String,
+        conn: &C,
+    ) -> ApiResult {
+        DecodeScriptVersionActiveModel {
+            id: Default::default(),
+            script_id: ActiveValue::Set(script.id),
+            version: ActiveValue::Set(script.version),
+            script: ActiveValue::Set(script.script.clone()),
+            lang: ActiveValue::Set(script.lang.clone()),
+            map: ActiveValue::Set(script.map.clone()),
+            codec: ActiveValue::Set(script.codec.clone()),
+            author: ActiveValue::Set(author),
+            changelog: ActiveValue::Set(changelog),
+            create_time: ActiveValue::Set(script.modify_time),
+        }.insert(conn).await?;
+        Ok(())
+    }
+
+    pub(super) async fn owned_script<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        conn: &C,
+    ) -> ApiResult<DecodeScriptModel> {
+        DecodeScriptEntity::find_by_id(script)
+            .filter(DecodeScriptColumn::Owner.eq(user.id))
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.not_found_script")))
+    }
+
+    async fn find_version<C: ConnectionTrait>(
+        script: Id,
+        version: i32,
+        conn: &C,
+    ) -> ApiResult<DecodeScriptVersionModel> {
+        DecodeScriptVersionEntity::find()
+            .filter(DecodeScriptVersionColumn::ScriptId.eq(script))
+            .filter(DecodeScriptVersionColumn::Version.eq(version))
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.version_not_found", version = version)))
+    }
+
+    /// Checks that the version of a script exists, the script must be owned by the user.
+    pub(crate) async fn check_version<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        version: i32,
+        conn: &C,
+    ) -> ApiResult {
+        Self::owned_script(user, script, conn).await?;
+        Self::find_version(script, version, conn).await?;
+        Ok(())
+    }
+
+    /// Versions of a script, newest first, without their content.
+    pub(crate) async fn versions<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        conn: &C,
+    ) -> ApiResult<Vec<ScriptVersion>> {
+        Self::owned_script(user, script, conn).await?;
+        let versions = DecodeScriptVersionEntity::find()
+            .filter(DecodeScriptVersionColumn::ScriptId.eq(script))
+            .order_by_desc(DecodeScriptVersionColumn::Version)
+            .all(conn)
+            .await?;
+        Ok(versions.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn version<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        version: i32,
+        conn: &C,
+    ) -> ApiResult<ScriptVersion> {
+        Self::owned_script(user, script, conn).await?;
+        let model = Self::find_version(script, version, conn).await?;
+        let code = model.script.clone();
+        let map = model.map.0.iter().map(|m| DecodeMap {
+            d_name: m.name.clone(),
+            d_unit: m.unit.clone(),
+            d_type: m.t,
+            d_id: m.id,
+        }).collect();
+        let mut version: ScriptVersion = model.into();
+        version.script = Some(code);
+        version.map = Some(map);
+        Ok(version)
+    }
+
+    /// Makes an old version the latest again, as a new version so the history is kept.
+    /// The old version must pass the current test vectors. Both writes happen in one transaction with the script row locked.
+    pub(crate) async fn rollback<C: ConnectionTrait + TransactionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        script: Id,
+        req: RollbackRequest,
+        redis: &mut R,
+        conn: &C,
+    ) -> ApiResult<ScriptVersion> {
+        Self::owned_script(user, script, conn).await?;
+        let old = Self::find_version(script, req.version, conn).await?;
+        // vectors may have been added since the old version was saved, it must pass them like any update
+        Self::check_vectors(script, old.lang.parse().unwrap_or(DecodeLang::JS), &old.script, redis, conn).await?;
+        let txn = conn.begin().await?;
+        let current = DecodeScriptEntity::find_by_id(script)
+            .filter(DecodeScriptColumn::Owner.eq(user.id))
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.not_found_script")))?;
+        let latest = current.version + 1;
+        let mut model = current.into_active_model();
+        model.script = ActiveValue::Set(old.script);
+        model.lang = ActiveValue::Set(old.lang);
+        model.map = ActiveValue::Set(old.map);
+        model.codec = ActiveValue::Set(old.codec);
+        model.version = ActiveValue::Set(latest);
+        model.modify_time = ActiveValue::Set(Timestamp::now());
+        let item = model.update(&txn).await?;
+        let changelog = req.changelog.unwrap_or_else(|| format!("Rollback to version {}", req.version));
+        Self::insert_version(&item, user.id, changelog, &txn).await?;
+        txn.commit().await?;
+        Self::publish_script_event(item.id, Some(item.modify_time), redis).await?;
+        Self::version(user, script, latest, conn).await
+    }
+
+    pub(crate) async fn diff<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        from: i32,
+        to: i32,
+        conn: &C,
+    ) -> ApiResult<ScriptDiff> {
+        Self::owned_script(user, script, conn).await?;
+        let old = Self::find_version(script, from, conn).await?;
+        let new = Self::find_version(script, to, conn).await?;
+        Ok(ScriptDiff {
+            from,
+            to,
+            lines: diff_lines(&old.script, &new.script),
+        })
+    }
+
+    /// Devices of the user decoding with the script and the version each one runs.
+    pub(crate) async fn script_devices<C: ConnectionTrait>(
+        user: &CurrentUser,
+        script: Id,
+        conn: &C,
+    ) -> ApiResult<Vec<ScriptDevice>> {
+        let current = Self::owned_script(user, script, conn).await?;
+        let devices = DeviceAuthorityEntity::find()
+            .filter(DeviceAuthorityColumn::ShareId.eq(user.id).and(DeviceAuthorityColumn::ShareType.eq(ShareType::User.as_ref())))
+            .filter(DevicesColumn::Script.eq(script))
+            .find_also_related(DevicesEntity)
+            .all(conn)
+            .await?;
+        Ok(devices.into_iter()
+            .filter_map(|(_, device)| device)
+            .map(|device| ScriptDevice {
+                id: device.id,
+                name: device.name,
+                pinned: device.script_version,
+                version: device.script_version.unwrap_or(current.version),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_lines, DiffOp, MAX_DIFF_LINES};
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = diff_lines("a\nb\nc", "a\nc\nd");
+        let ops: Vec<_> = lines.iter().map(|it| (&it.op, it.line.as_str())).collect();
+        assert_eq!(ops, vec![
+            (&DiffOp::Same, "a"),
+            (&DiffOp::Remove, "b"),
+            (&DiffOp::Same, "c"),
+            (&DiffOp::Add, "d"),
+        ]);
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn test_diff_lines_long() {
+        let old: Vec<String> = (0..MAX_DIFF_LINES + 10).map(|i| format!("old {i}")).collect();
+        let new: Vec<String> = (0..MAX_DIFF_LINES + 10).map(|i| format!("new {i}")).collect();
+        let lines = diff_lines(&format!("head\n{}\ntail", old.join("\n")), &format!("head\n{}\ntail", new.join("\n")));
+        assert_eq!(lines.len(), 2 * (MAX_DIFF_LINES + 10) + 2);
+        assert_eq!(lines[0].op, DiffOp::Same);
+        assert_eq!(lines[1].op, DiffOp::Remove);
+        assert_eq!(lines[MAX_DIFF_LINES + 11].op, DiffOp::Add);
+        assert_eq!(lines.last().unwrap().line, "tail");
+    }
+}
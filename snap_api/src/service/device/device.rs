@@ -1,17 +1,17 @@
 use std::collections::HashMap;
 use derive_new::new;
-use redis::AsyncCommands;
 use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, QueryFilter, Statement};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::{debug, instrument};
-use common_define::db::{DecodeScriptColumn, DecodeScriptEntity, DeviceAuthorityActiveModel, DeviceAuthorityColumn, DeviceAuthorityEntity, DeviceAuthorityModel, DeviceDataEntity, DeviceDataModel, DeviceFunctionColumn, DeviceFunctionEntity, DeviceFunctionModel, DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraGateModel, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DeviceLoraNodeModel, DevicesActiveModel, DevicesColumn, DevicesEntity, DevicesModel, Eui, Key, LoRaAddr, SnapDeviceColumn, SnapDeviceDataNameColumn, SnapDeviceDataNameEntity, SnapDeviceEntity, SnapDeviceModel};
+use common_define::db::{CodecVariables, DecodeScriptColumn, DecodeScriptEntity, DeviceAuthorityActiveModel, DeviceAuthorityColumn, DeviceAuthorityEntity, DeviceAuthorityModel, DeviceDataEntity, DeviceDataModel, DeviceFunctionColumn, DeviceFunctionEntity, DeviceFunctionModel, DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraGateModel, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DeviceLoraNodeModel, DevicesActiveModel, DevicesColumn, DevicesEntity, DevicesModel, Eui, FPortRoutes, GatewayAllowList, Key, LoRaAddr, SnapDeviceColumn, SnapDeviceDataNameColumn, SnapDeviceDataNameEntity, SnapDeviceEntity, SnapDeviceModel};
 use common_define::{last_device_data_key, Id};
+use common_define::codec::PayloadCodec;
 use common_define::decode::LastDecodeData;
-use common_define::lora::{LoRaJoinType, LoRaRegion};
+use common_define::lora::{LoRaJoinType, LoRaRegion, PayloadEncryption};
 use common_define::product::{DeviceType, ProductType, ShareType};
 use common_define::time::Timestamp;
-use device_info::lorawan::NodeInfo;
+use device_info::lorawan::{GatewayInfo, NodeInfo};
 
 
 use crate::error::ApiError;
@@ -20,9 +20,10 @@ use crate::{error::ApiResult};
 use crate::cache::DeviceCache;
 
 use crate::service::data::DataService;
+use crate::service::decode::DecodeService;
 use crate::service::data::query::{DataDeviceOneResponse, TimeDate};
 use crate::service::device::group::{DeviceGroupResp, DeviceGroupService};
-use crate::service::lorawan::{LoRaGateService, LoRaNodeService, ReqLoraGateway, ReqLoraNode};
+use crate::service::lorawan::{DeviceProfileService, LoRaGateService, LoRaNodeService, ReqLoraGateway, ReqLoraNode};
 use crate::service::mqtt::{MQTTService, ReqMQTT};
 use crate::service::snap::{ReqSnap, SnapDeviceService, SnapJoinParameter};
 use super::{DeviceService};
@@ -192,6 +193,12 @@ pub struct DeviceModify {
     pub description: Option<String>,
     pub script: Option<Id>,
     pub reset_script: Option<bool>,
+    /// Pins a version of the decode script, changing the script drops the pin.
+    pub script_version: Option<i32>,
+    /// Makes the device follow the latest version of its decode script.
+    pub reset_script_version: Option<bool>,
+    pub encoder: Option<Id>,
+    pub reset_encoder: Option<bool>,
     pub region: Option<LoRaRegion>,
     pub join_type: Option<LoRaJoinType>,
     pub app_eui: Option<Eui>,
@@ -200,6 +207,18 @@ pub struct DeviceModify {
     pub app_skey: Option<Key>,
     pub class_c: Option<bool>,
     pub product_id: Option<Id>,
+    pub profile: Option<Id>,
+    pub reset_profile: Option<bool>,
+    pub fport_routes: Option<FPortRoutes>,
+    pub payload_encryption: Option<PayloadEncryption>,
+    pub app_kek: Option<Key>,
+    pub allow_list: Option<GatewayAllowList>,
+    pub hmac_key: Option<Key>,
+    pub reset_hmac_key: Option<bool>,
+    pub variables: Option<CodecVariables>,
+    /// Built-in payload codec, used when the device has no decode or encoder script.
+    pub codec: Option<PayloadCodec>,
+    pub reset_codec: Option<bool>,
 }
 
 impl From<DeviceLoraNodeModel> for LoRaNodeDeviceInfo {
@@ -246,6 +265,9 @@ pub(crate) struct LoRaGateDeviceInfo {
     pub(crate) device_id: Id,
     pub(crate) region: LoRaRegion,
     pub(crate) eui: Eui,
+    pub(crate) allow_list: GatewayAllowList,
+    /// Packets must carry the HMAC signing extension.
+    pub(crate) hmac: bool,
 }
 impl From<DeviceLoraGateModel> for LoRaGateDeviceInfo {
     fn from(value: DeviceLoraGateModel) -> Self {
@@ -253,6 +275,8 @@ impl From<DeviceLoraGateModel> for LoRaGateDeviceInfo {
             device_id: value.device_id,
             region: value.region,
             eui: value.eui,
+            allow_list: value.allow_list,
+            hmac: value.hmac_key.is_some(),
         }
     }
 }
@@ -292,6 +316,16 @@ pub(crate) struct DeviceCreate {
     pub(crate) username: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) profile: Option<Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) payload_encryption: Option<PayloadEncryption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) app_kek: Option<Key>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) allow_list: Option<GatewayAllowList>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) hmac_key: Option<Key>,
 }
 #[derive(Deserialize, Debug)]
 pub(crate) struct ExtraParm {
@@ -381,7 +415,10 @@ impl DeviceService {
         Self::delete_lora_gateway(lora_gate.as_slice(), conn).await?;
         
         // delete data
-        DataService::delete_by_device_id_array(can_delete.as_slice(), conn).await?;
+        DataService::delete_by_device_id_array(can_delete.as_slice()).await?;
+        DataService::delete_device_retention_policies(can_delete.as_slice(), conn).await?;
+        DataService::delete_device_virtual_points(can_delete.as_slice(), conn).await?;
+        DataService::delete_device_calibrations(can_delete.as_slice(), conn).await?;
         
         // delete device
         Self::delete_list(can_delete.as_slice(), conn).await?;
@@ -439,19 +476,21 @@ impl DeviceService {
             match device.device_type {
                 DeviceType::Snap => {
                     SnapDeviceService::delete(device_id, redis, conn).await?;
-                    DataService::delete_by_device_id(device.id, conn).await?;
                 }
                 DeviceType::MQTT => {
                     MQTTService::delete(device_id, conn).await?;
                 }
                 DeviceType::LoRaNode => {
                     LoRaNodeService::delete_node(device.id, redis, conn).await?;
-                    DataService::delete_by_device_id(device.id, conn).await?;
                 }
                 DeviceType::LoRaGate => {
                     LoRaGateService::delete_gateway(device.id, redis, conn).await?;
                 }
             }
+            DataService::delete_by_device_id(device.id).await?;
+            DataService::delete_device_retention_policies(&[device.id], conn).await?;
+            DataService::delete_device_virtual_points(&[device.id], conn).await?;
+            DataService::delete_device_calibrations(&[device.id], conn).await?;
             device.delete(conn).await?;
             DeviceAuthorityEntity::delete_many()
                 .filter(DeviceAuthorityColumn::DeviceId.eq(device_id))
@@ -524,16 +563,22 @@ impl DeviceService {
                     region: req.region.ok_or(ApiError::User(
                         tt!("messages.device.common.region_missing")
                     ))?,
+                    allow_list: req.allow_list,
+                    hmac_key: req.hmac_key,
                 };
                 LoRaGateService::create(req_g, user, redis, conn).await?.device_id
             }
             DeviceType::LoRaNode => {
+                let profile = match req.profile {
+                    Some(profile) => Some(DeviceProfileService::get(user, profile, conn).await?),
+                    None => None
+                };
                 let req_g = ReqLoraNode {
                     eui,
                     device: req.device,
                     name: req.name.clone(),
                     description: req.description.unwrap_or(req.name),
-                    region: req.region.ok_or(ApiError::User(
+                    region: req.region.or(profile.as_ref().map(|profile| profile.region)).ok_or(ApiError::User(
                         tt!("messages.device.common.region_missing")
                     ))?,
                     join_type: req.join_type.ok_or(ApiError::User(
@@ -546,6 +591,9 @@ impl DeviceService {
                     blue_name: req.blue_name,
                     blue_parm: req.blue_parm,
                     extra_parm: req.extra_parm,
+                    profile,
+                    payload_encryption: req.payload_encryption,
+                    app_kek: req.app_kek,
                 };
                 LoRaNodeService::create_node(req_g, user, redis, conn).await?
             }
@@ -592,11 +640,15 @@ impl DeviceService {
             enable: ActiveValue::Set(true),
             online: ActiveValue::Set(false),
             script: ActiveValue::Set(None),
+            script_version: ActiveValue::Set(None),
+            encoder: ActiveValue::Set(None),
+            codec: ActiveValue::Set(None),
             data_id: Default::default(),
             product_id: Default::default(),
             device_type: ActiveValue::Set(device_type),
             active_time: ActiveValue::Set(None),
             create_time: ActiveValue::Set(Timestamp::now()),
+            variables: Default::default(),
         };
         let device = device.insert(conn).await?;
         Ok(device)
@@ -726,11 +778,20 @@ impl DeviceService {
                 .map(|it| (it.id, it))
                 .collect()
         };
-        let last_data: Vec<Option<LastDecodeData>> = conn.mget(data_keys).await?;
+        let virtual_maps = DataService::virtual_point_maps(devices, &state.db).await?;
+        let mut pipe = redis::pipe();
+        for key in data_keys {
+            pipe.get(key);
+        }
+        let last_data: Vec<Option<LastDecodeData>> = pipe.query_async(&mut conn).await?;
         let lang = get_lang().as_static_str();
         for ((device_id, _key, script, data_id), last_date) in ids.into_iter().zip(last_data) {
             let mut resp = vec![];
-            if let Some(last_data) = last_date {
+            let mut virtual_resp = vec![];
+            if let Some(mut last_data) = last_date {
+                if let Some(points) = virtual_maps.get(&device_id) {
+                    virtual_resp = DataService::take_virtual_data(points, &mut last_data);
+                }
                 match script {
                     Some(script_id) => {
                         if let Some(sc) = script_map.get(&script_id) {
@@ -743,6 +804,9 @@ impl DeviceService {
                                         name: m.name.to_string(),
                                         data_id: m.id,
                                         unit: m.unit.to_string(),
+                                        data_type: Some(value.data_type()),
+                                        source_unit: None,
+                                        precision: None,
                                         data: TimeDate {
                                             time: last_data.t,
                                             data: value.clone()
@@ -766,6 +830,9 @@ impl DeviceService {
                                                 name: m.name.to_string(),
                                                 data_id: m.id,
                                                 unit: m.unit.to_string(),
+                                                data_type: Some(value.data_type()),
+                                                source_unit: None,
+                                                precision: None,
                                                 data: TimeDate {
                                                     time: last_data.t,
                                                     data: value.clone()
@@ -783,6 +850,9 @@ impl DeviceService {
                                         name: data_name.name.to_string(),
                                         data_id: d.i,
                                         unit: data_name.unit.to_string(),
+                                        data_type: Some(d.v.data_type()),
+                                        source_unit: None,
+                                        precision: None,
                                         data: TimeDate {
                                             time: last_data.t,
                                             data: d.v
@@ -795,6 +865,7 @@ impl DeviceService {
                     }
                 }
             }
+            resp.extend(virtual_resp);
             map.insert(device_id, resp);
         }
         Ok(map)
@@ -861,6 +932,7 @@ impl DeviceService {
     }
 
     pub(crate) async fn update_info<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
         device_with_auth: DeviceWithAuth,
         info: DeviceModify,
         redis: &mut R,
@@ -889,14 +961,107 @@ impl DeviceService {
             device_active.script = ActiveValue::Set(None);
             NodeInfo::reset_by_eui(device_with_auth.device.eui, NodeInfo::script(), redis).await?;
         }
+        let script = match &device_active.script {
+            ActiveValue::Set(script) => *script,
+            _ => device_with_auth.device.script,
+        };
+        let script_version = match info.script_version {
+            Some(version) => {
+                let script = script.ok_or_else(|| ApiError::User(tt!("messages.device.decode.script_missing")))?;
+                DecodeService::check_version(user, script, version, conn).await?;
+                Some(Some(version))
+            }
+            None if device_active.script.is_set() || info.reset_script_version.unwrap_or(false) => Some(None),
+            None => None,
+        };
+        if let Some(version) = script_version {
+            let eui = device_with_auth.device.eui;
+            match (device_with_auth.device.device_type, version) {
+                (DeviceType::LoRaNode, Some(version)) => NodeInfo::update_by_eui(eui, NodeInfo::script_version(), version, redis).await?,
+                (DeviceType::LoRaNode, None) => NodeInfo::reset_by_eui(eui, NodeInfo::script_version(), redis).await?,
+                (DeviceType::Snap, Some(version)) => device_info::snap::SnapDeviceInfo::update_by_eui(eui, device_info::snap::SnapDeviceInfo::script_version(), version, redis).await?,
+                (DeviceType::Snap, None) => device_info::snap::SnapDeviceInfo::reset_by_eui(eui, device_info::snap::SnapDeviceInfo::script_version(), redis).await?,
+                _ => {}
+            }
+            device_active.script_version = ActiveValue::Set(version);
+        }
+        if let Some(encoder) = info.encoder {
+            let script = DecodeScriptEntity::find_by_id(encoder)
+                .one(conn)
+                .await?;
+            if script.is_none() {
+                return Err(ApiError::User("invalid script".into()));
+            }
+            device_active.encoder = ActiveValue::Set(Some(encoder));
+        }
+        if info.reset_encoder.unwrap_or(false) {
+            device_active.encoder = ActiveValue::Set(None);
+        }
+        if let Some(variables) = info.variables {
+            let eui = device_with_auth.device.eui;
+            match device_with_auth.device.device_type {
+                DeviceType::LoRaNode => NodeInfo::update_by_eui(eui, NodeInfo::variables(), &variables, redis).await?,
+                DeviceType::Snap => device_info::snap::SnapDeviceInfo::update_by_eui(eui, device_info::snap::SnapDeviceInfo::variables(), &variables, redis).await?,
+                _ => {}
+            }
+            device_active.variables = ActiveValue::Set(variables);
+        }
+        if let Some(codec) = info.codec {
+            let eui = device_with_auth.device.eui;
+            match device_with_auth.device.device_type {
+                DeviceType::LoRaNode => NodeInfo::update_by_eui(eui, NodeInfo::codec(), codec, redis).await?,
+                DeviceType::Snap => device_info::snap::SnapDeviceInfo::update_by_eui(eui, device_info::snap::SnapDeviceInfo::codec(), codec, redis).await?,
+                _ => {}
+            }
+            device_active.codec = ActiveValue::Set(Some(codec));
+        }
+        if info.reset_codec.unwrap_or(false) {
+            let eui = device_with_auth.device.eui;
+            match device_with_auth.device.device_type {
+                DeviceType::LoRaNode => NodeInfo::reset_by_eui(eui, NodeInfo::codec(), redis).await?,
+                DeviceType::Snap => device_info::snap::SnapDeviceInfo::reset_by_eui(eui, device_info::snap::SnapDeviceInfo::codec(), redis).await?,
+                _ => {}
+            }
+            device_active.codec = ActiveValue::Set(None);
+        }
         if device_with_auth.device.device_type == DeviceType::LoRaNode {
             let eui = device_with_auth.device.eui;
-            let node = device_with_auth.device.find_related(DeviceLoraNodeEntity)
+            let mut node = device_with_auth.device.find_related(DeviceLoraNodeEntity)
                 .one(conn)
                 .await?
                 .ok_or_else(|| ApiError::User("invalid device".into()))?;
 
-            let mut node = node.into_active_model();
+            let mut profile = node.load_profile(conn).await?;
+            let mut sync_profile = profile.is_some() && device_active.script.is_set();
+            if info.reset_profile.unwrap_or(false) && node.profile.is_some() {
+                // the node keeps the settings it had through the profile
+                if let Some(profile) = profile.take() {
+                    node = node.apply_profile(&profile);
+                }
+                node.profile = None;
+                node.overrides = Default::default();
+                sync_profile = true;
+            }
+            if let Some(profile_id) = info.profile {
+                if node.profile != Some(profile_id) {
+                    profile = Some(DeviceProfileService::get(user, profile_id, conn).await?);
+                    node.profile = Some(profile_id);
+                    node.overrides = Default::default();
+                    sync_profile = true;
+                }
+            }
+            if let Some(routes) = info.fport_routes {
+                DeviceProfileService::check_fport_routes(user, &routes, conn).await?;
+                node.fport_routes = routes;
+                sync_profile = true;
+            }
+            let mut overrides = node.overrides.clone();
+            let (encryption, app_kek, join_type) = (node.payload_encryption, node.app_kek, node.join_type);
+            let mut node = if sync_profile {
+                node.into_active_model().reset_all()
+            } else {
+                node.into_active_model()
+            };
 
             if let Some(name) = info.name {
                 device_active.name = ActiveValue::Set(name);
@@ -906,12 +1071,17 @@ impl DeviceService {
             }
             if let Some(region) = info.region {
                 node.region = ActiveValue::Set(region);
+                overrides.region = Some(region);
                 NodeInfo::update_by_eui(eui, NodeInfo::region(), region, redis).await?;
             }
             if let Some(class_c) = info.class_c {
                 node.class_c = ActiveValue::Set(class_c);
+                overrides.class_c = Some(class_c);
                 NodeInfo::update_by_eui(eui, NodeInfo::class_c(), class_c, redis).await?;
             }
+            if profile.is_some() && (info.region.is_some() || info.class_c.is_some()) {
+                node.overrides = ActiveValue::Set(overrides);
+            }
             if let Some(join_type) = info.join_type {
                 NodeInfo::update_by_eui(eui, NodeInfo::join_type(), join_type, redis).await?;
                 node.join_type = ActiveValue::Set(join_type);
@@ -929,12 +1099,81 @@ impl DeviceService {
                 NodeInfo::update_by_eui(eui, NodeInfo::nwk_skey(), &nwk_skey, redis).await?;
                 node.nwk_skey = ActiveValue::Set(nwk_skey);
             }
+            if let Some(app_kek) = info.app_kek {
+                NodeInfo::update_by_eui(eui, NodeInfo::app_kek(), app_kek, redis).await?;
+                node.app_kek = ActiveValue::Set(Some(app_kek));
+            }
+            let payload_encryption = info.payload_encryption.unwrap_or(encryption);
+            if payload_encryption != encryption {
+                match payload_encryption {
+                    PayloadEncryption::Application => {
+                        if info.app_kek.or(app_kek).is_none() {
+                            return Err(ApiError::User(
+                                tt!("messages.device.lora.app_kek_missing")
+                            ));
+                        }
+                        // the network server drops the AppSKey it holds
+                        NodeInfo::update_by_eui(eui, NodeInfo::app_skey(), Key::nil(), redis).await?;
+                        node.app_skey = ActiveValue::Set(Key::nil());
+                    }
+                    PayloadEncryption::Server => {
+                        if info.join_type.unwrap_or(join_type) == LoRaJoinType::ABP && info.app_skey.is_none() {
+                            return Err(ApiError::User(
+                                tt!("messages.device.lora.app_skey_missing")
+                            ));
+                        }
+                    }
+                }
+                NodeInfo::update_by_eui(eui, NodeInfo::payload_encryption(), payload_encryption, redis).await?;
+                node.payload_encryption = ActiveValue::Set(payload_encryption);
+            }
             if let Some(app_skey) = info.app_skey {
-                NodeInfo::update_by_eui(eui, NodeInfo::app_key(), &app_skey, redis).await?;
+                if payload_encryption == PayloadEncryption::Application {
+                    return Err(ApiError::User(
+                        tt!("messages.device.lora.app_skey_not_kept")
+                    ));
+                }
+                NodeInfo::update_by_eui(eui, NodeInfo::app_skey(), &app_skey, redis).await?;
                 node.app_skey = ActiveValue::Set(app_skey);
             }
             if node.is_changed() {
-                node.update(conn).await?;
+                let node = node.update(conn).await?;
+                if sync_profile {
+                    let mut device = device_with_auth.device.clone();
+                    if let ActiveValue::Set(script) = &device_active.script {
+                        device.script = *script;
+                    }
+                    if let ActiveValue::Set(version) = &device_active.script_version {
+                        device.script_version = *version;
+                    }
+                    NodeInfo::sync_profile(node, &device, profile.as_ref(), redis).await?;
+                }
+            }
+        }
+        if device_with_auth.device.device_type == DeviceType::LoRaGate {
+            let eui = device_with_auth.device.eui;
+            let mut gate = device_with_auth.device.find_related(DeviceLoraGateEntity)
+                .one(conn)
+                .await?
+                .ok_or_else(|| ApiError::User("invalid device".into()))?
+                .into_active_model();
+            if let Some(allow_list) = info.allow_list {
+                if allow_list.is_empty() {
+                    GatewayInfo::reset(eui, GatewayInfo::allow_list(), redis).await?;
+                } else {
+                    GatewayInfo::update(eui, GatewayInfo::allow_list(), &allow_list, redis).await?;
+                }
+                gate.allow_list = ActiveValue::Set(allow_list);
+            }
+            if let Some(hmac_key) = info.hmac_key {
+                GatewayInfo::update(eui, GatewayInfo::hmac_key(), hmac_key, redis).await?;
+                gate.hmac_key = ActiveValue::Set(Some(hmac_key));
+            } else if info.reset_hmac_key.unwrap_or(false) {
+                GatewayInfo::reset(eui, GatewayInfo::hmac_key(), redis).await?;
+                gate.hmac_key = ActiveValue::Set(None);
+            }
+            if gate.is_changed() {
+                gate.update(conn).await?;
             }
         }
         if device_active.is_changed() {
@@ -13,6 +13,7 @@ use common_define::time::Timestamp;
 use device_info::lorawan::{GatewayInfo, NodeInfo};
 use crate::cache::{DeviceGroupCache, DeviceGroupCacheItem};
 use crate::service::device::DeviceService;
+use crate::service::data::DataService;
 use crate::service::device::order::DeviceOrderService;
 use crate::service::snap::SnapDeviceService;
 use super::device::{DeviceResp };
@@ -439,6 +440,12 @@ impl DeviceGroupService {
         let mut snap_device: HashMap<_, _> = snap_device.into_iter().map(|item| (item.device_id, item)).collect();
 
         let mut last_data = DeviceService::query_last_data(order_device.as_slice(), state).await?;
+        let displays = DataService::data_displays(req_user, &device_order_save, conn).await?;
+        for (device, data) in last_data.iter_mut() {
+            if let Some(display) = displays.get(device) {
+                data.iter_mut().for_each(|it| display.apply_one(it));
+            }
+        }
 
         let mut order_device: HashMap<_, _> = order_device.into_iter().map(|item| (item.id, item)).collect();
         let mut v = Vec::with_capacity(order_device.len());
@@ -9,7 +9,7 @@ use sea_orm::{ConnectionTrait, EntityTrait};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 use uuid::Uuid;
-use common_define::db::{DeviceLoraGateActiveModel, DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraGateModel, Eui};
+use common_define::db::{DeviceLoraGateActiveModel, DeviceLoraGateColumn, DeviceLoraGateEntity, DeviceLoraGateModel, Eui, GatewayAllowList, Key};
 use common_define::Id;
 use common_define::lora::LoRaRegion;
 use common_define::product::DeviceType;
@@ -42,6 +42,8 @@ pub(crate) struct ReqLoraGateway {
     pub(crate) description: String,
     pub(crate) eui: Eui,
     pub(crate) region: LoRaRegion,
+    pub(crate) allow_list: Option<GatewayAllowList>,
+    pub(crate) hmac_key: Option<Key>,
 }
 
 impl LoRaGateService {
@@ -87,10 +89,13 @@ impl LoRaGateService {
             device_id: ActiveValue::Set(device.id),
             region: ActiveValue::Set(req.region),
             eui: ActiveValue::Set(req.eui),
+            allow_list: ActiveValue::Set(req.allow_list.unwrap_or_default()),
+            hmac_key: ActiveValue::Set(req.hmac_key),
         };
         let gate = gate.insert(conn).await?;
 
         GatewayInfo::new(device.id, 0, 0, Timestamp::now(), None, None)
+            .with_source(&gate)
             .register(eui, redis)
             .await?;
         Ok(gate)
@@ -0,0 +1,49 @@
+use sea_orm::{DatabaseConnection, TransactionTrait};
+use tracing::info;
+use common_define::db::rewrap_keys;
+use device_info::lorawan::{GatewayInfo, NodeInfo};
+use device_info::snap::SnapDeviceInfo;
+use crate::error::ApiResult;
+
+pub(crate) struct KeyService;
+
+impl KeyService {
+    /// Writes every stored LoRaWAN and snap key, including the application KEKs, again with the current key-encryption key.
+    /// Keys wrapped with `old_kek` are unwrapped first, plain keys are wrapped as they are.
+    ///
+    /// The rows are written in one transaction and rows already wrapped with the current key are skipped,
+    /// so a failed or repeated run leaves no key wrapped with another key-encryption key.
+    /// Redis is written after the commit from every row, which also repairs it after a failed run.
+    pub(crate) async fn rewrap<R: redis::aio::ConnectionLike>(
+        old_kek: Option<[u8; 16]>,
+        conn: &DatabaseConnection,
+        redis: &mut R,
+    ) -> ApiResult<usize> {
+        let txn = conn.begin().await?;
+        let keys = rewrap_keys(old_kek, &txn).await?;
+        txn.commit().await?;
+        info!(
+            nodes = keys.nodes.len(),
+            snaps = keys.snaps.len(),
+            gates = keys.gates.len(),
+            "rewrap {} rows",
+            keys.changed
+        );
+
+        for (eui, [app_key, nwk_skey, app_skey], app_kek) in keys.nodes {
+            NodeInfo::update_by_eui(eui, NodeInfo::app_key(), app_key, redis).await?;
+            NodeInfo::update_by_eui(eui, NodeInfo::nwk_skey(), nwk_skey, redis).await?;
+            NodeInfo::update_by_eui(eui, NodeInfo::app_skey(), app_skey, redis).await?;
+            if let Some(app_kek) = app_kek {
+                NodeInfo::update_by_eui(eui, NodeInfo::app_kek(), app_kek, redis).await?;
+            }
+        }
+        for (eui, key) in keys.snaps {
+            SnapDeviceInfo::update_by_eui(eui, SnapDeviceInfo::key(), key, redis).await?;
+        }
+        for (eui, hmac_key) in keys.gates {
+            GatewayInfo::update(eui, GatewayInfo::hmac_key(), hmac_key, redis).await?;
+        }
+        Ok(keys.changed)
+    }
+}
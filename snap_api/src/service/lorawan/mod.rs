@@ -1,5 +1,9 @@
 mod gateway;
+mod key;
 mod node;
+mod profile;
 
 pub(crate) use gateway::*;
+pub(crate) use key::*;
 pub(crate) use node::*;
+pub(crate) use profile::*;
@@ -3,9 +3,9 @@ use crate::error::{ApiError, ApiResult};
 use crate::{CurrentUser, get_current_user, tt};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
-use common_define::db::{DeviceLoraNodeActiveModel, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DeviceLoraNodeModel, Eui, Key, LoRaAddr};
+use common_define::db::{DeviceLoraNodeActiveModel, DeviceLoraNodeColumn, DeviceLoraNodeEntity, DeviceLoraNodeModel, DeviceProfileModel, Eui, Key, LoRaAddr};
 use common_define::Id;
-use common_define::lora::{LoRaJoinType, LoRaRegion};
+use common_define::lora::{LoRaJoinType, LoRaRegion, PayloadEncryption};
 use common_define::product::{DeviceType, ProductType};
 use device_info::lorawan::NodeInfo;
 use tracing::warn;
@@ -66,6 +66,51 @@ pub(crate) struct LoraNodeDeviceDefault {
     pub(crate) c_retry: i32,
     pub(crate) dutycyle: i32,
     pub(crate) product_type: ProductType,
+    pub(crate) profile: Option<DeviceProfileModel>,
+    pub(crate) payload_encryption: PayloadEncryption,
+    pub(crate) app_kek: Option<Key>,
+}
+
+/// Default RX2 frequency (100 Hz) and data rate of a region.
+pub(crate) fn default_rx2(region: LoRaRegion) -> (i32, i16) {
+    match region {
+        LoRaRegion::EU868 => {
+            (8695250, 0_i16)
+        }
+        LoRaRegion::US915 => {
+            (9233000, 8)
+        }
+        LoRaRegion::CN779 => {
+            (7860000, 0)
+        }
+        LoRaRegion::EU433 => {
+            (4346650, 0)
+        }
+        LoRaRegion::AU915 => {
+            (9233000, 8)
+        }
+        LoRaRegion::CN470 => {
+            (5053000, 0)
+        }
+        LoRaRegion::AS923_1 => {
+            (9232000, 2)
+        }
+        LoRaRegion::AS923_2 => {
+            (9232000, 2)
+        }
+        LoRaRegion::AS923_3 => {
+            (9232000, 2)
+        }
+        LoRaRegion::KR920 => {
+            (9219000, 0)
+        }
+        LoRaRegion::IN865 => {
+            (8665500, 2)
+        }
+        LoRaRegion::RU864 => {
+            (8691000, 0)
+        }
+    }
 }
 
 impl LoraNodeDeviceDefault {
@@ -107,6 +152,9 @@ impl LoraNodeDeviceDefault {
             c_retry: blue_param.retry,
             dutycyle: blue_param.duty_cycle,
             product_type: ProductType::Monitor,
+            profile: req.profile,
+            payload_encryption: req.payload_encryption.unwrap_or_default(),
+            app_kek: req.app_kek,
         };
         let join_type = blue_param.join_type.or(blue_param.jion_type)
             .ok_or(ApiError::User(
@@ -135,44 +183,7 @@ impl LoraNodeDeviceDefault {
         req: ReqLoraNode,
         conn: &C
     ) -> ApiResult<Self> {
-        let (rx2_freq, rx2_dr) = match req.region {
-            LoRaRegion::EU868 => {
-                (8695250, 0_i16)
-            }
-            LoRaRegion::US915 => {
-                (9233000, 8)
-            }
-            LoRaRegion::CN779 => {
-                (7860000, 0)
-            }
-            LoRaRegion::EU433 => {
-                (4346650, 0)
-            }
-            LoRaRegion::AU915 => {
-                (9233000, 8)
-            }
-            LoRaRegion::CN470 => {
-                (5053000, 0)
-            }
-            LoRaRegion::AS923_1 => {
-                (9232000, 2)
-            }
-            LoRaRegion::AS923_2 => {
-                (9232000, 2)
-            }
-            LoRaRegion::AS923_3 => {
-                (9232000, 2)
-            }
-            LoRaRegion::KR920 => {
-                (9219000, 0)
-            }
-            LoRaRegion::IN865 => {
-                (8665500, 2)
-            }
-            LoRaRegion::RU864 => {
-                (8691000, 0)
-            }
-        };
+        let (rx2_freq, rx2_dr) = default_rx2(req.region);
         let eui = req.eui;
         let (class_b, class_c) = req.extra_parm.map(|e| (e.class_b.unwrap_or(false), e.class_c.unwrap_or(false)))
             .unwrap_or((false, false));
@@ -205,6 +216,9 @@ impl LoraNodeDeviceDefault {
             c_retry: 0,
             dutycyle: 30,
             product_type: ProductType::Monitor,
+            profile: req.profile,
+            payload_encryption: req.payload_encryption.unwrap_or_default(),
+            app_kek: req.app_kek,
         };
         
         match req.join_type {
@@ -213,7 +227,13 @@ impl LoraNodeDeviceDefault {
                 this.dev_addr = LoRaNodeService::create_addr(conn).await?;
             }
             LoRaJoinType::ABP => {
-                this.abp(req.join_parameter.app_skey.as_ref(), req.join_parameter.nwk_skey.as_ref(), req.join_parameter.dev_addr.as_ref(), conn).await?;
+                // the AppSKey is kept by the application server in application mode
+                let nil = Key::nil().to_string();
+                let app_skey = match this.payload_encryption {
+                    PayloadEncryption::Server => req.join_parameter.app_skey.as_ref(),
+                    PayloadEncryption::Application => Some(&nil),
+                };
+                this.abp(app_skey, req.join_parameter.nwk_skey.as_ref(), req.join_parameter.dev_addr.as_ref(), conn).await?;
                 this.dev_eui = eui;
             }
         }
@@ -221,6 +241,28 @@ impl LoraNodeDeviceDefault {
         Ok(this)
     }
 
+    /// Takes the profile managed settings from the device profile, if any.
+    fn apply_profile(&mut self) {
+        let Some(profile) = self.profile.as_ref() else {
+            return;
+        };
+        self.region = profile.region;
+        self.class_b = profile.class_b;
+        self.class_c = profile.class_c;
+        self.adr = profile.adr;
+        self.rx1_delay = profile.rx1_delay;
+        self.des_rx1_delay = profile.rx1_delay;
+        self.rx1_dro = profile.rx1_dro;
+        self.des_rx1_dro = profile.rx1_dro;
+        self.rx2_dr = profile.rx2_dr;
+        self.des_rx2_dr = profile.rx2_dr;
+        self.rx2_freq = profile.rx2_freq;
+        self.des_rx2_freq = profile.rx2_freq;
+        self.d_retry = profile.d_retry as i32;
+        self.c_retry = profile.c_retry as i32;
+        self.dutycyle = profile.dutycyle;
+    }
+
     async fn abp<C: ConnectionTrait>(&mut self, 
                  app_skey: Option<&String>, 
                  nwk_skey: Option<&String>, 
@@ -502,7 +544,11 @@ pub(crate) struct ReqLoraNode {
     pub(crate) scan_eui: Option<String>,
     pub(crate) blue_name: Option<String>,
     pub(crate) blue_parm: Option<crate::service::device::device::BluetoothNode>,
-    pub(crate) extra_parm: Option<ExtraParm>
+    pub(crate) extra_parm: Option<ExtraParm>,
+    #[serde(skip)]
+    pub(crate) profile: Option<DeviceProfileModel>,
+    pub(crate) payload_encryption: Option<PayloadEncryption>,
+    pub(crate) app_kek: Option<Key>,
 }
 
 #[derive(Deserialize)]
@@ -678,7 +724,8 @@ impl LoRaNodeService {
                             }
 
                             NodeInfo::unregister(node.dev_eui, node.dev_addr, redis).await?;
-                            NodeInfo::register_to_redis(node, device.device, redis).await?;
+                            let profile = node.load_profile(conn).await?;
+                            NodeInfo::register_to_redis(node, device.device, profile, redis).await?;
                         }
                         Ok(device_id)
                     }
@@ -703,11 +750,20 @@ impl LoRaNodeService {
     }
     #[instrument(skip_all)]
     async fn insert_node<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
-        node: LoraNodeDeviceDefault, 
+        mut node: LoraNodeDeviceDefault, 
         user: &CurrentUser,
         redis: &mut R,
         conn: &C
     ) -> ApiResult<Id> {
+        node.apply_profile();
+        if node.payload_encryption == PayloadEncryption::Application {
+            if node.app_kek.is_none() {
+                return Err(ApiError::User(
+                    tt!("messages.device.lora.app_kek_missing")
+                ));
+            }
+            node.app_skey = Key::nil();
+        }
         match node.join_type {
             LoRaJoinType::OTAA=> {
                 if NodeInfo::check_eui(node.dev_eui, redis).await? {
@@ -788,13 +844,18 @@ impl LoRaNodeService {
             dev_non: ActiveValue::Set(0),
             app_non: ActiveValue::Set(0),
             net_id: ActiveValue::Set(0),
+            profile: ActiveValue::Set(node.profile.as_ref().map(|profile| profile.id)),
+            overrides: ActiveValue::Set(Default::default()),
+            fport_routes: ActiveValue::Set(Default::default()),
+            payload_encryption: ActiveValue::Set(node.payload_encryption),
+            app_kek: ActiveValue::Set(node.app_kek),
         };
         let model = model.insert(conn).await?;
         if let Some(blue_name) = node.blue_name {
             DeviceService::new_func_blue(device.id, blue_name.as_str(), conn ).await?;
         }
         let device_id = device.id;
-        NodeInfo::register_to_redis(model, device, redis).await?;
+        NodeInfo::register_to_redis(model, device, node.profile, redis).await?;
  
         Ok(device_id)
     }
@@ -0,0 +1,299 @@
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, ModelTrait, PaginatorTrait, QueryFilter, TryIntoModel};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use common_define::db::{DecodeScriptColumn, DecodeScriptEntity, DeviceLoraNodeColumn, FPortRoutes, DeviceLoraNodeEntity, DeviceProfileActiveModel, DeviceProfileColumn, DeviceProfileEntity, DeviceProfileModel, DevicesEntity};
+use common_define::Id;
+use common_define::lora::{FCntPolicy, LoRaRegion};
+use common_define::time::Timestamp;
+use device_info::lorawan::NodeInfo;
+use crate::{tt, CurrentUser};
+use crate::error::{ApiError, ApiResult};
+use crate::service::lorawan::default_rx2;
+
+pub(crate) struct DeviceProfileService;
+
+#[derive(Deserialize)]
+pub(crate) struct ReqDeviceProfile {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) mac_version: Option<String>,
+    pub(crate) reg_params_revision: Option<String>,
+    pub(crate) region: Option<LoRaRegion>,
+    pub(crate) class_b: Option<bool>,
+    pub(crate) class_c: Option<bool>,
+    pub(crate) adr: Option<bool>,
+    pub(crate) rx1_delay: Option<i16>,
+    pub(crate) rx1_dro: Option<i16>,
+    pub(crate) rx2_dr: Option<i16>,
+    pub(crate) rx2_freq: Option<i32>,
+    pub(crate) dutycyle: Option<i32>,
+    pub(crate) d_retry: Option<i16>,
+    pub(crate) c_retry: Option<i16>,
+    pub(crate) fcnt_policy: Option<FCntPolicy>,
+    pub(crate) script: Option<Id>,
+    pub(crate) reset_script: Option<bool>,
+    pub(crate) fport_routes: Option<FPortRoutes>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DeviceProfileResp {
+    pub(crate) id: Id,
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) mac_version: String,
+    pub(crate) reg_params_revision: String,
+    pub(crate) region: LoRaRegion,
+    pub(crate) class_b: bool,
+    pub(crate) class_c: bool,
+    pub(crate) adr: bool,
+    pub(crate) rx1_delay: i16,
+    pub(crate) rx1_dro: i16,
+    pub(crate) rx2_dr: i16,
+    pub(crate) rx2_freq: i32,
+    pub(crate) dutycyle: i32,
+    pub(crate) d_retry: i16,
+    pub(crate) c_retry: i16,
+    pub(crate) fcnt_policy: FCntPolicy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) script: Option<Id>,
+    pub(crate) fport_routes: FPortRoutes,
+    pub(crate) create_time: Timestamp,
+    pub(crate) modify_time: Timestamp,
+}
+
+impl From<DeviceProfileModel> for DeviceProfileResp {
+    fn from(value: DeviceProfileModel) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            mac_version: value.mac_version,
+            reg_params_revision: value.reg_params_revision,
+            region: value.region,
+            class_b: value.class_b,
+            class_c: value.class_c,
+            adr: value.adr,
+            rx1_delay: value.rx1_delay,
+            rx1_dro: value.rx1_dro,
+            rx2_dr: value.rx2_dr,
+            rx2_freq: value.rx2_freq,
+            dutycyle: value.dutycyle,
+            d_retry: value.d_retry,
+            c_retry: value.c_retry,
+            fcnt_policy: value.fcnt_policy,
+            script: value.script,
+            fport_routes: value.fport_routes,
+            create_time: value.create_time,
+            modify_time: value.modify_time,
+        }
+    }
+}
+
+impl DeviceProfileService {
+    async fn check_script<C: ConnectionTrait>(user: &CurrentUser, script: Id, conn: &C) -> ApiResult {
+        DecodeScriptEntity::find_by_id(script)
+            .filter(DecodeScriptColumn::Owner.eq(user.id))
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.decode.not_found_script")))?;
+        Ok(())
+    }
+
+    /// Checks the ports, topics and decoder scripts of FPort routes.
+    pub(crate) async fn check_fport_routes<C: ConnectionTrait>(user: &CurrentUser, routes: &FPortRoutes, conn: &C) -> ApiResult {
+        if let Some(port) = routes.invalid_port() {
+            return Err(ApiError::User(tt!("messages.device.profile.invalid_fport", port = port)));
+        }
+        if let Some(topic) = routes.invalid_topic() {
+            return Err(ApiError::User(tt!("messages.device.profile.invalid_topic", topic = topic)));
+        }
+        for script in routes.scripts() {
+            Self::check_script(user, script, conn).await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) async fn create<C: ConnectionTrait>(
+        user: &CurrentUser,
+        req: ReqDeviceProfile,
+        conn: &C
+    ) -> ApiResult<DeviceProfileModel> {
+        let name = req.name.ok_or_else(|| ApiError::User(tt!("messages.device.profile.name_missing")))?;
+        let region = req.region.ok_or_else(|| ApiError::User(tt!("messages.device.common.region_missing")))?;
+        if let Some(script) = req.script {
+            Self::check_script(user, script, conn).await?;
+        }
+        if let Some(ref routes) = req.fport_routes {
+            Self::check_fport_routes(user, routes, conn).await?;
+        }
+        let (rx2_freq, rx2_dr) = default_rx2(region);
+        let now = Timestamp::now();
+        let model = DeviceProfileActiveModel {
+            id: Default::default(),
+            owner: ActiveValue::Set(user.id),
+            name: ActiveValue::Set(name),
+            description: ActiveValue::Set(req.description.unwrap_or_default()),
+            mac_version: ActiveValue::Set(req.mac_version.unwrap_or_else(|| "1.0.3".to_string())),
+            reg_params_revision: ActiveValue::Set(req.reg_params_revision.unwrap_or_else(|| "A".to_string())),
+            region: ActiveValue::Set(region),
+            class_b: ActiveValue::Set(req.class_b.unwrap_or(false)),
+            class_c: ActiveValue::Set(req.class_c.unwrap_or(false)),
+            adr: ActiveValue::Set(req.adr.unwrap_or(true)),
+            rx1_delay: ActiveValue::Set(req.rx1_delay.unwrap_or(5)),
+            rx1_dro: ActiveValue::Set(req.rx1_dro.unwrap_or(0)),
+            rx2_dr: ActiveValue::Set(req.rx2_dr.unwrap_or(rx2_dr)),
+            rx2_freq: ActiveValue::Set(req.rx2_freq.unwrap_or(rx2_freq)),
+            dutycyle: ActiveValue::Set(req.dutycyle.unwrap_or(30)),
+            d_retry: ActiveValue::Set(req.d_retry.unwrap_or(0)),
+            c_retry: ActiveValue::Set(req.c_retry.unwrap_or(0)),
+            fcnt_policy: ActiveValue::Set(req.fcnt_policy.unwrap_or_default()),
+            script: ActiveValue::Set(req.script),
+            fport_routes: ActiveValue::Set(req.fport_routes.unwrap_or_default()),
+            create_time: ActiveValue::Set(now),
+            modify_time: ActiveValue::Set(now),
+        };
+        Ok(model.insert(conn).await?)
+    }
+
+    pub(crate) async fn list<C: ConnectionTrait>(
+        user: &CurrentUser,
+        conn: &C
+    ) -> ApiResult<Vec<DeviceProfileModel>> {
+        Ok(DeviceProfileEntity::find()
+            .filter(DeviceProfileColumn::Owner.eq(user.id))
+            .all(conn)
+            .await?)
+    }
+
+    pub(crate) async fn get<C: ConnectionTrait>(
+        user: &CurrentUser,
+        id: Id,
+        conn: &C
+    ) -> ApiResult<DeviceProfileModel> {
+        DeviceProfileEntity::find_by_id(id)
+            .filter(DeviceProfileColumn::Owner.eq(user.id))
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.device.profile.not_found")))
+    }
+
+    /// Updates the profile and rewrites the settings of every registered node using it.
+    #[instrument(skip_all)]
+    pub(crate) async fn update<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        user: &CurrentUser,
+        id: Id,
+        req: ReqDeviceProfile,
+        redis: &mut R,
+        conn: &C
+    ) -> ApiResult<DeviceProfileModel> {
+        let profile = Self::get(user, id, conn).await?;
+        if let Some(script) = req.script {
+            Self::check_script(user, script, conn).await?;
+        }
+        if let Some(ref routes) = req.fport_routes {
+            Self::check_fport_routes(user, routes, conn).await?;
+        }
+        let mut model = profile.into_active_model();
+        if let Some(name) = req.name {
+            model.name = ActiveValue::Set(name);
+        }
+        if let Some(description) = req.description {
+            model.description = ActiveValue::Set(description);
+        }
+        if let Some(mac_version) = req.mac_version {
+            model.mac_version = ActiveValue::Set(mac_version);
+        }
+        if let Some(reg_params_revision) = req.reg_params_revision {
+            model.reg_params_revision = ActiveValue::Set(reg_params_revision);
+        }
+        if let Some(region) = req.region {
+            model.region = ActiveValue::Set(region);
+        }
+        if let Some(class_b) = req.class_b {
+            model.class_b = ActiveValue::Set(class_b);
+        }
+        if let Some(class_c) = req.class_c {
+            model.class_c = ActiveValue::Set(class_c);
+        }
+        if let Some(adr) = req.adr {
+            model.adr = ActiveValue::Set(adr);
+        }
+        if let Some(rx1_delay) = req.rx1_delay {
+            model.rx1_delay = ActiveValue::Set(rx1_delay);
+        }
+        if let Some(rx1_dro) = req.rx1_dro {
+            model.rx1_dro = ActiveValue::Set(rx1_dro);
+        }
+        if let Some(rx2_dr) = req.rx2_dr {
+            model.rx2_dr = ActiveValue::Set(rx2_dr);
+        }
+        if let Some(rx2_freq) = req.rx2_freq {
+            model.rx2_freq = ActiveValue::Set(rx2_freq);
+        }
+        if let Some(dutycyle) = req.dutycyle {
+            model.dutycyle = ActiveValue::Set(dutycyle);
+        }
+        if let Some(d_retry) = req.d_retry {
+            model.d_retry = ActiveValue::Set(d_retry);
+        }
+        if let Some(c_retry) = req.c_retry {
+            model.c_retry = ActiveValue::Set(c_retry);
+        }
+        if let Some(fcnt_policy) = req.fcnt_policy {
+            model.fcnt_policy = ActiveValue::Set(fcnt_policy);
+        }
+        if req.script.is_some() {
+            model.script = ActiveValue::Set(req.script);
+        }
+        if req.reset_script.unwrap_or(false) {
+            model.script = ActiveValue::Set(None);
+        }
+        if let Some(routes) = req.fport_routes {
+            model.fport_routes = ActiveValue::Set(routes);
+        }
+        if !model.is_changed() {
+            return Ok(model.try_into_model()?);
+        }
+        model.modify_time = ActiveValue::Set(Timestamp::now());
+        let profile = model.update(conn).await?;
+        Self::sync_nodes(&profile, redis, conn).await?;
+        Ok(profile)
+    }
+
+    pub(crate) async fn sync_nodes<C: ConnectionTrait, R: redis::aio::ConnectionLike>(
+        profile: &DeviceProfileModel,
+        redis: &mut R,
+        conn: &C
+    ) -> ApiResult {
+        let nodes = DeviceLoraNodeEntity::find()
+            .filter(DeviceLoraNodeColumn::Profile.eq(profile.id))
+            .find_also_related(DevicesEntity)
+            .all(conn)
+            .await?;
+        for (node, device) in nodes {
+            if let Some(device) = device {
+                NodeInfo::sync_profile(node, &device, Some(profile), redis).await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) async fn delete<C: ConnectionTrait>(
+        user: &CurrentUser,
+        id: Id,
+        conn: &C
+    ) -> ApiResult {
+        let profile = Self::get(user, id, conn).await?;
+        let count = DeviceLoraNodeEntity::find()
+            .filter(DeviceLoraNodeColumn::Profile.eq(id))
+            .count(conn)
+            .await?;
+        if count > 0 {
+            return Err(ApiError::User(tt!("messages.device.profile.associated_device", count = count)));
+        }
+        profile.delete(conn).await?;
+        Ok(())
+    }
+}
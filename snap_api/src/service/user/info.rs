@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use sea_orm::{ActiveModelTrait, ActiveValue, ColIdx, ConnectionTrait, EntityTrait, IntoActiveModel};
 use tracing::warn;
 use common_define::db::{UsersEntity, UsersModel};
+use common_define::time;
 use crate::{CurrentUser, tt, AppState};
 use crate::error::{ApiError, ApiResult};
 use crate::load::load_config;
@@ -14,13 +15,16 @@ use crate::utils::{Base64, PasswordHash, Rand};
 pub(crate) struct UserPutInfo {
     pub(crate) password: Option<String>,
     pub(crate) old_password: Option<String>,
+    /// IANA time zone such as `Europe/Berlin`, empty to use the one of each device.
+    pub(crate) time_zone: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug,  utoipa::ToSchema)]
 pub(crate) struct UserRespInfo {
     pub(crate) username: String,
     pub(crate) picture: String,
-    pub(crate) email: String
+    pub(crate) email: String,
+    pub(crate) time_zone: Option<String>,
 }
 
 impl From<UsersModel> for UserRespInfo {
@@ -29,6 +33,7 @@ impl From<UsersModel> for UserRespInfo {
             username: value.user_login,
             picture: value.picture,
             email: value.email.unwrap_or_default(),
+            time_zone: value.time_zone,
         }
     }
 }
@@ -171,6 +176,12 @@ impl UserService {
         info: UserPutInfo,
         state: &AppState
     ) -> ApiResult {
+        if let Some(time_zone) = &info.time_zone {
+            Self::set_time_zone(user, time_zone, &state.db).await?;
+            if info.password.is_none() && info.old_password.is_none() {
+                return Ok(())
+            }
+        }
         if info.password.is_none() {
             return Err(ApiError::User(
                 tt!("messages.user.reset_password.password")
@@ -217,6 +228,28 @@ impl UserService {
         Ok(())
     }
 
+    async fn set_time_zone<C: ConnectionTrait>(
+        user: &CurrentUser,
+        time_zone: &str,
+        conn: &C
+    ) -> ApiResult {
+        let time_zone = match time_zone.trim() {
+            "" => None,
+            name => Some(time::time_zone(name)
+                .ok_or_else(|| ApiError::User(tt!("messages.user.info.time_zone", name = name)))?
+                .name()
+                .to_string()),
+        };
+        let u = UsersEntity::find_by_id(user.id)
+            .one(conn)
+            .await?
+            .ok_or_else(|| ApiError::User(tt!("messages.user.info.not_found")))?;
+        let mut m = u.into_active_model();
+        m.time_zone = ActiveValue::Set(time_zone);
+        m.update(conn).await?;
+        Ok(())
+    }
+
     pub(crate) async fn get_info(
         user: &CurrentUser,
         state: &AppState
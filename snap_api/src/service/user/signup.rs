@@ -104,6 +104,8 @@ impl UserService {
             active: ActiveValue::Set(true),
             active_token: ActiveValue::Set(token),
             picture: ActiveValue::Set("".into()),
+            units: Default::default(),
+            time_zone: Default::default(),
             create_time: ActiveValue::Set(Timestamp::now())
         };
         let mut redis = state.redis.get().await?;
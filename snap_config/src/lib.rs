@@ -9,6 +9,8 @@ pub use redis::RedisConfig;
 pub use database::DatabaseConfig;
 pub use device_topic::DeviceTopicConfig;
 pub use log_level::LogLevelConfig;
+pub use key_encryption::{KeyEncryptionConfig, parse_kek};
+pub use telemetry::TelemetryConfig;
 pub use log_level::init_logging;
 
 mod redis {
@@ -183,4 +185,56 @@ mod log_level {
             .with_max_level(log_level)
             .init();
     }
-}
\ No newline at end of file
+}
+mod key_encryption {
+    use serde::Deserialize;
+
+    /// Key-encryption key for LoRaWAN keys stored at rest, as 32 hex chars inline or in a file.
+    #[derive(Deserialize, Debug, Default)]
+    pub struct KeyEncryptionConfig {
+        #[serde(default)]
+        pub kek: Option<String>,
+        #[serde(default)]
+        pub kek_file: Option<String>,
+    }
+
+    impl KeyEncryptionConfig {
+        pub fn load(&self) -> anyhow::Result<Option<[u8; 16]>> {
+            let hex_kek = match (&self.kek, &self.kek_file) {
+                (Some(kek), _) => kek.clone(),
+                (None, Some(file)) => std::fs::read_to_string(file)?,
+                (None, None) => return Ok(None),
+            };
+            Ok(Some(parse_kek(&hex_kek)?))
+        }
+    }
+
+    pub fn parse_kek(s: &str) -> anyhow::Result<[u8; 16]> {
+        let mut kek = [0; 16];
+        hex::decode_to_slice(s.trim(), &mut kek)
+            .map_err(|e| anyhow::anyhow!("invalid key encryption key: {}", e))?;
+        Ok(kek)
+    }
+}
+
+mod telemetry {
+    use serde::Deserialize;
+
+    /// Where decoded uplinks are stored, `backend` selects the variant.
+    #[derive(Deserialize, Debug, Clone, Default)]
+    #[serde(tag = "backend", rename_all = "lowercase")]
+    pub enum TelemetryConfig {
+        /// The snap_device_data table.
+        #[default]
+        Postgres,
+        /// The snap_device_data table as a TimescaleDB hypertable.
+        Timescale {
+            #[serde(default = "_default_chunk_days")]
+            chunk_days: u32,
+        },
+    }
+
+    fn _default_chunk_days() -> u32 {
+        7
+    }
+}
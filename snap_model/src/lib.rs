@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 use std::fs;
 use serde::Deserialize;
+use common_define::decode::DecodeDataType;
 use common_define::lora::ValueType;
 
 #[derive(Deserialize, Debug)]
@@ -112,7 +113,19 @@ impl ModelMap {
     pub fn get_entry(&self, data_id: u32, lang: &str) -> ModelEntity {
         match self.map.get(&data_id) {
             None => {
-                self.get_default_entry(lang)
+                match common_define::codec::builtin_entry(data_id) {
+                    Some(entry) => ModelEntity {
+                        unit: entry.unit,
+                        v_type: match entry.data_type {
+                            DecodeDataType::I32 => Some(ValueType::I32),
+                            DecodeDataType::F64 => Some(ValueType::F64),
+                            DecodeDataType::Bool => Some(ValueType::Bool),
+                            _ => None,
+                        },
+                        name: if lang == "zh" { entry.zh } else { entry.en },
+                    },
+                    None => self.get_default_entry(lang),
+                }
             }
             Some(s) => {
                 ModelEntity {